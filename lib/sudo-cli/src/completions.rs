@@ -0,0 +1,14 @@
+//! Shell completion scripts, generated from the command line definition so they cannot get out
+//! of sync with the options sudo actually accepts.
+
+use std::io::Write;
+
+use clap::CommandFactory;
+pub use clap_complete::Shell;
+
+use crate::Cli;
+
+/// Write a completion script for `shell` to `out`
+pub fn generate(shell: Shell, out: &mut dyn Write) {
+    clap_complete::generate(shell, &mut Cli::command(), "sudo", out);
+}
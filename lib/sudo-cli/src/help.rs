@@ -0,0 +1,211 @@
+//! The help and usage messages, in the layout of the original sudo. They are generated from the
+//! command line definition, so they list exactly the options that sudo accepts.
+
+use clap::{Arg, Command, CommandFactory};
+
+use crate::Cli;
+
+/// Lines are broken after this many characters, where possible
+const WIDTH: usize = 94;
+
+/// The ways to invoke sudo: the mode option (if any), the options that go with it, and the
+/// arguments that follow them
+const MODES: &[(&str, &[&str], &str)] = &[
+    ("-h | -K | -k | -V", &[], ""),
+    (
+        "-v",
+        &[
+            "askpass",
+            "bell",
+            "reset_timestamp",
+            "no_update",
+            "non_interactive",
+            "stdin",
+            "group",
+            "host_or_help",
+            "prompt",
+            "user",
+        ],
+        "",
+    ),
+    (
+        "-l",
+        &[
+            "askpass",
+            "bell",
+            "reset_timestamp",
+            "no_update",
+            "non_interactive",
+            "stdin",
+            "group",
+            "host_or_help",
+            "prompt",
+            "other_user",
+            "user",
+        ],
+        "[command]",
+    ),
+    (
+        "",
+        &[
+            "askpass",
+            "background",
+            "bell",
+            "short_preserve_env",
+            "set_home",
+            "reset_timestamp",
+            "no_update",
+            "non_interactive",
+            "preserve_groups",
+            "stdin",
+            "num",
+            "directory",
+            "group",
+            "host_or_help",
+            "prompt",
+            "chroot",
+            "command_timeout",
+            "user",
+        ],
+        "[VAR=value] [-i|-s] [<command>]",
+    ),
+    (
+        "-e",
+        &[
+            "askpass",
+            "bell",
+            "reset_timestamp",
+            "no_update",
+            "non_interactive",
+            "stdin",
+            "num",
+            "directory",
+            "group",
+            "host_or_help",
+            "prompt",
+            "chroot",
+            "command_timeout",
+            "user",
+        ],
+        "file ...",
+    ),
+];
+
+/// Options that are shown differently from how they are defined: `-E` is short for
+/// `--preserve-env` without a list, and `-h` means `--help` unless it is given a host
+const NAMES: &[(&str, &str)] = &[
+    ("short_preserve_env", "-E, --preserve-env"),
+    ("host_or_help", "-h, --host=host"),
+    ("help", "-h, --help"),
+    ("stop_processing_args", "--"),
+];
+
+/// Options that this build of sudo does not support, and why
+const UNAVAILABLE: &[(&str, &str)] = &[
+    #[cfg(not(target_os = "linux"))]
+    ("sandbox", "only available on Linux"),
+];
+
+/// The message of `sudo -h`: the usage, and what every option does
+pub fn help_message() -> String {
+    let command = Cli::command();
+    let mut message = format!(
+        "sudo - execute a command as another user\n\n{}\n\nOptions:",
+        usage(&command)
+    );
+    for arg in command.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        let Some(help) = arg.get_help() else {
+            continue;
+        };
+        let mut help = help.to_string();
+        if let Some((_, reason)) = UNAVAILABLE.iter().find(|(id, _)| arg.get_id() == id) {
+            help = format!("{help} (not supported: {reason})");
+        }
+        message.push('\n');
+        message.push_str(&wrap(&format!("  {:<29}", names(arg)), &help, 32));
+    }
+    message
+}
+
+/// The message of `sudo --usage`, which is also shown with errors on the command line
+pub fn usage_message() -> String {
+    usage(&Cli::command())
+}
+
+fn usage(command: &Command) -> String {
+    let lines = MODES.iter().map(|(mode, ids, trailer)| {
+        let mut args = ids
+            .iter()
+            .filter_map(|id| command.get_arguments().find(|arg| arg.get_id() == id))
+            .filter(|arg| arg.get_short().is_some())
+            .collect::<Vec<_>>();
+        // like the original sudo, upper case goes before lower case (`-B` before `-b`)
+        args.sort_by_key(|arg| {
+            let short = arg.get_short().unwrap_or_default();
+            (short.to_ascii_lowercase(), short.is_ascii_lowercase())
+        });
+        let (options, flags): (Vec<_>, Vec<_>) = args.into_iter().partition(|arg| takes_value(arg));
+
+        let mut words = vec![mode.to_string()];
+        if !flags.is_empty() {
+            let shorts = flags.iter().filter_map(|arg| arg.get_short());
+            words.push(format!("[-{}]", shorts.collect::<String>()));
+        }
+        for arg in options {
+            let short = arg.get_short().unwrap_or_default();
+            words.push(format!("[-{short} {}]", value_name(arg)));
+        }
+        words.push(trailer.to_string());
+        words.retain(|word| !word.is_empty());
+        wrap("usage: sudo", &words.join(" "), 12)
+    });
+    lines.collect::<Vec<_>>().join("\n")
+}
+
+/// How an option is written in the help, such as `-u, --user=user`
+fn names(arg: &Arg) -> String {
+    if let Some((_, names)) = NAMES.iter().find(|(id, _)| arg.get_id() == id) {
+        return names.to_string();
+    }
+    let mut names = match (arg.get_short(), arg.get_long()) {
+        (Some(short), Some(long)) => format!("-{short}, --{long}"),
+        (Some(short), None) => format!("-{short}"),
+        (None, Some(long)) => format!("    --{long}"),
+        (None, None) => String::new(),
+    };
+    if takes_value(arg) {
+        names = format!("{names}={}", value_name(arg));
+    }
+    names
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_action().takes_values()
+}
+
+fn value_name(arg: &Arg) -> String {
+    match arg.get_value_names() {
+        Some([name, ..]) => name.to_string(),
+        _ => arg.get_id().to_string(),
+    }
+}
+
+/// Put the words of `text` after `start`, breaking lines at [WIDTH] characters (unless a word
+/// does not fit on a line by itself) and indenting the next lines by `indent` spaces
+fn wrap(start: &str, text: &str, indent: usize) -> String {
+    let mut result = start.to_string();
+    let mut line_length = start.len();
+    for word in text.split(' ') {
+        if line_length + 1 + word.len() > WIDTH && line_length > indent {
+            result.push('\n');
+            result.push_str(&" ".repeat(indent));
+            line_length = indent;
+        } else {
+            result.push(' ');
+            line_length += 1;
+        }
+        result.push_str(word);
+        line_length += word.len();
+    }
+    result
+}
@@ -1,47 +1,9 @@
-use clap::{error::Error, Parser};
+use clap::{error::Error, CommandFactory, FromArgMatches, Parser, ValueHint};
 use std::{path::PathBuf, process::exit};
 
-const HELP_MSG: &str = "sudo - execute a command as another user
-
-usage: sudo -h | -K | -k | -V
-usage: sudo -v [-ABkNnS] [-g group] [-h host] [-p prompt] [-u user]
-usage: sudo -l [-ABkNnS] [-g group] [-h host] [-p prompt] [-U user] [-u user] [command]
-usage: sudo [-ABbEHkNnPS] [-C num] [-D directory] [-g group] [-h host] [-p prompt] [-R
-            directory] [-T timeout] [-u user] [VAR=value] [-i|-s] [<command>]
-usage: sudo -e [-ABkNnS] [-C num] [-D directory] [-g group] [-h host] [-p prompt] [-R
-            directory] [-T timeout] [-u user] file ...
-
-Options:
-  -A, --askpass                 use a helper program for password prompting
-  -b, --background              run command in the background
-  -B, --bell                    ring bell when prompting
-  -C, --close-from=num          close all file descriptors >= num
-  -D, --chdir=directory         change the working directory before running command
-  -E, --preserve-env            preserve user environment when running command
-      --preserve-env=list       preserve specific environment variables
-  -e, --edit                    edit files instead of running a command
-  -g, --group=group             run command as the specified group name or ID
-  -H, --set-home                set HOME variable to target user's home dir
-  -h, --help                    display help message and exit
-  -h, --host=host               run command on host (if supported by plugin)
-  -i, --login                   run login shell as the target user; a command may also be
-                                specified
-  -K, --remove-timestamp        remove timestamp file completely
-  -k, --reset-timestamp         invalidate timestamp file
-  -l, --list                    list user's privileges or check a specific command; use twice
-                                for longer format
-  -n, --non-interactive         non-interactive mode, no prompts are used
-  -P, --preserve-groups         preserve group vector instead of setting to target's
-  -p, --prompt=prompt           use the specified password prompt
-  -R, --chroot=directory        change the root directory before running command
-  -S, --stdin                   read password from standard input
-  -s, --shell                   run shell as the target user; a command may also be specified
-  -T, --command-timeout=timeout terminate command after the specified time limit
-  -U, --other-user=user         in list mode, display privileges for user
-  -u, --user=user               run command (or edit file) as specified user name or ID
-  -V, --version                 display version information and exit
-  -v, --validate                update user's timestamp without running a command
-  --                            stop processing command line arguments";
+pub mod completions;
+pub mod help;
+pub mod names;
 
 #[derive(Debug, Parser, Clone, PartialEq)]
 #[clap(
@@ -50,12 +12,7 @@ Options:
     version,
     disable_version_flag = true,
     disable_help_flag = true,
-    trailing_var_arg = true,
-    override_usage = "usage: sudo -h | -K | -k | -V
-    usage: sudo -v [-AknS] [-g group] [-h host] [-p prompt] [-u user]
-    usage: sudo -l [-AknS] [-g group] [-h host] [-p prompt] [-U user] [-u user] [command]
-    usage: sudo [-AbEHknPS] [-C num] [-D directory] [-g group] [-h host] [-p prompt] [-R directory] [-T timeout] [-u user] [VAR=value] [-i|-s] [<command>]
-    usage: sudo -e [-AknS] [-C num] [-D directory] [-g group] [-h host] [-p prompt] [-R directory] [-T timeout] [-u user] file ..."
+    trailing_var_arg = true
 )]
 struct Cli {
     #[arg(
@@ -72,19 +29,22 @@ struct Cli {
     #[arg(
         short = 'C',
         long = "close-from",
-        help = "close all file descriptors >= num"
+        help = "close all file descriptors >= num",
+        value_name = "num"
     )]
     num: Option<i16>,
     #[arg(
         short = 'D',
         long = "chdir",
-        help = "change the working directory before running command"
+        help = "change the working directory before running command",
+        value_name = "directory",
+        value_hint = ValueHint::DirPath
     )]
     directory: Option<PathBuf>,
-    #[arg(long, help = "preserve specific environment variables", value_name = "list", value_delimiter=',', default_value = None, default_missing_value = "", require_equals = true, num_args = 0..)]
-    preserve_env: Vec<String>,
     #[arg(short = 'E', help = "preserve user environment when running command")]
     short_preserve_env: bool,
+    #[arg(long, help = "preserve specific environment variables", value_name = "list", value_delimiter=',', default_value = None, default_missing_value = "", require_equals = true, num_args = 0..)]
+    preserve_env: Vec<String>,
     #[arg(
         short = 'e',
         long,
@@ -95,7 +55,8 @@ struct Cli {
     #[arg(
         short = 'g',
         long = "group",
-        help = "run command as the specified group name or ID"
+        help = "run command as the specified group name or ID",
+        value_name = "group"
     )]
     group: Option<String>,
     #[arg(
@@ -105,6 +66,10 @@ struct Cli {
         action
     )]
     set_home: bool,
+    #[arg(long, help = "display help message and exit", action)]
+    help: bool,
+    #[arg(short = 'h', help = "run command on host (if supported by plugin)", value_name = "host", default_value = None, default_missing_value = "", require_equals = true, num_args = 0..=1)]
+    host_or_help: Option<String>,
     #[arg(
         short = 'i',
         long,
@@ -119,6 +84,9 @@ struct Cli {
         help = "remove timestamp file completely",
         action,
         conflicts_with("reset_timestamp"),
+        conflicts_with("validate"),
+        conflicts_with("list"),
+        conflicts_with("external_args"),
         conflicts_with("version")
     )]
     remove_timestamp: bool,
@@ -135,9 +103,31 @@ struct Cli {
         short,
         long,
         help = "list user's privileges or check a specific command; use twice for longer format",
-        action
+        action = clap::ArgAction::Count
+    )]
+    list: u8,
+    #[arg(
+        long,
+        help = "in list mode, display privileges as JSON",
+        action,
+        requires = "list"
     )]
-    list: bool,
+    json: bool,
+    #[arg(
+        long = "list-version",
+        help = "in list mode, use this version of the output format",
+        value_name = "version",
+        requires = "list"
+    )]
+    list_version: Option<u32>,
+    #[arg(
+        short = 'N',
+        long = "no-update",
+        help = "do not update user's timestamp, even if authentication succeeds",
+        action,
+        conflicts_with("remove_timestamp")
+    )]
+    no_update: bool,
     #[arg(
         short = 'n',
         long = "non-interactive",
@@ -155,16 +145,24 @@ struct Cli {
     #[arg(
         short = 'p',
         long = "prompt",
-        help = "use the specified password prompt"
+        help = "use the specified password prompt",
+        value_name = "prompt"
     )]
     prompt: Option<String>,
     #[arg(
         short = 'R',
         long = "chroot",
         help = "change the root directory before running command",
-        value_name = "directory"
+        value_name = "directory",
+        value_hint = ValueHint::DirPath
     )]
     chroot: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "run command with a read-only file system, in namespaces of its own (experimental)",
+        action
+    )]
+    sandbox: bool,
     #[arg(short = 'S', long, help = "read password from standard input", action)]
     stdin: bool,
     #[arg(
@@ -184,16 +182,42 @@ struct Cli {
     #[arg(
         short = 'U',
         long = "other-user",
-        help = "in list mode, display privileges for user",
-        value_name = "user"
+        help = "in list mode, display privileges for user; with -V, display the Defaults that apply to user",
+        value_name = "user",
+        value_hint = ValueHint::Username
     )]
     other_user: Option<String>,
     #[arg(
         short = 'u',
         long = "user",
-        help = "run command (or edit file) as specified user name or ID"
+        help = "run command (or edit file) as specified user name or ID",
+        value_name = "user",
+        value_hint = ValueHint::Username
     )]
     user: Option<String>,
+    #[arg(long, help = "display usage message and exit", action)]
+    usage: bool,
+    #[arg(
+        short = 'V',
+        long,
+        help = "display version information and exit",
+        action
+    )]
+    version: bool,
+    #[arg(
+        long,
+        help = "with -V, display only the version number",
+        action,
+        requires = "version"
+    )]
+    quiet: bool,
+    #[arg(
+        long = "has-feature",
+        help = "exit with status 0 if this sudo supports the feature, and 1 if not",
+        value_name = "feature",
+        conflicts_with_all(["version", "list", "validate", "external_args"])
+    )]
+    has_feature: Option<String>,
     #[arg(
         short = 'v',
         long,
@@ -201,21 +225,51 @@ struct Cli {
         action
     )]
     validate: bool,
-    #[arg(short = 'V', action = clap::ArgAction::Version, required = false)]
-    version: (),
-    #[arg(short = 'h', value_name = "host", default_value = None, default_missing_value = "", require_equals = true, num_args = 0..=1)]
-    host_or_help: Option<String>,
-    #[arg(long, value_name = "host")]
-    host: Option<String>,
-    #[arg(long)]
-    help: bool,
     // this is a hack to make help show up for `--`, which wouldn't be allowed as a flag in clap.
     // Ignore value of `stop_processing_args`.
     #[arg(long = " ", help = "stop processing command line arguments", action)]
     stop_processing_args: bool,
     // Arguments passed straight through, either seperated by -- or just trailing.
-    #[arg(hide = true)]
+    #[arg(hide = true, value_hint = ValueHint::CommandWithArguments)]
     external_args: Vec<String>,
+    #[arg(long, hide = true, value_name = "host", value_hint = ValueHint::Hostname)]
+    host: Option<String>,
+    // Shows the environment of the command instead of running it, for debugging the policy
+    #[arg(long, hide = true, action)]
+    preview_env: bool,
+    // Used to create shell completion scripts when packaging sudo-rs
+    #[arg(long, hide = true, value_name = "shell")]
+    generate_completions: Option<completions::Shell>,
+}
+
+/// The user given with `-u`: a name, or a numeric ID written as `#uid`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TargetUser {
+    Name(String),
+    Id(u32),
+}
+
+impl TryFrom<String> for TargetUser {
+    type Error = Error;
+
+    fn try_from(user: String) -> Result<Self, Self::Error> {
+        let invalid = |message: String| Error::raw(clap::error::ErrorKind::InvalidValue, message);
+
+        if user.is_empty() || user.contains('\0') {
+            return Err(invalid("invalid user name".to_string()));
+        }
+        // like the original sudo, group syntax and malformed IDs are reported as unknown users
+        if user.starts_with('%') {
+            return Err(invalid(format!("unknown user {user}")));
+        }
+        match user.strip_prefix('#') {
+            Some(id) => match id.parse() {
+                Ok(uid) => Ok(TargetUser::Id(uid)),
+                Err(_) => Err(invalid(format!("unknown user {user}"))),
+            },
+            None => Ok(TargetUser::Name(user)),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -236,20 +290,35 @@ pub struct SudoOptions {
     pub remove_timestamp: bool,
     pub reset_timestamp: bool,
     pub list: bool,
+    /// `-l` was given twice, for the long format
+    pub long_list: bool,
+    /// List the privileges in the machine-readable format
+    pub json: bool,
+    /// The version of the list format that the output has to follow (the latest if not given)
+    pub list_version: Option<u32>,
+    pub no_update: bool,
     pub non_interactive: bool,
     pub preserve_groups: bool,
     pub prompt: Option<String>,
     pub chroot: Option<PathBuf>,
+    pub sandbox: bool,
     pub stdin: bool,
     pub shell: bool,
     pub command_timeout: Option<String>,
     pub other_user: Option<String>,
-    pub user: Option<String>,
+    pub user: Option<TargetUser>,
     pub validate: bool,
+    pub version: bool,
+    /// With `-V`, print only the version number, for scripts
+    pub quiet: bool,
+    /// Only tell (with the exit code) whether a feature is supported
+    pub has_feature: Option<String>,
     pub host: Option<String>,
     // Arguments passed straight through, either seperated by -- or just trailing.
     pub external_args: Vec<String>,
     pub env_var_list: Vec<(String, String)>,
+    // Show the environment the command would get, without running it
+    pub preview_env: bool,
 }
 
 impl TryFrom<Cli> for SudoOptions {
@@ -259,10 +328,20 @@ impl TryFrom<Cli> for SudoOptions {
         let is_help = command.host_or_help.as_deref() == Some("");
 
         if is_help || command.help {
-            println!("{HELP_MSG}");
+            println!("{}", help::help_message());
             exit(0);
         };
 
+        if command.usage {
+            println!("{}", help::usage_message());
+            exit(0);
+        }
+
+        if let Some(shell) = command.generate_completions {
+            completions::generate(shell, &mut std::io::stdout());
+            exit(0);
+        }
+
         let host = if command.host.is_some() {
             return Err(Error::raw(
                 clap::error::ErrorKind::ArgumentConflict,
@@ -272,6 +351,14 @@ impl TryFrom<Cli> for SudoOptions {
             command.host_or_help
         };
 
+        // -U selects whose privileges are listed, or whose Defaults are shown
+        if command.other_user.is_some() && command.list == 0 && !command.version {
+            return Err(Error::raw(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "the -U option may only be used with the -l or -V option",
+            ));
+        }
+
         // This lets us know if the user passed `--preserve-env` with no args
         let preserve_env_no_args = command.preserve_env.iter().any(String::is_empty);
 
@@ -297,20 +384,29 @@ impl TryFrom<Cli> for SudoOptions {
             login: command.login,
             remove_timestamp: command.remove_timestamp,
             reset_timestamp: command.reset_timestamp,
-            list: command.list,
+            list: command.list > 0,
+            long_list: command.list > 1,
+            json: command.json,
+            list_version: command.list_version,
+            no_update: command.no_update,
             non_interactive: command.non_interactive,
             preserve_groups: command.preserve_groups,
             prompt: command.prompt,
             chroot: command.chroot,
+            sandbox: command.sandbox,
             stdin: command.stdin,
             shell: command.shell,
             command_timeout: command.command_timeout,
             other_user: command.other_user,
-            user: command.user,
+            user: command.user.map(TargetUser::try_from).transpose()?,
             validate: command.validate,
+            version: command.version,
+            quiet: command.quiet,
+            has_feature: command.has_feature,
             host,
             external_args: command.external_args,
             env_var_list: Default::default(),
+            preview_env: command.preview_env,
         })
     }
 }
@@ -368,7 +464,10 @@ impl SudoOptions {
 
         // Now that the remaining args are not environment variable declarations we can let `clap`
         // do its magic.
-        let mut opts: SudoOptions = Cli::try_parse_from(remaining_args)?.try_into()?;
+        let matches = Cli::command()
+            .override_usage(help::usage_message())
+            .try_get_matches_from(remaining_args)?;
+        let mut opts: SudoOptions = Cli::from_arg_matches(&matches)?.try_into()?;
         // Populate the environment variable declarations.
         opts.env_var_list = env_var_list;
 
@@ -428,8 +527,42 @@ impl SudoOptions {
         }
     }
 
+    /// Whether `-k` or `-K` was given on its own, which only discards the cached credentials;
+    /// together with a command (or `-v` or `-l`), `-k` instead means that the cached credentials
+    /// are ignored for this invocation, but left in place.
+    pub fn only_resets_timestamp(&self) -> bool {
+        (self.reset_timestamp || self.remove_timestamp)
+            && self.external_args.is_empty()
+            && !(self.validate || self.list || self.shell || self.login || self.edit)
+    }
+
+    /// Parse the arguments of `sudoedit`, which is `sudo -e`; so `-e` itself is not accepted
+    pub fn try_parse_sudoedit_from<I, T>(iter: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String> + Clone,
+    {
+        let mut opts = Self::try_parse_from(iter)?;
+        if opts.edit {
+            return Err(Error::raw(
+                clap::error::ErrorKind::ArgumentConflict,
+                "the -e option is implied by sudoedit and cannot be given",
+            ));
+        }
+        opts.edit = true;
+        Ok(opts)
+    }
+
     pub fn parse() -> Self {
-        match Self::try_parse_from(std::env::args()) {
+        Self::or_exit(Self::try_parse_from(std::env::args()))
+    }
+
+    pub fn parse_sudoedit() -> Self {
+        Self::or_exit(Self::try_parse_sudoedit_from(std::env::args()))
+    }
+
+    fn or_exit(result: Result<Self, Error>) -> Self {
+        match result {
             Ok(options) => options,
             Err(err) => {
                 eprintln!("{err}");
@@ -0,0 +1,69 @@
+//! How strictly user names given on the command line are checked before they are looked up, so
+//! that a mistyped or malicious name gets a clear error instead of a failed lookup.
+
+use std::str::FromStr;
+
+/// The rules for user names; sudo-rs.conf selects one with `user_names`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NamePolicy {
+    /// POSIX portable names: ASCII letters, digits, `.`, `_` and `-`, not starting with `-`
+    Portable,
+    /// The relaxed rules of systemd: any Unicode text, except that a name cannot start with
+    /// `-`, consist of digits only, be `.` or `..`, have white space at either end, or contain
+    /// control characters, `:` or `/`
+    Relaxed,
+    /// Whatever the user database could have; only the empty name is rejected
+    #[default]
+    Permissive,
+}
+
+impl FromStr for NamePolicy {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        match text {
+            "portable" => Ok(NamePolicy::Portable),
+            "relaxed" => Ok(NamePolicy::Relaxed),
+            "permissive" => Ok(NamePolicy::Permissive),
+            _ => Err(format!(
+                "unknown user name policy {text} (must be portable, relaxed or permissive)"
+            )),
+        }
+    }
+}
+
+impl NamePolicy {
+    /// Check a user name, giving the reason why it is invalid
+    pub fn check(self, name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("it is empty".to_string());
+        }
+        if self == NamePolicy::Permissive {
+            return Ok(());
+        }
+        if name.starts_with('-') {
+            return Err("it starts with '-'".to_string());
+        }
+
+        let allowed = |c: char| match self {
+            NamePolicy::Portable => c.is_ascii_alphanumeric() || "._-".contains(c),
+            _ => !c.is_control() && !":/".contains(c),
+        };
+        if let Some(c) = name.chars().find(|c| !allowed(*c)) {
+            return Err(format!("{c:?} is not allowed in it"));
+        }
+
+        if self == NamePolicy::Relaxed {
+            if name.chars().all(|c| c.is_ascii_digit()) {
+                return Err("it consists of digits only".to_string());
+            }
+            if name == "." || name == ".." {
+                return Err("it is not a name".to_string());
+            }
+            if name.trim() != name {
+                return Err("it starts or ends with white space".to_string());
+            }
+        }
+        Ok(())
+    }
+}
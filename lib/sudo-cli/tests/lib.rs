@@ -1,5 +1,5 @@
 use pretty_assertions::assert_eq;
-use sudo_cli::SudoOptions;
+use sudo_cli::{SudoOptions, TargetUser};
 
 /// --preserve-env
 /// Passing '-E' sets 'short_preserve_env' to true, 'preserve_env_list' stays empty
@@ -147,6 +147,84 @@ fn remove_and_reset_timestamp_exclusion() {
     SudoOptions::try_parse_from(["sudo", "--reset-timestamp", "--reboot-timestamp"]).unwrap();
 }
 
+#[test]
+fn reset_timestamp_with_command() {
+    let cmd = SudoOptions::try_parse_from(["sudo", "-k"]).unwrap();
+    assert!(cmd.only_resets_timestamp());
+    let cmd = SudoOptions::try_parse_from(["sudo", "-K"]).unwrap();
+    assert!(cmd.only_resets_timestamp());
+
+    let cmd = SudoOptions::try_parse_from(["sudo", "-k", "ls", "-l"]).unwrap();
+    assert!(cmd.reset_timestamp);
+    assert!(!cmd.only_resets_timestamp());
+    assert_eq!(cmd.external_args, ["ls", "-l"]);
+    let cmd = SudoOptions::try_parse_from(["sudo", "-kv"]).unwrap();
+    assert!(!cmd.only_resets_timestamp());
+    let cmd = SudoOptions::try_parse_from(["sudo", "-k", "-s"]).unwrap();
+    assert!(!cmd.only_resets_timestamp());
+
+    // -K removes the credentials, which cannot be combined with anything else
+    assert!(SudoOptions::try_parse_from(["sudo", "-K", "ls"]).is_err());
+    assert!(SudoOptions::try_parse_from(["sudo", "-K", "-v"]).is_err());
+    assert!(SudoOptions::try_parse_from(["sudo", "-K", "-l"]).is_err());
+    assert!(SudoOptions::try_parse_from(["sudo", "-K", "-k"]).is_err());
+}
+
+#[test]
+fn no_update() {
+    let cmd = SudoOptions::try_parse_from(["sudo", "-N", "ls"]).unwrap();
+    assert!(cmd.no_update);
+    assert_eq!(cmd.external_args, ["ls"]);
+    let cmd = SudoOptions::try_parse_from(["sudo", "--no-update", "-v"]).unwrap();
+    assert!(cmd.no_update && cmd.validate);
+    let cmd = SudoOptions::try_parse_from(["sudo", "-kN", "ls"]).unwrap();
+    assert!(cmd.no_update && cmd.reset_timestamp);
+    assert!(SudoOptions::try_parse_from(["sudo", "-K", "-N"]).is_err());
+}
+
+#[test]
+fn list_command_of_other_user() {
+    let cmd = SudoOptions::try_parse_from(["sudo", "-ll", "-U", "bob", "ls", "-l"]).unwrap();
+    assert!(cmd.list);
+    assert_eq!(cmd.other_user.as_deref(), Some("bob"));
+    assert_eq!(cmd.external_args, ["ls", "-l"]);
+
+    // -U only makes sense in list mode, or to show the Defaults for that user
+    assert!(SudoOptions::try_parse_from(["sudo", "-U", "bob", "ls"]).is_err());
+    let cmd = SudoOptions::try_parse_from(["sudo", "-V", "-U", "bob"]).unwrap();
+    assert!(cmd.version);
+    assert_eq!(cmd.other_user.as_deref(), Some("bob"));
+}
+
+#[test]
+fn list_formats() {
+    let cmd = SudoOptions::try_parse_from(["sudo", "-l"]).unwrap();
+    assert!(cmd.list && !cmd.long_list && !cmd.json);
+    assert_eq!(cmd.list_version, None);
+    let cmd = SudoOptions::try_parse_from(["sudo", "-ll", "--list-version=1"]).unwrap();
+    assert!(cmd.long_list);
+    assert_eq!(cmd.list_version, Some(1));
+    let cmd = SudoOptions::try_parse_from(["sudo", "-l", "--json"]).unwrap();
+    assert!(cmd.json);
+
+    // the format options only go with -l
+    assert!(SudoOptions::try_parse_from(["sudo", "--json", "ls"]).is_err());
+    assert!(SudoOptions::try_parse_from(["sudo", "-v", "--list-version=1"]).is_err());
+}
+
+#[test]
+fn probes() {
+    let cmd = SudoOptions::try_parse_from(["sudo", "--version", "--quiet"]).unwrap();
+    assert!(cmd.version && cmd.quiet);
+    assert!(SudoOptions::try_parse_from(["sudo", "--quiet"]).is_err());
+
+    let cmd = SudoOptions::try_parse_from(["sudo", "--has-feature", "iolog"]).unwrap();
+    assert_eq!(cmd.has_feature.as_deref(), Some("iolog"));
+    assert!(cmd.external_args.is_empty());
+    assert!(SudoOptions::try_parse_from(["sudo", "--has-feature=pam", "-V"]).is_err());
+    assert!(SudoOptions::try_parse_from(["sudo", "--has-feature=pam", "ls"]).is_err());
+}
+
 /// Check that the first environment variable declaration before any command is not treated as part
 /// of the command.
 #[test]
@@ -183,3 +261,116 @@ fn single_env_var_declaration() {
     assert_eq!(cmd.env_var_list, vec![("FOO".to_owned(), "1".to_owned())]);
     assert_eq!(cmd.external_args, vec!["command"]);
 }
+
+#[test]
+fn completions_contain_options() {
+    use sudo_cli::completions::{generate, Shell};
+
+    for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+        let mut script = Vec::new();
+        generate(shell, &mut script);
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("chdir"));
+        assert!(script.contains("other-user"));
+    }
+}
+
+#[test]
+fn sandbox() {
+    let cmd = SudoOptions::try_parse_from(["sudo", "--sandbox", "make", "install"]).unwrap();
+    assert!(cmd.sandbox);
+    assert_eq!(cmd.external_args, ["make", "install"]);
+    let cmd = SudoOptions::try_parse_from(["sudo", "make", "--sandbox"]).unwrap();
+    assert!(!cmd.sandbox);
+}
+
+#[test]
+fn target_user() {
+    let user =
+        |arg: &str| SudoOptions::try_parse_from(["sudo", "-u", arg, "id"]).map(|cmd| cmd.user);
+    assert_eq!(
+        user("alice").unwrap(),
+        Some(TargetUser::Name("alice".to_owned()))
+    );
+    assert_eq!(user("#1000").unwrap(), Some(TargetUser::Id(1000)));
+    assert_eq!(
+        user("%wheel").unwrap_err().to_string().trim(),
+        "error: unknown user %wheel"
+    );
+    assert!(user("#alice").is_err());
+    assert!(user("#-1").is_err());
+    assert!(user("").is_err());
+}
+
+#[test]
+fn help_lists_options() {
+    use sudo_cli::help::{help_message, usage_message};
+
+    let help = help_message();
+    assert!(help.starts_with("sudo - execute a command as another user\n\nusage: sudo -h"));
+    assert!(help.contains(&usage_message()));
+    for line in [
+        "  -E, --preserve-env            preserve user environment when running command",
+        "      --preserve-env=list       preserve specific environment variables",
+        "  -h, --help                    display help message and exit",
+        "  -u, --user=user               run command (or edit file) as specified user name or ID",
+        "  --                            stop processing command line arguments",
+    ] {
+        assert!(help.lines().any(|help| help == line), "{line}");
+    }
+    assert!(!help.contains("      --host=host"));
+    assert!(!help.contains("generate-completions"));
+
+    assert_eq!(
+        usage_message().lines().nth(3),
+        Some(
+            "usage: sudo [-ABbEHkNnPS] [-C num] [-D directory] [-g group] [-h host] [-p prompt] [-R"
+        )
+    );
+}
+
+#[test]
+fn preview_env() {
+    let cmd = SudoOptions::try_parse_from(["sudo", "--preview-env", "-E", "env"]).unwrap();
+    assert!(cmd.preview_env);
+    assert!(cmd.preserve_env);
+    assert!(!sudo_cli::help::help_message().contains("preview-env"));
+}
+
+#[test]
+fn sudoedit() {
+    let cmd = SudoOptions::try_parse_sudoedit_from(["sudoedit", "-u", "www", "/etc/motd"]).unwrap();
+    assert!(cmd.edit);
+    assert_eq!(cmd.external_args, ["/etc/motd"]);
+    let cmd = SudoOptions::try_parse_sudoedit_from(["sudoedit", "--", "-e"]).unwrap();
+    assert_eq!(cmd.external_args, ["-e"]);
+    assert!(SudoOptions::try_parse_sudoedit_from(["sudoedit", "-e", "/etc/motd"]).is_err());
+}
+
+#[test]
+fn user_name_policies() {
+    use sudo_cli::names::NamePolicy::{self, *};
+
+    let ok = |policy: NamePolicy, name: &str| policy.check(name).is_ok();
+    for name in ["alice", "www-data", "j.doe", "_apt"] {
+        assert!(ok(Portable, name) && ok(Relaxed, name) && ok(Permissive, name));
+    }
+    for name in ["José", "dom user"] {
+        assert!(!ok(Portable, name) && ok(Relaxed, name));
+    }
+    assert!(ok(Portable, "42") && !ok(Relaxed, "42"));
+    for name in ["-rf", "a:b", "../etc", "tab\tbed"] {
+        assert!(!ok(Portable, name) && !ok(Relaxed, name) && ok(Permissive, name));
+    }
+    assert!(!ok(Relaxed, ".."));
+    assert!(!ok(Relaxed, " alice"));
+    assert!(!ok(Permissive, ""));
+    assert_eq!(
+        Relaxed.check("a:b").unwrap_err(),
+        "':' is not allowed in it"
+    );
+
+    assert_eq!("portable".parse(), Ok(Portable));
+    assert_eq!(NamePolicy::default(), Permissive);
+    assert!("strict".parse::<NamePolicy>().is_err());
+}
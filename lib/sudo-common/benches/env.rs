@@ -0,0 +1,107 @@
+//! Build the environment of a command from a large environment, like the ones that CI systems
+//! export (hundreds of variables), with the default env_keep and env_check lists and with longer
+//! lists that contain patterns.
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use sudo_common::{
+    context::{CommandAndArguments, Context},
+    env::{get_target_environment, Environment, CHECK_ENV_TABLE, DELETE_ENV_TABLE, KEEP_ENV_TABLE},
+};
+use sudo_system::{Group, User};
+
+fn user(uid: libc::uid_t, name: &str) -> User {
+    User {
+        uid,
+        gid: uid,
+        name: name.to_string(),
+        gecos: String::new(),
+        home: format!("/home/{name}"),
+        shell: "/bin/sh".to_string(),
+        passwd: String::new(),
+        groups: None,
+    }
+}
+
+fn context(env_keep: Vec<String>, env_check: Vec<String>) -> Context {
+    Context {
+        hostname: "ci-runner".to_string(),
+        command: CommandAndArguments::try_from(vec!["/usr/bin/env"]).unwrap(),
+        current_user: user(1000, "runner"),
+        target_user: user(0, "root"),
+        target_group: Group {
+            gid: 0,
+            name: "root".to_string(),
+            passwd: String::new(),
+            members: Vec::new(),
+        },
+        target_environment: Default::default(),
+        chdir: None,
+        chroot: None,
+        preserve_env: false,
+        set_home: false,
+        login: false,
+        background: false,
+        exit_with_parent: false,
+        noexec: false,
+        intercept: None,
+        umask: None,
+        capabilities: None,
+        sandbox: None,
+        timeout: None,
+        log_input: false,
+        log_output: false,
+        log_passwords: false,
+        iolog: None,
+        preserve_env_list: vec!["GITHUB_TOKEN".to_string()],
+        env_keep,
+        env_check,
+        env_delete: table(DELETE_ENV_TABLE),
+        env_reset: true,
+    }
+}
+
+/// An environment of `size` variables, named like those of CI systems
+fn environment(size: usize) -> Environment {
+    let prefixes = ["CI_JOB_", "GITHUB_", "RUNNER_", "npm_config_", "LC_"];
+    (0..size)
+        .map(|i| {
+            let name = format!("{}VAR_{i}", prefixes[i % prefixes.len()]);
+            (name, format!("/opt/ci/value/{i}"))
+        })
+        .collect()
+}
+
+fn table(entries: &[&str]) -> Vec<String> {
+    entries.iter().map(|entry| entry.to_string()).collect()
+}
+
+fn bench(c: &mut Criterion) {
+    let mut long_keep = table(KEEP_ENV_TABLE);
+    long_keep.extend((0..50).map(|i| format!("KEEP_{i}")));
+    long_keep.extend(table(&["CI_*", "RUNNER_*", "EDITOR=vi*", "SSH_AUTH_SOCK"]));
+    let contexts = [
+        (
+            "default lists",
+            context(table(KEEP_ENV_TABLE), table(CHECK_ENV_TABLE)),
+        ),
+        ("long lists", context(long_keep, table(CHECK_ENV_TABLE))),
+    ];
+
+    let mut group = c.benchmark_group("get_target_environment");
+    for size in [50, 500] {
+        let env = environment(size);
+        for (name, context) in &contexts {
+            group.bench_function(format!("{name}, {size} variables"), |b| {
+                b.iter_batched(
+                    || env.clone(),
+                    |env| get_target_environment(env, black_box(context)),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,126 @@
+//! The source of time for timeouts, such as `passwd_timeout`, and delays. sudo itself uses the
+//! clocks of the system; tests can substitute a [FakeClock] that only moves when they say so.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+pub trait Clock {
+    /// The current point in time; this never goes backwards
+    fn now(&self) -> Instant;
+    /// The current time of day, which can be recorded for later invocations of sudo
+    fn system_time(&self) -> SystemTime;
+    /// Wait for some time
+    fn sleep(&self, duration: Duration);
+}
+
+/// The monotonic clock of the system
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Monotonic;
+
+impl Clock for Monotonic {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// A clock that stands still until it is advanced explicitly, or by sleeping
+#[derive(Debug)]
+pub struct FakeClock {
+    start: Instant,
+    start_time: SystemTime,
+    elapsed_nanos: AtomicU64,
+}
+
+impl Default for FakeClock {
+    fn default() -> Self {
+        FakeClock {
+            start: Instant::now(),
+            start_time: SystemTime::now(),
+            elapsed_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl FakeClock {
+    pub fn advance(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.elapsed_nanos.fetch_add(nanos, Ordering::SeqCst);
+    }
+
+    fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.elapsed_nanos.load(Ordering::SeqCst))
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_time + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+/// A point in time (according to some clock) after which an operation is abandoned
+#[derive(Clone, Copy)]
+pub struct Deadline<'a> {
+    clock: &'a dyn Clock,
+    at: Instant,
+}
+
+impl<'a> Deadline<'a> {
+    pub fn after(clock: &'a dyn Clock, timeout: Duration) -> Deadline<'a> {
+        Deadline {
+            clock,
+            at: clock.now() + timeout,
+        }
+    }
+
+    /// The time left until the deadline, which is zero once it has passed
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(self.clock.now())
+    }
+
+    pub fn has_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadlines() {
+        let clock = FakeClock::default();
+        let deadline = Deadline::after(&clock, Duration::from_secs(300));
+        assert_eq!(deadline.remaining(), Duration::from_secs(300));
+
+        clock.advance(Duration::from_secs(299));
+        assert_eq!(deadline.remaining(), Duration::from_secs(1));
+        assert!(!deadline.has_expired());
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(deadline.remaining(), Duration::ZERO);
+        assert!(deadline.has_expired());
+
+        let deadline = Deadline::after(&Monotonic, Duration::from_secs(60));
+        assert!(deadline.remaining() <= Duration::from_secs(60));
+    }
+}
@@ -0,0 +1,115 @@
+//! The configuration of sudo-rs itself in /etc/sudo-rs.conf, for what is not part of the sudoers
+//! policy, such as where the NOEXEC library is installed. The file consists of `key = value`
+//! lines; `#` starts a comment.
+
+use std::{
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+use crate::debug_log;
+
+pub const CONFIG_FILE: &str = "/etc/sudo-rs.conf";
+
+/// Look up a setting in the text of sudo-rs.conf (the last one counts)
+pub(crate) fn config_value(text: &str, key: &str) -> Option<String> {
+    text.lines()
+        .rev()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(|line| line.split_once('='))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim().to_string())
+}
+
+/// Look up a setting in sudo-rs.conf; the file is ignored if anyone but root could change it
+pub fn setting(key: &str) -> Option<String> {
+    if !is_trusted(Path::new(CONFIG_FILE)) {
+        if Path::new(CONFIG_FILE).exists() {
+            debug_log!(
+                Main,
+                Warn,
+                "ignoring {CONFIG_FILE}: it is not owned by root"
+            );
+        }
+        return None;
+    }
+    let text = fs::read_to_string(CONFIG_FILE).ok()?;
+    config_value(&text, key)
+}
+
+/// A file may only be used if it cannot be replaced by anyone but root: the file itself and every
+/// directory on the way to it (after resolving symbolic links) has to be owned by root, and not
+/// be writable by group or others
+pub(crate) fn is_trusted(path: &Path) -> bool {
+    let Ok(path) = fs::canonicalize(path) else {
+        return false;
+    };
+    fs::metadata(&path).is_ok_and(|meta| meta.is_file()) && path.ancestors().all(owned_by_root)
+}
+
+/// Like [is_trusted], for a directory in which root can create files that only root can replace
+pub(crate) fn is_trusted_dir(path: &Path) -> bool {
+    let Ok(path) = fs::canonicalize(path) else {
+        return false;
+    };
+    fs::metadata(&path).is_ok_and(|meta| meta.is_dir()) && path.ancestors().all(owned_by_root)
+}
+
+fn owned_by_root(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.uid() == 0 && meta.mode() & 0o022 == 0)
+}
+
+/// A configured path to a program or library, which has to be absolute and trusted
+pub(crate) fn trusted_path(key: &str) -> Result<Option<PathBuf>, String> {
+    let Some(path) = setting(key) else {
+        return Ok(None);
+    };
+    let path = PathBuf::from(path);
+    if path.is_absolute() && is_trusted(&path) {
+        Ok(Some(path))
+    } else {
+        Err(format!(
+            "{key} {} in {CONFIG_FILE} is not usable",
+            path.display()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+
+    #[test]
+    fn config_file() {
+        let text = "# sudo-rs configuration\n\
+                    noexec_path = /opt/lib/libsudo_noexec.so # local build\n\
+                    other=1\n";
+        assert_eq!(
+            config_value(text, "noexec_path").as_deref(),
+            Some("/opt/lib/libsudo_noexec.so")
+        );
+        assert_eq!(config_value(text, "other").as_deref(), Some("1"));
+        assert_eq!(config_value(text, "missing"), None);
+        assert_eq!(config_value("# noexec_path = /x", "noexec_path"), None);
+    }
+
+    #[test]
+    fn trusted_files() {
+        assert!(is_trusted(Path::new("/bin/sh")));
+        assert!(!is_trusted(Path::new("/bin")));
+        assert!(!is_trusted(Path::new("/nonexistent")));
+
+        // a file in a directory that anyone can write to could be replaced, whoever owns it
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("library.so");
+        fs::write(&file, "").unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(!is_trusted(&file));
+        assert!(!is_trusted_dir(dir.path()));
+        assert!(is_trusted_dir(Path::new("/bin")));
+        assert!(!is_trusted_dir(Path::new("/bin/sh")));
+    }
+}
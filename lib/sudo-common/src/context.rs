@@ -1,12 +1,19 @@
-use std::path::PathBuf;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use sudo_system::{Group, User};
+use sudo_system::{capabilities::CapabilitySet, Group, User};
 
 use crate::{env::Environment, error::Error};
 
 pub struct CommandAndArguments {
     pub command: PathBuf,
     pub arguments: Vec<String>,
+    /// The command with its directory resolved through symbolic links, if that is another path;
+    /// the policy also matches rules for this path (see [resolve_directory])
+    pub resolved: Option<PathBuf>,
 }
 
 impl TryFrom<Vec<&str>> for CommandAndArguments {
@@ -17,19 +24,163 @@ impl TryFrom<Vec<&str>> for CommandAndArguments {
 
         let command = iter.next().ok_or(Error::InvalidCommand)?.to_string();
         // TODO: we resolve in the context of the current user using the 'which' crate - we want to reconsider this in the future
-        let command = which::which(command).map_err(|_| Error::InvalidCommand)?;
+        let command = which::which(&command).map_err(|_| Error::CommandNotFound(command))?;
 
         Ok(CommandAndArguments {
+            resolved: resolve_directory(&command, Path::new("/")),
             command,
             arguments: iter.map(|v| v.to_string()).collect(),
         })
     }
 }
 
+impl CommandAndArguments {
+    /// Resolve the command as it will be found after changing the root directory to `root`; the
+    /// resulting path is relative to the new root. Commands must be given as a name (which is
+    /// looked up in `PATH`) or as an absolute path.
+    pub fn try_in_root(external_args: Vec<&str>, root: &Path) -> Result<Self, Error> {
+        let mut iter = external_args.into_iter();
+
+        let command = iter.next().ok_or(Error::InvalidCommand)?;
+        let not_found = || Error::CommandNotFound(command.to_string());
+        let inside = |path: &Path| root.join(path.strip_prefix("/").unwrap_or(path));
+        let command = if command.starts_with('/') {
+            if !inside(Path::new(command)).is_file() {
+                return Err(not_found());
+            }
+            PathBuf::from(command)
+        } else if !command.contains('/') {
+            let path = env::var_os("PATH").unwrap_or_default();
+            let search = env::join_paths(env::split_paths(&path).map(|dir| inside(&dir)))
+                .map_err(|_| Error::InvalidCommand)?;
+            let found = which::which_in(command, Some(search), root).map_err(|_| not_found())?;
+            let relative = found.strip_prefix(root).map_err(|_| not_found())?;
+            Path::new("/").join(relative)
+        } else {
+            return Err(Error::InvalidCommand);
+        };
+
+        Ok(CommandAndArguments {
+            resolved: resolve_directory(&command, root),
+            command,
+            arguments: iter.map(|v| v.to_string()).collect(),
+        })
+    }
+
+    /// Run a shell (for `sudo -s` and `sudo -i`); a command is passed to it using `-c`, escaped
+    /// in the same way as the original sudo does
+    pub fn shell(shell: PathBuf, external_args: Vec<&str>) -> Self {
+        let arguments = if external_args.is_empty() {
+            Vec::new()
+        } else {
+            vec!["-c".to_string(), escape_shell_args(&external_args)]
+        };
+
+        CommandAndArguments {
+            resolved: resolve_directory(&shell, Path::new("/")),
+            command: shell,
+            arguments,
+        }
+    }
+}
+
+/// Resolve the directory that `command` is in through symbolic links, as it is found inside
+/// `root`, but not the command itself; so `/bin/ls` becomes `/usr/bin/ls` if `/bin` links to
+/// `/usr/bin`, while `/usr/bin/vim` stays `/usr/bin/vim` if it links to `vim.basic`. This gives
+/// `None` if the path stays the same or cannot be resolved. Like in the original sudo, a command
+/// without wildcards in sudoers also matches this path; sudoers itself does not look at files, so
+/// it is resolved when the command is looked up.
+pub fn resolve_directory(command: &Path, root: &Path) -> Option<PathBuf> {
+    let (dir, name) = (command.parent()?, command.file_name()?);
+    let root = root.canonicalize().ok()?;
+    let real = root
+        .join(dir.strip_prefix("/").unwrap_or(dir))
+        .canonicalize()
+        .ok()?;
+    let resolved = Path::new("/")
+        .join(real.strip_prefix(&root).ok()?)
+        .join(name);
+    (resolved != command).then_some(resolved)
+}
+
+/// Join arguments into a single string for `sh -c`, putting a backslash in front of every
+/// character that is not alphanumeric, `_`, `-` or `$`
+pub fn escape_shell_args(args: &[&str]) -> String {
+    let mut result = String::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            result.push(' ');
+        }
+        for c in arg.chars() {
+            if !c.is_ascii_alphanumeric() && !"_-$".contains(c) {
+                result.push('\\');
+            }
+            result.push(c);
+        }
+    }
+
+    result
+}
+
+/// The umask for the command, given the umask of the user and the one from sudoers: unless
+/// `umask_override` is set, the two are combined. A sudoers umask of 0777 (or none at all) leaves
+/// the umask of the user alone.
+pub fn command_umask(
+    user: libc::mode_t,
+    sudoers: Option<libc::mode_t>,
+    umask_override: bool,
+) -> Option<libc::mode_t> {
+    match sudoers {
+        None | Some(0o777) => None,
+        Some(mask) if umask_override => Some(mask),
+        Some(mask) => Some(user | mask),
+    }
+}
+
 pub struct Context {
+    pub chdir: Option<PathBuf>,
+    /// The root directory for the command; `chdir` and the command path are relative to it
+    pub chroot: Option<PathBuf>,
     pub preserve_env: bool,
     pub preserve_env_list: Vec<String>,
+    pub env_keep: Vec<String>,
+    pub env_check: Vec<String>,
+    /// Variables that `-E` does not preserve (and neither does the command, without env_reset)
+    pub env_delete: Vec<String>,
+    /// Build the environment of the command from scratch (the env_reset setting); otherwise, it
+    /// gets the environment of the invoking user without what env_delete and env_check remove
+    pub env_reset: bool,
     pub set_home: bool,
+    /// The command is a login shell (`sudo -i`), which always starts from a fresh environment
+    pub login: bool,
+    /// Do not wait for the command to finish
+    pub background: bool,
+    /// Terminate the command if sudo itself is killed (not applicable to background commands)
+    pub exit_with_parent: bool,
+    /// Keep the command from executing other programs (the NOEXEC tag)
+    pub noexec: bool,
+    /// Only let the command execute the programs that this policy allows (the INTERCEPT tag, or
+    /// the `intercept` setting)
+    pub intercept: Option<crate::intercept::Policy>,
+    /// The file mode creation mask for the command (if not, it inherits that of the user)
+    pub umask: Option<libc::mode_t>,
+    /// If set, the only capabilities that the command can have (on Linux); it can not gain others
+    /// by executing setuid or file-capability programs either
+    pub capabilities: Option<CapabilitySet>,
+    /// Run the command in a sandbox (on Linux), in which the file system is read-only except for
+    /// these paths
+    pub sandbox: Option<Vec<PathBuf>>,
+    /// Terminate the command if it still runs after this long (the TIMEOUT tag)
+    pub timeout: Option<Duration>,
+    /// Record what is typed into the command, and what it outputs, in an I/O log (the LOG_INPUT
+    /// and LOG_OUTPUT tags, or the `log_input` and `log_output` settings)
+    pub log_input: bool,
+    pub log_output: bool,
+    /// Record passwords that are typed into the command as they are (the `log_passwords`
+    /// setting), instead of masking them
+    pub log_passwords: bool,
+    /// The I/O log that the streams of `log_input` and `log_output` are recorded in
+    pub iolog: Option<crate::iolog::Session>,
     pub command: CommandAndArguments,
     pub hostname: String,
     pub current_user: User,
@@ -37,3 +188,87 @@ pub struct Context {
     pub target_group: Group,
     pub target_environment: Environment,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_escaping() {
+        assert_eq!(escape_shell_args(&["echo", "$HOME"]), "echo $HOME");
+        assert_eq!(
+            escape_shell_args(&["ls", "-l", "my file.txt"]),
+            "ls -l my\\ file\\.txt"
+        );
+        assert_eq!(escape_shell_args(&["a;b", "'c'"]), "a\\;b \\'c\\'");
+
+        let command = CommandAndArguments::shell("/bin/sh".into(), vec!["echo", "a b"]);
+        assert_eq!(command.arguments, ["-c", "echo a\\ b"]);
+        assert!(CommandAndArguments::shell("/bin/sh".into(), vec![])
+            .arguments
+            .is_empty());
+    }
+
+    #[test]
+    fn umask_combination() {
+        assert_eq!(command_umask(0o002, Some(0o022), false), Some(0o022));
+        assert_eq!(command_umask(0o077, Some(0o022), false), Some(0o077));
+        assert_eq!(command_umask(0o027, Some(0o002), false), Some(0o027));
+        assert_eq!(command_umask(0o077, Some(0o022), true), Some(0o022));
+        assert_eq!(command_umask(0o077, Some(0o777), true), None);
+        assert_eq!(command_umask(0o077, None, false), None);
+    }
+
+    #[test]
+    fn command_not_found() {
+        assert!(matches!(
+            CommandAndArguments::try_from(vec!["sudo-rs-no-such-command"]),
+            Err(Error::CommandNotFound(command)) if command == "sudo-rs-no-such-command"
+        ));
+        assert!(matches!(
+            CommandAndArguments::try_from(Vec::<&str>::new()),
+            Err(Error::InvalidCommand)
+        ));
+    }
+
+    #[test]
+    fn resolve_in_root() {
+        let root = env::temp_dir().join(format!("sudo-common-chroot-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("usr/bin")).unwrap();
+        std::fs::copy("/bin/sh", root.join("usr/bin/true")).unwrap();
+
+        let command = CommandAndArguments::try_in_root(vec!["/usr/bin/true", "x"], &root).unwrap();
+        assert_eq!(command.command, Path::new("/usr/bin/true"));
+        assert_eq!(command.arguments, ["x"]);
+        assert!(matches!(
+            CommandAndArguments::try_in_root(vec!["/usr/bin/false"], &root),
+            Err(Error::CommandNotFound(command)) if command == "/usr/bin/false"
+        ));
+        assert!(CommandAndArguments::try_in_root(vec!["usr/bin/true"], &root).is_err());
+        if env::var_os("PATH")
+            .is_some_and(|path| env::split_paths(&path).any(|dir| dir == Path::new("/usr/bin")))
+        {
+            let command = CommandAndArguments::try_in_root(vec!["true"], &root).unwrap();
+            assert_eq!(command.command, Path::new("/usr/bin/true"));
+        }
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn linked_directories() {
+        let root = env::temp_dir().join(format!("sudo-common-links-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("usr/bin")).unwrap();
+        std::fs::write(root.join("usr/bin/vim.basic"), "").unwrap();
+        std::os::unix::fs::symlink("vim.basic", root.join("usr/bin/vim")).unwrap();
+        std::os::unix::fs::symlink("usr/bin", root.join("bin")).unwrap();
+
+        let resolve = |path: &str| resolve_directory(Path::new(path), &root);
+        assert_eq!(resolve("/bin/vim"), Some("/usr/bin/vim".into()));
+        // the command itself is not resolved
+        assert_eq!(resolve("/usr/bin/vim"), None);
+        assert_eq!(resolve("/no/such/dir/vim"), None);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
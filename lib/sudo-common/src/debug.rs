@@ -0,0 +1,250 @@
+//! Debug logging, modelled after the debug subsystem of the original sudo: messages belong to a
+//! subsystem and have a priority, and a specification such as
+//!
+//! ```text
+//! /var/log/sudo-rs.debug all@info,match@debug
+//! ```
+//!
+//! selects which of them are appended to a file. Logging is configured through the `debug_flags`
+//! setting in sudoers or (for root only) the `SUDO_RS_DEBUG` environment variable. When a message
+//! is not selected, the [debug_log!](crate::debug_log) macro only costs a single atomic load; its
+//! arguments are not even formatted.
+
+use std::{
+    fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Environment variable holding a debug specification (honored only if the real user is root)
+pub const DEBUG_ENV: &str = "SUDO_RS_DEBUG";
+
+/// Priorities, from most to least important; selecting a priority also selects all more
+/// important ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Crit = 1,
+    Err,
+    Warn,
+    Notice,
+    Diag,
+    Info,
+    Trace,
+    Debug,
+}
+
+const LEVELS: [(&str, Level); 8] = [
+    ("crit", Level::Crit),
+    ("err", Level::Err),
+    ("warn", Level::Warn),
+    ("notice", Level::Notice),
+    ("diag", Level::Diag),
+    ("info", Level::Info),
+    ("trace", Level::Trace),
+    ("debug", Level::Debug),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    /// The sudo front-end itself
+    Main,
+    /// Reading and parsing sudoers
+    Parser,
+    /// Matching a request against the rules in sudoers
+    Match,
+    /// Processing Defaults settings
+    Defaults,
+    /// Constructing the environment of the command
+    Env,
+    /// Authentication
+    Auth,
+    /// Running the command
+    Exec,
+}
+
+const SUBSYSTEMS: [(&str, Subsystem); 7] = [
+    ("main", Subsystem::Main),
+    ("parser", Subsystem::Parser),
+    ("match", Subsystem::Match),
+    ("defaults", Subsystem::Defaults),
+    ("env", Subsystem::Env),
+    ("auth", Subsystem::Auth),
+    ("exec", Subsystem::Exec),
+];
+
+/// The selected level for every subsystem (0 means: nothing)
+static SELECTED: [AtomicU8; SUBSYSTEMS.len()] = [const { AtomicU8::new(0) }; SUBSYSTEMS.len()];
+
+static OUTPUT: Mutex<Option<File>> = Mutex::new(None);
+
+fn name<T: PartialEq + Copy>(table: &[(&'static str, T)], value: T) -> &'static str {
+    table
+        .iter()
+        .find(|(_, v)| *v == value)
+        .map_or("?", |(name, _)| name)
+}
+
+/// A parsed debug specification: the file to write to and the level per subsystem
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spec {
+    pub path: String,
+    pub levels: Vec<(Subsystem, Level)>,
+}
+
+impl std::str::FromStr for Spec {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let (path, flags) = text
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or("expected a file name followed by debug flags")?;
+        if !path.starts_with('/') {
+            return Err(format!("{path}: debug file must be an absolute path"));
+        }
+
+        let mut levels = Vec::new();
+        for flag in flags.trim().split(',') {
+            let (subsystem, level) = flag
+                .split_once('@')
+                .ok_or_else(|| format!("{flag}: expected subsystem@priority"))?;
+            let level = LEVELS
+                .iter()
+                .find(|(name, _)| *name == level)
+                .ok_or_else(|| format!("{level}: unknown priority"))?
+                .1;
+            if subsystem == "all" {
+                levels.extend(SUBSYSTEMS.iter().map(|(_, sub)| (*sub, level)));
+            } else {
+                let sub = SUBSYSTEMS
+                    .iter()
+                    .find(|(name, _)| *name == subsystem)
+                    .ok_or_else(|| format!("{subsystem}: unknown subsystem"))?
+                    .1;
+                levels.push((sub, level));
+            }
+        }
+
+        Ok(Spec {
+            path: path.to_string(),
+            levels,
+        })
+    }
+}
+
+/// Start logging according to a specification; this replaces an earlier configuration.
+pub fn configure(spec: &str) -> Result<(), String> {
+    let spec = spec.parse::<Spec>()?;
+
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(&spec.path)
+        .map_err(|err| format!("{}: {err}", spec.path))?;
+    *OUTPUT.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+
+    for selected in &SELECTED {
+        selected.store(0, Ordering::Relaxed);
+    }
+    for (sub, level) in spec.levels {
+        SELECTED[sub as usize].fetch_max(level as u8, Ordering::Relaxed);
+    }
+
+    Ok(())
+}
+
+/// Configure logging from the environment, if the invoking user is root
+pub fn configure_from_env() -> Result<(), String> {
+    match std::env::var(DEBUG_ENV) {
+        Ok(spec) if sudo_system::User::real_uid() == 0 => configure(&spec),
+        _ => Ok(()),
+    }
+}
+
+/// Whether messages of the given subsystem and level are logged
+#[inline]
+pub fn enabled(sub: Subsystem, level: Level) -> bool {
+    SELECTED[sub as usize].load(Ordering::Relaxed) >= level as u8
+}
+
+#[doc(hidden)]
+pub fn write(sub: Subsystem, level: Level, message: fmt::Arguments, file: &str, line: u32) {
+    let mut output = OUTPUT.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(output) = output.as_mut() {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let _ = writeln!(
+            output,
+            "{}.{:06} sudo[{}] {}@{}: {message} @ {file}:{line}",
+            now.as_secs(),
+            now.subsec_micros(),
+            std::process::id(),
+            name(&SUBSYSTEMS, sub),
+            name(&LEVELS, level),
+        );
+    }
+}
+
+/// Log a message for a subsystem at a certain priority, for example:
+///
+/// ```ignore
+/// debug_log!(Match, Info, "no rule matched {cmdline}");
+/// ```
+#[macro_export]
+macro_rules! debug_log {
+    ($sub:ident, $level:ident, $($arg:tt)*) => {
+        if $crate::debug::enabled($crate::debug::Subsystem::$sub, $crate::debug::Level::$level) {
+            $crate::debug::write(
+                $crate::debug::Subsystem::$sub,
+                $crate::debug::Level::$level,
+                format_args!($($arg)*),
+                file!(),
+                line!(),
+            );
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_spec() {
+        let spec = "/tmp/debug match@debug,env@warn".parse::<Spec>().unwrap();
+        assert_eq!(spec.path, "/tmp/debug");
+        assert_eq!(
+            spec.levels,
+            vec![
+                (Subsystem::Match, Level::Debug),
+                (Subsystem::Env, Level::Warn)
+            ]
+        );
+
+        let spec = "/tmp/debug all@info".parse::<Spec>().unwrap();
+        assert_eq!(spec.levels.len(), SUBSYSTEMS.len());
+
+        assert!("all@info".parse::<Spec>().is_err());
+        assert!("debug all@info".parse::<Spec>().is_err());
+        assert!("/tmp/debug all@loud".parse::<Spec>().is_err());
+        assert!("/tmp/debug nothing@info".parse::<Spec>().is_err());
+        assert!("/tmp/debug all".parse::<Spec>().is_err());
+    }
+
+    #[test]
+    fn levels_are_ordered() {
+        assert!(Level::Crit < Level::Warn);
+        assert!(Level::Info < Level::Debug);
+        assert!(!enabled(Subsystem::Exec, Level::Crit));
+    }
+}
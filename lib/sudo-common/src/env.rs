@@ -1,8 +1,9 @@
 use crate::{
     context::{CommandAndArguments, Context},
+    debug_log,
     wildcard_match::wildcard_match,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use sudo_system::PATH_MAX;
 
 pub type Environment = HashMap<String, String>;
@@ -11,7 +12,7 @@ const PATH_MAILDIR: &str = env!("PATH_MAILDIR");
 const PATH_ZONEINFO: &str = env!("PATH_ZONEINFO");
 
 /// Remove if these environment variables if the value contains '/' or '%'
-const CHECK_ENV_TABLE: &[&str] = &[
+pub const CHECK_ENV_TABLE: &[&str] = &[
     "COLORTERM",
     "LANG",
     "LANGUAGE",
@@ -22,7 +23,7 @@ const CHECK_ENV_TABLE: &[&str] = &[
 ];
 
 /// Keep these environment variables by default
-const KEEP_ENV_TABLE: &[&str] = &[
+pub const KEEP_ENV_TABLE: &[&str] = &[
     "COLORS",
     "DISPLAY",
     "HOSTNAME",
@@ -36,6 +37,64 @@ const KEEP_ENV_TABLE: &[&str] = &[
     "XDG_CURRENT_DESKTOP",
 ];
 
+/// Remove these environment variables when the environment of the invoking user is passed on
+/// as a whole (with `-E`, or without env_reset); with env_reset, they are removed anyway, since they
+/// are not in env_keep.
+///
+/// Most of them are the table of "bad" variables of the original sudo: they change how the dynamic
+/// linker, a shell or an interpreter behaves, so a command running as root would run code of the
+/// invoking user's choosing. Variables starting with `LD_` are removed even if they are taken out
+/// of env_delete (see [EnvFilter::should_delete]).
+///
+/// The session bus variables point into the invoking user's runtime directory, which only that
+/// user can access; a command that inherits them talks to the wrong bus, or to none at all. This
+/// table shows what `sudo systemctl --user` ends up doing with the default lists:
+///
+/// | invocation                                 | variables            | `systemctl --user` as root        |
+/// |--------------------------------------------|----------------------|-----------------------------------|
+/// | `sudo`                                     | removed (env_reset)  | fails: `$XDG_RUNTIME_DIR` not set |
+/// | `sudo -E`                                  | removed (env_delete) | fails: `$XDG_RUNTIME_DIR` not set |
+/// | `sudo --preserve-env=XDG_RUNTIME_DIR,...`  | kept                 | manages the invoking user's units |
+/// | `Defaults env_keep += "XDG_RUNTIME_DIR"`   | kept                 | manages the invoking user's units |
+/// | `Defaults env_delete -= "XDG_RUNTIME_DIR"` | kept with `-E`       | manages the invoking user's units |
+///
+/// As another target user, the kept variables lead to `Permission denied` on the invoking user's
+/// bus instead. Use `systemctl --user -M user@` to reach the bus of the target user.
+pub const DELETE_ENV_TABLE: &[&str] = &[
+    "IFS",
+    "CDPATH",
+    "LOCALDOMAIN",
+    "RES_OPTIONS",
+    "HOSTALIASES",
+    "NLSPATH",
+    "PATH_LOCALE",
+    "LD_*",
+    "_RLD*",
+    "DYLD_*",
+    "TERMINFO",
+    "TERMINFO_DIRS",
+    "TERMPATH",
+    "TERMCAP",
+    "ENV",
+    "BASH_ENV",
+    "PS4",
+    "GLOBIGNORE",
+    "BASHOPTS",
+    "SHELLOPTS",
+    "JAVA_TOOL_OPTIONS",
+    "PERL*",
+    "FPATH",
+    "NULLCMD",
+    "READNULLCMD",
+    "ZDOTDIR",
+    "TMPPREFIX",
+    "PYTHON*",
+    "RUBY*",
+    "*=()*",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "XDG_RUNTIME_DIR",
+];
+
 /// Convert a list of `Into<String>` key value pars to an Environment
 pub fn environment_from_list<K: Into<String>, V: Into<String>>(list: Vec<(K, V)>) -> Environment {
     list.into_iter()
@@ -43,25 +102,24 @@ pub fn environment_from_list<K: Into<String>, V: Into<String>>(list: Vec<(K, V)>
         .collect::<Environment>()
 }
 
-/// Formats the command and arguments passed for the SUDO_COMMAND
-/// environment variable. Limit the length of arguments to 4096 bytes to prevent
-/// execve failure for very long argument vectors
+/// Formats the command and arguments passed for the SUDO_COMMAND environment variable: like in
+/// the original sudo, this is the path of the command followed by its arguments, separated by
+/// spaces. Limit the length of arguments to 4096 bytes to prevent execve failure for very long
+/// argument vectors
 fn format_command(command_and_arguments: &CommandAndArguments) -> String {
-    let mut args_bytes = command_and_arguments
-        .arguments
-        .join(" ")
-        .as_bytes()
-        .to_owned();
-
-    args_bytes.truncate(4096);
-
-    [
-        command_and_arguments.command.to_string_lossy(),
-        String::from_utf8_lossy(&args_bytes),
-    ]
-    .join(" ")
-    .trim()
-    .to_string()
+    let mut result = command_and_arguments.command.to_string_lossy().into_owned();
+    if !command_and_arguments.arguments.is_empty() {
+        let mut args = command_and_arguments.arguments.join(" ");
+        let mut end = args.len().min(4096);
+        while !args.is_char_boundary(end) {
+            end -= 1;
+        }
+        args.truncate(end);
+        result.push(' ');
+        result.push_str(&args);
+    }
+
+    result
 }
 
 /// Construct sudo-specific environment variables
@@ -119,28 +177,110 @@ fn is_safe_tz(value: &str) -> bool {
         && check_value.len() < PATH_MAX as usize
 }
 
-/// Check whether the needle exists in a haystack, in which the haystack is a list of patterns, possibly containing wildcards
-fn in_table(needle: &str, haystack: &[&str]) -> bool {
-    haystack
-        .iter()
-        .any(|pattern| wildcard_match(needle, pattern))
+/// An env_keep, env_check or env_delete list, prepared for checking many variables against it: names without
+/// a wildcard are looked up directly, only the other patterns are tried one by one
+#[derive(Debug, Default)]
+struct Table {
+    names: HashSet<String>,
+    /// Patterns for the name, possibly containing wildcards
+    patterns: Vec<String>,
+    /// Patterns of the form `NAME=value`, which only match if the value matches as well
+    with_value: Vec<String>,
 }
 
-/// Determine whether a specific environment variable should be kept
-fn should_keep(key: &str, value: &str, check_env: &[&str], keep_env: &[&str]) -> bool {
-    if value.starts_with("()") {
-        return false;
+impl Table {
+    fn new(list: &[String]) -> Table {
+        let mut table = Table::default();
+        for pattern in list {
+            if pattern.contains('=') {
+                table.with_value.push(pattern.clone());
+            } else if pattern.contains('*') {
+                table.patterns.push(pattern.clone());
+            } else {
+                table.names.insert(pattern.clone());
+            }
+        }
+        table
     }
 
-    if key == "TZ" && !is_safe_tz(value) {
-        return false;
+    fn matches(&self, key: &str, value: &str) -> bool {
+        self.names.contains(key)
+            || self
+                .patterns
+                .iter()
+                .any(|pattern| wildcard_match(key, pattern))
+            || (!self.with_value.is_empty() && {
+                let variable = format!("{key}={value}");
+                self.with_value
+                    .iter()
+                    .any(|pattern| wildcard_match(&variable, pattern))
+            })
     }
+}
+
+/// Decides which variables of the invoking user's environment the command gets, according to the
+/// env_check, env_keep and env_delete lists; these are prepared once, and then every variable is
+/// checked against them.
+#[derive(Debug, Default)]
+pub struct EnvFilter {
+    check: Table,
+    keep: Table,
+    delete: Table,
+}
+
+impl EnvFilter {
+    pub fn new(env_check: &[String], env_keep: &[String], env_delete: &[String]) -> EnvFilter {
+        EnvFilter {
+            check: Table::new(env_check),
+            keep: Table::new(env_keep),
+            delete: Table::new(env_delete),
+        }
+    }
+
+    /// Determine whether a variable is removed even though the whole environment is preserved:
+    /// like in the original sudo, that is the case if it is in env_delete, or if it is in
+    /// env_check and its value is not safe; variables for the dynamic linker are always removed
+    pub fn should_delete(&self, key: &str, value: &str) -> bool {
+        key.starts_with("LD_")
+            || self.delete.matches(key, value)
+            || (value.contains(['%', '/']) && self.check.matches(key, value))
+    }
+
+    /// Determine whether a specific environment variable should be kept
+    pub fn should_keep(&self, key: &str, value: &str) -> bool {
+        if value.starts_with("()") {
+            return false;
+        }
+
+        if key == "TZ" && !is_safe_tz(value) {
+            return false;
+        }
+
+        if !value.contains(['%', '/']) && self.check.matches(key, value) {
+            return true;
+        }
 
-    if in_table(key, check_env) && !value.contains(|c| c == '%' || c == '/') {
-        return true;
+        self.keep.matches(key, value)
     }
+}
 
-    in_table(key, keep_env)
+/// The variables named with `--preserve-env=list` that would not be kept anyway (by env_keep and
+/// env_check, or without env_reset, because env_delete and env_check do not remove them);
+/// preserving these requires the same permission as `-E`.
+pub fn disallowed_variables(current_env: &Environment, context: &Context) -> Vec<String> {
+    let filter = EnvFilter::new(&context.env_check, &context.env_keep, &context.env_delete);
+    context
+        .preserve_env_list
+        .iter()
+        .filter(|key| {
+            let value = current_env.get(*key).map_or("", |value| value.as_str());
+            match context.env_reset {
+                true => !filter.should_keep(key, value),
+                false => filter.should_delete(key, value),
+            }
+        })
+        .cloned()
+        .collect()
 }
 
 /// Construct the final environment from the current one and a sudo context
@@ -151,17 +291,40 @@ fn should_keep(key: &str, value: &str, check_env: &[&str], keep_env: &[&str]) ->
 /// and the SUDO_* variables are set based on the invoking user.
 ///
 /// Additional variables, such as DISPLAY, PATH and TERM, are preserved from the invoking user's
-/// environment if permitted by the env_check, or env_keep options
+/// environment if permitted by the env_check, or env_keep options (as configured in the context),
+/// or if the user asked for them with `-E` or `--preserve-env=list`; `-E` does not preserve the
+/// variables that env_delete names (see [DELETE_ENV_TABLE])
+///
+/// Without env_reset, the command gets the environment of the invoking user as if `-E` was given:
+/// only the variables that env_delete names, and those in env_check with an unsafe value, are
+/// removed.
+///
+/// For a login shell (`sudo -i`), the environment is always built from scratch as with env_reset:
+/// `-E` (and turning env_reset off) has no effect, but the variables named with
+/// `--preserve-env=list` are still kept. Either way, HOME, MAIL, SHELL, LOGNAME and USER are those
+/// of the target user.
 ///
 /// TODO: If the PATH and TERM variables are not preserved from the user's environment, they will be set to default value
 ///
 /// Environment variables with a value beginning with ‘()’ are removed
 pub fn get_target_environment(current_env: Environment, context: &Context) -> Environment {
-    let mut result = Environment::new();
+    let mut result = Environment::with_capacity(current_env.len());
+    let preserve_all = (context.preserve_env || !context.env_reset) && !context.login;
+    let preserve_list = context
+        .preserve_env_list
+        .iter()
+        .map(String::as_str)
+        .collect::<HashSet<_>>();
+    let filter = EnvFilter::new(&context.env_check, &context.env_keep, &context.env_delete);
 
     for (key, value) in current_env.into_iter() {
-        if should_keep(&key, &value, CHECK_ENV_TABLE, KEEP_ENV_TABLE) {
+        let preserved = (preserve_list.contains(key.as_str())
+            || (preserve_all && !filter.should_delete(&key, &value)))
+            && !value.starts_with("()");
+        if preserved || filter.should_keep(&key, &value) {
             result.insert(key, value);
+        } else {
+            debug_log!(Env, Debug, "removing {key} from the environment");
         }
     }
 
@@ -170,28 +333,101 @@ pub fn get_target_environment(current_env: Environment, context: &Context) -> En
     result
 }
 
+/// Show an environment the way `env` does, one `NAME=value` line per variable, but sorted by name;
+/// `sudo --preview-env` uses this to show the environment a command would get
+pub fn format_environment(env: &Environment) -> String {
+    let mut entries = env.iter().collect::<Vec<_>>();
+    entries.sort();
+    entries
+        .into_iter()
+        .map(|(name, value)| format!("{name}={value}\n"))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::env::{is_safe_tz, PATH_ZONEINFO};
+    use crate::context::CommandAndArguments;
+    use crate::env::{
+        environment_from_list, format_command, format_environment, is_safe_tz, EnvFilter,
+        PATH_ZONEINFO,
+    };
 
     #[test]
     fn test_tzinfo() {
-        assert_eq!(is_safe_tz("Europe/Amsterdam"), true);
-        assert_eq!(
-            is_safe_tz(format!("{PATH_ZONEINFO}/Europe/London").as_str()),
-            true
-        );
-        assert_eq!(
-            is_safe_tz(format!(":{PATH_ZONEINFO}/Europe/Amsterdam").as_str()),
-            true
-        );
-        assert_eq!(
-            is_safe_tz(format!("/schaap/Europe/Amsterdam").as_str()),
-            false
-        );
+        assert!(is_safe_tz("Europe/Amsterdam"));
+        assert!(is_safe_tz(
+            format!("{PATH_ZONEINFO}/Europe/London").as_str()
+        ));
+        assert!(is_safe_tz(
+            format!(":{PATH_ZONEINFO}/Europe/Amsterdam").as_str()
+        ));
+        assert!(!is_safe_tz("/schaap/Europe/Amsterdam"));
+        assert!(!is_safe_tz(
+            format!("{PATH_ZONEINFO}/../Europe/London").as_str()
+        ));
+    }
+
+    #[test]
+    fn test_keep_with_value() {
+        let keep = [
+            "LC_*".to_string(),
+            "EDITOR=vi*".to_string(),
+            "PAGER".to_string(),
+        ];
+        let filter = EnvFilter::new(&[], &keep, &[]);
+        assert!(filter.should_keep("LC_ALL", "C"));
+        assert!(filter.should_keep("EDITOR", "vim"));
+        assert!(!filter.should_keep("EDITOR", "emacs"));
+        assert!(!filter.should_keep("VISUAL", "vi"));
+        assert!(filter.should_keep("PAGER", "/usr/bin/less"));
+        assert!(!filter.should_keep("PAGER", "() { :; }"));
+
+        let check = ["LANG".to_string(), "LC_*".to_string()];
+        let filter = EnvFilter::new(&check, &[], &[]);
+        assert!(filter.should_keep("LANG", "C.UTF-8"));
+        assert!(!filter.should_keep("LANG", "../../tmp/evil"));
+        assert!(!filter.should_keep("LC_ALL", "%n"));
+    }
+
+    #[test]
+    fn test_delete() {
+        let check = ["LANG".to_string()];
+        let delete = ["DBUS_*".to_string(), "XDG_RUNTIME_DIR".to_string()];
+        let filter = EnvFilter::new(&check, &[], &delete);
+        assert!(filter.should_delete("XDG_RUNTIME_DIR", "/run/user/1000"));
+        assert!(filter.should_delete("DBUS_SESSION_BUS_ADDRESS", "unix:path=/run/user/1000/bus"));
+        assert!(filter.should_delete("LANG", "../../tmp/evil"));
+        assert!(!filter.should_delete("LANG", "C.UTF-8"));
+        assert!(!filter.should_delete("XDG_SESSION_TYPE", "wayland"));
+        // even when env_delete does not name them
+        assert!(filter.should_delete("LD_PRELOAD", "/tmp/evil.so"));
+        assert!(filter.should_delete("LD_LIBRARY_PATH", "/tmp"));
+    }
+
+    #[test]
+    fn test_format_environment() {
+        let env = environment_from_list(vec![("TERM", "xterm"), ("HOME", "/root"), ("A", "")]);
+        assert_eq!(format_environment(&env), "A=\nHOME=/root\nTERM=xterm\n");
+        assert_eq!(format_environment(&Default::default()), "");
+    }
+
+    #[test]
+    fn test_sudo_command() {
+        let command = |args: &[&str]| CommandAndArguments {
+            command: "/usr/bin/echo".into(),
+            arguments: args.iter().map(|arg| arg.to_string()).collect(),
+            resolved: None,
+        };
+        assert_eq!(format_command(&command(&[])), "/usr/bin/echo");
         assert_eq!(
-            is_safe_tz(format!("{PATH_ZONEINFO}/../Europe/London").as_str()),
-            false
+            format_command(&command(&["-n", " two  spaces "])),
+            "/usr/bin/echo -n  two  spaces "
         );
+
+        // long arguments are cut off, but not in the middle of a character
+        let long = "é".repeat(3000);
+        let formatted = format_command(&command(&[&long]));
+        assert_eq!(formatted.len(), "/usr/bin/echo ".len() + 4096);
+        assert!(formatted.ends_with('é'));
     }
 }
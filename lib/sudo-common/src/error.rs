@@ -1,10 +1,40 @@
+use std::{fmt, io, path::PathBuf};
+
+use sudo_system::setuid::Problem;
+
 #[derive(Debug)]
 pub enum Error {
     InvalidCommand,
+    CommandNotFound(String),
     UserNotFound,
+    /// The target user given with `-u` is not in the password database
+    UnknownUser(String),
+    InvalidUserName {
+        name: String,
+        reason: String,
+    },
     Exec,
+    ExecFailed(PathBuf, io::Error),
     Authentication(String),
+    /// The password was wrong, in this many attempts (see the `passwd_tries` setting)
+    IncorrectPassword(u32),
     Configuration(String),
+    ChDirNotAllowed {
+        chdir: PathBuf,
+        command: PathBuf,
+    },
+    ChRootNotAllowed {
+        chroot: PathBuf,
+        command: PathBuf,
+    },
+    PreserveEnvNotAllowed,
+    SetEnvNotAllowed(Vec<String>),
+    /// The requiretty flag is set, and sudo was run without a terminal
+    TtyRequired,
+    /// `-b` was given, but the command needs sudo to stay around, for the reason given
+    BackgroundNotAllowed(&'static str),
+    /// sudo does not have the privileges of root, because of how it was installed or started
+    NotPrivileged(Problem),
 }
 
 impl Error {
@@ -15,4 +45,101 @@ impl Error {
     pub fn conf(message: &str) -> Self {
         Self::Configuration(message.to_string())
     }
+
+    /// The exit code of sudo for this error; like a shell, sudo exits with 127 if the command
+    /// could not be found, and with 126 if it could not be executed
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::ExecFailed(_, err) if err.kind() == io::ErrorKind::NotFound => 127,
+            Error::ExecFailed(_, _) => 126,
+            _ => 1,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidCommand => write!(f, "invalid command"),
+            Error::CommandNotFound(command) => write!(f, "{command}: command not found"),
+            Error::UserNotFound => write!(f, "user not found"),
+            Error::UnknownUser(name) => write!(f, "unknown user {name}"),
+            Error::InvalidUserName { name, reason } => {
+                write!(f, "invalid user name {name:?}: {reason}")
+            }
+            Error::Exec => write!(f, "unable to execute command"),
+            Error::ExecFailed(command, err) => {
+                // leave out the "(os error N)" that is part of the description of the error
+                let message = err.to_string();
+                let message = message.split(" (os error").next().unwrap_or_default();
+                write!(f, "unable to execute {}: {message}", command.display())
+            }
+            Error::Authentication(message) => write!(f, "{message}"),
+            Error::IncorrectPassword(1) => write!(f, "1 incorrect password attempt"),
+            Error::IncorrectPassword(attempts) => {
+                write!(f, "{attempts} incorrect password attempts")
+            }
+            Error::Configuration(message) => write!(f, "{message}"),
+            Error::ChDirNotAllowed { chdir: _, command } => write!(
+                f,
+                "you are not permitted to use the -D option with {}",
+                command.display()
+            ),
+            Error::ChRootNotAllowed { chroot: _, command } => write!(
+                f,
+                "you are not permitted to use the -R option with {}",
+                command.display()
+            ),
+            Error::TtyRequired => write!(f, "sorry, you must have a tty to run sudo"),
+            Error::PreserveEnvNotAllowed => {
+                write!(f, "sorry, you are not allowed to preserve the environment")
+            }
+            Error::SetEnvNotAllowed(names) => write!(
+                f,
+                "sorry, you are not allowed to set the following environment variables: {}",
+                names.join(", ")
+            ),
+            Error::BackgroundNotAllowed(reason) => {
+                write!(f, "unable to run the command in the background: {reason}")
+            }
+            Error::NotPrivileged(problem) => write!(f, "{problem}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_failures() {
+        let not_found = Error::ExecFailed(
+            "/usr/bin/nope".into(),
+            io::Error::from_raw_os_error(libc::ENOENT),
+        );
+        assert_eq!(not_found.exit_code(), 127);
+        assert_eq!(
+            not_found.to_string(),
+            "unable to execute /usr/bin/nope: No such file or directory"
+        );
+
+        let denied = Error::ExecFailed(
+            "/etc/passwd".into(),
+            io::Error::from_raw_os_error(libc::EACCES),
+        );
+        assert_eq!(denied.exit_code(), 126);
+        assert_eq!(Error::UserNotFound.exit_code(), 1);
+    }
+
+    #[test]
+    fn password_attempts() {
+        assert_eq!(
+            Error::IncorrectPassword(1).to_string(),
+            "1 incorrect password attempt"
+        );
+        assert_eq!(
+            Error::IncorrectPassword(3).to_string(),
+            "3 incorrect password attempts"
+        );
+    }
 }
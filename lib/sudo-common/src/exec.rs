@@ -1,16 +1,684 @@
 use std::{
-    os::unix::process::CommandExt,
-    process::{Command, ExitStatus},
+    ffi::CString,
+    io,
+    os::fd::AsRawFd,
+    os::unix::{
+        ffi::OsStrExt,
+        process::{CommandExt, ExitStatusExt},
+    },
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus},
+    time::{Duration, Instant},
 };
 
-use crate::{context::Context, error::Error};
+#[cfg(target_os = "linux")]
+use sudo_system::capabilities;
+use sudo_system::{
+    capabilities::{CapabilitySet, SANDBOX_ESCAPES},
+    event::{Event, EventLoop},
+    signal::{self, SignalInfo},
+    socket::set_inheritable,
+    Group, Process, User,
+};
+
+use crate::{
+    context::Context,
+    debug_log,
+    error::Error,
+    intercept::{Listener, FD_ENV},
+    relay::Relay,
+};
+
+/// The shell that runs commands which are not binaries and do not start with `#!`
+const BOURNE_SHELL: &str = "/bin/sh";
+
+/// How long a command that has timed out gets to exit after SIGTERM, before it is killed
+const TIMEOUT_GRACE: Duration = Duration::from_secs(2);
+
+/// How a command ended; sudo ends the same way, so its caller sees the status of the command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The command exited with this status
+    Code(i32),
+    /// The command was terminated by this signal
+    Signal(i32),
+}
+
+impl ExitReason {
+    /// The reason in a status as reported by `waitpid(2)`
+    pub fn from_wait_status(status: libc::c_int) -> ExitReason {
+        ExitStatus::from_raw(status).into()
+    }
+
+    pub fn success(self) -> bool {
+        self == ExitReason::Code(0)
+    }
+
+    /// End the current process for the same reason
+    pub fn exit(self) -> ! {
+        match self {
+            ExitReason::Code(code) => std::process::exit(code),
+            ExitReason::Signal(signal) => {
+                signal::reraise(signal);
+                // the signal does not terminate a process by default
+                std::process::exit(1)
+            }
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitReason {
+    fn from(status: ExitStatus) -> ExitReason {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => ExitReason::Code(code),
+            (None, Some(signal)) => ExitReason::Signal(signal),
+            // a process that was only stopped has not ended yet
+            (None, None) => ExitReason::Code(1),
+        }
+    }
+}
+
+/// Reap the children that have ended, without waiting for the others; if `command` is one of
+/// them, this tells how it ended. Besides the command, that includes the helpers that sudo does
+/// not wait for, and those that sudo did not start itself (such as those left behind by a PAM
+/// module), which nobody else waits for.
+fn reap(command: libc::pid_t) -> io::Result<Option<ExitReason>> {
+    let mut ended = None;
+    loop {
+        let mut status = 0;
+        let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+        if pid == 0 {
+            return Ok(ended);
+        }
+        if pid < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(libc::EINTR) => continue,
+                Some(libc::ECHILD) if ended.is_some() => return Ok(ended),
+                // nobody else should reap the command, but if it is gone, there is nothing left
+                // to supervise
+                Some(libc::ECHILD) => {
+                    debug_log!(Exec, Warn, "the command ({command}) was reaped elsewhere");
+                    return Ok(Some(ExitReason::Code(1)));
+                }
+                _ => return Err(err),
+            }
+        }
+
+        let reason = ExitReason::from_wait_status(status);
+        if pid == command {
+            ended = Some(reason);
+        } else {
+            debug_log!(Exec, Diag, "reaped process {pid}: {reason:?}");
+        }
+    }
+}
+
+/// Run the command of the context, and wait for it to end (unless it runs in the background)
+pub fn exec(mut context: Context) -> Result<ExitReason, Error> {
+    debug_log!(
+        Exec,
+        Info,
+        "running {} {:?} as {}:{} in {:?} (root {:?})",
+        context.command.command.display(),
+        context.command.arguments,
+        context.target_user.uid,
+        context.target_user.gid,
+        context.chdir,
+        context.chroot
+    );
+
+    // a value from anywhere else would let the command pick which socket its requests go to
+    context.target_environment.remove(FD_ENV);
+    if context.noexec || context.intercept.is_some() {
+        let library = crate::noexec::library_path()?;
+        let preload = match context.target_environment.get("LD_PRELOAD") {
+            Some(other) => format!("{}:{other}", library.display()),
+            None => library.display().to_string(),
+        };
+        context
+            .target_environment
+            .insert("LD_PRELOAD".to_string(), preload);
+    }
+
+    // an intercepted command inherits a socket to send its requests to
+    let (listener, socket) = match context.intercept.take().map(Listener::new).transpose() {
+        Ok(Some((listener, socket))) => (Some(listener), Some(socket)),
+        Ok(None) => (None, None),
+        Err(err) => return Err(Error::ExecFailed(context.command.command.clone(), err)),
+    };
+    if let Some(socket) = &socket {
+        context
+            .target_environment
+            .insert(FD_ENV.to_string(), socket.as_raw_fd().to_string());
+    }
+
+    // a sandboxed command becomes the init process of a PID namespace of its own; since there can
+    // only be one, a command that turns out to need a shell (see below) can not run in a sandbox
+    if context.sandbox.is_some() {
+        new_pid_namespace()
+            .map_err(|err| Error::ExecFailed(context.command.command.clone(), err))?;
+    }
+
+    // sudo has to outlive the command to clean up, so from before the command starts, the signals
+    // that would terminate sudo are caught (to be relayed to the command)
+    let events = (!context.background)
+        .then(catch_signals)
+        .transpose()
+        .map_err(|err| Error::ExecFailed(context.command.command.clone(), err))?;
+
+    // the input and output that are recorded in the I/O log pass through sudo
+    let mut relay = context.iolog.take().map(|session| {
+        Relay::new(
+            session,
+            context.log_input,
+            context.log_output,
+            context.log_passwords,
+        )
+    });
+
+    let spawn = |program: &Path, script: Option<&Path>| -> Result<io::Result<Child>, Error> {
+        let mut command = build_command(&context, program, script)?;
+        if let Some(relay) = &relay {
+            relay.configure(&mut command);
+        }
+        if let Some(socket) = &socket {
+            let fd = socket.as_raw_fd();
+            // SAFETY: the closure only performs an async-signal-safe system call
+            unsafe { command.pre_exec(move || set_inheritable(fd)) };
+        }
+        Ok(command.spawn())
+    };
+    let program = &context.command.command;
+    let child = match spawn(program, None)? {
+        // like execvp, run a file that the kernel does not recognize as a shell script
+        Err(err) if err.raw_os_error() == Some(libc::ENOEXEC) => {
+            debug_log!(
+                Exec,
+                Diag,
+                "running {} with {BOURNE_SHELL}",
+                program.display()
+            );
+            spawn(Path::new(BOURNE_SHELL), Some(program))?
+        }
+        result => result,
+    };
+    // once only the command has the socket, the listener learns when it has finished
+    drop(socket);
+
+    let result = child.and_then(|child| match events {
+        Some(events) => supervise(child, events, listener, relay.as_mut(), context.timeout),
+        None => Ok(ExitReason::Code(0)),
+    });
+
+    result.map_err(|err| {
+        debug_log!(Exec, Err, "cannot execute command: {err}");
+        Error::ExecFailed(program.clone(), err)
+    })
+}
+
+/// Set up `program` to run as the command of the context; when a `script` is given, it is passed
+/// to the program as the first argument
+fn build_command(
+    context: &Context,
+    program: &Path,
+    script: Option<&Path>,
+) -> Result<Command, Error> {
+    let mut command = Command::new(program);
+    if let Some(writable) = &context.sandbox {
+        sandbox(&mut command, writable).map_err(|err| Error::ExecFailed(program.into(), err))?;
+    }
+    // without root, sudo can not take capabilities away (nor does the command have any); in a
+    // sandbox, the command can not be given those that would let it undo the sandbox
+    let restriction = context
+        .capabilities
+        .filter(|_| User::effective_uid() == 0)
+        .map(|retain| match context.sandbox {
+            Some(_) => retain.without(SANDBOX_ESCAPES),
+            None => retain,
+        });
+    if let Some(retain) = restriction {
+        limit_capabilities(&mut command, retain);
+    }
+    if User::effective_uid() == 0 {
+        apply_login_class(&mut command, context.target_user.uid)
+            .map_err(|err| Error::ExecFailed(program.into(), err))?;
+    }
+    switch_user(&mut command, context)?;
+    if let Some(retain) = restriction {
+        grant_capabilities(&mut command, retain);
+    }
+
+    // a command in the background is supposed to outlive sudo
+    if context.exit_with_parent && !context.background {
+        exit_with_parent(&mut command);
+    }
+
+    if let Some(mask) = context.umask {
+        // SAFETY: the closure only performs an async-signal-safe system call
+        unsafe {
+            command.pre_exec(move || {
+                Process::set_umask(mask);
+                Ok(())
+            });
+        }
+    }
+
+    command
+        .args(script)
+        .args(&context.command.arguments)
+        .envs(&context.target_environment);
+
+    Ok(command)
+}
+
+/// The event loop that supervises the command, with the signals that it handles caught
+fn catch_signals() -> io::Result<EventLoop> {
+    let mut events = EventLoop::new()?;
+    events.catch(libc::SIGCHLD)?;
+    for signal in signal::FORWARDED {
+        events.catch(signal)?;
+    }
+    Ok(events)
+}
+
+/// Whether a signal that sudo caught should be relayed to the command. Like in the original sudo,
+/// it is not if the command has it already: if the kernel sent it (such as the `SIGINT` of a
+/// Ctrl-C on the terminal, which the command shares with sudo), or if it came from the process
+/// group of the command (which sudo is in as well, so a signal to the group reaches both).
+fn relays(info: &SignalInfo, command: libc::pid_t) -> bool {
+    if !info.sent_by_process() {
+        return false;
+    }
+    if info.pid <= 0 {
+        return true;
+    }
+    match Process::group_of(info.pid) {
+        Ok(group) => {
+            Process::group_of(command).map_or(true, |own| group != own && group != command)
+        }
+        // the sender is gone already
+        Err(_) => info.pid != command,
+    }
+}
+
+/// Wait for the command to end, and take care of it in the meantime: the signals that would
+/// terminate sudo are relayed to the command (unless it has them already, see [relays]), and the
+/// other children are reaped as they end, so none remains a zombie for as long as the command
+/// runs. Everything that can happen (a signal, a child ending, a request of an intercepted
+/// command, a timeout) is an event of the same event loop, so the events are handled one at a
+/// time, in the order in which they arrived.
+fn supervise(
+    mut child: Child,
+    mut events: EventLoop,
+    mut listener: Option<Listener>,
+    mut relay: Option<&mut Relay>,
+    timeout: Option<Duration>,
+) -> io::Result<ExitReason> {
+    if let Some(listener) = &listener {
+        events.watch(listener.fd());
+    }
+    if let Some(relay) = relay.as_mut() {
+        relay.attach(&mut child, &mut events);
+    }
+    events.set_deadline(timeout.map(|timeout| Instant::now() + timeout));
+
+    let pid = child.id() as libc::pid_t;
+    let mut timed_out = false;
+    loop {
+        // the command (or another child) can have ended before SIGCHLD was caught
+        if let Some(reason) = reap(pid)? {
+            if let Some(relay) = relay {
+                relay.drain();
+            }
+            return Ok(reason);
+        }
+        match events.wait()? {
+            Event::Signal(info) if info.signal == libc::SIGCHLD => {}
+            Event::Signal(info) if !relays(&info, pid) => {
+                debug_log!(Exec, Diag, "the command has {info:?} already");
+            }
+            Event::Signal(info) => {
+                debug_log!(Exec, Diag, "relaying signal {} to the command", info.signal);
+                unsafe { libc::kill(pid, info.signal) };
+            }
+            Event::Readable(fd) => {
+                if let Some(relay) = relay.as_mut() {
+                    if relay.readable(fd, &mut events) {
+                        continue;
+                    }
+                }
+                // until none of the programs that the intercepted command runs has the socket
+                let done = match listener.as_mut().map(Listener::answer) {
+                    Some(Ok(more)) => !more,
+                    Some(Err(err)) => {
+                        debug_log!(Exec, Warn, "cannot answer an intercepted request: {err}");
+                        true
+                    }
+                    None => true,
+                };
+                if done {
+                    events.unwatch(fd);
+                    listener = None;
+                }
+            }
+            Event::Writable(fd) => {
+                if let Some(relay) = relay.as_mut() {
+                    relay.writable(fd, &mut events);
+                }
+            }
+            Event::Timeout if !timed_out => {
+                debug_log!(Exec, Info, "the command has timed out");
+                timed_out = true;
+                unsafe { libc::kill(pid, libc::SIGTERM) };
+                events.set_deadline(Some(Instant::now() + TIMEOUT_GRACE));
+            }
+            Event::Timeout => {
+                unsafe { libc::kill(pid, libc::SIGKILL) };
+            }
+        }
+    }
+}
+
+/// Run the command as the target user, with its supplementary groups, and in its root and working
+/// directory. The standard library would switch users before any `pre_exec` hook can run (and can
+/// not set supplementary groups), but changing the root directory requires root, so the switch is
+/// done here instead.
+fn switch_user(command: &mut Command, context: &Context) -> Result<(), Error> {
+    let cstring = |path: &Path| CString::new(path.as_os_str().as_bytes()).map_err(|_| Error::Exec);
+    let root = match &context.chroot {
+        Some(root) => {
+            let dir = context.chdir.as_deref().unwrap_or(Path::new("/"));
+            Some((cstring(root)?, cstring(dir)?))
+        }
+        None => {
+            if let Some(path) = &context.chdir {
+                command.current_dir(path);
+            }
+            None
+        }
+    };
+    let (uid, gid) = (context.target_user.uid, context.target_user.gid);
+    let groups = context
+        .target_user
+        .groups
+        .clone()
+        .unwrap_or_else(|| vec![gid]);
+    // without root, the credentials can not be changed, and need not be to stay the same user
+    let switch = User::effective_uid() == 0 || (uid, gid) != (User::real_uid(), Group::real_gid());
+
+    // SAFETY: the closure only performs async-signal-safe system calls and does not allocate
+    unsafe {
+        command.pre_exec(move || {
+            if let Some((root, dir)) = &root {
+                Process::change_root(root, dir)?;
+            }
+            if switch {
+                Process::set_credentials(uid, gid, &groups)?;
+            }
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+/// Make sure the command receives SIGTERM when sudo is killed, so no orphaned processes remain
+#[cfg(any(target_os = "linux", target_os = "freebsd"))]
+fn exit_with_parent(command: &mut Command) {
+    use std::io;
+
+    let parent = Process::process_id();
+    // SAFETY: the closure only performs async-signal-safe system calls and does not allocate;
+    // it runs after the credentials have been changed, which would otherwise clear the setting
+    unsafe {
+        command.pre_exec(move || {
+            Process::set_parent_death_signal(libc::SIGTERM)?;
+            // sudo could have been killed before the signal was set up
+            if Process::parent_id() != parent {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "freebsd")))]
+fn exit_with_parent(_command: &mut Command) {}
+
+#[cfg(target_os = "linux")]
+fn new_pid_namespace() -> io::Result<()> {
+    sudo_system::sandbox::new_pid_namespace()
+}
+
+/// Run the command in a mount namespace of its own, in which the file system is read-only except
+/// for the `writable` paths
+#[cfg(target_os = "linux")]
+fn sandbox(command: &mut Command, writable: &[PathBuf]) -> io::Result<()> {
+    let writable = writable
+        .iter()
+        .map(|path| CString::new(path.as_os_str().as_bytes()))
+        .collect::<Result<Vec<_>, _>>()?;
+    // SAFETY: the closure only performs async-signal-safe system calls and does not allocate
+    unsafe { command.pre_exec(move || sudo_system::sandbox::confine(&writable)) };
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn new_pid_namespace() -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sandbox(_command: &mut Command, _writable: &[PathBuf]) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Take all capabilities except `retain` out of the bounding set of the command, so that it can
+/// not gain them by executing other programs; this has to happen while it still runs as root
+#[cfg(target_os = "linux")]
+fn limit_capabilities(command: &mut Command, retain: CapabilitySet) {
+    let last = capabilities::last_capability();
+    // SAFETY: the closure only performs async-signal-safe system calls and does not allocate
+    unsafe {
+        command.pre_exec(move || {
+            if !retain.is_empty() {
+                capabilities::keep_on_setuid()?;
+            }
+            capabilities::limit_bounding_set(retain, last)
+        });
+    }
+}
+
+/// Give the command the capabilities in `retain` (and no others), even if it does not run as root
+#[cfg(target_os = "linux")]
+fn grant_capabilities(command: &mut Command, retain: CapabilitySet) {
+    // SAFETY: the closure only performs async-signal-safe system calls and does not allocate; it
+    // runs after the credentials have been changed, which clears the ambient capabilities
+    unsafe { command.pre_exec(move || capabilities::set_ambient(retain)) };
+}
+
+#[cfg(not(target_os = "linux"))]
+fn limit_capabilities(_command: &mut Command, _retain: CapabilitySet) {}
+
+#[cfg(not(target_os = "linux"))]
+fn grant_capabilities(_command: &mut Command, _retain: CapabilitySet) {}
+
+/// Give the command the resource limits, priority and umask of the login class of the target user
+/// (the umask of sudo still applies on top of it); this has to happen while it still runs as root
+#[cfg(target_os = "freebsd")]
+fn apply_login_class(command: &mut Command, uid: libc::uid_t) -> io::Result<()> {
+    let class = sudo_system::login_class::LoginClass::of_user(uid)?;
+    // SAFETY: the closure only performs async-signal-safe system calls and does not allocate
+    unsafe { command.pre_exec(move || class.apply(uid)) };
+    Ok(())
+}
+
+#[cfg(not(target_os = "freebsd"))]
+fn apply_login_class(_command: &mut Command, _uid: libc::uid_t) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(feature = "privsep")]
+pub use privsep::{unprivileged, UnprivilegedDatabase};
+
+/// Without privilege separation, the task runs in sudo itself
+#[cfg(not(feature = "privsep"))]
+pub fn unprivileged<T>(task: impl FnOnce() -> T) -> Result<T, Error> {
+    Ok(task())
+}
+
+/// Privilege separation (with the `privsep` feature)
+#[cfg(feature = "privsep")]
+mod privsep {
+    use std::{
+        io::{self, BufWriter, Read, Write},
+        panic::{self, AssertUnwindSafe},
+    };
+
+    use serde::{de::DeserializeOwned, Deserialize, Serialize};
+    use sudo_system::{
+        database::{self, Database},
+        Group, Process, User,
+    };
+
+    use crate::error::Error;
+
+    /// What an unprivileged helper sends back to sudo: the protocol is a single message, written as
+    /// JSON to a pipe, after which the helper exits. Sudo reads the pipe to its end and then reaps
+    /// the helper, so a helper that dies (or is killed) half way is noticed as a missing message.
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Message<T> {
+        /// The result of the task of the helper
+        Done(T),
+        /// The task panicked, with this message
+        Failed(String),
+    }
+
+    /// Privilege separation: run `task` in a helper process that does not run as root, and return
+    /// what it produced. This is meant for the parts of sudo that handle input which sudo does not
+    /// control, such as parsing and evaluating the sudoers policy and looking up users (NSS modules
+    /// are code that sudo does not control either), so that a flaw in them can not be exploited to
+    /// run code as root. The helper runs as the invoking user, and other processes of that user can
+    /// not trace it. To read the sudoers files, its only supplementary group is the group with gid 0
+    /// (`root`, or `wheel` on the BSDs), which the sudoers files belong to.
+    ///
+    /// Since the helper is forked, this can only be called while sudo has a single thread. Without
+    /// the `privsep` feature, the task runs in sudo itself.
+    pub fn unprivileged<T: Serialize + DeserializeOwned>(
+        task: impl FnOnce() -> T,
+    ) -> Result<T, Error> {
+        let failed =
+            |message: String| Error::Configuration(format!("privilege separation: {message}"));
+        let (mut reader, writer) = io::pipe().map_err(|err| failed(err.to_string()))?;
+
+        // SAFETY: sudo has a single thread, so the helper can do anything that sudo could
+        let pid = unsafe { libc::fork() };
+        if pid == 0 {
+            drop(reader);
+            let status = match helper(task, writer) {
+                Ok(()) => 0,
+                Err(_) => 1,
+            };
+            // SAFETY: the helper must not return into the code of sudo
+            unsafe { libc::_exit(status) }
+        } else if pid < 0 {
+            return Err(failed(io::Error::last_os_error().to_string()));
+        }
+
+        drop(writer);
+        let mut reply = Vec::new();
+        let read = reader.read_to_end(&mut reply);
+        let mut status = 0;
+        // SAFETY: waiting for the helper does not touch any memory other than `status`
+        while unsafe { libc::waitpid(pid, &mut status, 0) } < 0 {
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                return Err(failed(io::Error::last_os_error().to_string()));
+            }
+        }
+        read.map_err(|err| failed(err.to_string()))?;
+
+        match serde_json::from_slice(&reply) {
+            Ok(Message::Done(result)) => Ok(result),
+            Ok(Message::Failed(message)) => Err(failed(format!("the helper failed: {message}"))),
+            Err(_) if libc::WIFSIGNALED(status) => Err(failed(format!(
+                "the helper was killed by signal {}",
+                libc::WTERMSIG(status)
+            ))),
+            Err(err) => Err(failed(format!("invalid reply from the helper: {err}"))),
+        }
+    }
+
+    /// The life of the helper process: drop the privileges, do the task, and report its result
+    fn helper<T: Serialize>(task: impl FnOnce() -> T, writer: io::PipeWriter) -> io::Result<()> {
+        let message = match drop_privileges() {
+            Err(err) => Message::Failed(format!("cannot drop privileges: {err}")),
+            Ok(()) => match panic::catch_unwind(AssertUnwindSafe(task)) {
+                Ok(result) => Message::Done(result),
+                Err(payload) => Message::Failed(
+                    payload
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "panicked".to_string()),
+                ),
+            },
+        };
+        let mut writer = BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, &message)?;
+        writer.flush()
+    }
+
+    /// Become the invoking user, keeping only what is needed to read the sudoers files (see
+    /// [unprivileged]); a process that does not run as root has nothing to drop
+    fn drop_privileges() -> io::Result<()> {
+        if User::effective_uid() != 0 {
+            return Ok(());
+        }
+        Process::set_credentials(User::real_uid(), Group::real_gid(), &[0])?;
+        // the invoking user could otherwise take over the helper, and with it the answers to sudo
+        Process::disable_tracing()
+    }
+
+    /// With privilege separation, the user database as sudo consults it: every lookup that is not
+    /// cached yet is made in an unprivileged helper (see [unprivileged]). A process that does not run
+    /// as root, such as the helper itself, makes the lookups directly.
+    pub struct UnprivilegedDatabase;
+
+    impl UnprivilegedDatabase {
+        fn lookup<T: Serialize + DeserializeOwned>(
+            task: impl FnOnce(&database::System) -> io::Result<T>,
+        ) -> io::Result<T> {
+            if User::effective_uid() != 0 {
+                return task(&database::System);
+            }
+            unprivileged(|| task(&database::System).map_err(|err| err.to_string()))
+                .map_err(|err| io::Error::other(err.to_string()))?
+                .map_err(io::Error::other)
+        }
+    }
+
+    impl Database for UnprivilegedDatabase {
+        fn user_by_name(&self, name: &str) -> io::Result<Option<User>> {
+            Self::lookup(|db| db.user_by_name(name))
+        }
+
+        fn user_by_uid(&self, uid: libc::uid_t) -> io::Result<Option<User>> {
+            Self::lookup(|db| db.user_by_uid(uid))
+        }
+
+        fn group_by_name(&self, name: &str) -> io::Result<Option<Group>> {
+            Self::lookup(|db| db.group_by_name(name))
+        }
+
+        fn group_by_gid(&self, gid: libc::gid_t) -> io::Result<Option<Group>> {
+            Self::lookup(|db| db.group_by_gid(gid))
+        }
 
-pub fn exec(context: Context) -> Result<ExitStatus, Error> {
-    Command::new(context.command.command)
-        .args(context.command.arguments)
-        .uid(context.target_user.uid)
-        .gid(context.target_user.gid)
-        .envs(context.target_environment)
-        .status()
-        .map_err(|_| Error::Exec)
+        fn with_groups(&self, user: User) -> User {
+            // without its supplementary groups, the user has fewer privileges, not more
+            let groups = Self::lookup(|db| Ok(db.with_groups(user.clone()).groups)).unwrap_or(None);
+            User { groups, ..user }
+        }
+    }
 }
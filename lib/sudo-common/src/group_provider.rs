@@ -0,0 +1,191 @@
+//! Membership of groups that are not Unix groups, which sudoers refers to as `%:name` (or
+//! `%:#number`): for instance the groups of an Active Directory domain, which a host that is
+//! joined to it does not necessarily see as Unix groups. Without a provider, such groups have no
+//! members.
+//!
+//! The `group_provider` setting names a program that answers the question instead. It is run
+//! with the user name and the group (with `#` before a number) as its arguments, an empty
+//! environment, and no input; it exits with 0 if the user is a member of the group, and with 1 if
+//! not. Anything else is an error, which counts as not being a member.
+//!
+//! The program runs as root: with all user and group ids 0, no supplementary groups, and the
+//! no_new_privs flag set, so that the invoking user can neither trace it nor send it signals. Sudo
+//! asks it before the policy is evaluated ([ask_in_advance]); the helpers that evaluate the policy
+//! (see [unprivileged]) do not run as root, so they only get the answers that sudo already has.
+//!
+//! [unprivileged]: crate::exec::unprivileged
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io,
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Command, Stdio},
+    rc::Rc,
+};
+
+use sudo_system::{Process, User};
+
+use crate::debug_log;
+
+/// A source of non-Unix group membership
+pub trait GroupProvider {
+    /// Whether `user` is a member of the non-Unix group `group`
+    fn is_member(&self, user: &str, group: &str) -> io::Result<bool>;
+}
+
+/// Knows no non-Unix groups at all
+pub struct NoGroups;
+
+impl GroupProvider for NoGroups {
+    fn is_member(&self, _user: &str, _group: &str) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Asks an external program, following the protocol described in the [module documentation]; its
+/// answers are remembered, since the policy asks the same questions repeatedly.
+///
+/// [module documentation]: self
+pub struct External {
+    program: PathBuf,
+    answers: RefCell<HashMap<(String, String), bool>>,
+}
+
+impl External {
+    pub fn new(program: impl Into<PathBuf>) -> External {
+        External {
+            program: program.into(),
+            answers: RefCell::default(),
+        }
+    }
+
+    fn ask(&self, user: &str, group: &str) -> io::Result<bool> {
+        // a process that is not root would run the program as the invoking user
+        if User::effective_uid() != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "the group provider was not asked before privileges were dropped",
+            ));
+        }
+        let mut command = Command::new(&self.program);
+        command
+            .args([user, group])
+            .env_clear()
+            .stdin(Stdio::null())
+            .stdout(Stdio::null());
+        // SAFETY: these only make system calls, which is all that may happen between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                Process::set_credentials(0, 0, &[])?;
+                Process::set_no_new_privs()
+            })
+        };
+        let status = command.status()?;
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(io::Error::other(format!(
+                "{} failed ({status})",
+                self.program.display()
+            ))),
+        }
+    }
+}
+
+impl GroupProvider for External {
+    fn is_member(&self, user: &str, group: &str) -> io::Result<bool> {
+        let key = (user.to_string(), group.to_string());
+        if let Some(&answer) = self.answers.borrow().get(&key) {
+            return Ok(answer);
+        }
+        let answer = self.ask(user, group)?;
+        self.answers.borrow_mut().insert(key, answer);
+        Ok(answer)
+    }
+}
+
+thread_local! {
+    static PROVIDER: RefCell<Rc<dyn GroupProvider>> = RefCell::new(Rc::new(NoGroups));
+}
+
+/// The provider used in this thread; by default [NoGroups]
+pub fn group_provider() -> Rc<dyn GroupProvider> {
+    PROVIDER.with(|provider| provider.borrow().clone())
+}
+
+/// Replace the provider used in this thread (which processes forked from it inherit)
+pub fn set_group_provider(provider: impl GroupProvider + 'static) {
+    PROVIDER.with(|current| *current.borrow_mut() = Rc::new(provider));
+}
+
+/// Ask the provider of this thread whether `user` is a member of each of `groups`, so that the
+/// helpers forked afterwards (which inherit the answers) do not have to; see the [module
+/// documentation]
+///
+/// [module documentation]: self
+pub fn ask_in_advance(user: &str, groups: &[String]) {
+    for group in groups {
+        in_nonunix_group(user, group);
+    }
+}
+
+/// Whether `user` is a member of the non-Unix group `group`, according to the provider of this
+/// thread; errors are logged, and count as not being a member
+pub fn in_nonunix_group(user: &str, group: &str) -> bool {
+    group_provider()
+        .is_member(user, group)
+        .unwrap_or_else(|err| {
+            debug_log!(Match, Warn, "cannot look up non-Unix group {group}: {err}");
+            false
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn external_program() {
+        // only root runs the program (see processes.rs for the credentials that it gets)
+        if User::effective_uid() != 0 {
+            assert!(External::new("/bin/true")
+                .is_member("root", "admins")
+                .is_err());
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("sudo-common-groups-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("provider");
+        let log = dir.join("calls");
+        fs::write(
+            &program,
+            format!(
+                "#!/bin/sh\necho \"$1 $2\" >> {}\ncase \"$1:$2\" in\n\
+                 alice:admins|alice:#513) exit 0 ;;\nbob:*) exit 3 ;;\n*) exit 1 ;;\nesac\n",
+                log.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let provider = External::new(&program);
+        assert!(provider.is_member("alice", "admins").unwrap());
+        assert!(provider.is_member("alice", "#513").unwrap());
+        assert!(!provider.is_member("alice", "users").unwrap());
+        assert!(provider.is_member("bob", "admins").is_err());
+        assert!(provider.is_member("alice", "admins").unwrap());
+        // the answer to the repeated question was remembered
+        assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 4);
+
+        set_group_provider(provider);
+        assert!(in_nonunix_group("alice", "admins"));
+        assert!(!in_nonunix_group("bob", "admins"));
+        set_group_provider(NoGroups);
+        assert!(!in_nonunix_group("alice", "admins"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//! INTERCEPT: a command that may only run the programs that sudoers allows. The library that is
+//! preloaded for NOEXEC (the `sudo-noexec` crate) asks sudo before the command executes a
+//! program, over a socket that the command inherits as the file descriptor in [FD_ENV]; without
+//! that variable, the library refuses every program, as for NOEXEC.
+//!
+//! A request is a single message with the path of the program and its arguments, each followed
+//! by a NUL byte, and a socket of its own on which the answer is sent back: a single byte, 1 if
+//! the program may run. Processes that share the inherited socket therefore never get each
+//! other's answers.
+//!
+//! Like the original sudo, this only covers dynamically linked programs that execute other
+//! programs through the C library, and the command can give up the socket; it is a means to keep
+//! track of commands, not a way to confine them.
+
+use std::{
+    io::{self, Write},
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    os::unix::{ffi::OsStrExt, net::UnixStream},
+    path::Path,
+};
+
+use sudo_system::socket::{receive_with_fd, seqpacket_pair};
+
+use crate::debug_log;
+
+/// The variable with the file descriptor of the socket that requests are sent to
+pub const FD_ENV: &str = "SUDO_INTERCEPT_FD";
+
+/// Requests that are longer than this are refused
+const MAX_REQUEST: usize = 256 * 1024;
+
+/// Decides whether the command may run a program, given its path and arguments (including the
+/// name that it is run as)
+pub type Policy = Box<dyn FnMut(&Path, &[String]) -> bool + Send>;
+
+/// The end of the socket that sudo answers requests on
+pub struct Listener {
+    socket: OwnedFd,
+    policy: Policy,
+}
+
+impl Listener {
+    /// A listener, and the socket that the command has to inherit
+    pub fn new(policy: Policy) -> io::Result<(Listener, OwnedFd)> {
+        let (socket, command) = seqpacket_pair()?;
+        Ok((Listener { socket, policy }, command))
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    /// Answer the next request; returns false when no more requests can come, since all the
+    /// processes that had the socket have finished
+    pub fn answer(&mut self) -> io::Result<bool> {
+        let mut request = vec![0; MAX_REQUEST];
+        let (len, reply) = match receive_with_fd(&self.socket, &mut request) {
+            Ok((0, None)) => return Ok(false),
+            Ok(received) => received,
+            // a request that is too long is refused; its socket was closed with the message
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => return Ok(true),
+            Err(err) => return Err(err),
+        };
+        let Some(reply) = reply else {
+            debug_log!(Exec, Warn, "intercepted request without a reply socket");
+            return Ok(true);
+        };
+
+        let allowed = match parse(&request[..len]) {
+            Some((path, arguments)) => {
+                let allowed = (self.policy)(path, &arguments);
+                debug_log!(
+                    Exec,
+                    Info,
+                    "intercepted {} {:?}: {}",
+                    path.display(),
+                    arguments,
+                    if allowed { "allowed" } else { "denied" }
+                );
+                allowed
+            }
+            None => false,
+        };
+        // the process may have given up waiting already
+        let _ = UnixStream::from(reply).write_all(&[u8::from(allowed)]);
+        Ok(true)
+    }
+}
+
+/// Split a request into the path of the program and its arguments; the path has to be absolute
+fn parse(request: &[u8]) -> Option<(&Path, Vec<String>)> {
+    let mut fields = request.strip_suffix(b"\0")?.split(|&byte| byte == 0);
+    let path = Path::new(std::ffi::OsStr::from_bytes(fields.next()?));
+    let arguments = fields
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    path.is_absolute().then_some((path, arguments))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        let (path, arguments) = parse(b"/bin/ls\0ls\0-l\0").unwrap();
+        assert_eq!(path, Path::new("/bin/ls"));
+        assert_eq!(arguments, ["ls", "-l"]);
+        assert_eq!(parse(b"/bin/true\0").unwrap().1, Vec::<String>::new());
+        assert!(parse(b"ls\0ls\0").is_none());
+        assert!(parse(b"/bin/ls\0ls").is_none());
+    }
+}
@@ -0,0 +1,699 @@
+//! Naming of I/O log sessions. The `iolog_dir` and `iolog_file` settings are templates that can
+//! contain escapes such as `%{user}` and `%{seq}` as well as strftime(3) date escapes; the
+//! expanded paths follow the layout that sudoreplay expects.
+//!
+//! The `iolog_user`, `iolog_group` and `iolog_mode` settings determine who owns the files and
+//! directories that are created for the logs, so that they can be collected by an account other
+//! than root.
+//!
+//! A [Session] writes the log of a command while it runs (see the relay in the exec module); the
+//! reading side parses the `log` and `timing` files of a session, as used by `sudoreplay-rs`.
+
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs::{self, File, Permissions},
+    io::{self, Read, Seek, Write},
+    os::unix::fs::{fchown, PermissionsExt},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use sudo_system::secure_file::Directory;
+
+pub const DEFAULT_IOLOG_DIR: &str = "/var/log/sudo-io";
+pub const DEFAULT_IOLOG_FILE: &str = "%{seq}";
+
+/// The owner and permissions of the files and directories that are created for I/O logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ownership {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// The permissions of files; see [Ownership::dir_mode] for directories
+    pub mode: u32,
+}
+
+impl Default for Ownership {
+    /// Like the original sudo: owned by root, and only readable by root
+    fn default() -> Self {
+        Ownership {
+            uid: 0,
+            gid: 0,
+            mode: 0o600,
+        }
+    }
+}
+
+impl Ownership {
+    /// Like the original sudo, directories get the permissions of files, with search permission
+    /// for whoever can read them
+    pub fn dir_mode(&self) -> u32 {
+        let mode = self.mode & 0o777;
+        mode | (mode & 0o444) >> 2
+    }
+
+    /// Give a file that was just created its owner and permissions (whatever the umask is)
+    fn apply(&self, file: &File) -> io::Result<()> {
+        fchown(file, Some(self.uid), Some(self.gid))?;
+        file.set_permissions(Permissions::from_mode(self.mode & 0o777))
+    }
+}
+
+/// Open the directory `path`, creating it and any of its parents that are missing; the directories
+/// that are created get the given ownership. Like other files that sudo writes as root, the path
+/// must be absolute and no symbolic links are followed.
+pub fn create_dirs(path: &Path, ownership: &Ownership) -> io::Result<Directory> {
+    let root = Path::new("/");
+    let relative = path.strip_prefix(root).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not an absolute path: {}", path.display()),
+        )
+    })?;
+
+    let mut dir = Directory::open(root)?;
+    for name in relative.iter() {
+        dir = match dir.subdirectory(name) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                // only root can use the directory until it has its final ownership
+                let created = dir.create_subdirectory(name, 0o700)?;
+                let mode = ownership.dir_mode() as libc::mode_t;
+                created.set_ownership(ownership.uid, ownership.gid, mode)?;
+                created
+            }
+            result => result?,
+        };
+    }
+
+    Ok(dir)
+}
+
+/// Values for the `%{...}` escapes
+pub struct Escapes<'a> {
+    pub user: &'a str,
+    pub group: &'a str,
+    pub runas_user: &'a str,
+    pub runas_group: &'a str,
+    pub hostname: &'a str,
+    pub command: &'a str,
+}
+
+const SEQ_DIGITS: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// The base-36 number following `current`, which wraps around after `ZZZZZZ`
+fn increment_seq(current: &str) -> String {
+    let value = current.bytes().fold(0u64, |acc, b| {
+        let digit = SEQ_DIGITS.iter().position(|&d| d == b).unwrap_or(0);
+        acc * 36 + digit as u64
+    });
+
+    let mut next = (value + 1) % 36u64.pow(6);
+    let mut digits = [b'0'; 6];
+    for digit in digits.iter_mut().rev() {
+        *digit = SEQ_DIGITS[(next % 36) as usize];
+        next /= 36;
+    }
+    String::from_utf8_lossy(&digits).into_owned()
+}
+
+/// Allocate the next sequence number from the `seq` file in `dir` (which is created if needed);
+/// the file is locked while it is updated, so concurrent sudo invocations never get the same
+/// number.
+pub fn next_seq(dir: &Path, ownership: &Ownership) -> io::Result<String> {
+    let dir = create_dirs(dir, ownership)?;
+    let name = OsStr::new("seq");
+    let mut file = match dir.create(name, 0o600) {
+        Ok(file) => {
+            ownership.apply(&file)?;
+            file
+        }
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => dir.open_for_update(name)?,
+        Err(err) => return Err(err),
+    };
+    sudo_system::lock_exclusive(&file)?;
+
+    let mut current = String::new();
+    file.read_to_string(&mut current)?;
+    let next = increment_seq(current.trim());
+
+    file.rewind()?;
+    file.set_len(0)?;
+    writeln!(file, "{next}")?;
+
+    // the lock is released when the file is closed
+    Ok(next)
+}
+
+/// Format a point in time (in seconds since the epoch) as local time, using strftime(3)
+pub fn format_time(time: libc::time_t, format: &CStr) -> String {
+    let mut buf = [0; 128];
+    let len = unsafe {
+        let mut tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        libc::strftime(buf.as_mut_ptr(), buf.len(), format.as_ptr(), &tm)
+    };
+    buf[..len].iter().map(|&c| c as u8 as char).collect()
+}
+
+/// Expand a strftime(3) escape such as `%Y` for the current local time
+fn strftime(escape: char) -> String {
+    let Ok(format) = CString::new(format!("%{escape}")) else {
+        return String::new();
+    };
+    let now = unsafe { libc::time(std::ptr::null_mut()) };
+    format_time(now, &format)
+}
+
+/// Expand the escapes in a template; `seq` is only called if `%{seq}` occurs, and its result is
+/// split into directories of two characters each
+pub fn expand(
+    template: &str,
+    escapes: &Escapes,
+    seq: &mut dyn FnMut() -> io::Result<String>,
+) -> io::Result<String> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+    let mut result = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('{') => {
+                let name = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+                let value = match name.as_str() {
+                    "seq" => {
+                        let seq = seq()?;
+                        format!("{}/{}/{}", &seq[0..2], &seq[2..4], &seq[4..6])
+                    }
+                    "user" => escapes.user.to_string(),
+                    "group" => escapes.group.to_string(),
+                    "runas_user" => escapes.runas_user.to_string(),
+                    "runas_group" => escapes.runas_group.to_string(),
+                    "hostname" => escapes.hostname.to_string(),
+                    "command" => escapes.command.to_string(),
+                    _ => return Err(invalid(format!("unknown escape %{{{name}}}"))),
+                };
+                result.push_str(&value);
+            }
+            Some('%') => result.push('%'),
+            Some(escape) => result.push_str(&strftime(escape)),
+            None => return Err(invalid(format!("{template}: trailing %"))),
+        }
+    }
+
+    Ok(result)
+}
+
+/// The path of the log for a new session, given the `iolog_dir` and `iolog_file` templates; the
+/// sequence number (if used) is allocated in the expanded `iolog_dir`, which is created with the
+/// given ownership if it does not exist.
+pub fn session_path(
+    dir: &str,
+    file: &str,
+    escapes: &Escapes,
+    ownership: &Ownership,
+) -> io::Result<PathBuf> {
+    let dir = PathBuf::from(expand(dir, escapes, &mut || {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "%{seq} cannot be used in iolog_dir",
+        ))
+    })?);
+    let file = expand(file, escapes, &mut || next_seq(&dir, ownership))?;
+
+    Ok(dir.join(file))
+}
+
+/// The contents of the `log` file of a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Start of the session, in seconds since the epoch
+    pub time: libc::time_t,
+    pub user: String,
+    pub runas_user: String,
+    pub runas_group: String,
+    pub tty: String,
+    pub cwd: String,
+    pub command: String,
+}
+
+impl SessionInfo {
+    /// The text of a `log` file, as [SessionInfo::parse] reads it
+    pub fn format(&self) -> String {
+        format!(
+            "{}:{}:{}:{}:{}\n{}\n{}\n",
+            self.time,
+            self.user,
+            self.runas_user,
+            self.runas_group,
+            self.tty,
+            self.cwd,
+            self.command
+        )
+    }
+
+    /// Parse a `log` file: the first line is `time:user:runas_user:runas_group:tty`, followed by
+    /// the working directory and the command line
+    pub fn parse(text: &str) -> io::Result<SessionInfo> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid session log");
+
+        let mut lines = text.lines();
+        let mut fields = lines.next().ok_or_else(invalid)?.splitn(5, ':');
+        let mut field = || fields.next().ok_or_else(invalid).map(str::to_string);
+        let time = field()?;
+        let time = time
+            .split('.')
+            .next()
+            .and_then(|seconds| seconds.parse().ok())
+            .ok_or_else(invalid)?;
+
+        Ok(SessionInfo {
+            time,
+            user: field()?,
+            runas_user: field()?,
+            runas_group: field()?,
+            tty: field()?,
+            cwd: lines.next().unwrap_or_default().to_string(),
+            command: lines.next().unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// The kind of an entry in the `timing` file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdin,
+    Stdout,
+    Stderr,
+    TtyIn,
+    TtyOut,
+    /// The terminal was resized
+    WindowSize {
+        rows: u32,
+        cols: u32,
+    },
+    /// The command was suspended or resumed
+    Suspend,
+}
+
+impl Stream {
+    /// The file that holds the data of this stream, if it has any
+    pub fn file_name(self) -> Option<&'static str> {
+        match self {
+            Stream::Stdin => Some("stdin"),
+            Stream::Stdout => Some("stdout"),
+            Stream::Stderr => Some("stderr"),
+            Stream::TtyIn => Some("ttyin"),
+            Stream::TtyOut => Some("ttyout"),
+            Stream::WindowSize { .. } | Stream::Suspend => None,
+        }
+    }
+
+    /// Whether the data of this stream was shown on the terminal
+    pub fn is_output(self) -> bool {
+        matches!(self, Stream::Stdout | Stream::Stderr | Stream::TtyOut)
+    }
+
+    /// The entry for this stream in the `timing` file, as [parse_timing] reads it
+    fn timing_entry(self, delay: f64, size: usize) -> String {
+        match self {
+            Stream::Stdin => format!("0 {delay:.6} {size}"),
+            Stream::Stdout => format!("1 {delay:.6} {size}"),
+            Stream::Stderr => format!("2 {delay:.6} {size}"),
+            Stream::TtyIn => format!("3 {delay:.6} {size}"),
+            Stream::TtyOut => format!("4 {delay:.6} {size}"),
+            Stream::WindowSize { rows, cols } => format!("5 {delay:.6} {rows} {cols}"),
+            Stream::Suspend => format!("6 {delay:.6} SIGTSTP"),
+        }
+    }
+}
+
+/// The log of a session that is being recorded: the `log` file says what the session is, the data
+/// of every stream goes to a file of its own, and the `timing` file tells when each part of it
+/// arrived, so it can be played back as it happened
+pub struct Session {
+    dir: Directory,
+    ownership: Ownership,
+    timing: File,
+    /// The files of the streams that have had data so far
+    streams: Vec<(Stream, File)>,
+    /// When the previous entry was written to the `timing` file
+    last: Instant,
+}
+
+impl Session {
+    /// Start the log of a session in the directory `path`, which is created (with its parents) if
+    /// it does not exist; see [session_path]
+    pub fn create(path: &Path, info: &SessionInfo, ownership: &Ownership) -> io::Result<Session> {
+        let dir = create_dirs(path, ownership)?;
+        let mut log = create_file(&dir, "log", ownership)?;
+        log.write_all(info.format().as_bytes())?;
+        let timing = create_file(&dir, "timing", ownership)?;
+
+        Ok(Session {
+            dir,
+            ownership: *ownership,
+            timing,
+            streams: Vec::new(),
+            last: Instant::now(),
+        })
+    }
+
+    /// Record that `data` passed through a stream (or an event without data, such as a change of
+    /// the window size)
+    pub fn record(&mut self, stream: Stream, data: &[u8]) -> io::Result<()> {
+        if let Some(name) = stream.file_name() {
+            let file = match self.streams.iter().position(|(open, _)| *open == stream) {
+                Some(index) => &mut self.streams[index].1,
+                None => {
+                    let file = create_file(&self.dir, name, &self.ownership)?;
+                    self.streams.push((stream, file));
+                    &mut self.streams.last_mut().unwrap().1
+                }
+            };
+            file.write_all(data)?;
+        }
+
+        let now = Instant::now();
+        let delay = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        writeln!(self.timing, "{}", stream.timing_entry(delay, data.len()))
+    }
+}
+
+/// Create a file of a session, or start it over if it exists, with the given ownership
+fn create_file(dir: &Directory, name: &str, ownership: &Ownership) -> io::Result<File> {
+    let file = dir.open_or_create(OsStr::new(name), 0o600, true)?;
+    ownership.apply(&file)?;
+    Ok(file)
+}
+
+/// An entry in the `timing` file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timing {
+    pub stream: Stream,
+    /// Time since the previous entry, in seconds
+    pub delay: f64,
+    /// Number of bytes of data (zero for events without data)
+    pub size: usize,
+}
+
+/// Parse a `timing` file; each line is `type delay size`, where the size is replaced by the new
+/// terminal dimensions for window size changes and a signal name for suspends. Lines in the older
+/// `delay size` format refer to the tty output.
+pub fn parse_timing(text: &str) -> io::Result<Vec<Timing>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid timing entry: {line}"),
+                )
+            };
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            let number = |index: usize| -> io::Result<u32> {
+                fields
+                    .get(index)
+                    .and_then(|field| field.parse().ok())
+                    .ok_or_else(invalid)
+            };
+            let delay = |index: usize| -> io::Result<f64> {
+                fields
+                    .get(index)
+                    .and_then(|field| field.parse().ok())
+                    .filter(|delay: &f64| delay.is_finite() && *delay >= 0.0)
+                    .ok_or_else(invalid)
+            };
+
+            if fields.len() == 2 {
+                return Ok(Timing {
+                    stream: Stream::TtyOut,
+                    delay: delay(0)?,
+                    size: number(1)? as usize,
+                });
+            }
+
+            let (stream, size) = match number(0)? {
+                0 => (Stream::Stdin, number(2)?),
+                1 => (Stream::Stdout, number(2)?),
+                2 => (Stream::Stderr, number(2)?),
+                3 => (Stream::TtyIn, number(2)?),
+                4 => (Stream::TtyOut, number(2)?),
+                5 => {
+                    let (rows, cols) = (number(2)?, number(3)?);
+                    (Stream::WindowSize { rows, cols }, 0)
+                }
+                6 => (Stream::Suspend, 0),
+                _ => return Err(invalid()),
+            };
+            Ok(Timing {
+                stream,
+                delay: delay(1)?,
+                size: size as usize,
+            })
+        })
+        .collect()
+}
+
+/// Find all sessions below `dir`, with their identifiers (the path relative to `dir`, such as
+/// `00/00/01`), ordered by identifier; directories that cannot be read are skipped
+pub fn list_sessions(dir: &Path) -> Vec<(String, SessionInfo)> {
+    fn walk(root: &Path, dir: &Path, sessions: &mut Vec<(String, SessionInfo)>) {
+        if let Ok(text) = fs::read_to_string(dir.join("log")) {
+            if let (Ok(info), Ok(id)) = (SessionInfo::parse(&text), dir.strip_prefix(root)) {
+                sessions.push((id.to_string_lossy().into_owned(), info));
+            }
+            return;
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                walk(root, &entry.path(), sessions);
+            }
+        }
+    }
+
+    let mut sessions = Vec::new();
+    walk(dir, dir, &mut sessions);
+    sessions.sort_by(|(a, _), (b, _)| a.cmp(b));
+    sessions
+}
+
+/// The directory of a session: a plain sequence number such as `000001` is split up the way
+/// `%{seq}` is expanded, anything else is taken as a path relative to `dir`
+pub fn session_dir(dir: &Path, id: &str) -> PathBuf {
+    if id.len() == 6 && id.bytes().all(|b| SEQ_DIGITS.contains(&b)) {
+        dir.join(&id[0..2]).join(&id[2..4]).join(&id[4..6])
+    } else {
+        dir.join(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ESCAPES: Escapes = Escapes {
+        user: "alice",
+        group: "staff",
+        runas_user: "root",
+        runas_group: "root",
+        hostname: "web1",
+        command: "id",
+    };
+
+    /// The current user and group, which the tests can give files to
+    fn own() -> Ownership {
+        Ownership {
+            uid: unsafe { libc::geteuid() },
+            gid: unsafe { libc::getegid() },
+            mode: 0o640,
+        }
+    }
+
+    #[test]
+    fn seq_numbers() {
+        assert_eq!(increment_seq(""), "000001");
+        assert_eq!(increment_seq("000009"), "00000A");
+        assert_eq!(increment_seq("00000Z"), "000010");
+        assert_eq!(increment_seq("ZZZZZZ"), "000000");
+
+        let dir = std::env::temp_dir().join(format!("sudo-common-iolog-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(next_seq(&dir, &own()).unwrap(), "000001");
+        assert_eq!(next_seq(&dir, &own()).unwrap(), "000002");
+        assert_eq!(
+            session_path(dir.to_str().unwrap(), DEFAULT_IOLOG_FILE, &ESCAPES, &own()).unwrap(),
+            dir.join("00/00/03")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ownership() {
+        use std::os::unix::fs::MetadataExt;
+
+        assert_eq!(Ownership::default().dir_mode(), 0o700);
+        assert_eq!(own().dir_mode(), 0o750);
+        assert_eq!(
+            Ownership {
+                mode: 0o604,
+                ..own()
+            }
+            .dir_mode(),
+            0o705
+        );
+
+        let base = std::env::temp_dir().join(format!("sudo-common-owner-{}", std::process::id()));
+        let dir = base.join("sudo-io/alice");
+        assert_eq!(next_seq(&dir, &own()).unwrap(), "000001");
+        for (path, mode) in [(&base, 0o750), (&dir, 0o750), (&dir.join("seq"), 0o640)] {
+            let meta = std::fs::metadata(path).unwrap();
+            assert_eq!(meta.mode() & 0o7777, mode, "{}", path.display());
+            assert_eq!((meta.uid(), meta.gid()), (own().uid, own().gid));
+        }
+
+        // symbolic links are not followed
+        std::os::unix::fs::symlink(&dir, base.join("link")).unwrap();
+        assert!(create_dirs(&base.join("link/x"), &own()).is_err());
+        assert!(create_dirs(Path::new("relative/path"), &own()).is_err());
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn escapes() {
+        let mut no_seq = || -> io::Result<String> { panic!("no sequence number needed") };
+        assert_eq!(
+            expand(
+                "%{user}@%{hostname}/%{runas_user}:%{command}",
+                &ESCAPES,
+                &mut no_seq
+            )
+            .unwrap(),
+            "alice@web1/root:id"
+        );
+        assert_eq!(expand("100%%", &ESCAPES, &mut no_seq).unwrap(), "100%");
+        assert_eq!(expand("%Y", &ESCAPES, &mut no_seq).unwrap().len(), 4);
+        assert!(expand("%{nothing}", &ESCAPES, &mut no_seq).is_err());
+        assert!(expand("50%", &ESCAPES, &mut no_seq).is_err());
+        assert!(session_path("/var/log/%{seq}", "x", &ESCAPES, &own()).is_err());
+    }
+
+    #[test]
+    fn write_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("alice/00/00/01");
+        let info = SessionInfo {
+            time: 1700000000,
+            user: "alice".to_string(),
+            runas_user: "root".to_string(),
+            runas_group: "root".to_string(),
+            tty: "/dev/pts/1".to_string(),
+            cwd: "/home/alice".to_string(),
+            command: "/usr/bin/id -u".to_string(),
+        };
+        let mut session = Session::create(&path, &info, &own()).unwrap();
+        session.record(Stream::TtyOut, b"$ ").unwrap();
+        session.record(Stream::TtyIn, b"id\r").unwrap();
+        session
+            .record(Stream::WindowSize { rows: 24, cols: 80 }, b"")
+            .unwrap();
+        session.record(Stream::TtyOut, b"0\r\n").unwrap();
+        drop(session);
+
+        assert_eq!(
+            list_sessions(&dir.path().join("alice")),
+            [("00/00/01".to_string(), info)]
+        );
+        let read = |name: &str| std::fs::read_to_string(path.join(name)).unwrap();
+        assert_eq!(
+            (read("ttyout"), read("ttyin")),
+            ("$ 0\r\n".into(), "id\r".into())
+        );
+        let timing = parse_timing(&read("timing")).unwrap();
+        assert_eq!(
+            timing
+                .iter()
+                .map(|entry| (entry.stream, entry.size))
+                .collect::<Vec<_>>(),
+            [
+                (Stream::TtyOut, 2),
+                (Stream::TtyIn, 3),
+                (Stream::WindowSize { rows: 24, cols: 80 }, 0),
+                (Stream::TtyOut, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn read_sessions() {
+        let info =
+            SessionInfo::parse("1700000000:alice:root::/dev/pts/1\n/home/alice\n/usr/bin/id -u\n")
+                .unwrap();
+        assert_eq!(info.time, 1700000000);
+        assert_eq!(info.user, "alice");
+        assert_eq!(info.runas_user, "root");
+        assert_eq!(info.runas_group, "");
+        assert_eq!(info.tty, "/dev/pts/1");
+        assert_eq!(info.cwd, "/home/alice");
+        assert_eq!(info.command, "/usr/bin/id -u");
+        assert!(SessionInfo::parse("").is_err());
+        assert!(SessionInfo::parse("yesterday:alice:root::tty").is_err());
+
+        let timing = parse_timing("4 0.5 12\n5 0.000100 24 80\n1 1.25 3\n0.75 8\n").unwrap();
+        assert_eq!(
+            timing,
+            [
+                Timing {
+                    stream: Stream::TtyOut,
+                    delay: 0.5,
+                    size: 12
+                },
+                Timing {
+                    stream: Stream::WindowSize { rows: 24, cols: 80 },
+                    delay: 0.0001,
+                    size: 0
+                },
+                Timing {
+                    stream: Stream::Stdout,
+                    delay: 1.25,
+                    size: 3
+                },
+                Timing {
+                    stream: Stream::TtyOut,
+                    delay: 0.75,
+                    size: 8
+                },
+            ]
+        );
+        assert!(parse_timing("9 0.1 1").is_err());
+        assert!(parse_timing("4 -1 1").is_err());
+
+        let dir = std::env::temp_dir().join(format!("sudo-common-sessions-{}", std::process::id()));
+        for (id, user) in [("00/00/02", "bob"), ("00/00/01", "alice")] {
+            std::fs::create_dir_all(dir.join(id)).unwrap();
+            std::fs::write(
+                dir.join(id).join("log"),
+                format!("1700000000:{user}:root:root:/dev/pts/0\n/\nid\n"),
+            )
+            .unwrap();
+        }
+        std::fs::write(dir.join("seq"), "000002\n").unwrap();
+        let sessions = list_sessions(&dir);
+        assert_eq!(
+            sessions
+                .iter()
+                .map(|(id, info)| (id.as_str(), info.user.as_str()))
+                .collect::<Vec<_>>(),
+            [("00/00/01", "alice"), ("00/00/02", "bob")]
+        );
+        assert_eq!(session_dir(&dir, "000002"), dir.join("00/00/02"));
+        assert_eq!(session_dir(&dir, "00/00/02"), dir.join("00/00/02"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,7 +1,21 @@
+pub mod clock;
+pub mod config;
 pub mod context;
+pub mod debug;
 pub mod env;
 pub mod error;
 pub mod exec;
+pub mod group_provider;
+pub mod intercept;
+pub mod iolog;
+pub mod logging;
+pub mod metrics;
+pub mod noexec;
 pub mod pam;
+pub mod password_filter;
+pub mod prompt;
+mod relay;
+#[cfg(feature = "shadow")]
+pub mod shadow;
 pub mod sysuser;
 pub mod wildcard_match;
@@ -0,0 +1,345 @@
+//! Logging of sudo events, such as commands that were run or refused, to syslog and/or a log
+//! file. The log file may be rotated while sudo runs: before every entry, the path is checked
+//! and re-opened if it no longer refers to the open file. Entries are written under a lock, so
+//! they do not get mixed up with those of concurrent sudo invocations. Some events are also mailed
+//! (see [Mailer]).
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
+    path::PathBuf,
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sudo_system::syslog::{self, Facility, Priority};
+
+use crate::iolog::format_time;
+
+/// Where and how events are logged
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Facility for syslog, if syslog is used
+    pub syslog: Option<Facility>,
+    pub syslog_goodpri: Priority,
+    pub syslog_badpri: Priority,
+    pub logfile: Option<PathBuf>,
+    /// Include the year in the timestamps of the log file
+    pub log_year: bool,
+    /// Include the hostname in the entries of the log file
+    pub log_host: bool,
+    /// Log the events that succeeded, such as commands that were run
+    pub log_allowed: bool,
+    /// Log the events that failed, such as commands that were refused
+    pub log_denied: bool,
+    /// Where to mail about a wrong password (`mail_badpass`)
+    pub mail_badpass: Option<Mailer>,
+}
+
+/// A program that sends mail, like sendmail: it runs as root, with the `mailerflags` as its
+/// arguments, and gets the mail with its headers on its standard input
+#[derive(Debug, Clone)]
+pub struct Mailer {
+    /// The program (`mailerpath`)
+    pub path: PathBuf,
+    /// The arguments of the program, separated by whitespace (`mailerflags`)
+    pub flags: String,
+    /// The address that the mail is sent to (`mailto`)
+    pub to: String,
+}
+
+impl Mailer {
+    fn send(&self, subject: &str, body: &str) -> io::Result<()> {
+        let mut child = Command::new(&self.path)
+            .args(self.flags.split_whitespace())
+            .env_clear()
+            .env("PATH", "/usr/sbin:/usr/bin:/sbin:/bin")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let mail = format!("To: {}\nSubject: {subject}\n\n{body}\n", self.to);
+        let written = child
+            .stdin
+            .take()
+            .map_or(Ok(()), |mut stdin| stdin.write_all(mail.as_bytes()));
+        let status = child.wait()?;
+        written?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed: {status}",
+                self.path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            syslog: Some(Facility::AUTHPRIV),
+            syslog_goodpri: Priority::NOTICE,
+            syslog_badpri: Priority::ALERT,
+            logfile: None,
+            log_year: false,
+            log_host: false,
+            log_allowed: true,
+            log_denied: true,
+            mail_badpass: None,
+        }
+    }
+}
+
+/// A log file that follows its path when it is rotated
+struct LogFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl LogFile {
+    /// Whether the path still refers to the open file
+    fn is_current(&self) -> bool {
+        let (Some(file), Ok(on_disk)) = (&self.file, fs::metadata(&self.path)) else {
+            return false;
+        };
+        file.metadata()
+            .is_ok_and(|open| (open.dev(), open.ino()) == (on_disk.dev(), on_disk.ino()))
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let current = self.is_current();
+        let file = match &mut self.file {
+            Some(file) if current => file,
+            slot => slot.insert(
+                OpenOptions::new()
+                    .append(true)
+                    .create(true)
+                    .mode(0o600)
+                    .open(&self.path)?,
+            ),
+        };
+
+        sudo_system::lock_exclusive(file)?;
+        let result = file.write_all(format!("{line}\n").as_bytes());
+        sudo_system::unlock(file)?;
+        result
+    }
+}
+
+pub struct Logger {
+    options: Options,
+    hostname: String,
+    logfile: Option<LogFile>,
+}
+
+impl Logger {
+    pub fn new(options: Options, hostname: &str) -> Logger {
+        let logfile = options
+            .logfile
+            .clone()
+            .map(|path| LogFile { path, file: None });
+        Logger {
+            options,
+            hostname: hostname.to_string(),
+            logfile,
+        }
+    }
+
+    /// The entry for the log file, with a timestamp and (if `log_host` is set) the hostname
+    fn logfile_entry(&self, time: libc::time_t, user: &str, message: &str) -> String {
+        let format = if self.options.log_year {
+            c"%b %e %H:%M:%S %Y"
+        } else {
+            c"%b %e %H:%M:%S"
+        };
+        let timestamp = format_time(time, format);
+        if self.options.log_host {
+            format!("{timestamp} : {user} : HOST={} : {message}", self.hostname)
+        } else {
+            format!("{timestamp} : {user} : {message}")
+        }
+    }
+
+    /// Mail about a wrong password of `user` (see [Options::mail_badpass]), with the same message
+    /// as in the log; like the original sudo, this does not depend on `log_denied`
+    pub fn mail_badpass(&self, user: &str, message: &str) -> io::Result<()> {
+        let Some(mailer) = &self.options.mail_badpass else {
+            return Ok(());
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs() as libc::time_t);
+        let subject = format!("*** SECURITY information for {} ***", self.hostname);
+        let body = format!(
+            "{} : {} : {user} : {message}",
+            self.hostname,
+            format_time(now, c"%b %e %H:%M:%S")
+        );
+        mailer.send(&subject, &body)
+    }
+
+    /// Whether events that succeeded (or failed) are logged at all
+    pub fn logs(&self, success: bool) -> bool {
+        if success {
+            self.options.log_allowed
+        } else {
+            self.options.log_denied
+        }
+    }
+
+    /// Log an event of `user`; `success` determines the priority in syslog, and whether it is
+    /// logged at all (see [Logger::logs])
+    pub fn log(&mut self, user: &str, message: &str, success: bool) -> io::Result<()> {
+        if !self.logs(success) {
+            return Ok(());
+        }
+
+        if let Some(facility) = self.options.syslog {
+            let priority = if success {
+                self.options.syslog_goodpri
+            } else {
+                self.options.syslog_badpri
+            };
+            syslog::log(facility, priority, &format!("{user} : {message}"));
+        }
+
+        if self.logfile.is_some() {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs() as libc::time_t);
+            let entry = self.logfile_entry(now, user, message);
+            if let Some(logfile) = &mut self.logfile {
+                logfile.write_line(&entry)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_format() {
+        let options = Options {
+            syslog: None,
+            ..Default::default()
+        };
+        let logger = Logger::new(options.clone(), "web1");
+        let entry = logger.logfile_entry(1700000000, "alice", "COMMAND=/usr/bin/id");
+        assert!(
+            entry.ends_with(":20 : alice : COMMAND=/usr/bin/id"),
+            "{entry}"
+        );
+        assert!(!entry.contains("2023"));
+
+        let logger = Logger::new(
+            Options {
+                log_year: true,
+                log_host: true,
+                ..options
+            },
+            "web1",
+        );
+        let entry = logger.logfile_entry(1700000000, "alice", "COMMAND=/usr/bin/id");
+        assert!(
+            entry.ends_with(" 2023 : alice : HOST=web1 : COMMAND=/usr/bin/id"),
+            "{entry}"
+        );
+    }
+
+    #[test]
+    fn follows_rotation() {
+        let dir = std::env::temp_dir().join(format!("sudo-common-logging-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sudo.log");
+        let mut logger = Logger::new(
+            Options {
+                syslog: None,
+                logfile: Some(path.clone()),
+                ..Default::default()
+            },
+            "web1",
+        );
+
+        logger.log("alice", "first", true).unwrap();
+        fs::rename(&path, dir.join("sudo.log.1")).unwrap();
+        logger.log("alice", "second", false).unwrap();
+        logger.log("alice", "third", true).unwrap();
+
+        let rotated = fs::read_to_string(dir.join("sudo.log.1")).unwrap();
+        let current = fs::read_to_string(&path).unwrap();
+        assert!(rotated.ends_with(" : alice : first\n"));
+        assert_eq!(current.lines().count(), 2);
+        assert!(current.ends_with(" : alice : third\n"));
+        assert_eq!(fs::metadata(&path).unwrap().mode() & 0o777, 0o600);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn only_selected_events() {
+        let dir = std::env::temp_dir().join(format!("sudo-common-events-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("sudo.log");
+        let mut logger = Logger::new(
+            Options {
+                syslog: None,
+                logfile: Some(path.clone()),
+                log_allowed: false,
+                ..Default::default()
+            },
+            "web1",
+        );
+
+        assert!(!logger.logs(true));
+        assert!(logger.logs(false));
+        logger.log("alice", "allowed", true).unwrap();
+        logger.log("alice", "denied", false).unwrap();
+
+        let logged = fs::read_to_string(&path).unwrap();
+        assert_eq!(logged.lines().count(), 1);
+        assert!(logged.ends_with(" : alice : denied\n"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn mails_bad_passwords() {
+        let dir = std::env::temp_dir().join(format!("sudo-common-mail-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mail = dir.join("mail");
+        let logger = |path: &str| {
+            let mailer = Mailer {
+                path: path.into(),
+                flags: mail.display().to_string(),
+                to: "root".to_string(),
+            };
+            Logger::new(
+                Options {
+                    syslog: None,
+                    mail_badpass: Some(mailer),
+                    ..Default::default()
+                },
+                "web1",
+            )
+        };
+
+        let message = "3 incorrect password attempts ; TTY=pts/1";
+        logger("tee").mail_badpass("alice", message).unwrap();
+        let sent = fs::read_to_string(&mail).unwrap();
+        assert!(sent.starts_with("To: root\nSubject: *** SECURITY information for web1 ***\n\n"));
+        assert!(sent.contains("\n\nweb1 : "));
+        assert!(sent.ends_with(" : alice : 3 incorrect password attempts ; TTY=pts/1\n"));
+
+        assert!(logger("false").mail_badpass("alice", message).is_err());
+        let quiet = Logger::new(Default::default(), "web1");
+        assert!(quiet.mail_badpass("alice", message).is_ok());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
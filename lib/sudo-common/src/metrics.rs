@@ -0,0 +1,350 @@
+//! Counters of what sudo does, for monitoring a fleet of machines without scraping the logs: how
+//! often users authenticate (and fail to), how often the policy denies a request, and how long the
+//! commands run. By default they go nowhere; sudo-rs.conf can send them to a statsd server
+//! (`metrics_statsd = address:port`, over UDP) and keep them in a file for the textfile collector of
+//! the Prometheus node exporter (`metrics_textfile = /path/to/sudo.prom`).
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    os::unix::fs::{MetadataExt, PermissionsExt},
+    path::PathBuf,
+    rc::Rc,
+    time::Duration,
+};
+
+use sudo_system::{lock_exclusive, secure_file::open_parent, User};
+
+use crate::{
+    config::{self, CONFIG_FILE},
+    debug_log,
+};
+
+/// Where sudo reports what it does; each event is reported once, as it happens
+pub trait Metrics {
+    /// A user authenticated (or failed to)
+    fn authenticated(&self, _success: bool) {}
+    /// The policy denied a request
+    fn denied(&self) {}
+    /// A command that sudo waited for has ended, after running for `duration`
+    fn command_finished(&self, _duration: Duration) {}
+}
+
+/// Metrics that are not reported anywhere
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoMetrics;
+
+impl Metrics for NoMetrics {}
+
+/// Every event is reported to each of the metrics in the list
+impl Metrics for Vec<Box<dyn Metrics>> {
+    fn authenticated(&self, success: bool) {
+        self.iter()
+            .for_each(|metrics| metrics.authenticated(success))
+    }
+
+    fn denied(&self) {
+        self.iter().for_each(|metrics| metrics.denied())
+    }
+
+    fn command_finished(&self, duration: Duration) {
+        self.iter()
+            .for_each(|metrics| metrics.command_finished(duration))
+    }
+}
+
+thread_local! {
+    static METRICS: RefCell<Rc<dyn Metrics>> = RefCell::new(Rc::new(NoMetrics));
+}
+
+/// The metrics that events are reported to in this thread; by default [NoMetrics]
+pub fn metrics() -> Rc<dyn Metrics> {
+    METRICS.with(|metrics| metrics.borrow().clone())
+}
+
+/// Replace the metrics that events are reported to in this thread
+pub fn set_metrics(metrics: impl Metrics + 'static) {
+    METRICS.with(|current| *current.borrow_mut() = Rc::new(metrics));
+}
+
+/// The metrics that sudo-rs.conf asks for
+pub fn configured() -> Result<Vec<Box<dyn Metrics>>, String> {
+    let mut metrics: Vec<Box<dyn Metrics>> = Vec::new();
+    if let Some(address) = config::setting("metrics_statsd") {
+        let statsd = Statsd::new(&address)
+            .map_err(|err| format!("metrics_statsd {address} in {CONFIG_FILE}: {err}"))?;
+        metrics.push(Box::new(statsd));
+    }
+    if let Some(path) = config::setting("metrics_textfile") {
+        let textfile = Textfile::new(PathBuf::from(&path))
+            .ok_or_else(|| format!("metrics_textfile {path} in {CONFIG_FILE} is not usable"))?;
+        metrics.push(Box::new(textfile));
+    }
+    Ok(metrics)
+}
+
+/// Metrics that are sent to a statsd server, one datagram per event (which may get lost)
+pub struct Statsd {
+    socket: UdpSocket,
+}
+
+impl Statsd {
+    /// Send the metrics to the server at `address`, which is an IP address and a port (such as
+    /// `127.0.0.1:8125` or `[::1]:8125`): a host name would have to be looked up by sudo as root,
+    /// before it has read the policy
+    pub fn new(address: &str) -> io::Result<Statsd> {
+        let server: SocketAddr = address.parse().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "not an IP address with a port")
+        })?;
+        let local: IpAddr = match server {
+            SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind((local, 0))?;
+        socket.connect(server)?;
+        Ok(Statsd { socket })
+    }
+
+    fn send(&self, line: &str) {
+        if let Err(err) = self.socket.send(line.as_bytes()) {
+            debug_log!(Main, Warn, "cannot send metrics to statsd: {err}");
+        }
+    }
+}
+
+impl Metrics for Statsd {
+    fn authenticated(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.send(&format!("sudo.auth.{result}:1|c"));
+    }
+
+    fn denied(&self) {
+        self.send("sudo.denied:1|c");
+    }
+
+    fn command_finished(&self, duration: Duration) {
+        self.send(&format!(
+            "sudo.command.duration:{}|ms",
+            duration.as_millis()
+        ));
+    }
+}
+
+/// The counters in the text file, in the order in which they are written
+const COUNTERS: &[(&str, &str, &str)] = &[
+    (
+        "sudo_authentications_total",
+        "counter",
+        "Authentications of users by sudo",
+    ),
+    (
+        "sudo_denials_total",
+        "counter",
+        "Requests that the sudoers policy denied",
+    ),
+    (
+        "sudo_command_duration_seconds",
+        "summary",
+        "How long the commands that sudo waited for ran",
+    ),
+];
+
+/// Metrics that are kept in a file in the Prometheus text format: every invocation of sudo adds to
+/// the counters in it. The file is replaced as a whole, so a reader never sees half of it; its
+/// directory may only be writable by root, as sudo writes the file as root.
+pub struct Textfile {
+    path: PathBuf,
+}
+
+impl Textfile {
+    /// Metrics in the file at `path`, if that is an absolute path in a trusted directory
+    pub fn new(path: PathBuf) -> Option<Textfile> {
+        let dir = path.parent()?;
+        (path.is_absolute() && path.file_name().is_some() && config::is_trusted_dir(dir))
+            .then_some(Textfile { path })
+    }
+
+    /// Add to some of the counters in the file
+    fn add(&self, samples: &[(&str, f64)]) {
+        if let Err(err) = self.update(samples) {
+            debug_log!(
+                Main,
+                Warn,
+                "cannot update metrics in {}: {err}",
+                self.path.display()
+            );
+        }
+    }
+
+    fn update(&self, samples: &[(&str, f64)]) -> io::Result<()> {
+        let (dir, name) = open_parent(&self.path)?;
+        // other invocations of sudo wait until this one has replaced the file; nobody else can
+        // open the lock file, so nobody else can hold the lock (the file itself is readable)
+        let lock_name = OsString::from_iter([name, OsStr::new(".lock")]);
+        let lock = dir.open_or_create(&lock_name, 0o600, false)?;
+        let meta = lock.metadata()?;
+        if meta.uid() != User::effective_uid() || meta.mode() & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} is not private to sudo", lock_name.to_string_lossy()),
+            ));
+        }
+        lock_exclusive(&lock)?;
+
+        let mut values = match dir.open_file(name) {
+            Ok(mut file) => {
+                let mut text = String::new();
+                file.read_to_string(&mut text)?;
+                parse(&text)
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        for (series, value) in samples {
+            *values.entry(series.to_string()).or_default() += value;
+        }
+
+        let temporary = OsString::from_iter([name, OsStr::new(".tmp")]);
+        let mut file = dir.open_or_create(&temporary, 0o644, true)?;
+        // the exporter that reads the file does not run as root, whatever the umask of the user
+        file.set_permissions(fs::Permissions::from_mode(0o644))?;
+        file.write_all(render(&values).as_bytes())?;
+        file.sync_all()?;
+        dir.rename(&temporary, name)
+    }
+}
+
+impl Metrics for Textfile {
+    fn authenticated(&self, success: bool) {
+        let result = if success { "success" } else { "failure" };
+        self.add(&[(
+            &format!("sudo_authentications_total{{result=\"{result}\"}}"),
+            1.0,
+        )]);
+    }
+
+    fn denied(&self) {
+        self.add(&[("sudo_denials_total", 1.0)]);
+    }
+
+    fn command_finished(&self, duration: Duration) {
+        self.add(&[
+            ("sudo_command_duration_seconds_sum", duration.as_secs_f64()),
+            ("sudo_command_duration_seconds_count", 1.0),
+        ]);
+    }
+}
+
+/// The values of the series (the name and the labels) in a text file; anything else is dropped
+fn parse(text: &str) -> HashMap<String, f64> {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.rsplit_once(' '))
+        .filter_map(|(series, value)| Some((series.to_string(), value.parse().ok()?)))
+        .collect()
+}
+
+/// The text file with these values
+fn render(values: &HashMap<String, f64>) -> String {
+    let mut text = String::new();
+    for (name, kind, help) in COUNTERS {
+        let mut series = values
+            .iter()
+            .filter(|(series, _)| {
+                series
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with(['{', '_']))
+            })
+            .collect::<Vec<_>>();
+        if series.is_empty() {
+            continue;
+        }
+        series.sort_by(|a, b| a.0.cmp(b.0));
+        text.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+        for (series, value) in series {
+            text.push_str(&format!("{series} {value}\n"));
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn textfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sudo.prom");
+        let textfile = Textfile { path: path.clone() };
+        textfile.authenticated(true);
+        textfile.authenticated(false);
+        textfile.authenticated(true);
+        textfile.denied();
+        textfile.command_finished(Duration::from_millis(1500));
+        textfile.command_finished(Duration::from_millis(500));
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# HELP sudo_authentications_total Authentications of users by sudo\n\
+             # TYPE sudo_authentications_total counter\n\
+             sudo_authentications_total{result=\"failure\"} 1\n\
+             sudo_authentications_total{result=\"success\"} 2\n\
+             # HELP sudo_denials_total Requests that the sudoers policy denied\n\
+             # TYPE sudo_denials_total counter\n\
+             sudo_denials_total 1\n\
+             # HELP sudo_command_duration_seconds How long the commands that sudo waited for ran\n\
+             # TYPE sudo_command_duration_seconds summary\n\
+             sudo_command_duration_seconds_count 2\n\
+             sudo_command_duration_seconds_sum 2\n"
+        );
+        assert!(!dir.path().join("sudo.prom.tmp").exists());
+        let lock = fs::metadata(dir.path().join("sudo.prom.lock")).unwrap();
+        assert_eq!(lock.permissions().mode() & 0o777, 0o600);
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o644
+        );
+
+        // only absolute paths in directories that only root can write to
+        assert!(Textfile::new("sudo.prom".into()).is_none());
+        assert!(Textfile::new("/".into()).is_none());
+
+        // a lock file that others could open (and hold the lock of) is not used
+        fs::set_permissions(
+            dir.path().join("sudo.prom.lock"),
+            fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        let before = fs::read_to_string(&path).unwrap();
+        textfile.denied();
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+    }
+
+    #[test]
+    fn statsd() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let statsd = Statsd::new(&server.local_addr().unwrap().to_string()).unwrap();
+        statsd.authenticated(false);
+        statsd.denied();
+        statsd.command_finished(Duration::from_millis(1234));
+
+        let mut buffer = [0; 64];
+        let mut receive = || {
+            let len = server.recv(&mut buffer).unwrap();
+            String::from_utf8_lossy(&buffer[..len]).into_owned()
+        };
+        assert_eq!(receive(), "sudo.auth.failure:1|c");
+        assert_eq!(receive(), "sudo.denied:1|c");
+        assert_eq!(receive(), "sudo.command.duration:1234|ms");
+
+        // host names are not looked up
+        assert!(Statsd::new("localhost:8125").is_err());
+        assert!(Statsd::new("127.0.0.1").is_err());
+        assert!(Statsd::new("[::1]:8125").is_ok());
+    }
+}
@@ -0,0 +1,73 @@
+//! Locating the library that is preloaded into commands tagged NOEXEC (built from the
+//! `sudo-noexec` crate). Its location can be set with `noexec_path` in sudo-rs.conf; otherwise the
+//! usual installation directories are searched, including the multiarch library directory.
+
+use std::path::{Path, PathBuf};
+
+use crate::{config, error::Error};
+
+pub const LIBRARY_NAME: &str = "libsudo_noexec.so";
+
+/// The Debian-style multiarch tuple of the platform sudo was built for, such as
+/// `x86_64-linux-gnu`
+fn multiarch() -> Option<String> {
+    if !cfg!(target_os = "linux") {
+        return None;
+    }
+    let abi = if cfg!(target_env = "musl") {
+        "musl"
+    } else if cfg!(target_arch = "arm") {
+        "gnueabihf"
+    } else {
+        "gnu"
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86" => "i386",
+        arch => arch,
+    };
+    Some(format!("{arch}-linux-{abi}"))
+}
+
+/// The places where the library is looked for if no `noexec_path` is configured
+pub fn default_paths() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from("/usr/libexec/sudo-rs")];
+    if let Some(tuple) = multiarch() {
+        dirs.push(Path::new("/usr/lib").join(tuple).join("sudo-rs"));
+    }
+    dirs.push(PathBuf::from("/usr/lib/sudo-rs"));
+    dirs.push(PathBuf::from("/usr/local/libexec/sudo-rs"));
+
+    dirs.into_iter().map(|dir| dir.join(LIBRARY_NAME)).collect()
+}
+
+/// Find the library to preload for NOEXEC; a configured but unusable path is an error, since
+/// running the command without the library would silently drop the restriction
+pub fn library_path() -> Result<PathBuf, Error> {
+    match config::trusted_path("noexec_path").map_err(Error::Configuration)? {
+        Some(path) => Ok(path),
+        None => default_paths()
+            .into_iter()
+            .find(|path| config::is_trusted(path))
+            .ok_or_else(|| Error::conf("unable to find the noexec library")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_paths() {
+        let paths = default_paths();
+        assert!(paths.iter().all(|path| path.ends_with(LIBRARY_NAME)));
+        if cfg!(all(
+            target_os = "linux",
+            target_arch = "x86_64",
+            target_env = "gnu"
+        )) {
+            assert!(paths.contains(&PathBuf::from(
+                "/usr/lib/x86_64-linux-gnu/sudo-rs/libsudo_noexec.so"
+            )));
+        }
+    }
+}
@@ -1,19 +1,811 @@
-use crate::error::Error;
+//! Authentication of the invoking user; through PAM, unless sudo-rs is built without the `pam`
+//! feature, in which case the `shadow` feature selects the `shadow` module instead.
 
-pub fn authenticate(username: &str) -> Result<(), Error> {
-    let mut conversation = pam_client::conv_cli::Conversation::new();
-    conversation.set_info_prefix("");
+#[cfg(feature = "pam")]
+use std::ffi::{CStr, CString};
+use std::{
+    fs::{self, DirBuilder},
+    io,
+    os::unix::{fs::DirBuilderExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-    let mut context = pam_client::Context::new("sukkelsudo", Some(username), conversation)
+#[cfg(feature = "pam")]
+use pam_client::{ConversationHandler, ErrorCode};
+use sudo_system::{secure_buffer::SecureBuffer, Group, User};
+
+use crate::{
+    clock::{Clock, Deadline, Monotonic},
+    debug_log,
+    error::Error,
+    prompt,
+};
+
+/// A message in the authentication conversation. PAM modules can send any sequence of these, for
+/// instance a one-time code prompt (with echo) after the password, with information in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Message<'a> {
+    /// Ask for input that may be shown on the screen
+    EchoOn(&'a str),
+    /// Ask for a secret, such as a password
+    EchoOff(&'a str),
+    /// Show some information
+    Info(&'a str),
+    /// Show an error
+    Error(&'a str),
+}
+
+/// The place where the conversation takes place; everything that sudo asks or tells the user goes
+/// through it
+pub trait Conversation {
+    fn read_line(&mut self, prompt: &str, timeout: Option<Duration>) -> io::Result<String>;
+    fn read_password(
+        &mut self,
+        prompt: &str,
+        timeout: Option<Duration>,
+        countdown: bool,
+    ) -> io::Result<SecureBuffer>;
+    fn show(&mut self, text: &str);
+    /// Show the lecture (see the `lecture` setting) before the user authenticates, and tell
+    /// whether the user got to see it
+    fn lecture(&mut self, text: &str) -> bool {
+        self.show(text);
+        true
+    }
+}
+
+/// The terminal of the invoking user; timeouts are measured with the given clock
+#[derive(Default)]
+pub struct Tty<K: Clock = Monotonic> {
+    pub clock: K,
+}
+
+impl<K: Clock> Conversation for Tty<K> {
+    fn read_line(&mut self, prompt: &str, timeout: Option<Duration>) -> io::Result<String> {
+        let deadline = timeout.map(|timeout| Deadline::after(&self.clock, timeout));
+        prompt::read_line(prompt, deadline)
+    }
+
+    fn read_password(
+        &mut self,
+        prompt: &str,
+        timeout: Option<Duration>,
+        countdown: bool,
+    ) -> io::Result<SecureBuffer> {
+        let deadline = timeout.map(|timeout| Deadline::after(&self.clock, timeout));
+        prompt::read_password(prompt, deadline, countdown)
+    }
+
+    fn show(&mut self, text: &str) {
+        eprintln!("{text}");
+    }
+}
+
+/// A program that asks for passwords instead of the terminal (`-A`), such as a dialog window: it
+/// gets the prompt as its only argument, and prints the answer on its standard output. It runs as
+/// the invoking user. Since it may be all that the user sees, messages are shown with the prompt
+/// that follows them.
+pub struct Askpass {
+    pub program: PathBuf,
+    pending: Vec<String>,
+}
+
+impl Askpass {
+    pub fn new(program: PathBuf) -> Askpass {
+        Askpass {
+            program,
+            pending: Vec::new(),
+        }
+    }
+
+    fn ask(&mut self, prompt: &str) -> io::Result<SecureBuffer> {
+        let prompt = self
+            .pending
+            .drain(..)
+            .chain([prompt.to_string()])
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut output = Command::new(&self.program)
+            .arg(prompt)
+            .uid(User::real_uid())
+            .gid(Group::real_gid())
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+        let answer = output.stdout.split(|&byte| byte == b'\n').next();
+        let answer = SecureBuffer::from_slice(answer.unwrap_or_default());
+        output.stdout.fill(0);
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed: {}",
+                self.program.display(),
+                output.status
+            )));
+        }
+        Ok(answer)
+    }
+}
+
+impl Conversation for Askpass {
+    fn read_line(&mut self, prompt: &str, _timeout: Option<Duration>) -> io::Result<String> {
+        let answer = self.ask(prompt)?;
+        Ok(String::from_utf8_lossy(answer.as_bytes()).into_owned())
+    }
+
+    fn read_password(
+        &mut self,
+        prompt: &str,
+        _timeout: Option<Duration>,
+        _countdown: bool,
+    ) -> io::Result<SecureBuffer> {
+        self.ask(prompt)
+    }
+
+    fn show(&mut self, text: &str) {
+        self.pending.push(text.to_string());
+    }
+}
+
+/// Nothing can be asked (`-n`): every prompt fails, and there is no lecture for an authentication
+/// that needs no answers; other messages still appear on the terminal
+pub struct NonInteractive;
+
+impl Conversation for NonInteractive {
+    fn read_line(&mut self, _prompt: &str, _timeout: Option<Duration>) -> io::Result<String> {
+        Err(io::Error::other("a password is required"))
+    }
+
+    fn read_password(
+        &mut self,
+        _prompt: &str,
+        _timeout: Option<Duration>,
+        _countdown: bool,
+    ) -> io::Result<SecureBuffer> {
+        Err(io::Error::other("a password is required"))
+    }
+
+    fn show(&mut self, text: &str) {
+        eprintln!("{text}");
+    }
+
+    fn lecture(&mut self, _text: &str) -> bool {
+        false
+    }
+}
+
+/// Where sudo has the authentication conversation, as chosen on the command line
+pub enum Frontend {
+    Tty(Tty),
+    Askpass(Askpass),
+    NonInteractive(NonInteractive),
+}
+
+impl Default for Frontend {
+    fn default() -> Frontend {
+        Frontend::Tty(Tty::default())
+    }
+}
+
+impl Frontend {
+    fn conversation(&mut self) -> &mut dyn Conversation {
+        match self {
+            Frontend::Tty(tty) => tty,
+            Frontend::Askpass(askpass) => askpass,
+            Frontend::NonInteractive(none) => none,
+        }
+    }
+}
+
+impl Conversation for Frontend {
+    fn read_line(&mut self, prompt: &str, timeout: Option<Duration>) -> io::Result<String> {
+        self.conversation().read_line(prompt, timeout)
+    }
+
+    fn read_password(
+        &mut self,
+        prompt: &str,
+        timeout: Option<Duration>,
+        countdown: bool,
+    ) -> io::Result<SecureBuffer> {
+        self.conversation()
+            .read_password(prompt, timeout, countdown)
+    }
+
+    fn show(&mut self, text: &str) {
+        self.conversation().show(text)
+    }
+
+    fn lecture(&mut self, text: &str) -> bool {
+        self.conversation().lecture(text)
+    }
+}
+
+/// Handles the authentication conversation, normally on the terminal of the invoking user
+#[derive(Default)]
+pub struct CliConverser<C: Conversation = Frontend> {
+    /// Give up waiting for an answer to a prompt after this time (for each prompt separately)
+    pub timeout: Option<Duration>,
+    /// Show how much time is left to enter a password
+    pub countdown: bool,
+    /// The password prompt of sudo, which replaces that of PAM if it is a generic password prompt
+    /// (such as `Password: `), or always with `override_prompt`
+    pub prompt: Option<String>,
+    pub override_prompt: bool,
+    /// The user who is authenticating, whose name some PAM modules put in their password prompt
+    pub auth_user: String,
+    pub conversation: C,
+}
+
+impl<C: Conversation> CliConverser<C> {
+    /// Respond to a message; prompts are answered, other messages are only shown. Answers are
+    /// kept in a [SecureBuffer], also those that are echoed, since a one-time code is a secret too.
+    pub fn handle(&mut self, message: Message) -> io::Result<Option<SecureBuffer>> {
+        let answer = match message {
+            Message::EchoOn(prompt) => self
+                .conversation
+                .read_line(prompt, self.timeout)
+                .map(SecureBuffer::from),
+            Message::EchoOff(prompt) => {
+                let prompt = match &self.prompt {
+                    Some(own) if self.replaces(prompt) => own,
+                    _ => prompt,
+                };
+                self.conversation
+                    .read_password(prompt, self.timeout, self.countdown)
+            }
+            Message::Info(text) | Message::Error(text) => {
+                self.conversation.show(text);
+                return Ok(None);
+            }
+        };
+
+        match answer {
+            Ok(answer) => Ok(Some(answer)),
+            Err(err) => {
+                if err.kind() == io::ErrorKind::TimedOut {
+                    self.conversation.show(&format!("sudo: {err}"));
+                }
+                debug_log!(Auth, Notice, "no answer to {message:?}: {err}");
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<C: Conversation> CliConverser<C> {
+    /// Whether the prompt of sudo is to be shown instead of `pam_prompt`. Like the original sudo,
+    /// it only replaces the generic prompts of modules that ask for a password (`Password:` and
+    /// `<user>'s Password:`), so that the prompts of other modules (for a one-time code, say)
+    /// stay intact, unless `override_prompt` is set. A sudo prompt that is generic itself never
+    /// replaces that of PAM.
+    fn replaces(&self, pam_prompt: &str) -> bool {
+        let Some(own) = &self.prompt else {
+            return false;
+        };
+        if self.override_prompt {
+            return true;
+        }
+        let generic = |prompt: &str| {
+            let prompt = prompt.trim_end();
+            prompt == "Password:"
+                || prompt
+                    .strip_prefix(self.auth_user.as_str())
+                    .is_some_and(|rest| rest == "'s Password:")
+        };
+        generic(pam_prompt) && !generic(own)
+    }
+}
+
+/// Where [Throttle] records failed authentications
+pub const FAILURES_DIR: &str = "/run/sudo-rs/failures";
+
+/// Where sudo records which users have had the lecture, with `lecture=once`
+pub const LECTURED_DIR: &str = "/var/lib/sudo-rs/lectured";
+
+/// Whether `user` has had the lecture
+pub fn is_lectured(dir: &Path, user: &str) -> bool {
+    dir.join(user).exists()
+}
+
+/// Record that `user` has had the lecture
+pub fn set_lectured(dir: &Path, user: &str) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    fs::write(dir.join(user), "")
+}
+
+/// The longest delay after a failed authentication
+const MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// Failed authentications are forgotten once there have been none for this long
+const FORGET_AFTER: Duration = Duration::from_secs(900);
+
+/// Slows down the guessing of passwords: after a failed authentication, sudo waits before it
+/// gives up, and this delay doubles with every further failure of the same user on the same
+/// terminal, also across invocations of sudo, until an authentication succeeds or there have been
+/// no failures for a while
+pub struct Throttle<K: Clock = Monotonic> {
+    /// The delay after a single failure (`passwd_delay`)
+    pub delay: Duration,
+    /// The directory in which failures are recorded
+    pub dir: PathBuf,
+    pub clock: K,
+}
+
+impl<K: Clock> Throttle<K> {
+    /// Run `authenticate` for `user` on the terminal `tty`, waiting first if an earlier
+    /// invocation was interrupted while it waited, and afterwards if it fails
+    pub fn attempt(
+        &self,
+        user: &str,
+        tty: Option<&str>,
+        authenticate: impl FnOnce() -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let tty = tty.unwrap_or("notty").replace('/', "_");
+        let path = self.dir.join(format!("{user}@{tty}"));
+
+        let (failures, last) = self.recent_failures(&path);
+        if failures > 0 {
+            let until = last + self.delay_after(failures);
+            if let Ok(left) = until.duration_since(self.clock.system_time()) {
+                self.clock.sleep(left);
+            }
+        }
+
+        match authenticate() {
+            Ok(()) => {
+                let _ = fs::remove_file(&path);
+                Ok(())
+            }
+            Err(err) => {
+                let failures = failures + 1;
+                if let Err(err) = self.record_failures(&path, failures) {
+                    debug_log!(Auth, Warn, "cannot record failed authentication: {err}");
+                }
+                let delay = self.delay_after(failures);
+                debug_log!(
+                    Auth,
+                    Info,
+                    "failure {failures} of {user}, waiting {delay:?}"
+                );
+                self.clock.sleep(delay);
+                Err(err)
+            }
+        }
+    }
+
+    fn delay_after(&self, failures: u32) -> Duration {
+        let factor = 1 << failures.saturating_sub(1).min(16);
+        self.delay.saturating_mul(factor).min(MAX_DELAY)
+    }
+
+    /// The number of failures recorded in `path`, and the time of the last one; older failures
+    /// do not count
+    fn recent_failures(&self, path: &Path) -> (u32, SystemTime) {
+        let record = fs::read_to_string(path).unwrap_or_default();
+        let (failures, secs) = record.trim().split_once(' ').unwrap_or_default();
+        let last = UNIX_EPOCH + Duration::from_secs(secs.parse().unwrap_or(0));
+        match self.clock.system_time().duration_since(last) {
+            Ok(elapsed) if elapsed < FORGET_AFTER => (failures.parse().unwrap_or(0), last),
+            _ => (0, last),
+        }
+    }
+
+    fn record_failures(&self, path: &Path, failures: u32) -> io::Result<()> {
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)?;
+        let secs = self
+            .clock
+            .system_time()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        fs::write(path, format!("{failures} {secs}\n"))
+    }
+}
+
+#[cfg(feature = "pam")]
+impl<C: Conversation> CliConverser<C> {
+    /// pam-client copies the answer into memory that PAM owns, and then drops the CString
+    /// without overwriting it; keeping that copy short-lived is all that can be done here
+    fn answer(&mut self, message: Message) -> Result<CString, ErrorCode> {
+        let answer = self.handle(message).map_err(|_| ErrorCode::CONV_ERR)?;
+        let bytes = answer.as_ref().map_or(&[][..], SecureBuffer::as_bytes);
+        CString::new(bytes).map_err(|_| ErrorCode::CONV_ERR)
+    }
+}
+
+#[cfg(feature = "pam")]
+impl<C: Conversation> ConversationHandler for CliConverser<C> {
+    fn prompt_echo_on(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+        self.answer(Message::EchoOn(&msg.to_string_lossy()))
+    }
+
+    fn prompt_echo_off(&mut self, msg: &CStr) -> Result<CString, ErrorCode> {
+        self.answer(Message::EchoOff(&msg.to_string_lossy()))
+    }
+
+    fn text_info(&mut self, msg: &CStr) {
+        let _ = self.handle(Message::Info(&msg.to_string_lossy()));
+    }
+
+    fn error_msg(&mut self, msg: &CStr) {
+        let _ = self.handle(Message::Error(&msg.to_string_lossy()));
+    }
+}
+
+#[cfg(feature = "pam")]
+pub fn authenticate(username: &str, converser: CliConverser) -> Result<(), Error> {
+    debug_log!(Auth, Info, "authenticating {username}");
+    let mut context = pam_client::Context::new("sukkelsudo", Some(username), converser)
         .map_err(|_| Error::auth("failed to initialize PAM context"))?;
 
     context
         .authenticate(pam_client::Flag::NONE)
-        .map_err(|_| Error::auth("could not authenticate"))?;
+        .map_err(|err| {
+            debug_log!(Auth, Notice, "authentication of {username} failed: {err}");
+            match err.code() {
+                ErrorCode::AUTH_ERR => Error::IncorrectPassword(1),
+                _ => Error::auth("could not authenticate"),
+            }
+        })?;
 
-    context
-        .acct_mgmt(pam_client::Flag::NONE)
-        .map_err(|_| Error::auth("account validation failed"))?;
+    match context.acct_mgmt(pam_client::Flag::NONE) {
+        Ok(()) => Ok(()),
+        Err(err) if err.code() == ErrorCode::NEW_AUTHTOK_REQD => {
+            // the password has expired, but the user can still change it
+            let _ = context.conversation_mut().handle(Message::Error(
+                "Account or password is expired, reset your password and try again",
+            ));
+            context
+                .chauthtok(pam_client::Flag::CHANGE_EXPIRED_AUTHTOK)
+                .map_err(|err| {
+                    debug_log!(
+                        Auth,
+                        Notice,
+                        "changing password of {username} failed: {err}"
+                    );
+                    Error::Authentication(format!("unable to change expired password: {err}"))
+                })
+        }
+        Err(err) => {
+            debug_log!(
+                Auth,
+                Notice,
+                "account validation of {username} failed: {err}"
+            );
+            Err(account_error(err.code()))
+        }
+    }
+}
+
+/// The reason why `pam_acct_mgmt` refused an account
+#[cfg(feature = "pam")]
+fn account_error(code: ErrorCode) -> Error {
+    Error::auth(match code {
+        ErrorCode::AUTHTOK_EXPIRED => "Password expired, contact your system administrator",
+        ErrorCode::ACCT_EXPIRED => {
+            "Account expired or PAM config lacks an \"account\" section for sudo, contact your system administrator"
+        }
+        _ => "account validation failed",
+    })
+}
+
+/// Run `f` inside a PAM session for `username`; the session is closed again afterwards, also when
+/// the command that `f` runs is terminated by a signal.
+#[cfg(feature = "pam")]
+pub fn with_session<T>(username: &str, f: impl FnOnce() -> T) -> Result<T, Error> {
+    let mut context =
+        pam_client::Context::new("sukkelsudo", Some(username), CliConverser::<Tty>::default())
+            .map_err(|_| Error::auth("failed to initialize PAM context"))?;
+    let session = context
+        .open_session(pam_client::Flag::NONE)
+        .map_err(|_| Error::auth("cannot open PAM session"))?;
+    debug_log!(Auth, Diag, "opened session for {username}");
+
+    let result = f();
+
+    if let Err(err) = session.close(pam_client::Flag::NONE) {
+        debug_log!(Auth, Err, "cannot close session for {username}: {err}");
+    }
+    Ok(result)
+}
+
+#[cfg(not(feature = "pam"))]
+pub fn with_session<T>(_username: &str, f: impl FnOnce() -> T) -> Result<T, Error> {
+    Ok(f())
+}
+
+#[cfg(all(not(feature = "pam"), feature = "shadow"))]
+pub use crate::shadow::authenticate;
+
+#[cfg(not(any(feature = "pam", feature = "shadow")))]
+pub fn authenticate(username: &str, _converser: CliConverser) -> Result<(), Error> {
+    debug_log!(
+        Auth,
+        Err,
+        "cannot authenticate {username}: built without PAM or shadow support"
+    );
+    Err(Error::auth("no authentication method available"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::clock::FakeClock;
+
+    /// A conversation that answers prompts from a script and records what happened
+    #[derive(Default)]
+    struct Script {
+        answers: VecDeque<io::Result<&'static str>>,
+        transcript: Vec<String>,
+    }
+
+    impl Script {
+        fn next(&mut self, entry: String) -> io::Result<String> {
+            self.transcript.push(entry);
+            self.answers
+                .pop_front()
+                .expect("unexpected prompt")
+                .map(str::to_string)
+        }
+    }
+
+    impl Conversation for Script {
+        fn read_line(&mut self, prompt: &str, timeout: Option<Duration>) -> io::Result<String> {
+            self.next(format!("line {prompt:?} {timeout:?}"))
+        }
+
+        fn read_password(
+            &mut self,
+            prompt: &str,
+            timeout: Option<Duration>,
+            _countdown: bool,
+        ) -> io::Result<SecureBuffer> {
+            self.next(format!("password {prompt:?} {timeout:?}"))
+                .map(SecureBuffer::from)
+        }
+
+        fn show(&mut self, text: &str) {
+            self.transcript.push(format!("show {text:?}"));
+        }
+    }
+
+    fn converser(answers: Vec<io::Result<&'static str>>) -> CliConverser<Script> {
+        CliConverser {
+            timeout: Some(Duration::from_secs(30)),
+            countdown: false,
+            prompt: None,
+            override_prompt: false,
+            auth_user: "root".to_string(),
+            conversation: Script {
+                answers: answers.into(),
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn multiple_prompts() {
+        let mut converser = converser(vec![Ok("hunter2"), Ok("123456")]);
+
+        let messages = [
+            Message::EchoOff("Password: "),
+            Message::Info("Check your phone"),
+            Message::EchoOn("Verification code: "),
+            Message::Error("Code expires soon"),
+        ];
+        let answers = messages
+            .into_iter()
+            .map(|message| converser.handle(message).unwrap())
+            .map(|answer| answer.map(|answer| answer.as_bytes().to_vec()))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            answers,
+            [
+                Some(b"hunter2".to_vec()),
+                None,
+                Some(b"123456".to_vec()),
+                None
+            ]
+        );
+        // every prompt gets the full timeout
+        assert_eq!(
+            converser.conversation.transcript,
+            [
+                "password \"Password: \" Some(30s)",
+                "show \"Check your phone\"",
+                "line \"Verification code: \" Some(30s)",
+                "show \"Code expires soon\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn own_prompt() {
+        let mut converser = CliConverser {
+            prompt: Some("[sudo] password for root: ".to_string()),
+            ..converser(vec![Ok("hunter2"), Ok("123456"), Ok("hunter2")])
+        };
+        for prompt in ["Password: ", "Verification code: "] {
+            converser.handle(Message::EchoOff(prompt)).unwrap();
+        }
+        converser.override_prompt = true;
+        converser.handle(Message::EchoOff("PIN: ")).unwrap();
+
+        assert_eq!(
+            converser.conversation.transcript,
+            [
+                "password \"[sudo] password for root: \" Some(30s)",
+                "password \"Verification code: \" Some(30s)",
+                "password \"[sudo] password for root: \" Some(30s)",
+            ]
+        );
+    }
+
+    #[test]
+    fn generic_prompts() {
+        let converser = CliConverser {
+            prompt: Some("[sudo] password for root: ".to_string()),
+            ..converser(vec![])
+        };
+        for prompt in ["Password:", "Password: ", "root's Password: "] {
+            assert!(converser.replaces(prompt));
+        }
+        for prompt in ["user's Password: ", "Password for root: ", "PIN: "] {
+            assert!(!converser.replaces(prompt));
+        }
+
+        // a generic prompt of sudo does not replace the one of PAM, unless asked to
+        let mut converser = CliConverser {
+            prompt: Some("Password: ".to_string()),
+            ..converser
+        };
+        assert!(!converser.replaces("root's Password: "));
+        converser.override_prompt = true;
+        assert!(converser.replaces("PIN: "));
+    }
+
+    /// The prompts that a PAM module sends reach sudo through [ConversationHandler]
+    #[cfg(feature = "pam")]
+    #[test]
+    fn pam_module_prompts() {
+        let mut converser = CliConverser {
+            prompt: Some("[sudo] password for root: ".to_string()),
+            ..converser(vec![Ok("hunter2"), Ok("123456"), Ok("hunter2")])
+        };
+        let prompt = |text: &str| CString::new(text).unwrap();
+        let answer = converser.prompt_echo_off(&prompt("root's Password: "));
+        assert_eq!(answer.unwrap().as_bytes(), b"hunter2");
+        converser.text_info(&prompt("A code was sent to your phone"));
+        let answer = converser.prompt_echo_off(&prompt("One-time code: "));
+        assert_eq!(answer.unwrap().as_bytes(), b"123456");
+        converser.override_prompt = true;
+        let _ = converser.prompt_echo_off(&prompt("Smart card PIN: "));
+
+        assert_eq!(
+            converser.conversation.transcript,
+            [
+                "password \"[sudo] password for root: \" Some(30s)",
+                "show \"A code was sent to your phone\"",
+                "password \"One-time code: \" Some(30s)",
+                "password \"[sudo] password for root: \" Some(30s)",
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_slow_down_authentication() {
+        let dir = tempfile::tempdir().unwrap();
+        let throttle = Throttle {
+            delay: Duration::from_secs(2),
+            dir: dir.path().to_path_buf(),
+            clock: FakeClock::default(),
+        };
+        let start = throttle.clock.now();
+        let elapsed = || (throttle.clock.now() - start).as_secs();
+        let fail = || Err(Error::auth("could not authenticate"));
+        let tty = Some("/dev/pts/1");
+
+        for total in [2, 6, 14, 30] {
+            assert!(throttle.attempt("test", tty, fail).is_err());
+            assert_eq!(elapsed(), total);
+        }
+        // other users and terminals are not affected
+        assert!(throttle.attempt("test", Some("/dev/pts/2"), fail).is_err());
+        assert!(throttle.attempt("root", tty, fail).is_err());
+        assert_eq!(elapsed(), 34);
+
+        // success starts over
+        assert!(throttle.attempt("test", tty, || Ok(())).is_ok());
+        assert_eq!(elapsed(), 34);
+        assert!(throttle.attempt("test", tty, fail).is_err());
+        assert_eq!(elapsed(), 36);
+
+        // and so does a pause
+        assert!(throttle.attempt("test", tty, fail).is_err());
+        throttle.clock.advance(FORGET_AFTER);
+        assert!(throttle.attempt("test", tty, fail).is_err());
+        assert_eq!(elapsed(), 40 + FORGET_AFTER.as_secs() + 2);
+
+        // an invocation that was interrupted while it waited is made up for by the next one
+        let path = dir.path().join("test@notty");
+        throttle.record_failures(&path, 3).unwrap();
+        let before = elapsed();
+        assert!(throttle.attempt("test", None, || Ok(())).is_ok());
+        // (failures are recorded in whole seconds)
+        assert!((7..=8).contains(&(elapsed() - before)));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn prompt_timeout() {
+        let timeout = io::Error::new(io::ErrorKind::TimedOut, "timed out reading input");
+        let mut converser = converser(vec![Ok("hunter2"), Err(timeout)]);
+
+        assert!(converser.handle(Message::EchoOff("Password: ")).is_ok());
+        let err = converser.handle(Message::EchoOn("Code: ")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            converser.conversation.transcript.last().unwrap(),
+            "show \"sudo: timed out reading input\""
+        );
+    }
+
+    #[test]
+    fn askpass() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("askpass");
+        let prompts = dir.path().join("prompts");
+        fs::write(
+            &program,
+            format!(
+                "#!/bin/sh\nprintf '%s|' \"$1\" >> {}\necho hunter2\necho ignored\n",
+                prompts.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut askpass = Askpass::new(program);
+        assert!(askpass.lecture("Think before you type."));
+        let password = askpass.read_password("Password: ", None, false).unwrap();
+        assert_eq!(password.as_bytes(), b"hunter2");
+        assert_eq!(askpass.read_line("Code: ", None).unwrap(), "hunter2");
+        assert_eq!(
+            fs::read_to_string(&prompts).unwrap(),
+            "Think before you type.\nPassword: |Code: |"
+        );
+
+        let mut askpass = Askpass::new("/bin/false".into());
+        assert!(askpass.read_password("Password: ", None, false).is_err());
+    }
+
+    #[test]
+    fn non_interactive() {
+        let mut conversation = NonInteractive;
+        assert!(!conversation.lecture("Think before you type."));
+        assert!(conversation
+            .read_password("Password: ", None, false)
+            .is_err());
+        assert!(conversation.read_line("Code: ", None).is_err());
+    }
 
-    Ok(())
+    #[test]
+    fn lectured() {
+        let dir = tempfile::tempdir().unwrap();
+        let lectured = dir.path().join("lectured");
+        assert!(!is_lectured(&lectured, "test"));
+        set_lectured(&lectured, "test").unwrap();
+        assert!(is_lectured(&lectured, "test"));
+        assert!(!is_lectured(&lectured, "other"));
+    }
 }
@@ -0,0 +1,79 @@
+//! Keeping passwords out of the log of what is typed to a command.
+//!
+//! Like the original sudo, when the latest output of the command contains a password prompt, the
+//! input that follows is logged as `*` up to the end of the line, unless the `log_passwords`
+//! setting is on. The prompt is recognized by the default `passprompt_regex` of the original sudo,
+//! `[Pp]assword[: ]*`, which can not be changed here. Only input that passes through sudo can be
+//! filtered; a command that reads the password from the terminal itself does not show it to sudo
+//! at all. The filter is applied as the streams of the command are copied into the I/O log.
+
+pub struct PasswordFilter {
+    enabled: bool,
+    /// Whether the input is (still) a password
+    masking: bool,
+}
+
+impl PasswordFilter {
+    pub fn new(log_passwords: bool) -> PasswordFilter {
+        PasswordFilter {
+            enabled: !log_passwords,
+            masking: false,
+        }
+    }
+
+    /// Whether passwords are masked, which they are unless `log_passwords` is set
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Take note of output of the command
+    pub fn output(&mut self, data: &[u8]) {
+        self.masking = self.enabled
+            && data
+                .windows(b"assword".len() + 1)
+                .any(|word| matches!(word[0], b'P' | b'p') && &word[1..] == b"assword");
+    }
+
+    /// Replace the password in input for the command, as it is logged
+    pub fn input(&mut self, data: &mut [u8]) {
+        for byte in data {
+            if !self.masking {
+                break;
+            }
+            match byte {
+                b'\n' | b'\r' => self.masking = false,
+                _ => *byte = b'*',
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PasswordFilter;
+
+    #[test]
+    fn passwords_are_masked() {
+        let logged = |filter: &mut PasswordFilter, input: &str| {
+            let mut data = input.as_bytes().to_vec();
+            filter.input(&mut data);
+            String::from_utf8(data).unwrap()
+        };
+        let mut filter = PasswordFilter::new(false);
+        assert_eq!(logged(&mut filter, "ls\n"), "ls\n");
+        filter.output(b"Enter Password: ");
+        assert_eq!(logged(&mut filter, "hunt"), "****");
+        assert_eq!(logged(&mut filter, "er2\nls\n"), "***\nls\n");
+        filter.output(b"new password:");
+        filter.output(b"\n");
+        assert_eq!(logged(&mut filter, "ls\r"), "ls\r");
+        filter.output(b"[sudo] password for user: ");
+        assert_eq!(logged(&mut filter, "secret\r\n"), "******\r\n");
+        filter.output(b"passwd: PASSWORD:");
+        assert_eq!(logged(&mut filter, "secret"), "secret");
+
+        let mut filter = PasswordFilter::new(true);
+        filter.output(b"Password: ");
+        assert_eq!(logged(&mut filter, "hunter2\n"), "hunter2\n");
+    }
+}
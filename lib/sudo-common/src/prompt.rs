@@ -0,0 +1,280 @@
+//! Reading a password from the terminal. Input is read in raw mode, so the prompt can be redrawn
+//! when the terminal is resized, and (optionally) show how much time is left to answer it.
+//!
+//! The prompt of sudo itself (`-p`, or the `passprompt` setting) is a template with escapes such
+//! as `%p` for the user whose password is asked for.
+
+use std::{io, time::Duration};
+
+use sudo_system::{
+    secure_buffer::SecureBuffer,
+    term::{watch_window_size, window_changed, Terminal},
+};
+
+use crate::clock::Deadline;
+
+/// How often the prompt is checked for redrawing while waiting for input
+const TICK: Duration = Duration::from_secs(1);
+
+/// Passwords longer than this are truncated (the same limit PAM uses)
+const MAX_PASSWORD_LEN: usize = 512;
+
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const CTRL_U: u8 = 0x15;
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/// The prompt if neither `-p` nor `passprompt` gives one; a translation of it must keep the escapes
+pub const DEFAULT_PROMPT: &str = "[sudo] password for %p: ";
+
+/// The lecture if the `lecture_file` setting does not give one
+pub const DEFAULT_LECTURE: &str = "
+We trust you have received the usual lecture from the local System
+Administrator. It usually boils down to these three things:
+
+    #1) Respect the privacy of others.
+    #2) Think before you type.
+    #3) With great power comes great responsibility.
+";
+
+/// The values of the escapes in a prompt
+pub struct PromptValues<'a> {
+    /// The name of this host, which may include the domain (`%H`); `%h` is without the domain
+    pub host: &'a str,
+    /// The user whose password is asked for (`%p`), which is not the invoking user with
+    /// `rootpw`, `runaspw` or `targetpw`
+    pub auth_user: &'a str,
+    /// The user the command runs as (`%U`)
+    pub target_user: &'a str,
+    /// The invoking user (`%u`)
+    pub user: &'a str,
+}
+
+/// Expand the escapes in a prompt; like in the original sudo, `%%` is a `%` and a `%` that does
+/// not start an escape is kept as it is
+pub fn expand_prompt(template: &str, values: &PromptValues) -> String {
+    let mut result = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        let value = match (c, chars.peek()) {
+            ('%', Some('H')) => values.host,
+            ('%', Some('h')) => values.host.split('.').next().unwrap_or_default(),
+            ('%', Some('p')) => values.auth_user,
+            ('%', Some('U')) => values.target_user,
+            ('%', Some('u')) => values.user,
+            ('%', Some('%')) => "%",
+            _ => {
+                result.push(c);
+                continue;
+            }
+        };
+        result.push_str(value);
+        chars.next();
+    }
+    result
+}
+
+/// The text shown to the user: the prompt, followed by the time left if there is a countdown
+fn render(prompt: &str, remaining: Option<Duration>) -> String {
+    match remaining {
+        Some(left) => {
+            let secs = left.as_secs_f64().ceil() as u64;
+            format!("{prompt}({}:{:02}) ", secs / 60, secs % 60)
+        }
+        None => prompt.to_string(),
+    }
+}
+
+/// The number of terminal rows taken up by `text` (not containing newlines) in a given width
+fn rows_used(text: &str, width: usize) -> usize {
+    text.chars().count().max(1).div_ceil(width.max(1))
+}
+
+/// Escape sequence that erases `shown` (drawn at a terminal of `width` columns, with the cursor
+/// right behind it) so something else can be drawn in its place
+fn erase(shown: &str, width: usize) -> String {
+    let mut seq = String::from("\r");
+    let up = rows_used(shown.lines().last().unwrap_or_default(), width) - 1;
+    if up > 0 {
+        seq += &format!("\x1b[{up}A");
+    }
+    seq + "\x1b[J"
+}
+
+struct Screen<'a> {
+    tty: &'a Terminal,
+    shown: String,
+    width: usize,
+}
+
+impl Screen<'_> {
+    fn draw(&mut self, text: String) -> io::Result<()> {
+        let mut output = if self.shown.is_empty() {
+            String::new()
+        } else {
+            erase(&self.shown, self.width)
+        };
+        output += &text;
+        self.tty.write(&output)?;
+        self.shown = text;
+        self.width = self.tty.width().unwrap_or(80);
+        Ok(())
+    }
+}
+
+/// Show `prompt` on the terminal and read a password (without echoing it). If a `deadline` is
+/// given, reading fails once it has passed; with `countdown` the time left is shown as well. The
+/// password is read directly into a [SecureBuffer], so no copy of it is left behind.
+pub fn read_password(
+    prompt: &str,
+    deadline: Option<Deadline>,
+    countdown: bool,
+) -> io::Result<SecureBuffer> {
+    let tty = Terminal::open()?;
+    let _raw = tty.raw_mode()?;
+    watch_window_size();
+    let _ = window_changed();
+
+    let time_left = || deadline.map(|deadline| deadline.remaining());
+    let text = || render(prompt, time_left().filter(|_| countdown));
+
+    let mut screen = Screen {
+        tty: &tty,
+        shown: String::new(),
+        width: tty.width().unwrap_or(80),
+    };
+    screen.draw(text())?;
+
+    let mut password = SecureBuffer::with_capacity(MAX_PASSWORD_LEN);
+    loop {
+        let left = time_left();
+        if left == Some(Duration::ZERO) {
+            tty.write("\n")?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "timed out reading password",
+            ));
+        }
+
+        let input = tty.wait_for_input(left.map_or(TICK, |left| left.min(TICK)))?;
+        if window_changed() || (countdown && deadline.is_some()) {
+            screen.draw(text())?;
+        }
+        if !input {
+            continue;
+        }
+
+        match tty.read_byte()? {
+            Some(b'\r' | b'\n') => break,
+            Some(CTRL_C) => {
+                tty.write("\n")?;
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "interrupted"));
+            }
+            None | Some(CTRL_D) if password.is_empty() => {
+                tty.write("\n")?;
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            None | Some(CTRL_D) => break,
+            Some(BACKSPACE | DELETE) => {
+                password.pop();
+            }
+            Some(CTRL_U) => password.clear(),
+            Some(byte) => {
+                password.push(byte);
+            }
+        }
+    }
+
+    tty.write("\n")?;
+    Ok(password)
+}
+
+/// Show `prompt` on the terminal and read a line of input, which is echoed as usual; if a
+/// `deadline` is given, reading fails once it has passed
+pub fn read_line(prompt: &str, deadline: Option<Deadline>) -> io::Result<String> {
+    let tty = Terminal::open()?;
+    tty.write(prompt)?;
+    // the terminal is in canonical mode, so input only becomes available once a line is complete
+    if let Some(deadline) = deadline {
+        loop {
+            let left = deadline.remaining();
+            if left == Duration::ZERO {
+                tty.write("\n")?;
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out reading input",
+                ));
+            }
+            if tty.wait_for_input(left)? {
+                break;
+            }
+        }
+    }
+    tty.read_line()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FakeClock;
+
+    #[test]
+    fn prompt_escapes() {
+        let values = PromptValues {
+            host: "web1.example.org",
+            auth_user: "root",
+            target_user: "www-data",
+            user: "alice",
+        };
+        assert_eq!(
+            expand_prompt(DEFAULT_PROMPT, &values),
+            "[sudo] password for root: "
+        );
+        assert_eq!(
+            expand_prompt("%u@%h (%H) as %U: ", &values),
+            "alice@web1 (web1.example.org) as www-data: "
+        );
+        assert_eq!(expand_prompt("100%% %x %", &values), "100% %x %");
+        assert_eq!(expand_prompt("%%p", &values), "%p");
+    }
+
+    #[test]
+    fn countdown_rendering() {
+        let prompt = "Password: ";
+        assert_eq!(render(prompt, None), "Password: ");
+        let left = |secs| Some(Duration::from_secs(secs));
+        assert_eq!(render(prompt, left(300)), "Password: (5:00) ");
+        assert_eq!(render(prompt, left(59)), "Password: (0:59) ");
+        assert_eq!(
+            render(prompt, Some(Duration::from_millis(1500))),
+            "Password: (0:02) "
+        );
+    }
+
+    #[test]
+    fn countdown_follows_the_clock() {
+        let clock = FakeClock::default();
+        let deadline = Deadline::after(&clock, Duration::from_secs(120));
+        assert_eq!(
+            render("Password: ", Some(deadline.remaining())),
+            "Password: (2:00) "
+        );
+        clock.advance(Duration::from_millis(90_500));
+        assert_eq!(
+            render("Password: ", Some(deadline.remaining())),
+            "Password: (0:30) "
+        );
+    }
+
+    #[test]
+    fn redraw_after_wrapping() {
+        assert_eq!(rows_used("", 80), 1);
+        assert_eq!(rows_used("Password: ", 80), 1);
+        assert_eq!(rows_used("Password: ", 10), 1);
+        assert_eq!(rows_used("Password: ", 4), 3);
+
+        assert_eq!(erase("Password: ", 80), "\r\x1b[J");
+        assert_eq!(erase("Password: ", 4), "\r\x1b[2A\x1b[J");
+    }
+}
@@ -0,0 +1,314 @@
+//! Passing the input and output of a command through sudo, so they can be recorded in an I/O log.
+//! Only the streams that are recorded go through sudo: the command gets a pipe for those, and sudo
+//! copies what arrives on one end to where it would have gone otherwise, and into the log. The
+//! other streams are inherited by the command as usual. Unlike the original sudo, sudo-rs has no
+//! pseudo-terminal (nor a `use_pty` setting), so a recorded stream is never a terminal for the
+//! command.
+//!
+//! Input that follows a password prompt is logged as `*` (see [crate::password_filter]). To see
+//! the prompts, the output of the command also passes through sudo when only its input is
+//! recorded, unless the `log_passwords` setting is on.
+//!
+//! The streams are copied in the event loop that supervises the command, so sudo must never block
+//! on them: the ends that are written to are non-blocking, and what they do not take at once is
+//! kept until they are writable again. Until then, nothing more is read from that stream, so a
+//! command that does not read its input (because it waits for its output to be read) holds up
+//! neither its output nor the other events.
+
+use std::{
+    io,
+    os::fd::{AsRawFd, OwnedFd, RawFd},
+    process::{Child, Command, Stdio},
+};
+
+use sudo_system::event::EventLoop;
+
+use crate::{
+    debug_log,
+    iolog::{Session, Stream},
+    password_filter::PasswordFilter,
+};
+
+/// A stream that is copied from one file descriptor to another
+struct Channel {
+    from: RawFd,
+    to: RawFd,
+    stream: Stream,
+    /// Whether the stream is written to the I/O log, or only passes through
+    recorded: bool,
+    /// What has been read, but not yet written
+    pending: Vec<u8>,
+    /// The end of the pipe to the command, which is closed when the stream ends
+    _pipe: OwnedFd,
+}
+
+impl Channel {
+    /// Write as much of what is pending as the destination takes; returns whether it can still
+    /// be written to
+    fn flush(&mut self) -> bool {
+        while !self.pending.is_empty() {
+            match write(self.to, &self.pending) {
+                Ok(len) => {
+                    self.pending.drain(..len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => {
+                    debug_log!(Exec, Diag, "cannot write {:?}: {err}", self.stream);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+pub(crate) struct Relay {
+    session: Session,
+    input: bool,
+    output: bool,
+    filter: PasswordFilter,
+    channels: Vec<Channel>,
+    /// The file status flags of the outputs of sudo, as they were before they were made
+    /// non-blocking; they are shared with other processes, so they are restored at the end
+    restore: Vec<(RawFd, libc::c_int)>,
+}
+
+impl Relay {
+    /// Record what is typed into the command (`input`) and what it outputs (`output`) in `session`;
+    /// passwords in the input are masked unless `log_passwords` is set
+    pub fn new(session: Session, input: bool, output: bool, log_passwords: bool) -> Relay {
+        Relay {
+            session,
+            input,
+            output,
+            filter: PasswordFilter::new(log_passwords),
+            channels: Vec::new(),
+            restore: Vec::new(),
+        }
+    }
+
+    /// Whether the output of the command passes through sudo: when it is recorded, or when the
+    /// password prompts in it have to be seen to filter the input
+    fn relays_output(&self) -> bool {
+        self.output || (self.input && self.filter.enabled())
+    }
+
+    /// Give the command pipes for the streams that are recorded
+    pub fn configure(&self, command: &mut Command) {
+        if self.input {
+            command.stdin(Stdio::piped());
+        }
+        if self.relays_output() {
+            command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+    }
+
+    /// Take over the ends of the pipes of the command that was started, and watch them in `events`
+    pub fn attach(&mut self, child: &mut Child, events: &mut EventLoop) {
+        if let Some(stdin) = child.stdin.take() {
+            self.channels.push(Channel {
+                from: libc::STDIN_FILENO,
+                to: stdin.as_raw_fd(),
+                stream: Stream::Stdin,
+                recorded: true,
+                pending: Vec::new(),
+                _pipe: stdin.into(),
+            });
+        }
+        let outputs = [
+            (child.stdout.take().map(OwnedFd::from), Stream::Stdout),
+            (child.stderr.take().map(OwnedFd::from), Stream::Stderr),
+        ];
+        for (pipe, stream) in outputs {
+            if let Some(pipe) = pipe {
+                let to = match stream {
+                    Stream::Stdout => libc::STDOUT_FILENO,
+                    _ => libc::STDERR_FILENO,
+                };
+                self.channels.push(Channel {
+                    from: pipe.as_raw_fd(),
+                    to,
+                    stream,
+                    recorded: self.output,
+                    pending: Vec::new(),
+                    _pipe: pipe,
+                });
+            }
+        }
+
+        // the flags of all outputs are saved first, since they can share them
+        for channel in &self.channels {
+            if let (true, Ok(flags)) = (channel.stream.is_output(), get_flags(channel.to)) {
+                self.restore.push((channel.to, flags));
+            }
+        }
+        for channel in &self.channels {
+            let result = get_flags(channel.to)
+                .and_then(|flags| set_flags(channel.to, flags | libc::O_NONBLOCK));
+            if let Err(err) = result {
+                debug_log!(
+                    Exec,
+                    Diag,
+                    "cannot make {:?} non-blocking: {err}",
+                    channel.stream
+                );
+            }
+            events.watch(channel.from);
+        }
+    }
+
+    /// Copy what can be read from `fd`, if it is one of the relay; returns whether it is. When
+    /// the destination does not take all of it, `fd` is no longer watched until the rest has
+    /// been written (see [Relay::writable]).
+    pub fn readable(&mut self, fd: RawFd, events: &mut EventLoop) -> bool {
+        let Some(index) = self.channels.iter().position(|channel| channel.from == fd) else {
+            return false;
+        };
+        let channel = &mut self.channels[index];
+        let mut buffer = [0u8; 4096];
+        let more = match read(channel.from, &mut buffer) {
+            Ok(0) => false,
+            Ok(len) => {
+                let data = &buffer[..len];
+                if channel.recorded {
+                    let result = if channel.stream.is_output() {
+                        self.session.record(channel.stream, data)
+                    } else {
+                        let mut logged = data.to_vec();
+                        self.filter.input(&mut logged);
+                        self.session.record(channel.stream, &logged)
+                    };
+                    if let Err(err) = result {
+                        debug_log!(Exec, Warn, "cannot write to the I/O log: {err}");
+                    }
+                }
+                if channel.stream.is_output() {
+                    self.filter.output(data);
+                }
+                channel.pending.extend_from_slice(data);
+                channel.flush()
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => true,
+            Err(err) => {
+                debug_log!(Exec, Diag, "cannot read {:?}: {err}", channel.stream);
+                false
+            }
+        };
+        if !more {
+            self.end(index, events);
+        } else if !channel.pending.is_empty() {
+            events.unwatch(channel.from);
+            events.watch_writable(channel.to);
+        }
+        true
+    }
+
+    /// Write what is left of a stream to `fd`, if it is one of the relay; returns whether it is.
+    /// Once everything has been written, the stream is read from again.
+    pub fn writable(&mut self, fd: RawFd, events: &mut EventLoop) -> bool {
+        let Some(index) = self
+            .channels
+            .iter()
+            .position(|channel| channel.to == fd && !channel.pending.is_empty())
+        else {
+            return false;
+        };
+        let channel = &mut self.channels[index];
+        if !channel.flush() {
+            self.end(index, events);
+        } else if channel.pending.is_empty() {
+            events.unwatch_writable(channel.to);
+            events.watch(channel.from);
+        }
+        true
+    }
+
+    /// Stop copying a stream; this closes the pipe, so the command sees the end of its input
+    fn end(&mut self, index: usize, events: &mut EventLoop) {
+        let channel = self.channels.remove(index);
+        events.unwatch(channel.from);
+        events.unwatch_writable(channel.to);
+    }
+
+    /// Copy the output that the command left in the pipes when it ended; the pipes are not waited
+    /// on, since other processes can still have them open
+    pub fn drain(&mut self) {
+        // the command is done, so sudo can wait for its outputs to take the rest
+        self.restore_flags();
+        for channel in self.channels.iter_mut() {
+            if !channel.stream.is_output() {
+                continue;
+            }
+            if let Ok(flags) = get_flags(channel.from) {
+                let _ = set_flags(channel.from, flags | libc::O_NONBLOCK);
+            }
+            let mut buffer = [0u8; 4096];
+            while channel.flush() {
+                let len = match read(channel.from, &mut buffer) {
+                    Ok(len) if len > 0 => len,
+                    _ => break,
+                };
+                let data = &buffer[..len];
+                if channel.recorded {
+                    if let Err(err) = self.session.record(channel.stream, data) {
+                        debug_log!(Exec, Warn, "cannot write to the I/O log: {err}");
+                    }
+                }
+                channel.pending.extend_from_slice(data);
+            }
+        }
+    }
+
+    fn restore_flags(&mut self) {
+        for (fd, flags) in self.restore.drain(..) {
+            let _ = set_flags(fd, flags);
+        }
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        self.restore_flags();
+    }
+}
+
+fn get_flags(fd: RawFd) -> io::Result<libc::c_int> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(flags)
+}
+
+fn set_flags(fd: RawFd, flags: libc::c_int) -> io::Result<()> {
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn read(fd: RawFd, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        let len = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if len >= 0 {
+            return Ok(len as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+fn write(fd: RawFd, data: &[u8]) -> io::Result<usize> {
+    loop {
+        let len = unsafe { libc::write(fd, data.as_ptr().cast(), data.len()) };
+        if len >= 0 {
+            return Ok(len as usize);
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
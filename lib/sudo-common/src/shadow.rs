@@ -0,0 +1,221 @@
+//! Authentication against the shadow password file, for systems without PAM. Hashes are verified
+//! with crypt(3) of the system, and like pam_unix, the aging fields of the entry are checked: an
+//! account that has expired or has been inactive for too long is refused, and so is a password
+//! that has expired (since it can not be changed here).
+
+use std::{
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use sudo_system::{audit, crypt, term};
+
+use crate::{
+    debug_log,
+    error::Error,
+    pam::{CliConverser, Message},
+};
+
+pub const SHADOW_FILE: &str = "/etc/shadow";
+
+/// The fields of a shadow(5) entry that authentication needs; dates are in days since 1970-01-01,
+/// and an empty field is `None`
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    hash: String,
+    last_change: Option<i64>,
+    max_age: Option<i64>,
+    inactive: Option<i64>,
+    expire: Option<i64>,
+}
+
+/// Look up the entry of a user in a file in shadow(5) format
+fn read_entry(path: &Path, username: &str) -> io::Result<Option<Entry>> {
+    Ok(fs::read_to_string(path)?.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next() != Some(username) {
+            return None;
+        }
+        let fields = fields.collect::<Vec<_>>();
+        // a field that is missing or not a number counts as empty, like in libc
+        let days = |index: usize| fields.get(index).and_then(|field| field.parse().ok());
+        Some(Entry {
+            hash: fields.first().unwrap_or(&"").to_string(),
+            last_change: days(1),
+            max_age: days(3),
+            inactive: days(5),
+            expire: days(6),
+        })
+    }))
+}
+
+/// Whether the password matches the hash; locked accounts (with a hash starting with `!` or `*`)
+/// and accounts without a password never match
+fn verify(password: &[u8], hash: &str) -> bool {
+    if hash.is_empty() || hash.starts_with(['!', '*']) {
+        return false;
+    }
+    crypt::verify(password, hash)
+}
+
+/// Check the aging fields of the entry on day `today`, in the same way as pam_unix
+fn check_account(entry: &Entry, today: i64) -> Result<(), Error> {
+    let account_expired = || Error::auth("Account expired, contact your system administrator");
+    let password_expired = || Error::auth("Password expired, contact your system administrator");
+
+    if entry.expire.is_some_and(|expire| today >= expire) {
+        return Err(account_expired());
+    }
+    let Some(last_change) = entry.last_change else {
+        return Ok(());
+    };
+    // the password has to be changed before the next login
+    if last_change == 0 {
+        return Err(password_expired());
+    }
+    let Some(max_age) = entry.max_age else {
+        return Ok(());
+    };
+    let age = today - last_change;
+    if entry
+        .inactive
+        .is_some_and(|inactive| age > max_age + inactive)
+    {
+        return Err(account_expired());
+    }
+    if age > max_age {
+        return Err(password_expired());
+    }
+    Ok(())
+}
+
+pub fn authenticate(username: &str, mut converser: CliConverser) -> Result<(), Error> {
+    debug_log!(Auth, Info, "authenticating {username} using {SHADOW_FILE}");
+    let entry = read_entry(Path::new(SHADOW_FILE), username)
+        .map_err(|err| Error::Authentication(format!("cannot read {SHADOW_FILE}: {err}")))?
+        .ok_or_else(|| Error::auth("account validation failed"))?;
+
+    // like pam_unix, ask with the generic prompt, which the converser replaces with that of sudo
+    let password = converser
+        .handle(Message::EchoOff("Password: "))
+        .map_err(|err| Error::Authentication(err.to_string()))?
+        .unwrap_or_default();
+    let valid = verify(password.as_bytes(), &entry.hash);
+    drop(password);
+
+    let record = audit::format_record(
+        &[
+            ("op", audit::encode_value("authentication")),
+            ("acct", audit::encode_value(username)),
+            (
+                "terminal",
+                term::tty_name().unwrap_or_else(|| "?".to_string()),
+            ),
+        ],
+        valid,
+    );
+    if let Err(err) = audit::log(audit::Event::Auth, &record) {
+        debug_log!(Auth, Warn, "cannot write audit record: {err}");
+    }
+
+    if !valid {
+        debug_log!(Auth, Notice, "authentication of {username} failed");
+        return Err(Error::IncorrectPassword(1));
+    }
+
+    let today = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / (24 * 60 * 60)) as i64;
+    check_account(&entry, today).inspect_err(|err| {
+        debug_log!(
+            Auth,
+            Notice,
+            "account validation of {username} failed: {err}"
+        );
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_password() {
+        let hash = "$6$saltsaltsalt$/YcSJBZ1GwOaYU2XCNxvWBX3MbYsX7qkf0.0L28t35vap3Pr7yS0JA8iUjaUrIKpD1vbB841OQkrlcX47Jjuy/";
+        assert!(verify(b"hunter2", hash));
+        assert!(!verify(b"hunter3", hash));
+        assert!(!verify(b"hunter2", &format!("!{hash}")));
+        assert!(!verify(b"hunter2", &format!("*{hash}")));
+        assert!(!verify(b"", ""));
+    }
+
+    #[test]
+    fn read_shadow_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        io::Write::write_all(
+            &mut file,
+            b"root:*:19000:0:99999:7:::\ntest:$6$salt$hash:19000::90::30:20000:\nold:x\n",
+        )
+        .unwrap();
+        let entry = |name| read_entry(file.path(), name).unwrap();
+        assert_eq!(entry("root").unwrap().hash, "*");
+        assert_eq!(
+            entry("test"),
+            Some(Entry {
+                hash: "$6$salt$hash".to_string(),
+                last_change: Some(19000),
+                max_age: Some(90),
+                inactive: Some(30),
+                expire: Some(20000),
+            })
+        );
+        assert_eq!(
+            entry("old"),
+            Some(Entry {
+                hash: "x".to_string(),
+                last_change: None,
+                max_age: None,
+                inactive: None,
+                expire: None,
+            })
+        );
+        assert_eq!(entry("nobody"), None);
+    }
+
+    #[test]
+    fn account_aging() {
+        let entry = |last_change, max_age, inactive, expire| Entry {
+            hash: String::new(),
+            last_change,
+            max_age,
+            inactive,
+            expire,
+        };
+        let check = |entry: &Entry, today| match check_account(entry, today) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => err.to_string(),
+        };
+        let account = "Account expired, contact your system administrator";
+        let password = "Password expired, contact your system administrator";
+
+        // no aging at all
+        assert_eq!(check(&entry(None, None, None, None), 20000), "ok");
+        assert_eq!(check(&entry(Some(19000), None, None, None), 20000), "ok");
+        // the account expires on a given day
+        assert_eq!(check(&entry(None, None, None, Some(20000)), 19999), "ok");
+        assert_eq!(check(&entry(None, None, None, Some(20000)), 20000), account);
+        // the password has to be changed first
+        assert_eq!(check(&entry(Some(0), None, None, None), 20000), password);
+        // the password expires after max_age days, the account inactive days after that
+        let aging = entry(Some(19000), Some(90), Some(30), None);
+        assert_eq!(check(&aging, 19090), "ok");
+        assert_eq!(check(&aging, 19091), password);
+        assert_eq!(check(&aging, 19120), password);
+        assert_eq!(check(&aging, 19121), account);
+        assert_eq!(
+            check(&entry(Some(19000), Some(90), None, None), 30000),
+            password
+        );
+    }
+}
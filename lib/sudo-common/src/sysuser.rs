@@ -1,4 +1,4 @@
-use sudo_system::Group;
+use sudo_system::database::database;
 
 pub type GroupId = libc::gid_t;
 pub type UserId = libc::uid_t;
@@ -22,6 +22,11 @@ pub trait UnixUser {
     fn in_group_by_gid(&self, _gid: GroupId) -> bool {
         false
     }
+    /// Membership of a group that is not a Unix group (`%:name` in sudoers); see
+    /// [crate::group_provider]
+    fn in_nonunix_group(&self, _name: &str) -> bool {
+        false
+    }
 }
 
 pub trait UnixGroup {
@@ -54,7 +59,7 @@ impl UnixUser for sudo_system::User {
         self.has_uid(0)
     }
     fn in_group_by_name(&self, name: &str) -> bool {
-        if let Ok(Some(group)) = Group::from_name(name) {
+        if let Ok(Some(group)) = database().group_by_name(name) {
             self.in_group_by_gid(group.gid)
         } else {
             false
@@ -66,6 +71,9 @@ impl UnixUser for sudo_system::User {
             _ => false,
         }
     }
+    fn in_nonunix_group(&self, name: &str) -> bool {
+        crate::group_provider::in_nonunix_group(&self.name, name)
+    }
 }
 
 impl UnixGroup for sudo_system::Group {
@@ -86,3 +94,49 @@ impl UnixGroup for (u16, &str) {
         self.0 as GroupId
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use sudo_system::{database::Database, Group, User};
+
+    struct FakeDatabase;
+
+    impl Database for FakeDatabase {
+        fn user_by_name(&self, _name: &str) -> io::Result<Option<User>> {
+            Ok(None)
+        }
+        fn user_by_uid(&self, _uid: UserId) -> io::Result<Option<User>> {
+            Ok(None)
+        }
+        fn group_by_name(&self, name: &str) -> io::Result<Option<Group>> {
+            Ok((name == "wheel").then(|| Group {
+                gid: 10,
+                name: "wheel".to_string(),
+                passwd: String::new(),
+                members: Vec::new(),
+            }))
+        }
+        fn group_by_gid(&self, _gid: GroupId) -> io::Result<Option<Group>> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn group_membership_by_name() {
+        sudo_system::database::set_database(FakeDatabase);
+        let user = User {
+            uid: 1000,
+            gid: 1000,
+            name: "test".to_string(),
+            gecos: String::new(),
+            home: "/home/test".to_string(),
+            shell: "/bin/sh".to_string(),
+            passwd: String::new(),
+            groups: Some(vec![1000, 10]),
+        };
+        assert!(user.in_group_by_name("wheel"));
+        assert!(!user.in_group_by_name("sudo"));
+    }
+}
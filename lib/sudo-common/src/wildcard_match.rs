@@ -1,17 +1,17 @@
 /// Match a  test input with a pattern
 /// Only wildcard characters (*) in the pattern string have a special meaning: they match on zero or more characters
 pub(crate) fn wildcard_match(test: &str, pattern: &str) -> bool {
+    // comparing bytes gives the same result as comparing characters: a `*` is a single byte, and
+    // the character that follows it can only match at the start of a character
+    let (test, pattern) = (test.as_bytes(), pattern.as_bytes());
     let mut test_index = 0;
     let mut pattern_index = 0;
     let mut last_star = None;
 
     loop {
-        match (
-            pattern.chars().nth(pattern_index),
-            test.chars().nth(test_index),
-        ) {
+        match (pattern.get(pattern_index), test.get(test_index)) {
             (Some(p), Some(t)) => {
-                if p == '*' {
+                if *p == b'*' {
                     pattern_index += 1;
                     last_star = Some((test_index, pattern_index));
                 } else if p == t {
@@ -34,7 +34,7 @@ pub(crate) fn wildcard_match(test: &str, pattern: &str) -> bool {
                     return false;
                 }
             }
-            (Some('*'), None) => {
+            (Some(b'*'), None) => {
                 pattern_index += 1;
             }
             (None, None) => {
@@ -73,6 +73,10 @@ mod tests {
             ("#%^$V@#TYH%&rot13%#@$%#$%", "*%^*%&rot*%#$%", true),
             ("#%^$V@#TYH%&rot13%#@$%#$%", "#%^$V@#TYH%&r*%#@$#$%", false),
             ("#%^$V@#TYH%&rot13%#@$%#$%", "#%^$V@#*******@$%#$%", true),
+            ("LANG=fr_FR.UTF-8", "LANG=*", true),
+            ("TZ=Zürich", "TZ=*ch", true),
+            ("TZ=Zürich", "TZ=Z*rich", true),
+            ("TZ=Zürich", "TZ=Z?rich", false),
         ];
 
         for (test, pattern, expected) in tests.into_iter() {
@@ -1,14 +1,18 @@
 use std::collections::HashSet;
 
-use sudo_cli::SudoOptions;
+use sudo_cli::{SudoOptions, TargetUser};
 use sudo_common::{
     context::{CommandAndArguments, Context},
-    env::{get_target_environment, Environment},
+    env::{
+        disallowed_variables, get_target_environment, Environment, CHECK_ENV_TABLE,
+        DELETE_ENV_TABLE, KEEP_ENV_TABLE,
+    },
 };
 use sudo_system::{Group, User};
 
 const TESTS: &str = "
 > env
+    DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/1000/bus
     FOO=BAR
     HOME=/home/test
     HOSTNAME=test-ubuntu
@@ -20,6 +24,7 @@ const TESTS: &str = "
     PWD=/home/test
     SHLVL=0
     TERM=xterm
+    XDG_RUNTIME_DIR=/run/user/1000
     _=/usr/bin/sudo
 > sudo env
     HOME=/root
@@ -55,6 +60,209 @@ const TESTS: &str = "
     SUDO_USER=test
     TERM=xterm
     USER=test
+> sudo -E env
+    FOO=BAR
+    HOME=/root
+    HOSTNAME=test-ubuntu
+    LANG=en_US.UTF-8
+    LANGUAGE=en_US.UTF-8
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    LS_COLORS=cd=40;33;01:*.jpg=01;35:*.mp3=00;36:
+    MAIL=/var/mail/root
+    PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin
+    PWD=/home/test
+    SHELL=/bin/bash
+    SHLVL=0
+    SUDO_COMMAND=/usr/bin/env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+    _=/usr/bin/sudo
+> sudo --preserve-env=FOO env
+    FOO=BAR
+    HOME=/root
+    HOSTNAME=test-ubuntu
+    LANG=en_US.UTF-8
+    LANGUAGE=en_US.UTF-8
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    LS_COLORS=cd=40;33;01:*.jpg=01;35:*.mp3=00;36:
+    MAIL=/var/mail/root
+    PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin
+    SHELL=/bin/bash
+    SUDO_COMMAND=/usr/bin/env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+> sudo -i env
+    HOME=/root
+    HOSTNAME=test-ubuntu
+    LANG=en_US.UTF-8
+    LANGUAGE=en_US.UTF-8
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    LS_COLORS=cd=40;33;01:*.jpg=01;35:*.mp3=00;36:
+    MAIL=/var/mail/root
+    PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin
+    SHELL=/bin/bash
+    SUDO_COMMAND=/bin/bash -c env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+> sudo -i -E env
+    HOME=/root
+    HOSTNAME=test-ubuntu
+    LANG=en_US.UTF-8
+    LANGUAGE=en_US.UTF-8
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    LS_COLORS=cd=40;33;01:*.jpg=01;35:*.mp3=00;36:
+    MAIL=/var/mail/root
+    PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin
+    SHELL=/bin/bash
+    SUDO_COMMAND=/bin/bash -c env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+> sudo -i --preserve-env=FOO env
+    FOO=BAR
+    HOME=/root
+    HOSTNAME=test-ubuntu
+    LANG=en_US.UTF-8
+    LANGUAGE=en_US.UTF-8
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    LS_COLORS=cd=40;33;01:*.jpg=01;35:*.mp3=00;36:
+    MAIL=/var/mail/root
+    PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin
+    SHELL=/bin/bash
+    SUDO_COMMAND=/bin/bash -c env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+> sudo -i --preserve-env=FOO,HOME,SHELL env
+    FOO=BAR
+    HOME=/root
+    HOSTNAME=test-ubuntu
+    LANG=en_US.UTF-8
+    LANGUAGE=en_US.UTF-8
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    LS_COLORS=cd=40;33;01:*.jpg=01;35:*.mp3=00;36:
+    MAIL=/var/mail/root
+    PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin
+    SHELL=/bin/bash
+    SUDO_COMMAND=/bin/bash -c env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+> sudo -u test -i -E --preserve-env=FOO env
+    FOO=BAR
+    HOME=/home/test
+    HOSTNAME=test-ubuntu
+    LANG=en_US.UTF-8
+    LANGUAGE=en_US.UTF-8
+    LC_ALL=en_US.UTF-8
+    LOGNAME=test
+    LS_COLORS=cd=40;33;01:*.jpg=01;35:*.mp3=00;36:
+    MAIL=/var/mail/test
+    PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin
+    SHELL=/bin/sh
+    SUDO_COMMAND=/bin/sh -c env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=test
+";
+
+/// Like [TESTS], with `Defaults !env_reset`: the environment is filtered instead of rebuilt
+const NO_ENV_RESET_TESTS: &str = "
+> env
+    BASH_ENV=/tmp/evil.sh
+    DBUS_SESSION_BUS_ADDRESS=unix:path=/run/user/1000/bus
+    FOO=BAR
+    HOME=/home/test
+    LANG=../../tmp/evil
+    LC_ALL=en_US.UTF-8
+    LD_PRELOAD=/tmp/evil.so
+    PATH=/usr/bin:/bin
+    PYTHONPATH=/tmp
+    PWD=/home/test
+    TERM=xterm
+    XDG_RUNTIME_DIR=/run/user/1000
+> sudo env
+    FOO=BAR
+    HOME=/root
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    MAIL=/var/mail/root
+    PATH=/usr/bin:/bin
+    PWD=/home/test
+    SHELL=/bin/bash
+    SUDO_COMMAND=/usr/bin/env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+> sudo -E env
+    FOO=BAR
+    HOME=/root
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    MAIL=/var/mail/root
+    PATH=/usr/bin:/bin
+    PWD=/home/test
+    SHELL=/bin/bash
+    SUDO_COMMAND=/usr/bin/env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
+> sudo --preserve-env=XDG_RUNTIME_DIR -u test env
+    FOO=BAR
+    HOME=/home/test
+    LC_ALL=en_US.UTF-8
+    LOGNAME=test
+    MAIL=/var/mail/test
+    PATH=/usr/bin:/bin
+    PWD=/home/test
+    SHELL=/bin/sh
+    SUDO_COMMAND=/usr/bin/env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=test
+    XDG_RUNTIME_DIR=/run/user/1000
+> sudo -i env
+    HOME=/root
+    LC_ALL=en_US.UTF-8
+    LOGNAME=root
+    MAIL=/var/mail/root
+    PATH=/usr/bin:/bin
+    SHELL=/bin/bash
+    SUDO_COMMAND=/bin/bash -c env
+    SUDO_GID=1000
+    SUDO_UID=1000
+    SUDO_USER=test
+    TERM=xterm
+    USER=root
 ";
 
 fn parse_env_commands(input: &str) -> Vec<(&str, Environment)> {
@@ -83,8 +291,6 @@ fn create_test_context(sudo_options: &SudoOptions) -> Context {
         .map(|v| v.as_str())
         .collect::<Vec<&str>>();
 
-    let command = CommandAndArguments::try_from(command_args).unwrap();
-
     let current_user = User {
         uid: 1000,
         gid: 1000,
@@ -121,24 +327,48 @@ fn create_test_context(sudo_options: &SudoOptions) -> Context {
         members: Vec::new(),
     };
 
+    let as_test = sudo_options.user == Some(TargetUser::Name("test".to_string()));
+    let target_user = if as_test {
+        current_user.clone()
+    } else {
+        root_user
+    };
+
+    let command = if sudo_options.login {
+        CommandAndArguments::shell(target_user.shell.clone().into(), command_args)
+    } else {
+        CommandAndArguments::try_from(command_args).unwrap()
+    };
+
     Context {
         hostname: "test-ubuntu".to_string(),
         command,
-        current_user: current_user.clone(),
-        target_user: if sudo_options.user.as_deref() == Some("test") {
-            current_user
-        } else {
-            root_user
-        },
-        target_group: if sudo_options.user.as_deref() == Some("test") {
-            current_group
-        } else {
-            root_group
-        },
+        current_user,
+        target_user,
+        target_group: if as_test { current_group } else { root_group },
         target_environment: Default::default(),
+        chdir: None,
+        chroot: None,
         preserve_env: sudo_options.preserve_env,
         set_home: sudo_options.set_home,
+        login: sudo_options.login,
+        background: sudo_options.background,
+        exit_with_parent: false,
+        noexec: false,
+        intercept: None,
+        umask: None,
+        capabilities: None,
+        sandbox: None,
+        timeout: None,
+        log_input: false,
+        log_output: false,
+        log_passwords: false,
+        iolog: None,
         preserve_env_list: sudo_options.preserve_env_list.clone(),
+        env_keep: KEEP_ENV_TABLE.iter().map(|v| v.to_string()).collect(),
+        env_check: CHECK_ENV_TABLE.iter().map(|v| v.to_string()).collect(),
+        env_delete: DELETE_ENV_TABLE.iter().map(|v| v.to_string()).collect(),
+        env_reset: true,
     }
 }
 
@@ -146,14 +376,15 @@ fn environment_to_set(environment: Environment) -> HashSet<String> {
     HashSet::from_iter(environment.iter().map(|(k, v)| format!("{}={}", k, v)))
 }
 
-#[test]
-fn test_environment_variable_filtering() {
-    let mut parts = parse_env_commands(TESTS);
+/// Check every command of `tests` against the initial environment that comes first
+fn check_environments(tests: &str, env_reset: bool) {
+    let mut parts = parse_env_commands(tests);
     let initial_env = parts.remove(0).1;
 
     for (cmd, expected_env) in parts {
         let options = SudoOptions::try_parse_from(cmd.split_whitespace()).unwrap();
-        let context = create_test_context(&options);
+        let mut context = create_test_context(&options);
+        context.env_reset = env_reset;
         let resulting_env = get_target_environment(initial_env.clone(), &context);
 
         let resulting_env = environment_to_set(resulting_env);
@@ -170,3 +401,59 @@ fn test_environment_variable_filtering() {
         );
     }
 }
+
+#[test]
+fn test_environment_variable_filtering() {
+    check_environments(TESTS, true);
+}
+
+#[test]
+fn test_environment_without_env_reset() {
+    check_environments(NO_ENV_RESET_TESTS, false);
+}
+
+#[test]
+fn test_preserving_variables_requires_permission() {
+    let initial_env = parse_env_commands(TESTS).remove(0).1;
+
+    let options = SudoOptions::try_parse_from(["sudo", "--preserve-env=FOO,TERM", "env"]).unwrap();
+    let context = create_test_context(&options);
+    assert_eq!(disallowed_variables(&initial_env, &context), vec!["FOO"]);
+
+    let options = SudoOptions::try_parse_from(["sudo", "--preserve-env=LANG", "env"]).unwrap();
+    let mut context = create_test_context(&options);
+    assert!(disallowed_variables(&initial_env, &context).is_empty());
+
+    // without env_reset, only what env_delete (and env_check) would remove needs permission
+    context.env_reset = false;
+    context.preserve_env_list = vec!["FOO".into(), "XDG_RUNTIME_DIR".into()];
+    assert_eq!(
+        disallowed_variables(&initial_env, &context),
+        vec!["XDG_RUNTIME_DIR"]
+    );
+}
+
+#[test]
+fn session_bus_variables() {
+    let initial_env = parse_env_commands(TESTS).remove(0).1;
+    let bus = |args: &[&str], adjust: fn(&mut Context)| {
+        let options = SudoOptions::try_parse_from(args.iter().copied()).unwrap();
+        let mut context = create_test_context(&options);
+        adjust(&mut context);
+        let env = get_target_environment(initial_env.clone(), &context);
+        ["XDG_RUNTIME_DIR", "DBUS_SESSION_BUS_ADDRESS"].map(|key| env.contains_key(key))
+    };
+
+    assert_eq!(bus(&["sudo", "env"], |_| {}), [false, false]);
+    assert_eq!(bus(&["sudo", "-E", "env"], |_| {}), [false, false]);
+    let preserve = "--preserve-env=XDG_RUNTIME_DIR,DBUS_SESSION_BUS_ADDRESS";
+    assert_eq!(bus(&["sudo", preserve, "env"], |_| {}), [true, true]);
+    // Defaults env_keep += "XDG_RUNTIME_DIR"
+    let keep = |context: &mut Context| context.env_keep.push("XDG_RUNTIME_DIR".into());
+    assert_eq!(bus(&["sudo", "env"], keep), [true, false]);
+    // Defaults env_delete -= "XDG_RUNTIME_DIR"
+    let undelete =
+        |context: &mut Context| context.env_delete.retain(|key| key != "XDG_RUNTIME_DIR");
+    assert_eq!(bus(&["sudo", "-E", "env"], undelete), [true, false]);
+    assert_eq!(bus(&["sudo", "env"], undelete), [false, false]);
+}
@@ -0,0 +1,836 @@
+//! Tests that fork: a forked child of a process with several threads may only make
+//! async-signal-safe calls, so these run in a process of their own, one after the other, without
+//! the test harness.
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::fs::PermissionsExt,
+    },
+    thread,
+    time::Duration,
+};
+
+use sudo_common::{
+    context::{CommandAndArguments, Context},
+    env::{get_target_environment, Environment},
+    exec::{exec, ExitReason},
+    iolog::{self, Ownership, Session, SessionInfo, Stream},
+};
+use sudo_system::{Group, User};
+
+/// A context for running `command` as the current user
+fn context(command: &[&str]) -> Context {
+    let user = User::real().unwrap().unwrap();
+    let group = Group::real().unwrap().unwrap();
+    Context {
+        hostname: "test".to_string(),
+        command: CommandAndArguments::try_from(command.to_vec()).unwrap(),
+        current_user: user.clone(),
+        target_user: user,
+        target_group: group,
+        target_environment: Default::default(),
+        chdir: None,
+        chroot: None,
+        preserve_env: false,
+        set_home: false,
+        login: false,
+        background: false,
+        exit_with_parent: false,
+        noexec: false,
+        intercept: None,
+        umask: None,
+        capabilities: None,
+        sandbox: None,
+        timeout: None,
+        log_input: false,
+        log_output: false,
+        log_passwords: false,
+        iolog: None,
+        preserve_env_list: Vec::new(),
+        env_keep: Vec::new(),
+        env_check: Vec::new(),
+        env_delete: Vec::new(),
+        env_reset: true,
+    }
+}
+
+/// Wait (for at most five seconds) until the file at `path` exists, and return what it holds
+fn wait_for_file(path: &std::path::Path) -> Option<String> {
+    (0..500).find_map(|_| {
+        let text = fs::read_to_string(path).ok();
+        if text.is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        text
+    })
+}
+
+/// Wait for a child process (such as a forked stand-in for sudo) to end
+fn wait(pid: libc::pid_t) -> ExitReason {
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    ExitReason::from_wait_status(status)
+}
+
+/// sudo reports how the command ended: with an exit status, or terminated by a signal
+fn commands_report_how_they_ended() {
+    for (script, expected) in [
+        ("exit 0", ExitReason::Code(0)),
+        ("exit 42", ExitReason::Code(42)),
+        ("kill -TERM $$", ExitReason::Signal(libc::SIGTERM)),
+        ("kill -KILL $$", ExitReason::Signal(libc::SIGKILL)),
+    ] {
+        let reason = exec(context(&["/bin/sh", "-c", script])).unwrap();
+        assert_eq!(reason, expected, "{script}");
+        assert_eq!(reason.success(), script == "exit 0");
+
+        // and ends the same way itself
+        let sudo = unsafe { libc::fork() };
+        if sudo == 0 {
+            reason.exit();
+        }
+        assert_eq!(wait(sudo), expected, "{script}");
+    }
+}
+
+/// Start a stand-in for sudo that runs `script` with `/bin/sh`, and ends like the command did;
+/// this returns once the command has started, with the process ID of the stand-in
+fn start_sudo(dir: &std::path::Path, script: &str) -> libc::pid_t {
+    let pid_file = dir.join("pid");
+    let script = format!(
+        "echo $$ > {0}.tmp; mv {0}.tmp {0}; {script}",
+        pid_file.display()
+    );
+    let context = context(&["/bin/sh", "-c", &script]);
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        // like a shell starts a job, so the signals of the test come from another process group
+        unsafe { libc::setpgid(0, 0) };
+        exec(context).unwrap().exit();
+    }
+    wait_for_file(&pid_file).expect("the command did not start");
+    sudo
+}
+
+/// The signals that would terminate sudo are relayed to the command instead
+fn signals_reach_the_command() {
+    for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGUSR1] {
+        let dir = tempfile::tempdir().unwrap();
+        let sudo = start_sudo(dir.path(), "exec /bin/sleep 10");
+        unsafe { libc::kill(sudo, signal) };
+        assert_eq!(wait(sudo), ExitReason::Signal(signal));
+    }
+}
+
+/// A storm of signals does not disturb sudo while it waits for the command
+fn signal_storms_pass_over() {
+    let dir = tempfile::tempdir().unwrap();
+    let done = dir.path().join("done");
+    let sudo = start_sudo(
+        dir.path(),
+        &format!(
+            "trap '' USR1 USR2; while [ ! -e {} ]; do /bin/sleep 0.01; done; exit 7",
+            done.display()
+        ),
+    );
+    for _ in 0..1000 {
+        unsafe { libc::kill(sudo, libc::SIGUSR1) };
+        unsafe { libc::kill(sudo, libc::SIGUSR2) };
+    }
+    fs::write(done, "").unwrap();
+    assert_eq!(wait(sudo), ExitReason::Code(7));
+}
+
+/// A signal that the command has already is not relayed to it again: one that comes from the
+/// process group of the command (which sudo is in as well), such as the `SIGINT` that a shell
+/// sends to the whole job
+fn signals_are_relayed_once() {
+    let dir = tempfile::tempdir().unwrap();
+    let hits = dir.path().join("hits");
+    let sudo = start_sudo(
+        dir.path(),
+        &format!(
+            "trap 'echo >> {}' USR1; kill -USR1 0; /bin/sleep 0.3; kill -USR1 $PPID; \
+             /bin/sleep 0.3; exit 5",
+            hits.display()
+        ),
+    );
+    assert_eq!(wait(sudo), ExitReason::Code(5));
+    assert_eq!(fs::read_to_string(hits).unwrap(), "\n");
+}
+
+/// The supervisor cleans up after itself: sudo can run many commands, one after the other
+fn many_commands_in_a_row() {
+    for _ in 0..200 {
+        assert_eq!(exec(context(&["/bin/true"])).unwrap(), ExitReason::Code(0));
+    }
+}
+
+/// A command that runs for too long is terminated, and killed if it does not end then
+fn commands_time_out() {
+    for (script, expected, grace) in [
+        ("exec /bin/sleep 10", libc::SIGTERM, false),
+        ("trap '' TERM; /bin/sleep 10", libc::SIGKILL, true),
+    ] {
+        let mut context = context(&["/bin/sh", "-c", script]);
+        context.timeout = Some(Duration::from_millis(200));
+        let start = std::time::Instant::now();
+        assert_eq!(exec(context).unwrap(), ExitReason::Signal(expected));
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_millis(200),
+            "{script}: {elapsed:?}"
+        );
+        assert_eq!(
+            elapsed >= Duration::from_secs(2),
+            grace,
+            "{script}: {elapsed:?}"
+        );
+        assert!(elapsed < Duration::from_secs(5), "{script}: {elapsed:?}");
+    }
+}
+
+/// Auxiliary processes do not remain zombies while the command runs, whether sudo started them or
+/// not, nor do they get in the way of the status of the command
+fn auxiliary_processes_are_reaped() {
+    let dir = tempfile::tempdir().unwrap();
+    let (pids, done) = (dir.path().join("pids"), dir.path().join("done"));
+    let script = format!(
+        "while [ ! -e {} ]; do /bin/sleep 0.01; done; exit 5",
+        done.display()
+    );
+    let context = context(&["/bin/sh", "-c", &script]);
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        // a helper that sudo does not wait for, which ends while the command runs, and a process
+        // that sudo did not start (like one left behind by a PAM module), which ends right away
+        let helper = unsafe { libc::fork() };
+        if helper == 0 {
+            unsafe {
+                libc::usleep(200_000);
+                libc::_exit(0)
+            };
+        }
+        let stray = unsafe { libc::fork() };
+        if stray == 0 {
+            unsafe { libc::_exit(0) };
+        }
+        fs::write(&pids, format!("{helper} {stray}")).unwrap();
+        exec(context).unwrap().exit();
+    }
+
+    let pids = wait_for_file(&pids).unwrap();
+    // a zombie still exists
+    let reaped = pids
+        .split(' ')
+        .map(|pid| pid.parse().unwrap())
+        .filter(|&pid| {
+            (0..500).any(|_| {
+                thread::sleep(Duration::from_millis(10));
+                let exists = unsafe { libc::kill(pid, 0) } == 0;
+                !exists
+            })
+        })
+        .count();
+    fs::write(done, "").unwrap();
+    assert_eq!(wait(sudo), ExitReason::Code(5));
+    assert_eq!(reaped, 2, "{pids}");
+}
+
+/// With an I/O log, the input and output of the command pass through sudo, which records them on
+/// the way: the command still gets its input, and its output still arrives where it would have
+fn input_and_output_are_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let (input, output) = (dir.path().join("input"), dir.path().join("output"));
+    let log = dir.path().join("sudo-io/00/00/01");
+    fs::write(&input, "hunter2\n").unwrap();
+    let info = SessionInfo {
+        time: 1700000000,
+        user: "test".to_string(),
+        runas_user: "root".to_string(),
+        runas_group: "root".to_string(),
+        tty: "unknown".to_string(),
+        cwd: "/".to_string(),
+        command: "/bin/sh".to_string(),
+    };
+    let ownership = Ownership {
+        uid: User::effective_uid(),
+        gid: Group::effective_gid(),
+        mode: 0o600,
+    };
+    let script = "read line; echo \"got $line\"; echo oops >&2; exit 3";
+    let mut context = context(&["/bin/sh", "-c", script]);
+    (context.log_input, context.log_output) = (true, true);
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        // the stand-in for sudo reads its input from a file, and writes its output to another
+        let stdin = fs::File::open(&input).unwrap();
+        let stdout = fs::File::create(&output).unwrap();
+        unsafe {
+            libc::dup2(stdin.as_raw_fd(), 0);
+            libc::dup2(stdout.as_raw_fd(), 1);
+            libc::dup2(stdout.as_raw_fd(), 2);
+        }
+        context.iolog = Some(Session::create(&log, &info, &ownership).unwrap());
+        exec(context).unwrap().exit();
+    }
+
+    assert_eq!(wait(sudo), ExitReason::Code(3));
+    assert_eq!(fs::read_to_string(output).unwrap(), "got hunter2\noops\n");
+    let read = |name: &str| fs::read_to_string(log.join(name)).unwrap();
+    assert_eq!(SessionInfo::parse(&read("log")).unwrap(), info);
+    assert_eq!(read("stdin"), "hunter2\n");
+    assert_eq!(read("stdout"), "got hunter2\n");
+    assert_eq!(read("stderr"), "oops\n");
+    let timing = iolog::parse_timing(&read("timing")).unwrap();
+    assert_eq!(
+        timing
+            .iter()
+            .map(|entry| (entry.stream, entry.size))
+            .collect::<Vec<_>>(),
+        [
+            (Stream::Stdin, 8),
+            (Stream::Stdout, 12),
+            (Stream::Stderr, 5)
+        ]
+    );
+}
+
+/// A command that gets more input than fits in a pipe, and outputs all of it again, does not get
+/// stuck with sudo: while the command waits for its output to be read, sudo reads it instead of
+/// waiting to pass on more input
+fn large_streams_are_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    let (input, log) = (
+        dir.path().join("input"),
+        dir.path().join("sudo-io/00/00/01"),
+    );
+    let data = (0..100_000)
+        .map(|line| format!("{line}\n"))
+        .collect::<String>();
+    assert!(data.len() > 4 * 65536);
+    fs::write(&input, &data).unwrap();
+    let info = SessionInfo {
+        time: 1700000000,
+        user: "test".to_string(),
+        runas_user: "root".to_string(),
+        runas_group: "root".to_string(),
+        tty: "unknown".to_string(),
+        cwd: "/".to_string(),
+        command: "/bin/cat".to_string(),
+    };
+    let ownership = Ownership {
+        uid: User::effective_uid(),
+        gid: Group::effective_gid(),
+        mode: 0o600,
+    };
+    let mut context = context(&["/bin/cat"]);
+    (context.log_input, context.log_output) = (true, true);
+    let mut output = [0; 2];
+    assert_eq!(unsafe { libc::pipe(output.as_mut_ptr()) }, 0);
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        // the output of the stand-in for sudo is a pipe as well, which is read slower than the
+        // command writes
+        let stdin = fs::File::open(&input).unwrap();
+        unsafe {
+            libc::dup2(stdin.as_raw_fd(), 0);
+            libc::dup2(output[1], 1);
+            libc::close(output[0]);
+            libc::close(output[1]);
+        }
+        context.iolog = Some(Session::create(&log, &info, &ownership).unwrap());
+        exec(context).unwrap().exit();
+    }
+    unsafe { libc::close(output[1]) };
+    let mut shown = unsafe { fs::File::from_raw_fd(output[0]) };
+    thread::sleep(Duration::from_millis(200));
+    let mut received = Vec::new();
+    loop {
+        // sudo would stop writing if it got stuck
+        let mut ready = libc::pollfd {
+            fd: output[0],
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        if unsafe { libc::poll(&mut ready, 1, 5000) } != 1 {
+            break;
+        }
+        let mut buffer = [0; 65536];
+        match shown.read(&mut buffer).unwrap() {
+            0 => break,
+            len => received.extend_from_slice(&buffer[..len]),
+        }
+    }
+    let received = String::from_utf8(received).unwrap();
+
+    // sudo would not end if it got stuck
+    let mut status = 0;
+    let ended = (0..500).any(|_| {
+        thread::sleep(Duration::from_millis(10));
+        unsafe { libc::waitpid(sudo, &mut status, libc::WNOHANG) == sudo }
+    });
+    if !ended {
+        unsafe { libc::kill(sudo, libc::SIGKILL) };
+        wait(sudo);
+    }
+    assert!(ended, "sudo got stuck after {} bytes", received.len());
+    assert_eq!(ExitReason::from_wait_status(status), ExitReason::Code(0));
+    assert!(
+        received == data,
+        "{} of {} bytes",
+        received.len(),
+        data.len()
+    );
+    for name in ["stdin", "stdout"] {
+        assert!(
+            fs::read_to_string(log.join(name)).unwrap() == data,
+            "{name}"
+        );
+    }
+}
+
+/// Input that follows a password prompt is masked in the I/O log, unless `log_passwords` is set;
+/// the output passes through sudo to show the prompt, even though it is not recorded
+fn passwords_are_not_recorded() {
+    for (log_passwords, logged) in [(false, "*******\n"), (true, "hunter2\n")] {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("sudo-io/00/00/01");
+        let info = SessionInfo {
+            time: 1700000000,
+            user: "test".to_string(),
+            runas_user: "root".to_string(),
+            runas_group: "root".to_string(),
+            tty: "unknown".to_string(),
+            cwd: "/".to_string(),
+            command: "/bin/sh".to_string(),
+        };
+        let ownership = Ownership {
+            uid: User::effective_uid(),
+            gid: Group::effective_gid(),
+            mode: 0o600,
+        };
+        let script = "printf 'Password: '; read password; echo \"got $password\"";
+        let mut context = context(&["/bin/sh", "-c", script]);
+        context.log_input = true;
+        context.log_passwords = log_passwords;
+        let (mut input, mut output) = ([0; 2], [0; 2]);
+        assert_eq!(unsafe { libc::pipe(input.as_mut_ptr()) }, 0);
+        assert_eq!(unsafe { libc::pipe(output.as_mut_ptr()) }, 0);
+        let sudo = unsafe { libc::fork() };
+        if sudo == 0 {
+            // the stand-in for sudo is typed into, and shows its output, through pipes
+            unsafe {
+                libc::dup2(input[0], 0);
+                libc::dup2(output[1], 1);
+                libc::dup2(output[1], 2);
+                for fd in input.into_iter().chain(output) {
+                    libc::close(fd);
+                }
+            }
+            context.iolog = Some(Session::create(&log, &info, &ownership).unwrap());
+            exec(context).unwrap().exit();
+        }
+        unsafe {
+            libc::close(input[0]);
+            libc::close(output[1]);
+        }
+        let mut terminal = unsafe { fs::File::from_raw_fd(output[0]) };
+        let mut typed = unsafe { fs::File::from_raw_fd(input[1]) };
+
+        // the password is typed when the prompt is shown, as it would be by the user
+        let mut shown = Vec::new();
+        while !shown.ends_with(b"Password: ") {
+            let mut buffer = [0; 64];
+            let len = terminal.read(&mut buffer).unwrap();
+            assert_ne!(len, 0, "{}", String::from_utf8_lossy(&shown));
+            shown.extend_from_slice(&buffer[..len]);
+        }
+        typed.write_all(b"hunter2\n").unwrap();
+        drop(typed);
+        terminal.read_to_end(&mut shown).unwrap();
+
+        assert_eq!(wait(sudo), ExitReason::Code(0));
+        assert_eq!(String::from_utf8(shown).unwrap(), "Password: got hunter2\n");
+        assert_eq!(fs::read_to_string(log.join("stdin")).unwrap(), logged);
+        assert!(!log.join("stdout").exists());
+    }
+}
+
+/// A command in the background gets the variables that are kept, like any other command, even
+/// though sudo does not wait for it
+fn background_commands_get_their_environment() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = dir.path().join("output");
+    let script = format!("echo \"$FOO\" > {0}.tmp; mv {0}.tmp {0}", output.display());
+    let mut context = context(&["/bin/sh", "-c", &script]);
+    context.background = true;
+    context.exit_with_parent = true;
+    context.env_keep = vec!["FOO".to_string()];
+    let mut current: Environment = env::vars().collect();
+    current.insert("FOO".to_string(), "bar".to_string());
+    context.target_environment = get_target_environment(current, &context);
+
+    assert!(exec(context).unwrap().success());
+    let text = wait_for_file(&output).expect("the command in the background did not run");
+    assert_eq!(text, "bar\n");
+}
+
+/// Commands get the supplementary groups of the target user, instead of those of sudo
+fn commands_get_the_groups_of_the_target_user() {
+    if User::effective_uid() != 0 {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+    let output = dir.path().join("groups");
+    let script = format!("/usr/bin/id -G > {}", output.display());
+    let target = User::from_name("nobody").unwrap().unwrap().with_groups();
+    let mut context = context(&["/bin/sh", "-c", &script]);
+    context.target_user = target.clone();
+    assert!(exec(context).unwrap().success());
+
+    let mut received = fs::read_to_string(&output)
+        .unwrap()
+        .split_whitespace()
+        .map(|gid| gid.parse().unwrap())
+        .collect::<Vec<libc::gid_t>>();
+    let mut expected = target.groups.unwrap();
+    expected.push(target.gid);
+    for groups in [&mut received, &mut expected] {
+        groups.sort();
+        groups.dedup();
+    }
+    assert_eq!(received, expected);
+}
+
+/// A command for which capabilities are restricted gets the retained ones, even when it does not
+/// run as root, and can not gain any others
+#[cfg(target_os = "linux")]
+fn commands_keep_only_retained_capabilities() {
+    use sudo_system::capabilities::CapabilitySet;
+
+    if User::effective_uid() != 0 {
+        return;
+    }
+    for (retain, expected) in [
+        (&["cap_net_raw"][..], "0000000000002000"),
+        (&[], "0000000000000000"),
+    ] {
+        let dir = tempfile::tempdir().unwrap();
+        fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        let output = dir.path().join("status");
+        let script = format!("grep ^Cap /proc/self/status > {}", output.display());
+        let mut context = context(&["/bin/sh", "-c", &script]);
+        context.target_user = User::from_name("nobody").unwrap().unwrap();
+        context.capabilities = Some(CapabilitySet::from_names(retain.iter().copied()).unwrap());
+        assert!(exec(context).unwrap().success());
+
+        let status = fs::read_to_string(&output).unwrap();
+        for set in ["CapEff", "CapBnd", "CapAmb"] {
+            assert!(
+                status.contains(&format!("{set}:\t{expected}\n")),
+                "{retain:?}: {status}"
+            );
+        }
+    }
+}
+
+/// A command in a sandbox can only write to the paths that are writable there, and is the init
+/// process of a PID namespace of its own
+#[cfg(target_os = "linux")]
+fn commands_run_in_a_sandbox() {
+    if User::effective_uid() != 0 {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let (writable, other) = (dir.path().join("writable"), dir.path().join("other"));
+    for path in [&writable, &other] {
+        fs::create_dir(path).unwrap();
+    }
+    let script = format!(
+        "echo $$ > {}/pid; touch {}/file",
+        writable.display(),
+        other.display()
+    );
+    let mut context = context(&["/bin/sh", "-c", &script]);
+    context.sandbox = Some(vec![writable.clone()]);
+
+    // a stand-in for sudo, which can only start a single command in a new PID namespace
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        exec(context)
+            .map_or(ExitReason::Code(2), |reason| reason)
+            .exit();
+    }
+    // the command failed to create the file
+    assert_eq!(wait(sudo), ExitReason::Code(1));
+    assert!(!other.join("file").exists());
+    assert_eq!(fs::read_to_string(writable.join("pid")).unwrap(), "1\n");
+}
+
+/// A command that runs as root in a sandbox can not make the file system writable again, since it
+/// has lost the capabilities to change mounts
+#[cfg(target_os = "linux")]
+fn sandboxes_can_not_be_undone() {
+    if User::effective_uid() != 0 {
+        return;
+    }
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("file");
+    let script = format!(
+        "for mount in / \"$(stat -c %m {0})\"; do \
+             mount -o remount,bind,rw \"$mount\" 2> /dev/null && exit 3; \
+         done; \
+         umount -l \"$(stat -c %m {0})\" 2> /dev/null && exit 4; \
+         touch {1}",
+        dir.path().display(),
+        file.display()
+    );
+    let mut context = context(&["/bin/sh", "-c", &script]);
+    context.sandbox = Some(Vec::new());
+
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        exec(context)
+            .map_or(ExitReason::Code(2), |reason| reason)
+            .exit();
+    }
+    assert_eq!(wait(sudo), ExitReason::Code(1));
+    assert!(!file.exists());
+}
+
+/// With `exit_with_parent`, a command is terminated when sudo is killed, unless it runs in the
+/// background
+#[cfg(target_os = "linux")]
+fn exit_with_parent() {
+    // the commands are reparented to the tests once sudo is gone, so they can be waited for
+    assert_eq!(unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) }, 0);
+    for background in [false, true] {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let script = format!(
+            "echo $$ > {0}.tmp; mv {0}.tmp {0}; exec /bin/sleep 10",
+            pid_file.display()
+        );
+        let mut context = context(&["/bin/sh", "-c", &script]);
+        context.exit_with_parent = true;
+        context.background = background;
+
+        // a stand-in for sudo, which starts the command and is killed
+        let sudo = unsafe { libc::fork() };
+        if sudo == 0 {
+            let _ = exec(context);
+            unsafe { libc::_exit(0) };
+        }
+        let pid = wait_for_file(&pid_file).expect("the command did not start");
+        let pid: libc::pid_t = pid.trim().parse().unwrap();
+        unsafe { libc::kill(sudo, libc::SIGKILL) };
+        wait(sudo);
+
+        if background {
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+        let expected = if background {
+            libc::SIGKILL
+        } else {
+            libc::SIGTERM
+        };
+        assert_eq!(
+            wait(pid),
+            ExitReason::Signal(expected),
+            "background: {background}"
+        );
+    }
+    assert_eq!(unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 0) }, 0);
+}
+
+#[cfg(feature = "privsep")]
+fn helpers_report_their_result() {
+    use sudo_common::exec::unprivileged;
+
+    let ids = unprivileged(|| (User::effective_uid(), Group::effective_gid())).unwrap();
+    assert_eq!(ids, (User::real_uid(), Group::real_gid()));
+    let words = unprivileged(|| vec!["hello".to_string(), "world".to_string()]).unwrap();
+    assert_eq!(words, ["hello", "world"]);
+}
+
+#[cfg(feature = "privsep")]
+fn helpers_can_fail() {
+    use sudo_common::exec::unprivileged;
+
+    // the helper would report its panic on stderr as well
+    std::panic::set_hook(Box::new(|_| {}));
+    let err = unprivileged(|| -> u8 { panic!("no sudoers") }).unwrap_err();
+    let _ = std::panic::take_hook();
+    assert_eq!(
+        err.to_string(),
+        "privilege separation: the helper failed: no sudoers"
+    );
+    let err = unprivileged::<()>(|| unsafe { libc::_exit(3) }).unwrap_err();
+    assert!(err.to_string().contains("invalid reply"), "{err}");
+}
+
+/// Like sudo, a process that runs as root for another user gets helpers that run as that user
+#[cfg(feature = "privsep")]
+fn helpers_drop_privileges() {
+    use sudo_common::exec::unprivileged;
+
+    if User::effective_uid() != 0 {
+        return;
+    }
+    let (nobody, nogroup) = (65534, 65534);
+    // a stand-in for sudo, which only has the effective ids of root
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        unsafe {
+            libc::setresgid(nogroup, 0, 0);
+            libc::setresuid(nobody, 0, 0);
+        }
+        let credentials = unprivileged(|| {
+            let mut groups = vec![0; 16];
+            let count = unsafe { libc::getgroups(16, groups.as_mut_ptr()) };
+            groups.truncate(count.max(0) as usize);
+            (User::effective_uid(), Group::effective_gid(), groups)
+        });
+        // the group of the sudoers files is the only one that is kept
+        let dropped = credentials.is_ok_and(|ids| ids == (nobody, nogroup, vec![0]));
+        unsafe { libc::_exit(if dropped { 0 } else { 1 }) };
+    }
+    assert_eq!(wait(sudo), ExitReason::Code(0));
+}
+
+/// The group provider runs as root, not as the invoking user, whatever the real ids of sudo are;
+/// helpers only get the answers that sudo asked for in advance
+#[cfg(target_os = "linux")]
+fn group_providers_run_as_root() {
+    use sudo_common::group_provider::{
+        ask_in_advance, in_nonunix_group, set_group_provider, External,
+    };
+
+    if User::effective_uid() != 0 {
+        return;
+    }
+    let dir = env::temp_dir().join(format!("sudo-common-provider-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (program, credentials) = (dir.join("provider"), dir.join("credentials"));
+    fs::write(
+        &program,
+        format!(
+            "#!/bin/sh\n{{ id -ru; id -u; id -rg; id -g; id -G; \
+             grep NoNewPrivs /proc/self/status; }} >> {}\n[ \"$2\" = admins ]\n",
+            credentials.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let (nobody, nogroup) = (65534, 65534);
+    // a stand-in for sudo, which only has the effective ids of root
+    let sudo = unsafe { libc::fork() };
+    if sudo == 0 {
+        unsafe {
+            libc::setgroups(1, &nogroup);
+            libc::setresgid(nogroup, 0, 0);
+            libc::setresuid(nobody, 0, 0);
+        }
+        set_group_provider(External::new(&program));
+        ask_in_advance("alice", &["admins".to_string()]);
+        #[cfg(feature = "privsep")]
+        {
+            let answers = sudo_common::exec::unprivileged(|| {
+                (
+                    in_nonunix_group("alice", "admins"),
+                    in_nonunix_group("alice", "users"),
+                )
+            });
+            assert_eq!(answers.unwrap(), (true, false));
+        }
+        let member = in_nonunix_group("alice", "admins");
+        unsafe { libc::_exit(if member { 0 } else { 1 }) };
+    }
+    assert_eq!(wait(sudo), ExitReason::Code(0));
+    // the program ran once, as root; the answer was remembered, and the helper did not run it
+    assert_eq!(
+        fs::read_to_string(&credentials).unwrap(),
+        "0\n0\n0\n0\n0\nNoNewPrivs:\t1\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// Users are looked up in helpers, and found the same way as by sudo itself
+#[cfg(feature = "privsep")]
+fn helpers_look_up_users() {
+    use sudo_common::exec::UnprivilegedDatabase;
+    use sudo_system::database::{Database, System};
+
+    let db = UnprivilegedDatabase;
+    let root = db.user_by_uid(0).unwrap().unwrap();
+    assert_eq!(Some(&root), System.user_by_name("root").unwrap().as_ref());
+    assert!(db.user_by_name("no-such-user").unwrap().is_none());
+    assert_eq!(db.group_by_gid(0).unwrap().unwrap().gid, 0);
+    assert_eq!(
+        db.with_groups(root.clone()).groups,
+        System.with_groups(root).groups
+    );
+}
+
+fn main() {
+    let tests: &[(&str, fn())] = &[
+        (
+            "background_commands_get_their_environment",
+            background_commands_get_their_environment,
+        ),
+        (
+            "commands_report_how_they_ended",
+            commands_report_how_they_ended,
+        ),
+        ("signals_reach_the_command", signals_reach_the_command),
+        ("signal_storms_pass_over", signal_storms_pass_over),
+        ("signals_are_relayed_once", signals_are_relayed_once),
+        ("many_commands_in_a_row", many_commands_in_a_row),
+        ("commands_time_out", commands_time_out),
+        (
+            "auxiliary_processes_are_reaped",
+            auxiliary_processes_are_reaped,
+        ),
+        (
+            "input_and_output_are_recorded",
+            input_and_output_are_recorded,
+        ),
+        ("large_streams_are_recorded", large_streams_are_recorded),
+        ("passwords_are_not_recorded", passwords_are_not_recorded),
+        (
+            "commands_get_the_groups_of_the_target_user",
+            commands_get_the_groups_of_the_target_user,
+        ),
+        #[cfg(target_os = "linux")]
+        (
+            "commands_keep_only_retained_capabilities",
+            commands_keep_only_retained_capabilities,
+        ),
+        #[cfg(target_os = "linux")]
+        ("commands_run_in_a_sandbox", commands_run_in_a_sandbox),
+        #[cfg(target_os = "linux")]
+        ("sandboxes_can_not_be_undone", sandboxes_can_not_be_undone),
+        #[cfg(target_os = "linux")]
+        ("exit_with_parent", exit_with_parent),
+        #[cfg(target_os = "linux")]
+        ("group_providers_run_as_root", group_providers_run_as_root),
+        #[cfg(feature = "privsep")]
+        ("helpers_report_their_result", helpers_report_their_result),
+        #[cfg(feature = "privsep")]
+        ("helpers_can_fail", helpers_can_fail),
+        #[cfg(feature = "privsep")]
+        ("helpers_drop_privileges", helpers_drop_privileges),
+        #[cfg(feature = "privsep")]
+        ("helpers_look_up_users", helpers_look_up_users),
+    ];
+    for (name, test) in tests {
+        test();
+        println!("test {name} ... ok");
+    }
+}
@@ -0,0 +1,124 @@
+//! Search the memory of the process itself (through /proc/self/mem) for a password while the
+//! authentication conversation handles it, and once it has been dropped. The passwords are kept
+//! bitwise inverted, so that the search does not find them in the test itself.
+
+use std::{
+    fs::{self, File},
+    hint::black_box,
+    io,
+    os::unix::fs::FileExt,
+    time::Duration,
+};
+
+use sudo_common::pam::{CliConverser, Conversation, Message};
+use sudo_system::secure_buffer::SecureBuffer;
+
+const CHUNK: usize = 1 << 16;
+
+/// Whether the inverse of `inverted` is anywhere in the private, writable memory of the process:
+/// the heap, the stacks, and anonymous mappings such as those of [SecureBuffer]
+fn in_memory(inverted: &[u8]) -> bool {
+    let mem = File::open("/proc/self/mem").unwrap();
+    let maps = fs::read_to_string("/proc/self/maps").unwrap();
+    let regions = maps
+        .lines()
+        .filter(|line| line.split_whitespace().nth(1) == Some("rw-p"))
+        .map(|line| {
+            let range = line.split_whitespace().next().unwrap();
+            let (start, end) = range.split_once('-').unwrap();
+            let address = |text| u64::from_str_radix(text, 16).unwrap();
+            (address(start), address(end))
+        })
+        .collect::<Vec<_>>();
+
+    // chunks overlap, so the password is found also where it crosses the end of a chunk
+    let mut chunk = vec![0; CHUNK];
+    let mut found = false;
+    for (start, end) in regions {
+        let mut offset = start;
+        while offset < end && !found {
+            let len = CHUNK.min((end - offset) as usize);
+            if let Ok(read) = mem.read_at(&mut chunk[..len], offset) {
+                found = chunk[..read].windows(inverted.len()).any(|window| {
+                    window
+                        .iter()
+                        .zip(inverted)
+                        .all(|(byte, inverse)| *byte == !*inverse)
+                });
+            }
+            offset += (CHUNK - inverted.len()) as u64;
+        }
+        // what has been read must not be found in the next search
+        chunk.fill(0);
+    }
+    found
+}
+
+/// Answers every prompt with a password, which is put in place one byte at a time
+struct Keyboard {
+    inverted: &'static [u8],
+}
+
+impl Conversation for Keyboard {
+    fn read_line(&mut self, _prompt: &str, _timeout: Option<Duration>) -> io::Result<String> {
+        let mut line = String::with_capacity(self.inverted.len());
+        for byte in black_box(self.inverted) {
+            line.push(char::from(!byte));
+        }
+        Ok(line)
+    }
+
+    fn read_password(
+        &mut self,
+        _prompt: &str,
+        _timeout: Option<Duration>,
+        _countdown: bool,
+    ) -> io::Result<SecureBuffer> {
+        let mut password = SecureBuffer::with_capacity(512);
+        for byte in black_box(self.inverted) {
+            password.push(!byte);
+        }
+        Ok(password)
+    }
+
+    fn show(&mut self, _text: &str) {}
+}
+
+fn inverse(text: &str) -> Vec<u8> {
+    text.bytes().map(|byte| !byte).collect()
+}
+
+/// Long enough that the search itself does not allocate memory of the same size, which would
+/// overwrite a copy that was left behind on the heap
+const RECOVERY_CODE: &str = "7f3a-91c2-44de-b801-2c6f-e93d-05ab-77c4-d1e8-3b92-6fa0-c457-\
+                             8e1d-29b6-f0c3-5a7e-13d9-b2c8";
+
+#[test]
+fn passwords_are_overwritten() {
+    for (message, password) in [
+        (
+            Message::EchoOff("Password: "),
+            "correct horse battery staple",
+        ),
+        (Message::EchoOn("Recovery code: "), RECOVERY_CODE),
+    ] {
+        let inverted = inverse(password).leak();
+        let mut converser = CliConverser {
+            timeout: None,
+            countdown: false,
+            prompt: None,
+            override_prompt: false,
+            auth_user: "root".to_string(),
+            conversation: Keyboard { inverted },
+        };
+        assert!(!in_memory(inverted));
+
+        let answer = converser.handle(message).unwrap().unwrap();
+        assert_eq!(answer.len(), password.len());
+        assert!(in_memory(inverted), "{message:?} is not in memory");
+        drop(answer);
+        // malloc reuses the start of freed memory, which would hide a copy on the heap
+        let tail = &inverted[inverted.len() - 12..];
+        assert!(!in_memory(tail), "{message:?} is still in memory");
+    }
+}
@@ -0,0 +1,415 @@
+//! A library that is preloaded (using `LD_PRELOAD`) into commands that are tagged NOEXEC or
+//! INTERCEPT, which replaces the functions that execute programs. For NOEXEC, the replacements
+//! always fail with `EACCES`, which keeps commands like editors and pagers from running a shell.
+//!
+//! For INTERCEPT, sudo passes a socket in `SUDO_INTERCEPT_FD`, and the replacements first ask
+//! sudo whether sudoers allows the program (see the `intercept` module of sudo-common for the
+//! protocol). A program that is allowed runs with the library preloaded and the socket in its
+//! environment as well, whatever environment the command gives it. fexecve, execveat and the
+//! variadic execl family do not give a path and arguments that can be checked, so they fail as
+//! for NOEXEC.
+//!
+//! Like the `sudo_noexec.so` of the original sudo this only works for dynamically linked programs,
+//! and cannot stop a program that makes the system calls itself.
+
+use std::{
+    ffi::{CStr, CString, OsStr},
+    io::Read,
+    mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::{ffi::OsStrExt, net::UnixStream},
+    },
+    ptr,
+    sync::OnceLock,
+};
+
+use libc::{c_char, c_int, pid_t, FILE};
+
+/// The variable with the socket to ask sudo on (`sudo_common::intercept::FD_ENV`)
+const FD_ENV: &CStr = c"SUDO_INTERCEPT_FD";
+const PRELOAD_ENV: &CStr = c"LD_PRELOAD";
+
+/// The search path of execvp when `PATH` is not set
+const DEFAULT_PATH: &[u8] = b"/bin:/usr/bin";
+
+extern "C" {
+    static environ: *const *const c_char;
+}
+
+/// What an intercepted command started with; the command can not change it afterwards
+struct Intercept {
+    socket: c_int,
+    /// `LD_PRELOAD=...` and `SUDO_INTERCEPT_FD=...`, for the programs that are allowed
+    environment: [CString; 2],
+}
+
+static INTERCEPT: OnceLock<Option<Intercept>> = OnceLock::new();
+
+/// Runs when the library is loaded, before the program itself
+#[used]
+#[link_section = ".init_array"]
+static INIT: extern "C" fn() = init;
+
+extern "C" fn init() {
+    INTERCEPT.get_or_init(|| {
+        let variable = |name: &CStr| {
+            // SAFETY: getenv returns null or a string
+            let value = unsafe { libc::getenv(name.as_ptr()) };
+            (!value.is_null()).then(|| unsafe { CStr::from_ptr(value) })
+        };
+        let socket = variable(FD_ENV)?;
+        let entry = |name: &CStr, value: &CStr| {
+            CString::new([name.to_bytes(), b"=", value.to_bytes()].concat()).ok()
+        };
+        Some(Intercept {
+            socket: socket.to_str().ok()?.parse().ok()?,
+            environment: [
+                entry(PRELOAD_ENV, variable(PRELOAD_ENV)?)?,
+                entry(FD_ENV, socket)?,
+            ],
+        })
+    });
+}
+
+fn set_errno(error: c_int) {
+    // SAFETY: errno is thread-local
+    #[cfg(target_os = "linux")]
+    unsafe {
+        *libc::__errno_location() = error
+    };
+    // SAFETY: errno is thread-local
+    #[cfg(target_os = "freebsd")]
+    unsafe {
+        *libc::__error() = error
+    };
+}
+
+/// Fail with `EACCES`, as the kernel would for a file that is not executable
+fn deny() -> c_int {
+    set_errno(libc::EACCES);
+    -1
+}
+
+/// The function with this name in the library after this one (the C library)
+unsafe fn next<F: Copy>(name: &CStr) -> Option<F> {
+    let symbol = libc::dlsym(libc::RTLD_NEXT, name.as_ptr());
+    (!symbol.is_null()).then(|| mem::transmute_copy(&symbol))
+}
+
+/// The strings of a null-terminated array, such as `argv`
+unsafe fn strings<'a>(mut array: *const *const c_char) -> Vec<&'a CStr> {
+    let mut strings = Vec::new();
+    while !array.is_null() && !(*array).is_null() {
+        strings.push(CStr::from_ptr(*array));
+        array = array.add(1);
+    }
+    strings
+}
+
+/// Ask sudo whether the program at `path` may run with `argv`; anything going wrong means no
+fn ask(socket: c_int, path: &CStr, argv: &[&CStr]) -> bool {
+    // sudo does not know the working directory of the command
+    let Ok(path) = std::path::absolute(OsStr::from_bytes(path.to_bytes())) else {
+        return false;
+    };
+    let mut request = path.as_os_str().as_bytes().to_vec();
+    request.push(0);
+    for arg in argv {
+        request.extend_from_slice(arg.to_bytes_with_nul());
+    }
+
+    let mut pair = [0; 2];
+    let kind = libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC;
+    if unsafe { libc::socketpair(libc::AF_UNIX, kind, 0, pair.as_mut_ptr()) } == -1 {
+        return false;
+    }
+    // SAFETY: socketpair returned two new file descriptors
+    let (answer, reply) = unsafe { (OwnedFd::from_raw_fd(pair[0]), OwnedFd::from_raw_fd(pair[1])) };
+    if !send_with_fd(socket, &request, reply.as_raw_fd()) {
+        return false;
+    }
+    // sudo has the only other copy of the reply socket now
+    drop(reply);
+    let mut byte = [0];
+    UnixStream::from(answer).read_exact(&mut byte).is_ok() && byte == [1]
+}
+
+/// Send `message` with the file descriptor `fd` attached
+fn send_with_fd(socket: c_int, message: &[u8], fd: c_int) -> bool {
+    // SAFETY: CMSG_SPACE and CMSG_LEN only compute sizes
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<c_int>() as u32) } as usize;
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+    let mut iov = libc::iovec {
+        iov_base: message.as_ptr() as *mut _,
+        iov_len: message.len(),
+    };
+    // SAFETY: all fields of msghdr are integers or pointers, for which zero is valid
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+    // SAFETY: the control buffer has room for one message with a file descriptor
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<c_int>() as u32) as _;
+        ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<c_int>(), fd);
+    }
+    let sent = unsafe { libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) };
+    sent == message.len() as isize
+}
+
+/// The environment of a program that is allowed to run: `envp`, with the variables that keep
+/// the program intercepted replaced by those that the command started with
+unsafe fn environment(
+    intercept: &'static Intercept,
+    envp: *const *const c_char,
+) -> Vec<*const c_char> {
+    let replaced = |entry: &&CStr| {
+        let entry = entry.to_bytes();
+        [PRELOAD_ENV, FD_ENV].iter().any(|name| {
+            let name = name.to_bytes();
+            entry.starts_with(name) && entry.get(name.len()) == Some(&b'=')
+        })
+    };
+    let mut pointers = strings(envp)
+        .into_iter()
+        .filter(|entry| !replaced(entry))
+        .map(CStr::as_ptr)
+        .collect::<Vec<_>>();
+    pointers.extend(intercept.environment.iter().map(|entry| entry.as_ptr()));
+    pointers.push(ptr::null());
+    pointers
+}
+
+/// For an intercepted command, ask sudo whether the program at `path` may run, and if so, return
+/// the environment to run it with; otherwise, the error to fail with
+unsafe fn permit(
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> Result<Vec<*const c_char>, c_int> {
+    let Some(intercept) = INTERCEPT.get().and_then(Option::as_ref) else {
+        return Err(libc::EACCES);
+    };
+    if path.is_null() {
+        return Err(libc::EFAULT);
+    }
+    let path = CStr::from_ptr(path);
+    // a program that does not exist is not worth asking about (shells try every directory in
+    // PATH in turn)
+    if libc::access(path.as_ptr(), libc::F_OK) != 0 {
+        return Err(libc::ENOENT);
+    }
+    if ask(intercept.socket, path, &strings(argv)) {
+        Ok(environment(intercept, envp))
+    } else {
+        Err(libc::EACCES)
+    }
+}
+
+/// Find a program in `PATH` like execvp does, for an intercepted command
+unsafe fn resolve(file: *const c_char) -> Option<CString> {
+    if file.is_null() || INTERCEPT.get()?.is_none() {
+        return None;
+    }
+    let file = CStr::from_ptr(file).to_bytes();
+    if file.is_empty() || file.contains(&b'/') {
+        return CString::new(file).ok();
+    }
+    let path = libc::getenv(c"PATH".as_ptr());
+    let path = if path.is_null() {
+        DEFAULT_PATH
+    } else {
+        CStr::from_ptr(path).to_bytes()
+    };
+    path.split(|&c| c == b':')
+        .map(|dir| if dir.is_empty() { &b"."[..] } else { dir })
+        .filter_map(|dir| CString::new([dir, b"/", file].concat()).ok())
+        .find(|candidate| libc::access(candidate.as_ptr(), libc::X_OK) == 0)
+}
+
+type Execve =
+    unsafe extern "C" fn(*const c_char, *const *const c_char, *const *const c_char) -> c_int;
+type PosixSpawn = unsafe extern "C" fn(
+    *mut pid_t,
+    *const c_char,
+    *const libc::posix_spawn_file_actions_t,
+    *const libc::posix_spawnattr_t,
+    *const *mut c_char,
+    *const *mut c_char,
+) -> c_int;
+
+/// # Safety
+/// The arguments have to be valid for execve(2)
+#[no_mangle]
+pub unsafe extern "C" fn execve(
+    path: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    match (permit(path, argv, envp), next::<Execve>(c"execve")) {
+        (Ok(environment), Some(execve)) => execve(path, argv, environment.as_ptr()),
+        (Err(error), _) => {
+            set_errno(error);
+            -1
+        }
+        (_, None) => deny(),
+    }
+}
+
+/// # Safety
+/// The arguments have to be valid for execv(3)
+#[no_mangle]
+pub unsafe extern "C" fn execv(path: *const c_char, argv: *const *const c_char) -> c_int {
+    execve(path, argv, environ)
+}
+
+/// # Safety
+/// The arguments have to be valid for execvp(3)
+#[no_mangle]
+pub unsafe extern "C" fn execvp(file: *const c_char, argv: *const *const c_char) -> c_int {
+    execvpe(file, argv, environ)
+}
+
+/// # Safety
+/// The arguments have to be valid for execvpe(3)
+#[no_mangle]
+pub unsafe extern "C" fn execvpe(
+    file: *const c_char,
+    argv: *const *const c_char,
+    envp: *const *const c_char,
+) -> c_int {
+    if INTERCEPT.get().is_none_or(Option::is_none) {
+        return deny();
+    }
+    match resolve(file) {
+        Some(path) => execve(path.as_ptr(), argv, envp),
+        None => {
+            set_errno(libc::ENOENT);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn fexecve(
+    _fd: c_int,
+    _argv: *const *const c_char,
+    _envp: *const *const c_char,
+) -> c_int {
+    deny()
+}
+
+#[no_mangle]
+pub extern "C" fn execveat(
+    _dirfd: c_int,
+    _path: *const c_char,
+    _argv: *const *const c_char,
+    _envp: *const *const c_char,
+    _flags: c_int,
+) -> c_int {
+    deny()
+}
+
+// execl, execle and execlp are variadic; since their arguments are never read, they can be
+// replaced by functions that only declare the first ones
+#[no_mangle]
+pub extern "C" fn execl(_path: *const c_char, _arg: *const c_char) -> c_int {
+    deny()
+}
+
+#[no_mangle]
+pub extern "C" fn execle(_path: *const c_char, _arg: *const c_char) -> c_int {
+    deny()
+}
+
+#[no_mangle]
+pub extern "C" fn execlp(_file: *const c_char, _arg: *const c_char) -> c_int {
+    deny()
+}
+
+// posix_spawn reports errors through its return value instead of errno
+
+/// # Safety
+/// The arguments have to be valid for posix_spawn(3)
+#[no_mangle]
+pub unsafe extern "C" fn posix_spawn(
+    pid: *mut pid_t,
+    path: *const c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    let permitted = permit(path, argv.cast(), envp.cast());
+    match (permitted, next::<PosixSpawn>(c"posix_spawn")) {
+        (Ok(environment), Some(posix_spawn)) => posix_spawn(
+            pid,
+            path,
+            file_actions,
+            attrp,
+            argv,
+            environment.as_ptr().cast(),
+        ),
+        (Err(error), _) => error,
+        (_, None) => libc::EACCES,
+    }
+}
+
+/// # Safety
+/// The arguments have to be valid for posix_spawnp(3)
+#[no_mangle]
+pub unsafe extern "C" fn posix_spawnp(
+    pid: *mut pid_t,
+    file: *const c_char,
+    file_actions: *const libc::posix_spawn_file_actions_t,
+    attrp: *const libc::posix_spawnattr_t,
+    argv: *const *mut c_char,
+    envp: *const *mut c_char,
+) -> c_int {
+    if INTERCEPT.get().is_none_or(Option::is_none) {
+        return libc::EACCES;
+    }
+    match resolve(file) {
+        Some(path) => posix_spawn(pid, path.as_ptr(), file_actions, attrp, argv, envp),
+        None => libc::ENOENT,
+    }
+}
+
+// the C library implements these without going through the functions above; the shell that
+// they run is what is asked about
+
+/// Whether an intercepted command may run `command` with the shell
+unsafe fn shell_permitted(command: *const c_char) -> bool {
+    let argv = [c"sh".as_ptr(), c"-c".as_ptr(), command, ptr::null()];
+    !command.is_null() && permit(c"/bin/sh".as_ptr(), argv.as_ptr(), environ).is_ok()
+}
+
+/// # Safety
+/// The argument has to be valid for system(3)
+#[no_mangle]
+pub unsafe extern "C" fn system(command: *const c_char) -> c_int {
+    type System = unsafe extern "C" fn(*const c_char) -> c_int;
+    match next::<System>(c"system") {
+        Some(system) if shell_permitted(command) => system(command),
+        _ => deny(),
+    }
+}
+
+/// # Safety
+/// The arguments have to be valid for popen(3)
+#[no_mangle]
+pub unsafe extern "C" fn popen(command: *const c_char, mode: *const c_char) -> *mut FILE {
+    type Popen = unsafe extern "C" fn(*const c_char, *const c_char) -> *mut FILE;
+    match next::<Popen>(c"popen") {
+        Some(popen) if shell_permitted(command) => popen(command, mode),
+        _ => {
+            deny();
+            ptr::null_mut()
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    env,
+    os::{fd::AsRawFd, unix::process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    sync::{Arc, Mutex},
+};
+
+use sudo_common::intercept::{Listener, FD_ENV};
+use sudo_system::socket::set_inheritable;
+
+/// The library as cargo has built it for these tests, next to the test binary
+fn library() -> PathBuf {
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let library = deps.join("libsudo_noexec.so");
+    assert!(library.exists(), "{} is missing", library.display());
+    library
+}
+
+/// Runs `/bin/true` and reports whether that worked
+const SCRIPT: &str = "/bin/true && echo ran; echo done";
+
+fn stdout(output: Output) -> String {
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn noexec_commands_cannot_run_programs() {
+    let run = |preload: bool| {
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", SCRIPT]).env_remove(FD_ENV);
+        if preload {
+            command.env("LD_PRELOAD", library());
+        }
+        stdout(command.output().unwrap())
+    };
+    assert_eq!(run(false), "ran\ndone\n");
+    assert_eq!(run(true), "done\n");
+}
+
+#[test]
+fn intercepted_commands_ask_first() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    let (mut listener, socket) = Listener::new(Box::new(move |path: &Path, args: &[String]| {
+        recorded
+            .lock()
+            .unwrap()
+            .push((path.to_path_buf(), args.to_vec()));
+        path == Path::new("/bin/true") || path == Path::new("/bin/sh")
+    }))
+    .unwrap();
+
+    let fd = socket.as_raw_fd();
+    let mut command = Command::new("/bin/sh");
+    command
+        .args([
+            "-c",
+            &format!("{SCRIPT}; /bin/echo denied; /bin/sh -c /bin/true && echo nested"),
+        ])
+        .env("LD_PRELOAD", library())
+        .env(FD_ENV, fd.to_string())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    // SAFETY: the closure only performs an async-signal-safe system call
+    unsafe { command.pre_exec(move || set_inheritable(fd)) };
+    let child = command.spawn().unwrap();
+    // the listener sees the end of the requests once the command and its programs are done
+    drop(socket);
+    drop(command);
+    while listener.answer().unwrap() {}
+
+    assert_eq!(
+        stdout(child.wait_with_output().unwrap()),
+        "ran\ndone\nnested\n"
+    );
+    let requests = requests.lock().unwrap();
+    let asked: Vec<_> = requests
+        .iter()
+        .map(|(path, args)| (path.to_str().unwrap(), args.join(" ")))
+        .collect();
+    assert_eq!(
+        asked,
+        [
+            ("/bin/true", "/bin/true".to_string()),
+            ("/bin/echo", "/bin/echo denied".to_string()),
+            ("/bin/sh", "/bin/sh -c /bin/true".to_string()),
+            ("/bin/true", "/bin/true".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,72 @@
+//! The user and group lookups of a single invocation, like those made while judging a request
+//! against a sudoers file with many rules, through the system database and through a cache in
+//! front of it; and the same with a backend that takes a while to answer, like NSS backed by
+//! LDAP does.
+
+use std::{io, thread, time::Duration};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use sudo_system::{
+    database::{Cached, Database, System},
+    Group, User,
+};
+
+/// The system database, with a delay for every lookup
+struct Slow(Duration);
+
+impl Slow {
+    fn wait(&self) {
+        thread::sleep(self.0);
+    }
+}
+
+impl Database for Slow {
+    fn user_by_name(&self, name: &str) -> io::Result<Option<User>> {
+        self.wait();
+        System.user_by_name(name)
+    }
+
+    fn user_by_uid(&self, uid: libc::uid_t) -> io::Result<Option<User>> {
+        self.wait();
+        System.user_by_uid(uid)
+    }
+
+    fn group_by_name(&self, name: &str) -> io::Result<Option<Group>> {
+        self.wait();
+        System.group_by_name(name)
+    }
+
+    fn group_by_gid(&self, gid: libc::gid_t) -> io::Result<Option<Group>> {
+        self.wait();
+        System.group_by_gid(gid)
+    }
+}
+
+/// The lookups for 20 rules that name users, groups and runas users (most of them the same few)
+fn lookups(db: &dyn Database) {
+    for _ in 0..20 {
+        black_box(db.user_by_uid(0).unwrap());
+        black_box(db.user_by_name("root").unwrap());
+        black_box(db.user_by_name("no-such-user").unwrap());
+        black_box(db.group_by_gid(0).unwrap());
+        black_box(db.group_by_name("root").unwrap());
+    }
+}
+
+fn bench(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lookups");
+    group.bench_function("system", |b| b.iter(|| lookups(&System)));
+    // a new cache for every invocation, like sudo has
+    group.bench_function("system, cached", |b| {
+        b.iter(|| lookups(&Cached::new(System)))
+    });
+    let delay = Duration::from_micros(100);
+    group.bench_function("slow backend", |b| b.iter(|| lookups(&Slow(delay))));
+    group.bench_function("slow backend, cached", |b| {
+        b.iter(|| lookups(&Cached::new(Slow(delay))))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
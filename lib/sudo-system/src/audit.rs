@@ -0,0 +1,128 @@
+//! Records for the Linux audit subsystem, in the format that auditd (and tools like ausearch)
+//! expect from sudo. The kernel adds the pid, uid, auid and session of the sender itself. On
+//! other platforms, or when the kernel does not support auditing, nothing is logged.
+
+use std::io;
+
+/// The kind of audit record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A command was run (or refused)
+    Command,
+    /// A user authenticated (or failed to)
+    Auth,
+}
+
+#[cfg(target_os = "linux")]
+impl Event {
+    fn message_type(self) -> u16 {
+        match self {
+            Event::Command => 1123, // AUDIT_USER_CMD
+            Event::Auth => 1100,    // AUDIT_USER_AUTH
+        }
+    }
+}
+
+/// Encode a value for a record: plain values are quoted, values that contain spaces, quotes or
+/// control characters are hex encoded (like `audit_encode_value` in libaudit)
+pub fn encode_value(value: &str) -> String {
+    if value.bytes().any(|b| b <= b' ' || b == b'"' || b >= 0x7f) {
+        value.bytes().map(|b| format!("{b:02X}")).collect()
+    } else {
+        format!("\"{value}\"")
+    }
+}
+
+/// Format the text of a record
+pub fn format_record(fields: &[(&str, String)], success: bool) -> String {
+    let mut text = String::new();
+    for (key, value) in fields {
+        text.push_str(&format!("{key}={value} "));
+    }
+    text.push_str(if success { "res=success" } else { "res=failed" });
+    text
+}
+
+/// Send a record to the kernel; a kernel without audit support is not an error
+#[cfg(target_os = "linux")]
+pub fn log(event: Event, text: &str) -> io::Result<()> {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+    const NLMSG_HDRLEN: usize = 16;
+
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+            libc::NETLINK_AUDIT,
+        )
+    };
+    if fd < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::EINVAL | libc::EPROTONOSUPPORT | libc::EAFNOSUPPORT) => Ok(()),
+            _ => Err(err),
+        };
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // netlink header, followed by the NUL-terminated text, padded to a multiple of 4 bytes
+    let len = NLMSG_HDRLEN + text.len() + 1;
+    let mut message = Vec::with_capacity((len + 3) & !3);
+    message.extend_from_slice(&(len as u32).to_ne_bytes());
+    message.extend_from_slice(&event.message_type().to_ne_bytes());
+    message.extend_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    message.extend_from_slice(&1u32.to_ne_bytes()); // sequence number
+    message.extend_from_slice(&0u32.to_ne_bytes()); // port id; filled in by the kernel
+    message.extend_from_slice(text.as_bytes());
+    message.resize((len + 3) & !3, 0);
+
+    let mut addr: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    let sent = unsafe {
+        libc::sendto(
+            fd.as_raw_fd(),
+            message.as_ptr().cast(),
+            message.len(),
+            0,
+            (&addr as *const libc::sockaddr_nl).cast(),
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if sent < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ECONNREFUSED) => Ok(()),
+            _ => Err(err),
+        };
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn log(_event: Event, _text: &str) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_format() {
+        assert_eq!(encode_value("/usr/bin/id"), "\"/usr/bin/id\"");
+        assert_eq!(encode_value("ls -l"), "6C73202D6C");
+        assert_eq!(
+            format_record(
+                &[
+                    ("cwd", encode_value("/root")),
+                    ("cmd", encode_value("id")),
+                    ("terminal", "pts/0".to_string())
+                ],
+                false
+            ),
+            "cwd=\"/root\" cmd=\"id\" terminal=pts/0 res=failed"
+        );
+    }
+}
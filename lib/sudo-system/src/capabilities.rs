@@ -0,0 +1,231 @@
+//! Linux capabilities: the bounding set of a process limits the capabilities that it and the
+//! programs it executes can ever get, and its ambient set holds the capabilities that a program
+//! keeps when it executes another one without running as root (see `capabilities(7)`)
+
+/// The names of the capabilities, indexed by their number
+const NAMES: &[&str] = &[
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+/// A set of capabilities, as a bit mask indexed by capability number
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CapabilitySet(u64);
+
+/// The capabilities with which a command could undo a sandbox: changing or removing mounts
+/// (`sys_admin`), loading kernel modules or another kernel (`sys_module`, `sys_boot`), accessing
+/// devices and memory directly (`sys_rawio`, `mknod`), and taking over other processes
+/// (`sys_ptrace`, `perfmon`, `bpf`)
+pub const SANDBOX_ESCAPES: CapabilitySet =
+    CapabilitySet::from_numbers(&[16, 17, 19, 21, 22, 27, 38, 39]);
+
+impl CapabilitySet {
+    /// The set of the capabilities with these numbers
+    pub const fn from_numbers(numbers: &[u32]) -> Self {
+        let mut bits = 0;
+        let mut index = 0;
+        while index < numbers.len() {
+            bits |= 1 << numbers[index];
+            index += 1;
+        }
+        CapabilitySet(bits)
+    }
+
+    /// The set of the capabilities with these names, such as `cap_net_raw`; case does not
+    /// matter, and the `cap_` prefix may be left out. An unknown name is returned as the error.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, &'a str> {
+        let mut set = CapabilitySet::default();
+        for name in names {
+            let lower = name.to_ascii_lowercase();
+            let bare = lower.strip_prefix("cap_").unwrap_or(&lower);
+            let number = NAMES.iter().position(|known| *known == bare).ok_or(name)?;
+            set.0 |= 1 << number;
+        }
+        Ok(set)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, number: u32) -> bool {
+        number < 64 && self.0 & (1 << number) != 0
+    }
+
+    /// The capabilities of this set that are not in `other`
+    pub fn without(self, other: CapabilitySet) -> CapabilitySet {
+        CapabilitySet(self.0 & !other.0)
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use linux::*;
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{fs, io};
+
+    use super::{CapabilitySet, NAMES};
+    use crate::{cerr, cerr_long};
+
+    /// The highest capability number that the running kernel knows about
+    pub fn last_capability() -> u32 {
+        fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+            .ok()
+            .and_then(|text| text.trim().parse().ok())
+            .unwrap_or(NAMES.len() as u32 - 1)
+    }
+
+    /// Remove every capability up to number `last` (see [last_capability]) that is not in
+    /// `retain` from the bounding set of the current process; this requires `CAP_SETPCAP`
+    pub fn limit_bounding_set(retain: CapabilitySet, last: u32) -> io::Result<()> {
+        for number in (0..=last.min(63)).filter(|number| !retain.contains(*number)) {
+            cerr(unsafe { libc::prctl(libc::PR_CAPBSET_DROP, number as libc::c_ulong) })?;
+        }
+        Ok(())
+    }
+
+    /// Remove the capabilities in `set` from the bounding set of the current process, as far as
+    /// the running kernel knows them; this requires `CAP_SETPCAP`
+    pub fn drop_from_bounding_set(set: CapabilitySet) -> io::Result<()> {
+        for number in (0..64).filter(|number| set.contains(*number)) {
+            match cerr(unsafe { libc::prctl(libc::PR_CAPBSET_DROP, number as libc::c_ulong) }) {
+                Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {}
+                result => {
+                    result?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep the permitted capabilities of the current process when it switches from root to
+    /// another user (until it executes another program)
+    pub fn keep_on_setuid() -> io::Result<()> {
+        cerr(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1 as libc::c_ulong) })?;
+        Ok(())
+    }
+
+    /// Make `set` the effective, permitted, inheritable and ambient capabilities of the current
+    /// process, so that the next program it executes gets them as well; these capabilities
+    /// must all be permitted already
+    pub fn set_ambient(set: CapabilitySet) -> io::Result<()> {
+        const VERSION_3: u32 = 0x2008_0522;
+
+        #[repr(C)]
+        struct Header {
+            version: u32,
+            pid: libc::c_int,
+        }
+
+        #[repr(C)]
+        struct Data {
+            effective: u32,
+            permitted: u32,
+            inheritable: u32,
+        }
+
+        let mut header = Header {
+            version: VERSION_3,
+            pid: 0,
+        };
+        let data = [set.0 as u32, (set.0 >> 32) as u32].map(|bits| Data {
+            effective: bits,
+            permitted: bits,
+            inheritable: bits,
+        });
+        cerr_long(unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) })?;
+
+        let ambient = |action: libc::c_int, number: u32| {
+            let (action, number) = (action as libc::c_ulong, number as libc::c_ulong);
+            let unused: libc::c_ulong = 0;
+            cerr(unsafe { libc::prctl(libc::PR_CAP_AMBIENT, action, number, unused, unused) })
+        };
+        ambient(libc::PR_CAP_AMBIENT_CLEAR_ALL, 0)?;
+        for number in (0..64).filter(|number| set.contains(*number)) {
+            ambient(libc::PR_CAP_AMBIENT_RAISE, number)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capability_names() {
+        let set = CapabilitySet::from_names(["cap_net_raw", "CAP_SYS_ADMIN", "chown"]).unwrap();
+        assert!(set.contains(0));
+        assert!(set.contains(13));
+        assert!(set.contains(21));
+        assert!(!set.contains(12));
+        assert!(CapabilitySet::from_names(["checkpoint_restore"])
+            .unwrap()
+            .contains(40));
+        assert_eq!(
+            CapabilitySet::from_names(["cap_net_raw", "cap_teleport"]),
+            Err("cap_teleport")
+        );
+        assert!(CapabilitySet::from_names([]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn sandbox_escapes() {
+        let names = [
+            "sys_admin",
+            "sys_module",
+            "sys_boot",
+            "sys_rawio",
+            "mknod",
+            "sys_ptrace",
+            "perfmon",
+            "bpf",
+        ];
+        assert_eq!(CapabilitySet::from_names(names).unwrap(), SANDBOX_ESCAPES);
+        let set = CapabilitySet::from_names(["net_raw", "sys_admin"]).unwrap();
+        assert_eq!(
+            set.without(SANDBOX_ESCAPES),
+            CapabilitySet::from_names(["net_raw"]).unwrap()
+        );
+    }
+}
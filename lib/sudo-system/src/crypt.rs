@@ -0,0 +1,100 @@
+//! Verification of password hashes with crypt(3) from the system libcrypt (libxcrypt on Linux),
+//! which knows every scheme that the system itself writes to the shadow file: yescrypt,
+//! sha512crypt, bcrypt and the rest.
+
+use std::ffi::{c_char, CStr, CString};
+
+use crate::secure_buffer::SecureBuffer;
+
+/// The size of `struct crypt_data` in libxcrypt; that of FreeBSD is smaller
+const CRYPT_DATA_SIZE: usize = 32768;
+
+#[link(name = "crypt")]
+extern "C" {
+    #[cfg(target_os = "linux")]
+    fn crypt_rn(
+        phrase: *const c_char,
+        setting: *const c_char,
+        data: *mut libc::c_void,
+        size: libc::c_int,
+    ) -> *mut c_char;
+    #[cfg(not(target_os = "linux"))]
+    fn crypt_r(
+        phrase: *const c_char,
+        setting: *const c_char,
+        data: *mut libc::c_void,
+    ) -> *mut c_char;
+}
+
+/// Hash `phrase` (which ends in a NUL byte) with the scheme and parameters of `setting`
+///
+/// # Safety
+/// `data` must be at least [CRYPT_DATA_SIZE] bytes of zeroed memory, which the result points into.
+unsafe fn crypt(phrase: &CStr, setting: &CStr, data: *mut u8) -> *mut c_char {
+    #[cfg(target_os = "linux")]
+    return crypt_rn(
+        phrase.as_ptr(),
+        setting.as_ptr(),
+        data.cast(),
+        CRYPT_DATA_SIZE as libc::c_int,
+    );
+    #[cfg(not(target_os = "linux"))]
+    return crypt_r(phrase.as_ptr(), setting.as_ptr(), data.cast());
+}
+
+/// Whether the password matches the hash; a hash that crypt(3) can not use (an unknown scheme,
+/// or a marker of a locked account, such as `!` or `*`) never matches
+pub fn verify(password: &[u8], hash: &str) -> bool {
+    let Ok(setting) = CString::new(hash) else {
+        return false;
+    };
+    if password.contains(&0) {
+        return false;
+    }
+    // the password and what crypt(3) derives from it are wiped afterwards
+    let mut phrase = SecureBuffer::with_capacity(password.len() + 1);
+    for &byte in password.iter().chain(&[0]) {
+        phrase.push(byte);
+    }
+    let Ok(phrase) = CStr::from_bytes_with_nul(phrase.as_bytes()) else {
+        return false;
+    };
+    let mut data = SecureBuffer::with_capacity(CRYPT_DATA_SIZE);
+
+    // SAFETY: the memory of the buffer is zeroed when it is mapped, and outlives the result
+    let result = unsafe { crypt(phrase, &setting, data.as_mut_ptr()) };
+    if result.is_null() {
+        return false;
+    }
+    let result = unsafe { CStr::from_ptr(result) }.to_bytes();
+    // crypt_r reports failure with a string that starts with `*`, which no hash does
+    !result.starts_with(b"*") && equal(result, hash.as_bytes())
+}
+
+/// Compare in constant time, so the time it takes tells nothing about the hash
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_password() {
+        for hash in [
+            "$6$saltsaltsalt$/YcSJBZ1GwOaYU2XCNxvWBX3MbYsX7qkf0.0L28t35vap3Pr7yS0JA8iUjaUrIKpD1vbB841OQkrlcX47Jjuy/",
+            "$1$abcdefgh$vhxKZ/s1ygZHyCEDPyqtQ/",
+            #[cfg(target_os = "linux")]
+            "$y$j75$abcdefghijklmnop$AJUVXlyUF.BGs4gvB2HOGzX.2pMYTMEUqRK7Ar4ApHC",
+        ] {
+            assert!(verify(b"hunter2", hash), "{hash}");
+            assert!(!verify(b"hunter3", hash), "{hash}");
+            assert!(!verify(b"hunter2\0", hash), "{hash}");
+            assert!(!verify(b"hunter2", &format!("!{hash}")), "{hash}");
+        }
+        assert!(!verify(b"hunter2", "$y$j9T$salt$hash"));
+        assert!(!verify(b"", ""));
+        assert!(!verify(b"", "*"));
+    }
+}
@@ -0,0 +1,325 @@
+//! Access to the user and group database. Lookups normally go through NSS, which can be slow
+//! (e.g. when it is backed by LDAP) and is hit repeatedly while evaluating a sudoers file; so
+//! results are cached for the duration of a single invocation. The backend can be replaced, so
+//! tests can run against a fake user database.
+//!
+//! With the `files-only` feature, NSS is not used at all and the user and group files are read
+//! directly; this allows building a fully static binary.
+
+use std::{cell::RefCell, collections::HashMap, fs, hash::Hash, io, path::PathBuf, rc::Rc};
+
+use crate::{Group, User};
+
+/// A source of user and group records
+pub trait Database {
+    fn user_by_name(&self, name: &str) -> io::Result<Option<User>>;
+    fn user_by_uid(&self, uid: libc::uid_t) -> io::Result<Option<User>>;
+    fn group_by_name(&self, name: &str) -> io::Result<Option<Group>>;
+    fn group_by_gid(&self, gid: libc::gid_t) -> io::Result<Option<Group>>;
+
+    /// `user` with its supplementary groups filled in
+    fn with_groups(&self, user: User) -> User {
+        user.with_groups()
+    }
+}
+
+/// The system user database, i.e. whatever NSS is configured to use (or [Files] when built with
+/// the `files-only` feature)
+pub struct System;
+
+impl Database for System {
+    fn user_by_name(&self, name: &str) -> io::Result<Option<User>> {
+        User::from_name(name)
+    }
+
+    fn user_by_uid(&self, uid: libc::uid_t) -> io::Result<Option<User>> {
+        User::from_uid(uid)
+    }
+
+    fn group_by_name(&self, name: &str) -> io::Result<Option<Group>> {
+        Group::from_name(name)
+    }
+
+    fn group_by_gid(&self, gid: libc::gid_t) -> io::Result<Option<Group>> {
+        Group::from_gid(gid)
+    }
+}
+
+/// Reads users and groups straight from `/etc/passwd` and `/etc/group`
+pub struct Files {
+    passwd: PathBuf,
+    group: PathBuf,
+}
+
+impl Default for Files {
+    fn default() -> Self {
+        Files::new("/etc/passwd", "/etc/group")
+    }
+}
+
+fn parse_user(line: &str) -> Option<User> {
+    let mut fields = line.split(':');
+    let mut field = || fields.next().map(str::to_string);
+    Some(User {
+        name: field()?,
+        passwd: field()?,
+        uid: field()?.parse().ok()?,
+        gid: field()?.parse().ok()?,
+        gecos: field()?,
+        home: field()?,
+        shell: field()?,
+        groups: None,
+    })
+}
+
+fn parse_group(line: &str) -> Option<Group> {
+    let mut fields = line.split(':');
+    let mut field = || fields.next().map(str::to_string);
+    Some(Group {
+        name: field()?,
+        passwd: field()?,
+        gid: field()?.parse().ok()?,
+        members: field()?
+            .split(',')
+            .filter(|member| !member.is_empty())
+            .map(str::to_string)
+            .collect(),
+    })
+}
+
+/// The entries of a file in passwd(5) or group(5) format; comments and (NIS) compat entries are
+/// skipped, as are malformed lines
+fn entries<T>(path: &PathBuf, parse: fn(&str) -> Option<T>) -> io::Result<Vec<T>> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.starts_with(['#', '+', '-']))
+        .filter_map(parse)
+        .collect())
+}
+
+impl Files {
+    pub fn new(passwd: impl Into<PathBuf>, group: impl Into<PathBuf>) -> Self {
+        Files {
+            passwd: passwd.into(),
+            group: group.into(),
+        }
+    }
+
+    fn find_user(&self, pred: impl Fn(&User) -> bool) -> io::Result<Option<User>> {
+        Ok(entries(&self.passwd, parse_user)?.into_iter().find(pred))
+    }
+
+    fn find_group(&self, pred: impl Fn(&Group) -> bool) -> io::Result<Option<Group>> {
+        Ok(entries(&self.group, parse_group)?.into_iter().find(pred))
+    }
+
+    /// The groups a user is a member of: its primary group, and all groups listing it as member
+    pub fn group_list(&self, name: &str, gid: libc::gid_t) -> io::Result<Vec<libc::gid_t>> {
+        let mut groups = vec![gid];
+        for group in entries(&self.group, parse_group)? {
+            if group.members.iter().any(|member| member == name) && !groups.contains(&group.gid) {
+                groups.push(group.gid);
+            }
+        }
+        Ok(groups)
+    }
+}
+
+impl Database for Files {
+    fn user_by_name(&self, name: &str) -> io::Result<Option<User>> {
+        self.find_user(|user| user.name == name)
+    }
+
+    fn user_by_uid(&self, uid: libc::uid_t) -> io::Result<Option<User>> {
+        self.find_user(|user| user.uid == uid)
+    }
+
+    fn group_by_name(&self, name: &str) -> io::Result<Option<Group>> {
+        self.find_group(|group| group.name == name)
+    }
+
+    fn group_by_gid(&self, gid: libc::gid_t) -> io::Result<Option<Group>> {
+        self.find_group(|group| group.gid == gid)
+    }
+}
+
+type Table<K, V> = RefCell<HashMap<K, Option<V>>>;
+
+/// Remembers successful lookups (including "not found") of another database; errors are not
+/// cached, so a transient failure can be retried. Lookups by name and by id are cached
+/// separately: a user found by name is not necessarily the one that NSS returns for its uid
+/// (several names can share one), so each answer is only reused for the same question.
+pub struct Cached<D: Database> {
+    backend: D,
+    users: Table<String, User>,
+    uids: Table<libc::uid_t, User>,
+    groups: Table<String, Group>,
+    gids: Table<libc::gid_t, Group>,
+}
+
+impl<D: Database> Cached<D> {
+    pub fn new(backend: D) -> Self {
+        Cached {
+            backend,
+            users: Default::default(),
+            uids: Default::default(),
+            groups: Default::default(),
+            gids: Default::default(),
+        }
+    }
+}
+
+fn lookup<K: Eq + Hash, V: Clone>(
+    table: &Table<K, V>,
+    key: K,
+    fetch: impl FnOnce() -> io::Result<Option<V>>,
+) -> io::Result<Option<V>> {
+    if let Some(entry) = table.borrow().get(&key) {
+        return Ok(entry.clone());
+    }
+    let entry = fetch()?;
+    table.borrow_mut().insert(key, entry.clone());
+    Ok(entry)
+}
+
+impl<D: Database> Database for Cached<D> {
+    fn user_by_name(&self, name: &str) -> io::Result<Option<User>> {
+        lookup(&self.users, name.to_string(), || {
+            self.backend.user_by_name(name)
+        })
+    }
+
+    fn user_by_uid(&self, uid: libc::uid_t) -> io::Result<Option<User>> {
+        lookup(&self.uids, uid, || self.backend.user_by_uid(uid))
+    }
+
+    fn group_by_name(&self, name: &str) -> io::Result<Option<Group>> {
+        lookup(&self.groups, name.to_string(), || {
+            self.backend.group_by_name(name)
+        })
+    }
+
+    fn group_by_gid(&self, gid: libc::gid_t) -> io::Result<Option<Group>> {
+        lookup(&self.gids, gid, || self.backend.group_by_gid(gid))
+    }
+
+    fn with_groups(&self, user: User) -> User {
+        self.backend.with_groups(user)
+    }
+}
+
+thread_local! {
+    static DATABASE: RefCell<Rc<dyn Database>> = RefCell::new(Rc::new(Cached::new(System)));
+}
+
+/// The database used for lookups in this thread; by default a cache in front of [System].
+pub fn database() -> Rc<dyn Database> {
+    DATABASE.with(|db| db.borrow().clone())
+}
+
+/// Replace the database used for lookups in this thread (for instance with a fake one in tests).
+pub fn set_database(db: impl Database + 'static) {
+    DATABASE.with(|current| *current.borrow_mut() = Rc::new(db));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[derive(Default)]
+    struct Fake {
+        calls: Cell<usize>,
+    }
+
+    impl Database for Fake {
+        fn user_by_name(&self, name: &str) -> io::Result<Option<User>> {
+            self.calls.set(self.calls.get() + 1);
+            Ok((name == "test").then(|| User {
+                uid: 1000,
+                gid: 1000,
+                name: "test".to_string(),
+                gecos: String::new(),
+                home: "/home/test".to_string(),
+                shell: "/bin/sh".to_string(),
+                passwd: String::new(),
+                groups: None,
+            }))
+        }
+
+        fn user_by_uid(&self, _uid: libc::uid_t) -> io::Result<Option<User>> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(None)
+        }
+
+        fn group_by_name(&self, _name: &str) -> io::Result<Option<Group>> {
+            self.calls.set(self.calls.get() + 1);
+            Err(io::Error::from(io::ErrorKind::Other))
+        }
+
+        fn group_by_gid(&self, _gid: libc::gid_t) -> io::Result<Option<Group>> {
+            self.calls.set(self.calls.get() + 1);
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn files_backend() {
+        let dir = std::env::temp_dir().join(format!("sudo-system-files-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (passwd, group) = (dir.join("passwd"), dir.join("group"));
+        fs::write(
+            &passwd,
+            "# comment\nroot:x:0:0:root:/root:/bin/sh\n+nis\ntest:x:1000:1000:Test:/home/test:/bin/sh\nbroken:x\n",
+        )
+        .unwrap();
+        fs::write(
+            &group,
+            "root:x:0:\ntest:x:1000:\nwheel:x:10:root,test\naudio:x:29:root\n",
+        )
+        .unwrap();
+        let db = Files::new(&passwd, &group);
+
+        let test = db.user_by_name("test").unwrap().unwrap();
+        assert_eq!(
+            (test.uid, test.gid, test.home.as_str()),
+            (1000, 1000, "/home/test")
+        );
+        assert_eq!(db.user_by_uid(0).unwrap().unwrap().name, "root");
+        assert!(db.user_by_name("broken").unwrap().is_none());
+        assert!(db.user_by_name("nis").unwrap().is_none());
+
+        let wheel = db.group_by_name("wheel").unwrap().unwrap();
+        assert_eq!((wheel.gid, wheel.members.len()), (10, 2));
+        assert_eq!(db.group_by_gid(29).unwrap().unwrap().name, "audio");
+        assert_eq!(db.group_list("test", 1000).unwrap(), vec![1000, 10]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn lookups_are_cached() {
+        let db = Cached::new(Fake::default());
+        let calls = || db.backend.calls.get();
+
+        assert_eq!(db.user_by_name("test").unwrap().unwrap().uid, 1000);
+        assert_eq!(db.user_by_name("test").unwrap().unwrap().uid, 1000);
+        assert_eq!(calls(), 1);
+        // a lookup by uid is a question of its own, with its own answer
+        assert!(db.user_by_uid(1000).unwrap().is_none());
+        assert!(db.user_by_uid(1000).unwrap().is_none());
+        assert_eq!(calls(), 2);
+
+        // negative results are remembered
+        assert!(db.user_by_name("nobody").unwrap().is_none());
+        assert!(db.user_by_name("nobody").unwrap().is_none());
+        assert!(db.group_by_gid(42).unwrap().is_none());
+        assert!(db.group_by_gid(42).unwrap().is_none());
+        assert_eq!(calls(), 4);
+
+        // but errors are not
+        assert!(db.group_by_name("wheel").is_err());
+        assert!(db.group_by_name("wheel").is_err());
+        assert_eq!(calls(), 6);
+    }
+}
@@ -0,0 +1,237 @@
+//! The event loop that sudo waits in while it supervises a command: a single poll(2) over the
+//! file descriptors that are watched, and a pipe that the handlers of the caught signals write
+//! (the signal and where it came from) to, with an optional deadline. Signals are handled like any other event, in the order in which
+//! they arrived, so nothing has to happen in a signal handler, and no signal is missed between
+//! checking for one and starting to wait.
+
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::atomic::{AtomicI32, Ordering},
+    time::Instant,
+};
+
+use crate::{cerr, signal::SignalInfo};
+
+/// Something that happened while waiting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A signal that is caught (see [EventLoop::catch]) arrived
+    Signal(SignalInfo),
+    /// A watched file descriptor can be read from, or has been closed on the other end; it is
+    /// reported again until it is read from or no longer watched
+    Readable(RawFd),
+    /// A file descriptor that is watched for writing (see [EventLoop::watch_writable]) can be
+    /// written to, or has been closed on the other end; it is reported again until it is no longer
+    /// watched
+    Writable(RawFd),
+    /// The deadline (see [EventLoop::set_deadline]) has passed; it is cleared
+    Timeout,
+}
+
+/// The end of the pipe that the signal handlers write to (-1 if there is no event loop)
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+/// The size of what the handlers write to the pipe for a signal: its number, its `si_code` and
+/// the process that sent it; this is less than `PIPE_BUF`, so it is written all at once or not at
+/// all
+const RECORD: usize = 3 * std::mem::size_of::<libc::c_int>();
+
+extern "C" fn on_signal(signal: libc::c_int, info: *mut libc::siginfo_t, _: *mut libc::c_void) {
+    let fd = SIGNAL_PIPE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let saved = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+        // SAFETY: the kernel passes the information about the signal, since SA_SIGINFO is set
+        let (code, pid) = unsafe { ((*info).si_code, (*info).si_pid()) };
+        let record: [libc::c_int; 3] = [signal, code, pid];
+        // SAFETY: write is async-signal-safe; if the pipe is full, the signal is dropped
+        unsafe { libc::write(fd, record.as_ptr().cast(), RECORD) };
+        crate::set_errno(saved);
+    }
+}
+
+/// There can only be one event loop at a time, since signal handlers are process-wide; the
+/// original handlers of the caught signals are restored when it is dropped
+pub struct EventLoop {
+    signals: OwnedFd,
+    /// Keeps the end of the pipe that the handlers write to open
+    _handlers: OwnedFd,
+    watched: Vec<RawFd>,
+    writable: Vec<RawFd>,
+    deadline: Option<Instant>,
+    previous: Vec<(libc::c_int, libc::sigaction)>,
+}
+
+impl EventLoop {
+    pub fn new() -> io::Result<EventLoop> {
+        let mut fds = [0; 2];
+        // the command must not inherit the pipe, and a handler must never block on it
+        cerr(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) })?;
+        // SAFETY: pipe2 has just opened these
+        let (signals, handlers) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+        SIGNAL_PIPE
+            .compare_exchange(-1, handlers.as_raw_fd(), Ordering::SeqCst, Ordering::SeqCst)
+            .map_err(|_| io::Error::other("there already is an event loop"))?;
+
+        Ok(EventLoop {
+            signals,
+            _handlers: handlers,
+            watched: Vec::new(),
+            writable: Vec::new(),
+            deadline: None,
+            previous: Vec::new(),
+        })
+    }
+
+    /// Report `signal` as an [Event::Signal] instead of handling it as before
+    pub fn catch(&mut self, signal: libc::c_int) -> io::Result<()> {
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal
+                as extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)
+                as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART | libc::SA_SIGINFO;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut old: libc::sigaction = std::mem::zeroed();
+            cerr(libc::sigaction(signal, &action, &mut old))?;
+            self.previous.push((signal, old));
+        }
+        Ok(())
+    }
+
+    pub fn watch(&mut self, fd: RawFd) {
+        if !self.watched.contains(&fd) {
+            self.watched.push(fd);
+        }
+    }
+
+    pub fn unwatch(&mut self, fd: RawFd) {
+        self.watched.retain(|&watched| watched != fd);
+    }
+
+    /// Report when `fd` can be written to, as an [Event::Writable]
+    pub fn watch_writable(&mut self, fd: RawFd) {
+        if !self.writable.contains(&fd) {
+            self.writable.push(fd);
+        }
+    }
+
+    pub fn unwatch_writable(&mut self, fd: RawFd) {
+        self.writable.retain(|&writable| writable != fd);
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Wait for the next event; signals go first, then the deadline, then the file descriptors
+    /// in the order in which they are watched, those watched for reading before the others
+    pub fn wait(&mut self) -> io::Result<Event> {
+        loop {
+            let mut record: [libc::c_int; 3] = [0; 3];
+            let read =
+                unsafe { libc::read(self.signals.as_raw_fd(), record.as_mut_ptr().cast(), RECORD) };
+            if read == RECORD as isize {
+                let [signal, code, pid] = record;
+                return Ok(Event::Signal(SignalInfo { signal, code, pid }));
+            }
+
+            let timeout = match self.deadline {
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        self.deadline = None;
+                        return Ok(Event::Timeout);
+                    }
+                    // rounded up, so the deadline has passed when poll returns
+                    let millis = remaining.as_nanos().div_ceil(1_000_000);
+                    millis.min(libc::c_int::MAX as u128) as libc::c_int
+                }
+                None => -1,
+            };
+
+            let reading = [self.signals.as_raw_fd()]
+                .into_iter()
+                .chain(self.watched.iter().copied())
+                .map(|fd| (fd, libc::POLLIN));
+            let writing = self.writable.iter().map(|&fd| (fd, libc::POLLOUT));
+            let mut fds = reading
+                .chain(writing)
+                .map(|(fd, events)| libc::pollfd {
+                    fd,
+                    events,
+                    revents: 0,
+                })
+                .collect::<Vec<_>>();
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if let Some(fd) = fds[1..].iter().find(|fd| fd.revents != 0) {
+                if fds[0].revents == 0 {
+                    return Ok(match fd.events {
+                        libc::POLLOUT => Event::Writable(fd.fd),
+                        _ => Event::Readable(fd.fd),
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Drop for EventLoop {
+    fn drop(&mut self) {
+        for (signal, old) in self.previous.iter().rev() {
+            unsafe { libc::sigaction(*signal, old, std::ptr::null_mut()) };
+        }
+        SIGNAL_PIPE.store(-1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Write, os::unix::net::UnixStream, time::Duration};
+
+    #[test]
+    fn events() {
+        let mut events = EventLoop::new().unwrap();
+        assert!(EventLoop::new().is_err());
+
+        events.catch(libc::SIGUSR1).unwrap();
+        events.catch(libc::SIGUSR2).unwrap();
+        unsafe {
+            libc::raise(libc::SIGUSR2);
+            libc::raise(libc::SIGUSR1);
+        }
+        for expected in [libc::SIGUSR2, libc::SIGUSR1] {
+            let Event::Signal(info) = events.wait().unwrap() else {
+                panic!("no signal");
+            };
+            assert_eq!(info.signal, expected);
+            assert!(info.sent_by_process());
+            assert_eq!(info.pid, crate::Process::process_id());
+        }
+
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+        events.watch(reader.as_raw_fd());
+        writer.write_all(b"x").unwrap();
+        let start = Instant::now();
+        events.set_deadline(Some(start + Duration::from_millis(50)));
+        assert_eq!(events.wait().unwrap(), Event::Readable(reader.as_raw_fd()));
+        events.unwatch(reader.as_raw_fd());
+        events.watch_writable(writer.as_raw_fd());
+        assert_eq!(events.wait().unwrap(), Event::Writable(writer.as_raw_fd()));
+        events.unwatch_writable(writer.as_raw_fd());
+        assert_eq!(events.wait().unwrap(), Event::Timeout);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        drop(events);
+        assert!(EventLoop::new().is_ok());
+    }
+}
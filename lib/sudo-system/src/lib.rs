@@ -1,13 +1,29 @@
-use std::{
-    ffi::{CStr, CString},
-    fs::OpenOptions,
-    mem::MaybeUninit,
-    os::fd::AsRawFd,
-    path::PathBuf,
-};
+use std::{ffi::CStr, fs::OpenOptions, os::fd::AsRawFd, path::PathBuf};
+#[cfg(not(feature = "files-only"))]
+use std::{ffi::CString, mem::MaybeUninit};
 
 pub use libc::PATH_MAX;
 
+pub mod audit;
+pub mod capabilities;
+#[cfg(feature = "crypt")]
+pub mod crypt;
+pub mod database;
+pub mod event;
+#[cfg(target_os = "freebsd")]
+pub mod login_class;
+pub mod login_defs;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
+pub mod secure_buffer;
+pub mod secure_file;
+pub mod setuid;
+pub mod shells;
+pub mod signal;
+pub mod socket;
+pub mod syslog;
+pub mod term;
+
 fn cerr(res: libc::c_int) -> std::io::Result<libc::c_int> {
     match res {
         -1 => Err(std::io::Error::last_os_error()),
@@ -41,10 +57,7 @@ fn set_errno(no: libc::c_int) {
 
 fn sysconf(name: libc::c_int) -> Option<libc::c_long> {
     set_errno(0);
-    match cerr_long(unsafe { libc::sysconf(name) }) {
-        Ok(res) => Some(res),
-        Err(_) => None,
-    }
+    cerr_long(unsafe { libc::sysconf(name) }).ok()
 }
 
 fn string_from_ptr(ptr: *const libc::c_char) -> String {
@@ -56,6 +69,19 @@ fn string_from_ptr(ptr: *const libc::c_char) -> String {
     }
 }
 
+/// Take an exclusive lock on a file (blocking until it is available); the lock is released when
+/// the file is closed
+pub fn lock_exclusive(file: &impl AsRawFd) -> std::io::Result<()> {
+    cerr(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) })?;
+    Ok(())
+}
+
+/// Release a lock taken with [lock_exclusive]
+pub fn unlock(file: &impl AsRawFd) -> std::io::Result<()> {
+    cerr(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) })?;
+    Ok(())
+}
+
 pub fn hostname() -> String {
     let max_hostname_size = sysconf(libc::_SC_HOST_NAME_MAX).unwrap_or(256);
     let mut buf = vec![0; max_hostname_size as usize];
@@ -69,6 +95,7 @@ pub fn hostname() -> String {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct User {
     pub uid: libc::uid_t,
     pub gid: libc::gid_t,
@@ -94,6 +121,7 @@ impl User {
         }
     }
 
+    #[cfg(not(feature = "files-only"))]
     pub fn from_uid(uid: libc::uid_t) -> std::io::Result<Option<User>> {
         let max_pw_size = sysconf(libc::_SC_GETPW_R_SIZE_MAX).unwrap_or(16_384);
         let mut buf = vec![0; max_pw_size as usize];
@@ -116,6 +144,12 @@ impl User {
         }
     }
 
+    #[cfg(feature = "files-only")]
+    pub fn from_uid(uid: libc::uid_t) -> std::io::Result<Option<User>> {
+        use database::Database;
+        database::Files::default().user_by_uid(uid)
+    }
+
     pub fn effective_uid() -> libc::uid_t {
         unsafe { libc::geteuid() }
     }
@@ -132,6 +166,7 @@ impl User {
         Self::from_uid(Self::real_uid())
     }
 
+    #[cfg(not(feature = "files-only"))]
     pub fn from_name(name: &str) -> std::io::Result<Option<User>> {
         let max_pw_size = sysconf(libc::_SC_GETPW_R_SIZE_MAX).unwrap_or(16_384);
         let mut buf = vec![0; max_pw_size as usize];
@@ -155,6 +190,21 @@ impl User {
         }
     }
 
+    #[cfg(feature = "files-only")]
+    pub fn from_name(name: &str) -> std::io::Result<Option<User>> {
+        use database::Database;
+        database::Files::default().user_by_name(name)
+    }
+
+    #[cfg(feature = "files-only")]
+    pub fn with_groups(mut self) -> User {
+        self.groups = database::Files::default()
+            .group_list(&self.name, self.gid)
+            .ok();
+        self
+    }
+
+    #[cfg(not(feature = "files-only"))]
     pub fn with_groups(mut self) -> User {
         let mut groups = vec![];
         let mut buf_len: libc::c_int = 32;
@@ -193,6 +243,7 @@ impl User {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Group {
     pub gid: libc::gid_t,
     pub name: String,
@@ -239,6 +290,7 @@ impl Group {
         Self::from_gid(Self::real_gid())
     }
 
+    #[cfg(not(feature = "files-only"))]
     pub fn from_gid(gid: libc::gid_t) -> std::io::Result<Option<Group>> {
         let max_gr_size = sysconf(libc::_SC_GETGR_R_SIZE_MAX).unwrap_or(16_384);
         let mut buf = vec![0; max_gr_size as usize];
@@ -261,6 +313,13 @@ impl Group {
         }
     }
 
+    #[cfg(feature = "files-only")]
+    pub fn from_gid(gid: libc::gid_t) -> std::io::Result<Option<Group>> {
+        use database::Database;
+        database::Files::default().group_by_gid(gid)
+    }
+
+    #[cfg(not(feature = "files-only"))]
     pub fn from_name(name: &str) -> std::io::Result<Option<Group>> {
         let max_gr_size = sysconf(libc::_SC_GETGR_R_SIZE_MAX).unwrap_or(16_384);
         let mut buf = vec![0; max_gr_size as usize];
@@ -283,6 +342,12 @@ impl Group {
             Ok(Some(Group::from_libc(&grp)))
         }
     }
+
+    #[cfg(feature = "files-only")]
+    pub fn from_name(name: &str) -> std::io::Result<Option<Group>> {
+        use database::Database;
+        database::Files::default().group_by_name(name)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -327,11 +392,103 @@ impl Process {
         unsafe { libc::getppid() }
     }
 
+    /// Have the kernel send `signal` to the current process when its parent exits; note that
+    /// this setting is cleared when the credentials of the process change
+    #[cfg(target_os = "linux")]
+    pub fn set_parent_death_signal(signal: libc::c_int) -> std::io::Result<()> {
+        cerr(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, signal as libc::c_ulong) })?;
+        Ok(())
+    }
+
+    /// Have the kernel send `signal` to the current process when its parent exits; note that
+    /// this setting is cleared when the credentials of the process change
+    #[cfg(target_os = "freebsd")]
+    pub fn set_parent_death_signal(mut signal: libc::c_int) -> std::io::Result<()> {
+        let data = &mut signal as *mut libc::c_int as *mut libc::c_void;
+        cerr(unsafe { libc::procctl(libc::P_PID, 0, libc::PROC_PDEATHSIG_CTL, data) })?;
+        Ok(())
+    }
+
+    /// Keep other processes of the same user from tracing the current process or reading its
+    /// memory, and keep it from dumping core
+    #[cfg(target_os = "linux")]
+    pub fn disable_tracing() -> std::io::Result<()> {
+        cerr(unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0) })?;
+        Ok(())
+    }
+
+    /// Keep other processes of the same user from tracing the current process or reading its
+    /// memory, and keep it from dumping core
+    #[cfg(target_os = "freebsd")]
+    pub fn disable_tracing() -> std::io::Result<()> {
+        let mut control = libc::PROC_TRACE_CTL_DISABLE;
+        let data = &mut control as *mut libc::c_int as *mut libc::c_void;
+        cerr(unsafe { libc::procctl(libc::P_PID, 0, libc::PROC_TRACE_CTL, data) })?;
+        Ok(())
+    }
+
+    /// Keep the current process and its children from gaining privileges through exec, as by a
+    /// setuid executable or file capabilities
+    #[cfg(target_os = "linux")]
+    pub fn set_no_new_privs() -> std::io::Result<()> {
+        cerr(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) })?;
+        Ok(())
+    }
+
+    /// Keep the current process and its children from gaining privileges through exec, as by a
+    /// setuid executable
+    #[cfg(target_os = "freebsd")]
+    pub fn set_no_new_privs() -> std::io::Result<()> {
+        let mut control = libc::PROC_NO_NEW_PRIVS_ENABLE;
+        let data = &mut control as *mut libc::c_int as *mut libc::c_void;
+        cerr(unsafe { libc::procctl(libc::P_PID, 0, libc::PROC_NO_NEW_PRIVS_CTL, data) })?;
+        Ok(())
+    }
+
+    /// Change the root directory of the current process to `root`, and then the working
+    /// directory to `dir` inside it
+    pub fn change_root(root: &CStr, dir: &CStr) -> std::io::Result<()> {
+        cerr(unsafe { libc::chroot(root.as_ptr()) })?;
+        cerr(unsafe { libc::chdir(dir.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Switch the current process to the given user, group and supplementary groups (which
+    /// requires the process to be running as root)
+    pub fn set_credentials(
+        uid: libc::uid_t,
+        gid: libc::gid_t,
+        groups: &[libc::gid_t],
+    ) -> std::io::Result<()> {
+        cerr(unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) })?;
+        cerr(unsafe { libc::setgid(gid) })?;
+        cerr(unsafe { libc::setuid(uid) })?;
+        Ok(())
+    }
+
+    /// Set the file mode creation mask of the current process, returning the previous one
+    pub fn set_umask(mask: libc::mode_t) -> libc::mode_t {
+        unsafe { libc::umask(mask) }
+    }
+
+    /// Return the file mode creation mask of the current process
+    pub fn umask() -> libc::mode_t {
+        // the mask can only be read by changing it
+        let mask = Self::set_umask(0o022);
+        Self::set_umask(mask);
+        mask
+    }
+
     /// Return the process group id for the current process
     pub fn group_id() -> libc::pid_t {
         unsafe { libc::getpgid(0) }
     }
 
+    /// Return the process group id of the process `pid`
+    pub fn group_of(pid: libc::pid_t) -> std::io::Result<libc::pid_t> {
+        cerr(unsafe { libc::getpgid(pid) })
+    }
+
     /// Get the session id for the current process
     pub fn session_id() -> libc::pid_t {
         unsafe { libc::getsid(0) }
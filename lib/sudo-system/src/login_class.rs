@@ -0,0 +1,106 @@
+//! Login classes on FreeBSD: the class of a user in the password database selects resource
+//! limits, a scheduling priority and a umask from `/etc/login.conf`, which a command should get
+//! just as it would when the user logs in.
+
+use std::{
+    ffi::{c_char, c_uint, CStr},
+    io,
+    mem::MaybeUninit,
+    ptr,
+};
+
+use crate::{cerr, sysconf};
+
+const LOGIN_SETPRIORITY: c_uint = 0x0008;
+const LOGIN_SETRESOURCES: c_uint = 0x0010;
+const LOGIN_SETUMASK: c_uint = 0x0020;
+
+/// `login_cap_t`, which is only used through pointers
+#[repr(C)]
+struct LoginCap {
+    _private: [u8; 0],
+}
+
+#[link(name = "util")]
+extern "C" {
+    fn login_getclass(name: *const c_char) -> *mut LoginCap;
+    fn login_close(class: *mut LoginCap);
+    fn setusercontext(
+        class: *mut LoginCap,
+        pwd: *const libc::passwd,
+        uid: libc::uid_t,
+        flags: c_uint,
+    ) -> libc::c_int;
+}
+
+/// The settings of a login class; they are read when the class is looked up
+pub struct LoginClass(*mut LoginCap);
+
+// SAFETY: the settings are not changed after they have been read, and only freed on drop
+unsafe impl Send for LoginClass {}
+unsafe impl Sync for LoginClass {}
+
+impl LoginClass {
+    /// The login class of the user with `uid`; a user without a class (or who is not in the
+    /// password database) gets the `default` class, like at login
+    pub fn of_user(uid: libc::uid_t) -> io::Result<LoginClass> {
+        let max_pw_size = sysconf(libc::_SC_GETPW_R_SIZE_MAX).unwrap_or(16_384);
+        let mut buf = vec![0; max_pw_size as usize];
+        let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut pwd_ptr = ptr::null_mut();
+        cerr(unsafe {
+            libc::getpwuid_r(
+                uid,
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut pwd_ptr,
+            )
+        })?;
+        let name = if pwd_ptr.is_null() {
+            ptr::null()
+        } else {
+            unsafe { pwd.assume_init_ref() }.pw_class
+        };
+
+        let class = unsafe { login_getclass(name) };
+        if class.is_null() {
+            let class = if name.is_null() {
+                "default".into()
+            } else {
+                unsafe { CStr::from_ptr(name) }.to_string_lossy()
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("unknown login class {class}"),
+            ));
+        }
+        Ok(LoginClass(class))
+    }
+
+    /// Apply the resource limits, priority and umask of the class to the current process, which
+    /// is about to run as `uid`; the user, groups and environment are left to sudo. This requires
+    /// root, so it has to happen before the credentials are changed; it does not allocate, so it
+    /// can run between fork and exec.
+    pub fn apply(&self, uid: libc::uid_t) -> io::Result<()> {
+        let flags = LOGIN_SETRESOURCES | LOGIN_SETPRIORITY | LOGIN_SETUMASK;
+        cerr(unsafe { setusercontext(self.0, ptr::null(), uid, flags) })?;
+        Ok(())
+    }
+}
+
+impl Drop for LoginClass {
+    fn drop(&mut self) {
+        unsafe { login_close(self.0) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_has_a_login_class() {
+        assert!(LoginClass::of_user(0).is_ok());
+    }
+}
@@ -0,0 +1,99 @@
+//! The configuration of the shadow password suite in `/etc/login.defs` (see `login.defs(5)`),
+//! which tools like `su` use for defaults such as the `PATH` of a login environment
+
+use std::{collections::HashMap, fs, io, path::Path};
+
+/// Like util-linux `su`, the `PATH` for users other than root if `ENV_PATH` is not set
+pub const DEFAULT_PATH: &str = "/usr/local/bin:/bin:/usr/bin";
+
+/// Like util-linux `su`, the `PATH` for root if `ENV_SUPATH` is not set
+pub const DEFAULT_SUPATH: &str = "/usr/local/sbin:/usr/local/bin:/sbin:/bin:/usr/sbin:/usr/bin";
+
+/// The settings in a login.defs file
+#[derive(Debug, Default)]
+pub struct LoginDefs {
+    entries: HashMap<String, String>,
+}
+
+impl LoginDefs {
+    /// Parse the contents of a login.defs file: every line is a name and a value, separated by
+    /// whitespace, and the value may be put in double quotes; blank lines, comments and lines
+    /// without a value are skipped
+    pub fn parse(text: &str) -> Self {
+        let entries = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once(char::is_whitespace))
+            .map(|(name, value)| {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                (name.to_string(), value.to_string())
+            })
+            .collect();
+
+        LoginDefs { entries }
+    }
+
+    /// Read the login.defs file at `path`; if there is none, no settings are set
+    pub fn read_from(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Read `/etc/login.defs`
+    pub fn read() -> io::Result<Self> {
+        Self::read_from(Path::new("/etc/login.defs"))
+    }
+
+    /// The value of a setting, if it is set
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries.get(name).map(String::as_str)
+    }
+
+    /// The `PATH` of a login environment: `ENV_SUPATH` for root and `ENV_PATH` for other users,
+    /// which may be written as `PATH=...`
+    pub fn path(&self, for_root: bool) -> &str {
+        let (name, default) = if for_root {
+            ("ENV_SUPATH", DEFAULT_SUPATH)
+        } else {
+            ("ENV_PATH", DEFAULT_PATH)
+        };
+        self.get(name)
+            .map(|value| value.strip_prefix("PATH=").unwrap_or(value))
+            .unwrap_or(default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn login_defs_file() {
+        let defs = LoginDefs::parse(
+            "# /etc/login.defs\n\
+             MAIL_DIR /var/mail\n\
+             \tENV_SUPATH\tPATH=/usr/sbin:/usr/bin\n\
+             ENV_PATH \"/usr/bin\"\n\
+             UMASK 022 \n\
+             EMPTY\n",
+        );
+        assert_eq!(defs.get("MAIL_DIR"), Some("/var/mail"));
+        assert_eq!(defs.get("UMASK"), Some("022"));
+        assert_eq!(defs.get("EMPTY"), None);
+        assert_eq!(defs.get("# /etc/login.defs"), None);
+        assert_eq!(defs.path(true), "/usr/sbin:/usr/bin");
+        assert_eq!(defs.path(false), "/usr/bin");
+
+        let defaults = LoginDefs::default();
+        assert_eq!(defaults.path(true), DEFAULT_SUPATH);
+        assert_eq!(defaults.path(false), DEFAULT_PATH);
+    }
+}
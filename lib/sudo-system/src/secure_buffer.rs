@@ -0,0 +1,202 @@
+//! Memory for secrets such as passwords, which should not outlive their use: it has pages of its
+//! own, which are locked (so they are not written to swap), left out of core dumps where the
+//! system allows that, and overwritten with zeroes before they are given back.
+
+use std::{
+    fmt, io,
+    ptr::{self, NonNull},
+    slice,
+    sync::atomic::{compiler_fence, Ordering},
+};
+
+use crate::{cerr, sysconf};
+
+/// A buffer of bytes with a fixed capacity, which never reallocates, since that would leave a
+/// copy of its contents behind
+pub struct SecureBuffer {
+    /// Null if nothing is mapped, for a capacity of 0
+    data: *mut u8,
+    /// The size of the mapping, a whole number of pages
+    mapped: usize,
+    capacity: usize,
+    len: usize,
+}
+
+// SAFETY: the buffer owns its memory, like a Vec
+unsafe impl Send for SecureBuffer {}
+unsafe impl Sync for SecureBuffer {}
+
+impl SecureBuffer {
+    /// An empty buffer for up to `capacity` bytes; locking the memory is best effort, since the
+    /// amount of locked memory can be limited
+    pub fn with_capacity(capacity: usize) -> SecureBuffer {
+        let mut buffer = SecureBuffer {
+            data: ptr::null_mut(),
+            mapped: 0,
+            capacity,
+            len: 0,
+        };
+        if capacity == 0 {
+            return buffer;
+        }
+
+        let page = sysconf(libc::_SC_PAGESIZE).unwrap_or(4096) as usize;
+        let mapped = capacity.div_ceil(page) * page;
+        let data =
+            map(mapped).unwrap_or_else(|err| panic!("cannot allocate {mapped} bytes: {err}"));
+        buffer.data = data.as_ptr();
+        buffer.mapped = mapped;
+        unsafe {
+            libc::mlock(data.as_ptr().cast(), mapped);
+            #[cfg(target_os = "linux")]
+            libc::madvise(data.as_ptr().cast(), mapped, libc::MADV_DONTDUMP);
+        }
+        buffer
+    }
+
+    /// A buffer that holds exactly `bytes`
+    pub fn from_slice(bytes: &[u8]) -> SecureBuffer {
+        let mut buffer = SecureBuffer::with_capacity(bytes.len());
+        for &byte in bytes {
+            buffer.push(byte);
+        }
+        buffer
+    }
+
+    /// Append a byte; returns false (and drops the byte) if the buffer is full
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == self.capacity {
+            return false;
+        }
+        // SAFETY: len is smaller than the capacity, which fits in the mapping
+        unsafe { self.data.add(self.len).write(byte) };
+        self.len += 1;
+        true
+    }
+
+    /// Remove the last byte, if any
+    pub fn pop(&mut self) {
+        if self.len > 0 {
+            self.len -= 1;
+            // SAFETY: the byte is in the mapping
+            unsafe { self.data.add(self.len).write_volatile(0) };
+        }
+    }
+
+    /// Remove all bytes, overwriting them
+    pub fn clear(&mut self) {
+        self.wipe();
+        self.len = 0;
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.data.is_null() {
+            return &[];
+        }
+        // SAFETY: the first len bytes have been written
+        unsafe { slice::from_raw_parts(self.data, self.len) }
+    }
+
+    /// The memory of the buffer, for a C function that works in all of its capacity; it is wiped
+    /// like the rest of the contents
+    #[cfg(feature = "crypt")]
+    pub(crate) fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn wipe(&mut self) {
+        if self.data.is_null() {
+            return;
+        }
+        // SAFETY: the mapping is writable; the fence keeps the compiler from leaving out the
+        // writes because the memory is not read afterwards
+        unsafe { ptr::write_bytes(self.data, 0, self.mapped) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+fn map(len: usize) -> io::Result<NonNull<u8>> {
+    let data = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if data == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(NonNull::new(data.cast()).expect("mmap returned null"))
+}
+
+impl Drop for SecureBuffer {
+    fn drop(&mut self) {
+        self.wipe();
+        if !self.data.is_null() {
+            let _ = cerr(unsafe { libc::munmap(self.data.cast(), self.mapped) });
+        }
+    }
+}
+
+impl Default for SecureBuffer {
+    fn default() -> SecureBuffer {
+        SecureBuffer::with_capacity(0)
+    }
+}
+
+/// Takes over the contents of a string, which is overwritten
+impl From<String> for SecureBuffer {
+    fn from(text: String) -> SecureBuffer {
+        let mut bytes = text.into_bytes();
+        let buffer = SecureBuffer::from_slice(&bytes);
+        bytes.fill(0);
+        compiler_fence(Ordering::SeqCst);
+        buffer
+    }
+}
+
+/// Never shows the contents
+impl fmt::Debug for SecureBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecureBuffer({} bytes)", self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_capacity() {
+        let mut buffer = SecureBuffer::with_capacity(4);
+        for byte in *b"hunter2" {
+            buffer.push(byte);
+        }
+        assert_eq!(buffer.as_bytes(), b"hunt");
+        buffer.pop();
+        assert_eq!(buffer.as_bytes(), b"hun");
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert!(buffer.push(b'x'));
+
+        assert_eq!(SecureBuffer::default().as_bytes(), b"");
+        let buffer = SecureBuffer::from("hunter2".to_string());
+        assert_eq!((buffer.as_bytes(), buffer.capacity()), (&b"hunter2"[..], 7));
+        assert_eq!(format!("{buffer:?}"), "SecureBuffer(7 bytes)");
+    }
+}
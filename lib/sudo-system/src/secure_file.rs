@@ -0,0 +1,432 @@
+//! File operations that cannot be redirected by other users, for files that sudo opens with root
+//! privileges in places that the invoking user may be able to influence (such as the files edited
+//! with sudoedit). Paths are resolved one component at a time relative to the directory opened
+//! before, and symbolic links are never followed, so a path cannot be swapped for a link between
+//! checking and using it. Files with more than one hard link are refused as well, since another
+//! name for the file could be in a place that the user controls.
+
+use std::{
+    ffi::{CString, OsStr},
+    fs::File,
+    io,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Component, Path},
+};
+
+use crate::cerr;
+
+fn cstring(name: &OsStr) -> io::Result<CString> {
+    CString::new(name.as_bytes()).map_err(|_| io::ErrorKind::InvalidInput.into())
+}
+
+/// Check that `name` is a single path component, so it names an entry of a directory
+fn entry_name(name: &OsStr) -> io::Result<CString> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => cstring(name),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not a file name: {}", Path::new(name).display()),
+        )),
+    }
+}
+
+fn fstat(fd: &impl AsRawFd) -> io::Result<libc::stat> {
+    let mut stat = MaybeUninit::uninit();
+    cerr(unsafe { libc::fstat(fd.as_raw_fd(), stat.as_mut_ptr()) })?;
+    Ok(unsafe { stat.assume_init() })
+}
+
+/// A directory that was opened without following symbolic links
+#[derive(Debug)]
+pub struct Directory(OwnedFd);
+
+impl Directory {
+    /// Open an absolute path as a directory; none of its components may be a symbolic link
+    pub fn open(path: &Path) -> io::Result<Directory> {
+        Directory::walk(path, None)
+    }
+
+    /// Open an absolute path as a directory like [Directory::open], creating the directories on
+    /// the way that do not exist yet with `mode`
+    pub fn create_all(path: &Path, mode: libc::mode_t) -> io::Result<Directory> {
+        Directory::walk(path, Some(mode))
+    }
+
+    fn walk(path: &Path, create: Option<libc::mode_t>) -> io::Result<Directory> {
+        if !path.is_absolute() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("not an absolute path: {}", path.display()),
+            ));
+        }
+
+        let mut dir = Directory::open_at(libc::AT_FDCWD, c"/")?;
+        for component in path.components() {
+            match component {
+                Component::RootDir => {}
+                Component::Normal(name) => {
+                    dir = match create {
+                        Some(mode) => dir.create_subdirectory(name, mode)?,
+                        None => dir.subdirectory(name)?,
+                    }
+                }
+                // ".." could lead out of a directory that was checked
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("path is not canonical: {}", path.display()),
+                    ))
+                }
+            }
+        }
+
+        Ok(dir)
+    }
+
+    fn open_at(dirfd: libc::c_int, name: &std::ffi::CStr) -> io::Result<Directory> {
+        let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+        let fd = cerr(unsafe { libc::openat(dirfd, name.as_ptr(), flags) })?;
+        Ok(Directory(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    /// Open a directory in this directory
+    pub fn subdirectory(&self, name: &OsStr) -> io::Result<Directory> {
+        Directory::open_at(self.0.as_raw_fd(), &entry_name(name)?)
+    }
+
+    /// Open a directory in this directory, creating it with `mode` if it does not exist
+    pub fn create_subdirectory(&self, name: &OsStr, mode: libc::mode_t) -> io::Result<Directory> {
+        let entry = entry_name(name)?;
+        match cerr(unsafe { libc::mkdirat(self.0.as_raw_fd(), entry.as_ptr(), mode) }) {
+            Err(err) if err.kind() != io::ErrorKind::AlreadyExists => Err(err),
+            _ => self.subdirectory(name),
+        }
+    }
+
+    /// Give this directory to a user and group, with `mode`
+    pub fn set_ownership(
+        &self,
+        uid: libc::uid_t,
+        gid: libc::gid_t,
+        mode: libc::mode_t,
+    ) -> io::Result<()> {
+        cerr(unsafe { libc::fchown(self.0.as_raw_fd(), uid, gid) })?;
+        cerr(unsafe { libc::fchmod(self.0.as_raw_fd(), mode) })?;
+        Ok(())
+    }
+
+    /// The status of this directory, as `fstat` reports it
+    pub fn metadata(&self) -> io::Result<libc::stat> {
+        fstat(&self.0)
+    }
+
+    /// Refuse directories in which the user with `uid` could replace files: directories that a
+    /// group or the world can write to (even with the sticky bit set, the user could still replace
+    /// their own files), and directories that belong to the user, who can make them writable at
+    /// any time. With `allow_writable`, the caller accepts such directories (like the original
+    /// sudo does for sudoedit with `!sudoedit_checkdir`).
+    pub fn check_not_writable_by(&self, uid: libc::uid_t, allow_writable: bool) -> io::Result<()> {
+        if allow_writable {
+            return Ok(());
+        }
+        let stat = fstat(&self.0)?;
+        let shared_writable = stat.st_mode & 0o022 != 0;
+        let owned_by_user = uid != 0 && stat.st_uid == uid;
+        if shared_writable || owned_by_user {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "directory is writable by other users",
+            ));
+        }
+        Ok(())
+    }
+
+    fn open_entry(&self, name: &OsStr, flags: libc::c_int, mode: libc::mode_t) -> io::Result<File> {
+        let name = entry_name(name)?;
+        let flags = flags | libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC;
+        let fd = cerr(unsafe {
+            libc::openat(
+                self.0.as_raw_fd(),
+                name.as_ptr(),
+                flags,
+                libc::c_uint::from(mode),
+            )
+        })?;
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        let stat = fstat(&file)?;
+        if stat.st_mode & libc::S_IFMT != libc::S_IFREG {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not a regular file",
+            ));
+        }
+        if stat.st_nlink > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "file has more than one hard link",
+            ));
+        }
+
+        // O_NONBLOCK was only there to not hang on a FIFO
+        let flags = cerr(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+        cerr(unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) })?;
+        Ok(file)
+    }
+
+    /// Open an existing regular file in this directory for reading
+    pub fn open_file(&self, name: &OsStr) -> io::Result<File> {
+        self.open_entry(name, libc::O_RDONLY, 0)
+    }
+
+    /// Open an existing regular file in this directory for reading and writing
+    pub fn open_for_update(&self, name: &OsStr) -> io::Result<File> {
+        self.open_entry(name, libc::O_RDWR, 0)
+    }
+
+    /// Open an existing regular file in this directory for writing; when `truncate` is false,
+    /// data is appended instead
+    pub fn open_for_writing(&self, name: &OsStr, truncate: bool) -> io::Result<File> {
+        if truncate {
+            // O_TRUNC would take effect before the file could be checked
+            let file = self.open_entry(name, libc::O_WRONLY, 0)?;
+            file.set_len(0)?;
+            Ok(file)
+        } else {
+            self.open_entry(name, libc::O_WRONLY | libc::O_APPEND, 0)
+        }
+    }
+
+    /// Open a regular file in this directory for writing, creating it with `mode` if it does not
+    /// exist; when `truncate` is false, data is appended instead
+    pub fn open_or_create(
+        &self,
+        name: &OsStr,
+        mode: libc::mode_t,
+        truncate: bool,
+    ) -> io::Result<File> {
+        if truncate {
+            let file = self.open_entry(name, libc::O_WRONLY | libc::O_CREAT, mode)?;
+            file.set_len(0)?;
+            Ok(file)
+        } else {
+            self.open_entry(name, libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND, mode)
+        }
+    }
+
+    /// Create a new file in this directory; this fails if anything with that name already exists
+    pub fn create(&self, name: &OsStr, mode: libc::mode_t) -> io::Result<File> {
+        self.open_entry(name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL, mode)
+    }
+
+    /// Rename a file within this directory, replacing `to` if it exists
+    pub fn rename(&self, from: &OsStr, to: &OsStr) -> io::Result<()> {
+        let (from, to) = (entry_name(from)?, entry_name(to)?);
+        let fd = self.0.as_raw_fd();
+        cerr(unsafe { libc::renameat(fd, from.as_ptr(), fd, to.as_ptr()) })?;
+        Ok(())
+    }
+
+    /// Remove a file (or a symbolic link, which is not followed) from this directory
+    pub fn remove(&self, name: &OsStr) -> io::Result<()> {
+        let name = entry_name(name)?;
+        cerr(unsafe { libc::unlinkat(self.0.as_raw_fd(), name.as_ptr(), 0) })?;
+        Ok(())
+    }
+}
+
+/// Open the directory that contains `path`, and return it with the name of the file in it
+pub fn open_parent(path: &Path) -> io::Result<(Directory, &OsStr)> {
+    match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => Ok((Directory::open(parent)?, name)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not a file: {}", path.display()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        os::unix::fs::{symlink, PermissionsExt},
+        path::PathBuf,
+    };
+
+    use super::*;
+
+    /// A scratch directory, removed again at the end of the test
+    struct Scratch(PathBuf);
+
+    impl Scratch {
+        fn new(name: &str) -> Scratch {
+            let path = std::env::temp_dir()
+                .canonicalize()
+                .unwrap()
+                .join(format!("sudo-system-{name}-{}", std::process::id()));
+            fs::create_dir_all(&path).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            Scratch(path)
+        }
+    }
+
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn plain_files() {
+        let scratch = Scratch::new("plain");
+        fs::write(scratch.0.join("file"), "contents").unwrap();
+
+        let path = scratch.0.join("file");
+        let (dir, name) = open_parent(&path).unwrap();
+        let mut text = String::new();
+        dir.open_file(name)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "contents");
+
+        let mut new = dir.create("new".as_ref(), 0o600).unwrap();
+        new.write_all(b"edited").unwrap();
+        dir.rename("new".as_ref(), name).unwrap();
+        assert_eq!(
+            fs::read_to_string(scratch.0.join("file")).unwrap(),
+            "edited"
+        );
+        dir.remove(name).unwrap();
+        assert!(!scratch.0.join("file").exists());
+    }
+
+    #[test]
+    fn symlink_attacks() {
+        let scratch = Scratch::new("symlink");
+        fs::write(scratch.0.join("secret"), "root only").unwrap();
+        fs::create_dir(scratch.0.join("real")).unwrap();
+        symlink(scratch.0.join("secret"), scratch.0.join("link")).unwrap();
+        symlink(scratch.0.join("real"), scratch.0.join("linked-dir")).unwrap();
+
+        let dir = Directory::open(&scratch.0).unwrap();
+        // a link to the file that is to be edited
+        let err = dir.open_file("link".as_ref()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+        let err = dir.open_for_writing("link".as_ref(), true).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+        assert_eq!(
+            fs::read_to_string(scratch.0.join("secret")).unwrap(),
+            "root only"
+        );
+
+        // a link somewhere on the way to the file
+        assert!(Directory::open(&scratch.0.join("linked-dir")).is_err());
+        assert!(open_parent(&scratch.0.join("linked-dir/file")).is_err());
+        assert!(Directory::open(&scratch.0.join("real/../linked-dir")).is_err());
+
+        // a file that is created where a link was planted
+        let err = dir.create("link".as_ref(), 0o600).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        // names have to stay within the directory
+        assert!(dir.open_file("../secret".as_ref()).is_err());
+        assert!(dir.open_file("..".as_ref()).is_err());
+    }
+
+    #[test]
+    fn created_files() {
+        let scratch = Scratch::new("created");
+        fs::write(scratch.0.join("secret"), "root only").unwrap();
+        symlink(scratch.0.join("secret"), scratch.0.join("link")).unwrap();
+        symlink(&scratch.0, scratch.0.join("linked-dir")).unwrap();
+
+        let dir = Directory::create_all(&scratch.0.join("a/b"), 0o700).unwrap();
+        let mode = fs::metadata(scratch.0.join("a/b")).unwrap().permissions();
+        assert_eq!(mode.mode() & 0o777, 0o700);
+        dir.open_or_create("log".as_ref(), 0o600, false)
+            .unwrap()
+            .write_all(b"one ")
+            .unwrap();
+        dir.open_or_create("log".as_ref(), 0o600, false)
+            .unwrap()
+            .write_all(b"two")
+            .unwrap();
+        let path = scratch.0.join("a/b/log");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one two");
+        dir.open_or_create("log".as_ref(), 0o600, true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+
+        // links are not followed, for files or for directories
+        let dir = Directory::open(&scratch.0).unwrap();
+        let err = dir
+            .open_or_create("link".as_ref(), 0o600, true)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+        assert_eq!(
+            fs::read_to_string(scratch.0.join("secret")).unwrap(),
+            "root only"
+        );
+        assert!(Directory::create_all(&scratch.0.join("linked-dir/c"), 0o700).is_err());
+        assert!(!scratch.0.join("c").exists());
+    }
+
+    #[test]
+    fn hard_link_attacks() {
+        let scratch = Scratch::new("hardlink");
+        fs::write(scratch.0.join("secret"), "root only").unwrap();
+        fs::hard_link(scratch.0.join("secret"), scratch.0.join("alias")).unwrap();
+
+        let dir = Directory::open(&scratch.0).unwrap();
+        let err = dir.open_for_writing("alias".as_ref(), true).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            fs::read_to_string(scratch.0.join("secret")).unwrap(),
+            "root only"
+        );
+    }
+
+    #[test]
+    fn special_files() {
+        let scratch = Scratch::new("special");
+        fs::create_dir(scratch.0.join("dir")).unwrap();
+        let fifo = CString::new(scratch.0.join("fifo").as_os_str().as_bytes()).unwrap();
+        cerr(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }).unwrap();
+
+        let dir = Directory::open(&scratch.0).unwrap();
+        assert!(dir.open_file("dir".as_ref()).is_err());
+        // opening the FIFO does not wait for a writer
+        let err = dir.open_file("fifo".as_ref()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn writable_directories() {
+        let scratch = Scratch::new("writable");
+        let dir = Directory::open(&scratch.0).unwrap();
+        let owner = fs::metadata(&scratch.0).unwrap();
+        let owner = std::os::unix::fs::MetadataExt::uid(&owner);
+        assert!(dir.check_not_writable_by(owner + 1, false).is_ok());
+        if owner != 0 {
+            // even without write permission, the owner could grant it to themselves
+            fs::set_permissions(&scratch.0, fs::Permissions::from_mode(0o500)).unwrap();
+            assert!(dir.check_not_writable_by(owner, false).is_err());
+            assert!(dir.check_not_writable_by(owner, true).is_ok());
+        }
+
+        for mode in [0o770, 0o1777] {
+            fs::set_permissions(&scratch.0, fs::Permissions::from_mode(mode)).unwrap();
+            let err = dir.check_not_writable_by(owner + 1, false).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+            assert!(dir.check_not_writable_by(owner + 1, true).is_ok());
+        }
+        fs::set_permissions(&scratch.0, fs::Permissions::from_mode(0o700)).unwrap();
+    }
+}
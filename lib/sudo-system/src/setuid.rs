@@ -0,0 +1,127 @@
+//! Why sudo does not run with the privileges of root, if it does not: sudo has to be a setuid-root
+//! binary, on a file system that honours the setuid bit, started by a process that may gain
+//! privileges. Without this, sudo would only fail later on, with errors that do not tell why.
+
+use std::{
+    ffi::CString,
+    fmt, fs,
+    mem::MaybeUninit,
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The executable is not owned by root, or does not have the setuid bit set
+    NotSetuidRoot(PathBuf),
+    /// The executable is on a file system that is mounted with the `nosuid` option
+    NosuidMount(PathBuf),
+    /// The no_new_privs flag of the process is set, so the setuid bit has no effect
+    NoNewPrivileges,
+    /// None of the above; for instance, an NFS file system that maps root to nobody
+    Unknown(PathBuf),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NotSetuidRoot(path) => write!(
+                f,
+                "{} must be owned by uid 0 and have the setuid bit set",
+                path.display()
+            ),
+            Problem::NosuidMount(path) => write!(
+                f,
+                "effective uid is not 0, {} is on a file system with the 'nosuid' option set",
+                path.display()
+            ),
+            Problem::NoNewPrivileges => write!(
+                f,
+                "effective uid is not 0, sudo was started with the no_new_privs flag set (as by \
+                 NoNewPrivileges= in a systemd unit, or by a container runtime)"
+            ),
+            Problem::Unknown(path) => write!(
+                f,
+                "effective uid is not 0, is {} on a file system with the 'nosuid' option set or \
+                 an NFS file system without root privileges?",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Why the current process, which runs `executable`, does not have an effective uid of 0 (or
+/// nothing, if it does)
+pub fn check(executable: &Path) -> Option<Problem> {
+    if unsafe { libc::geteuid() } == 0 {
+        return None;
+    }
+    Some(diagnose(executable))
+}
+
+/// The most likely reason why running `executable` did not give the process the privileges of
+/// root; the checks go from what is easiest to fix to what is hardest to find out
+fn diagnose(executable: &Path) -> Problem {
+    let path = executable.to_path_buf();
+    // 0o4000 is the setuid bit, S_ISUID (which is not a u32 on every platform)
+    let setuid_root = fs::metadata(executable)
+        .is_ok_and(|metadata| metadata.uid() == 0 && metadata.mode() & 0o4000 != 0);
+    if !setuid_root {
+        Problem::NotSetuidRoot(path)
+    } else if is_nosuid(executable) {
+        Problem::NosuidMount(path)
+    } else if has_no_new_privs() {
+        Problem::NoNewPrivileges
+    } else {
+        Problem::Unknown(path)
+    }
+}
+
+/// Whether the file system that `path` is on ignores setuid bits
+fn is_nosuid(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    // SAFETY: statvfs succeeded, so it has filled in the struct
+    let stat = unsafe { stat.assume_init() };
+    stat.f_flag & libc::ST_NOSUID != 0
+}
+
+#[cfg(target_os = "linux")]
+fn has_no_new_privs() -> bool {
+    unsafe { libc::prctl(libc::PR_GET_NO_NEW_PRIVS, 0, 0, 0, 0) == 1 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn has_no_new_privs() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diagnoses() {
+        let path = std::env::temp_dir().join(format!("sudo-system-setuid-{}", std::process::id()));
+        fs::write(&path, "").unwrap();
+        assert_eq!(diagnose(&path), Problem::NotSetuidRoot(path.clone()));
+        assert_eq!(
+            diagnose(&path).to_string(),
+            format!(
+                "{} must be owned by uid 0 and have the setuid bit set",
+                path.display()
+            )
+        );
+        assert_eq!(
+            diagnose(Path::new("/nonexistent")),
+            Problem::NotSetuidRoot("/nonexistent".into())
+        );
+        fs::remove_file(&path).unwrap();
+        assert!(!is_nosuid(Path::new("/nonexistent")));
+    }
+}
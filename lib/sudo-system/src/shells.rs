@@ -0,0 +1,58 @@
+//! The login shells that are considered valid on this system, as listed in `/etc/shells`
+
+use std::{fs, io, path::Path};
+
+/// Like `getusershell(3)`, these are the valid shells if there is no `/etc/shells`
+const DEFAULT_SHELLS: &[&str] = &["/bin/sh", "/bin/csh"];
+
+/// The shells listed in the contents of a shells file: one absolute path per line; blank lines,
+/// comments and anything that is not an absolute path are skipped
+fn parse(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| line.starts_with('/'))
+}
+
+/// Whether `shell` is listed in the shells file at `path`
+pub fn is_listed_in(shell: &Path, path: &Path) -> io::Result<bool> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(DEFAULT_SHELLS.iter().any(|valid| shell == Path::new(valid)))
+        }
+        Err(err) => return Err(err),
+    };
+    let listed = parse(&text).any(|valid| shell == Path::new(valid));
+    Ok(listed)
+}
+
+/// Whether `shell` is one of the valid login shells in `/etc/shells`
+pub fn is_valid_shell(shell: &Path) -> io::Result<bool> {
+    is_listed_in(shell, Path::new("/etc/shells"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shells_file() {
+        let path = std::env::temp_dir().join(format!("sudo-system-shells-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# /etc/shells: valid login shells\n/bin/sh\n\n  /usr/bin/zsh  # zsh\nbash\n",
+        )
+        .unwrap();
+        let listed = |shell: &str| is_listed_in(Path::new(shell), &path).unwrap();
+        assert!(listed("/bin/sh"));
+        assert!(listed("/usr/bin/zsh"));
+        assert!(!listed("bash"));
+        assert!(!listed("/bin/bash"));
+        assert!(!listed("/usr/sbin/nologin"));
+        fs::remove_file(&path).unwrap();
+
+        // without a shells file, only the traditional shells are valid
+        assert!(listed("/bin/sh"));
+        assert!(!listed("/usr/bin/zsh"));
+    }
+}
@@ -0,0 +1,40 @@
+//! Signal handling while sudo supervises a command: signals that would otherwise terminate sudo
+//! are passed on to the command instead, so sudo lives long enough to clean up after it. The
+//! supervisor catches them with an [EventLoop](crate::event::EventLoop).
+
+/// Signals that are relayed to the command
+pub const FORWARDED: [libc::c_int; 6] = [
+    libc::SIGHUP,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGUSR1,
+    libc::SIGUSR2,
+];
+
+/// A signal that was caught, and where it came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignalInfo {
+    pub signal: libc::c_int,
+    /// The `si_code` of the signal: positive if the kernel sent it (such as the `SIGINT` of a
+    /// Ctrl-C on the terminal), zero or negative if a process did
+    pub code: libc::c_int,
+    /// The process that sent the signal, if a process did
+    pub pid: libc::pid_t,
+}
+
+impl SignalInfo {
+    /// Whether a process sent the signal (with `kill(2)` or the like), rather than the kernel
+    pub fn sent_by_process(&self) -> bool {
+        self.code <= 0
+    }
+}
+
+/// Terminate the current process with the default action of a signal, so the parent observes
+/// the same exit status as the command had; if the signal does not terminate, this returns.
+pub fn reraise(signal: libc::c_int) {
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
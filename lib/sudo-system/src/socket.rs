@@ -0,0 +1,75 @@
+//! Unix sockets that carry a file descriptor along with their messages, for requests that are
+//! answered on a socket of their own (see the `intercept` module of sudo-common)
+
+use std::{
+    io, mem,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr,
+};
+
+use crate::cerr;
+
+/// A connected pair of sockets that keep messages apart (`SOCK_SEQPACKET`); neither is inherited
+/// by programs that are executed
+pub fn seqpacket_pair() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    let kind = libc::SOCK_SEQPACKET | libc::SOCK_CLOEXEC;
+    cerr(unsafe { libc::socketpair(libc::AF_UNIX, kind, 0, fds.as_mut_ptr()) })?;
+    // SAFETY: socketpair returned two new file descriptors
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Let `fd` be inherited by the program that this process executes; only performs an
+/// async-signal-safe system call, so this can be used between fork and exec
+pub fn set_inheritable(fd: RawFd) -> io::Result<()> {
+    cerr(unsafe { libc::fcntl(fd, libc::F_SETFD, 0) })?;
+    Ok(())
+}
+
+/// Receive a message, and the file descriptor that was sent with it (if any); a message that
+/// does not fit in `buf` is an error. Returns a length of 0 when the other end is closed.
+pub fn receive_with_fd(
+    socket: &impl AsRawFd,
+    buf: &mut [u8],
+) -> io::Result<(usize, Option<OwnedFd>)> {
+    // SAFETY: CMSG_SPACE only computes a size
+    let space = unsafe { libc::CMSG_SPACE(mem::size_of::<RawFd>() as u32) } as usize;
+    // u64 keeps the buffer aligned for the cmsghdr in it
+    let mut control = vec![0u64; space.div_ceil(mem::size_of::<u64>())];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    // SAFETY: all fields of msghdr are integers or pointers, for which zero is valid
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = space as _;
+
+    let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut fd = None;
+    // SAFETY: the control messages are in `control`, as recvmsg has described them in `msg`
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_RIGHTS {
+            let received = unsafe { ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<RawFd>()) };
+            // SAFETY: the kernel has installed this file descriptor for this process
+            fd = Some(unsafe { OwnedFd::from_raw_fd(received) });
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    if msg.msg_flags & (libc::MSG_TRUNC | libc::MSG_CTRUNC) != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message is too long",
+        ));
+    }
+    Ok((len as usize, fd))
+}
@@ -0,0 +1,89 @@
+//! Messages to the system logger. sudo logs under its own name, to the facility selected by the
+//! `syslog` setting.
+
+use std::ffi::CString;
+
+/// Facilities that can be selected by name
+const FACILITIES: [(&str, libc::c_int); 12] = [
+    ("auth", libc::LOG_AUTH),
+    ("authpriv", libc::LOG_AUTHPRIV),
+    ("daemon", libc::LOG_DAEMON),
+    ("user", libc::LOG_USER),
+    ("local0", libc::LOG_LOCAL0),
+    ("local1", libc::LOG_LOCAL1),
+    ("local2", libc::LOG_LOCAL2),
+    ("local3", libc::LOG_LOCAL3),
+    ("local4", libc::LOG_LOCAL4),
+    ("local5", libc::LOG_LOCAL5),
+    ("local6", libc::LOG_LOCAL6),
+    ("local7", libc::LOG_LOCAL7),
+];
+
+/// Priorities that can be selected by name
+const PRIORITIES: [(&str, libc::c_int); 8] = [
+    ("alert", libc::LOG_ALERT),
+    ("crit", libc::LOG_CRIT),
+    ("debug", libc::LOG_DEBUG),
+    ("emerg", libc::LOG_EMERG),
+    ("err", libc::LOG_ERR),
+    ("info", libc::LOG_INFO),
+    ("notice", libc::LOG_NOTICE),
+    ("warning", libc::LOG_WARNING),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Facility(libc::c_int);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Priority(libc::c_int);
+
+impl Facility {
+    pub const AUTHPRIV: Facility = Facility(libc::LOG_AUTHPRIV);
+
+    pub fn from_name(name: &str) -> Option<Facility> {
+        FACILITIES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|&(_, facility)| Facility(facility))
+    }
+}
+
+impl Priority {
+    pub const NOTICE: Priority = Priority(libc::LOG_NOTICE);
+    pub const ALERT: Priority = Priority(libc::LOG_ALERT);
+
+    pub fn from_name(name: &str) -> Option<Priority> {
+        PRIORITIES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|&(_, priority)| Priority(priority))
+    }
+}
+
+/// Send a message to the system logger
+pub fn log(facility: Facility, priority: Priority, message: &str) {
+    // a message cannot contain NUL bytes; the logger would cut it short anyway
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    unsafe {
+        libc::openlog(c"sudo".as_ptr(), 0, facility.0);
+        libc::syslog(priority.0, c"%s".as_ptr(), message.as_ptr());
+        libc::closelog();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(Facility::from_name("authpriv"), Some(Facility::AUTHPRIV));
+        assert_eq!(
+            Facility::from_name("local7"),
+            Some(Facility(libc::LOG_LOCAL7))
+        );
+        assert_eq!(Facility::from_name("kern"), None);
+        assert_eq!(Priority::from_name("alert"), Some(Priority::ALERT));
+        assert_eq!(Priority::from_name("loud"), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Low-level access to the controlling terminal, used for reading passwords.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    mem::MaybeUninit,
+    os::fd::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+    time::Duration,
+};
+
+use crate::cerr;
+
+/// The controlling terminal of the process (i.e. `/dev/tty`)
+pub struct Terminal {
+    tty: File,
+}
+
+/// While this guard exists, the terminal does not echo input, delivers it byte by byte, and does
+/// not generate signals for keys such as ^C; the original settings are restored on drop.
+pub struct RawMode<'a> {
+    terminal: &'a Terminal,
+    saved: libc::termios,
+}
+
+impl Terminal {
+    pub fn open() -> io::Result<Terminal> {
+        let tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        Ok(Terminal { tty })
+    }
+
+    fn get_attributes(&self) -> io::Result<libc::termios> {
+        let mut term = MaybeUninit::uninit();
+        cerr(unsafe { libc::tcgetattr(self.tty.as_raw_fd(), term.as_mut_ptr()) })?;
+        Ok(unsafe { term.assume_init() })
+    }
+
+    fn set_attributes(&self, term: &libc::termios) -> io::Result<()> {
+        cerr(unsafe { libc::tcsetattr(self.tty.as_raw_fd(), libc::TCSADRAIN, term) })?;
+        Ok(())
+    }
+
+    pub fn raw_mode(&self) -> io::Result<RawMode<'_>> {
+        let saved = self.get_attributes()?;
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ECHO | libc::ECHONL | libc::ICANON | libc::ISIG | libc::IEXTEN);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        self.set_attributes(&raw)?;
+
+        Ok(RawMode {
+            terminal: self,
+            saved,
+        })
+    }
+
+    /// The width of the terminal in columns, if known
+    pub fn width(&self) -> Option<usize> {
+        let mut size = MaybeUninit::<libc::winsize>::uninit();
+        cerr(unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCGWINSZ, size.as_mut_ptr()) })
+            .ok()?;
+        let size = unsafe { size.assume_init() };
+        (size.ws_col > 0).then_some(size.ws_col as usize)
+    }
+
+    /// Wait until input is available, for at most `timeout`; returns `false` if no input arrived,
+    /// which can also happen because a signal was received in the meantime.
+    pub fn wait_for_input(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.tty.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match cerr(unsafe { libc::poll(&mut fds, 1, millis) }) {
+            Ok(ready) => Ok(ready > 0),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Read a single byte; `None` signals the end of input
+    pub fn read_byte(&self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match (&self.tty).read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Read a line of input (using the current terminal settings), without the line terminator
+    pub fn read_line(&self) -> io::Result<String> {
+        let mut line = Vec::new();
+        while let Some(byte) = self.read_byte()? {
+            if byte == b'\n' {
+                break;
+            }
+            line.push(byte);
+        }
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    pub fn write(&self, text: &str) -> io::Result<()> {
+        let mut tty = &self.tty;
+        tty.write_all(text.as_bytes())?;
+        tty.flush()
+    }
+}
+
+impl Drop for RawMode<'_> {
+    fn drop(&mut self) {
+        let _ = self.terminal.set_attributes(&self.saved);
+    }
+}
+
+static WINDOW_CHANGED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigwinch(_: libc::c_int) {
+    WINDOW_CHANGED.store(true, Ordering::SeqCst);
+}
+
+/// Start noticing changes to the terminal size (SIGWINCH); the handler is installed without
+/// SA_RESTART, so a pending [Terminal::wait_for_input] returns early when the size changes.
+pub fn watch_window_size() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigwinch as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut());
+    });
+}
+
+/// The name of the terminal on standard input without the `/dev/` prefix (e.g. `pts/0`), as it
+/// is shown in log messages
+pub fn tty_name() -> Option<String> {
+    let mut buf = [0; 256];
+    if unsafe { libc::ttyname_r(0, buf.as_mut_ptr(), buf.len()) } != 0 {
+        return None;
+    }
+    let name = crate::string_from_ptr(buf.as_ptr());
+    Some(name.strip_prefix("/dev/").unwrap_or(&name).to_string())
+}
+
+/// Whether the terminal size changed since the last call of this function
+pub fn window_changed() -> bool {
+    WINDOW_CHANGED.swap(false, Ordering::SeqCst)
+}
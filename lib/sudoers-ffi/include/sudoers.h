@@ -0,0 +1,67 @@
+/* C interface to the sudo-rs sudoers policy library (libsudoers_ffi) */
+#ifndef SUDOERS_H
+#define SUDOERS_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define SUDOERS_DENIED 0
+#define SUDOERS_ALLOWED 1
+#define SUDOERS_ERROR (-1)
+
+/* The version of this interface, see sudoers_abi_version; version 2 only
+ * reports the tag that is in effect of those that undo each other */
+#define SUDOERS_ABI_VERSION 2
+
+/* Of the tags that undo each other (like NOPASSWD and PASSWD), only the one
+ * that is in effect for the command is reported; the options that take a value
+ * are reported by whether the command has them. */
+#define SUDOERS_TAG_NOPASSWD (1u << 0)
+#define SUDOERS_TAG_TIMEOUT (1u << 1)
+#define SUDOERS_TAG_CWD (1u << 2)
+#define SUDOERS_TAG_PASSWD (1u << 3)
+#define SUDOERS_TAG_SETENV (1u << 4)
+#define SUDOERS_TAG_NOSETENV (1u << 5)
+#define SUDOERS_TAG_CHROOT (1u << 6)
+#define SUDOERS_TAG_NOEXEC (1u << 7)
+#define SUDOERS_TAG_EXEC (1u << 8)
+#define SUDOERS_TAG_INTERCEPT (1u << 9)
+#define SUDOERS_TAG_NOINTERCEPT (1u << 10)
+#define SUDOERS_TAG_UMASK (1u << 11)
+#define SUDOERS_TAG_NOTBEFORE (1u << 12)
+#define SUDOERS_TAG_NOTAFTER (1u << 13)
+#define SUDOERS_TAG_LOG_INPUT (1u << 14)
+#define SUDOERS_TAG_NOLOG_INPUT (1u << 15)
+#define SUDOERS_TAG_LOG_OUTPUT (1u << 16)
+#define SUDOERS_TAG_NOLOG_OUTPUT (1u << 17)
+#define SUDOERS_TAG_FOLLOW (1u << 18)
+#define SUDOERS_TAG_NOFOLLOW (1u << 19)
+
+typedef struct SudoersHandle sudoers_t;
+
+/* The version of the interface that the library implements; callers should
+ * check that it is the SUDOERS_ABI_VERSION that they were built against. */
+unsigned sudoers_abi_version(void);
+
+/* Parse a sudoers file; returns NULL if it cannot be read. The number of parse
+ * errors is stored in *errors (if errors is not NULL). */
+sudoers_t *sudoers_compile(const char *path, unsigned *errors);
+
+/* Release a handle returned by sudoers_compile; NULL is allowed. */
+void sudoers_free(sudoers_t *sudoers);
+
+/* Check whether user may run cmdline on host as runas_user:runas_group (if
+ * runas_group is NULL, the primary group of runas_user is used); the words of
+ * cmdline are separated by single spaces, so an argument cannot contain one.
+ * Returns SUDOERS_ALLOWED, SUDOERS_DENIED or SUDOERS_ERROR; when allowed, the
+ * SUDOERS_TAG_* bits of the matching rule are stored in *tags (if not NULL). */
+int sudoers_check(const sudoers_t *sudoers, const char *user,
+                  const char *runas_user, const char *runas_group,
+                  const char *host, const char *cmdline, unsigned *tags);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SUDOERS_H */
@@ -0,0 +1,284 @@
+//! A C interface to the sudoers parser and policy evaluation, so that existing C-based tools can
+//! query a sudoers file without spawning a `sudo` process. See `include/sudoers.h` for the C side.
+//!
+//! All functions are safe to call with NULL pointers (they will report an error), and never unwind
+//! across the FFI boundary.
+
+use std::ffi::CStr;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+
+use libc::{c_char, c_int, c_uint};
+use sudo_common::{clock::Monotonic, context::resolve_directory};
+use sudo_system::{Group, User};
+use sudoers::{Sudoers, Tag};
+
+pub const SUDOERS_DENIED: c_int = 0;
+pub const SUDOERS_ALLOWED: c_int = 1;
+pub const SUDOERS_ERROR: c_int = -1;
+
+/// The version of this interface, as returned by [sudoers_abi_version]; it changes whenever a
+/// function or constant changes in a way that existing callers would notice. Version 2 only
+/// reports the tag that is in effect of those that undo each other.
+pub const SUDOERS_ABI_VERSION: c_uint = 2;
+
+/// Bits reported through the `tags` out-parameter of [sudoers_check]. Of the tags that undo each
+/// other (like NOPASSWD and PASSWD), only the one that is in effect for the command is reported.
+pub const SUDOERS_TAG_NOPASSWD: c_uint = 1 << 0;
+/// The options that take a value are reported by whether the command has them
+pub const SUDOERS_TAG_TIMEOUT: c_uint = 1 << 1;
+pub const SUDOERS_TAG_CWD: c_uint = 1 << 2;
+pub const SUDOERS_TAG_PASSWD: c_uint = 1 << 3;
+pub const SUDOERS_TAG_SETENV: c_uint = 1 << 4;
+pub const SUDOERS_TAG_NOSETENV: c_uint = 1 << 5;
+pub const SUDOERS_TAG_CHROOT: c_uint = 1 << 6;
+pub const SUDOERS_TAG_NOEXEC: c_uint = 1 << 7;
+pub const SUDOERS_TAG_EXEC: c_uint = 1 << 8;
+pub const SUDOERS_TAG_INTERCEPT: c_uint = 1 << 9;
+pub const SUDOERS_TAG_NOINTERCEPT: c_uint = 1 << 10;
+pub const SUDOERS_TAG_UMASK: c_uint = 1 << 11;
+pub const SUDOERS_TAG_NOTBEFORE: c_uint = 1 << 12;
+pub const SUDOERS_TAG_NOTAFTER: c_uint = 1 << 13;
+pub const SUDOERS_TAG_LOG_INPUT: c_uint = 1 << 14;
+pub const SUDOERS_TAG_NOLOG_INPUT: c_uint = 1 << 15;
+pub const SUDOERS_TAG_LOG_OUTPUT: c_uint = 1 << 16;
+pub const SUDOERS_TAG_NOLOG_OUTPUT: c_uint = 1 << 17;
+pub const SUDOERS_TAG_FOLLOW: c_uint = 1 << 18;
+pub const SUDOERS_TAG_NOFOLLOW: c_uint = 1 << 19;
+
+/// Opaque handle to a compiled sudoers file
+pub struct SudoersHandle(Sudoers);
+
+/// The version of the interface that this library implements, see [SUDOERS_ABI_VERSION]
+#[no_mangle]
+pub extern "C" fn sudoers_abi_version() -> c_uint {
+    SUDOERS_ABI_VERSION
+}
+
+/// Convert a (non-null) C string to a `&str`; fails on NULL or invalid UTF-8
+unsafe fn to_str<'a>(ptr: *const c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        None
+    } else {
+        CStr::from_ptr(ptr).to_str().ok()
+    }
+}
+
+/// Parse the sudoers file at `path`. Returns NULL if the file could not be read; otherwise the
+/// number of (non-fatal) parse errors is stored in `errors` (if it is not NULL).
+///
+/// # Safety
+/// `path` must be NULL or point to a NUL-terminated string, and `errors` must be NULL or point to
+/// writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sudoers_compile(
+    path: *const c_char,
+    errors: *mut c_uint,
+) -> *mut SudoersHandle {
+    let Some(path) = to_str(path) else {
+        return std::ptr::null_mut();
+    };
+
+    match catch_unwind(|| sudoers::compile(path)) {
+        Ok(Ok((sudoers, diagnostics))) => {
+            if !errors.is_null() {
+                let is_error = |diagnostic: &&_| !matches!(diagnostic, sudoers::Error::Warning(_));
+                *errors = diagnostics.iter().filter(is_error).count() as c_uint;
+            }
+            Box::into_raw(Box::new(SudoersHandle(sudoers)))
+        }
+        _ => std::ptr::null_mut(),
+    }
+}
+
+/// Release a handle obtained from [sudoers_compile]; passing NULL is a no-op.
+///
+/// # Safety
+/// `handle` must be NULL or a pointer returned by [sudoers_compile] that was not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn sudoers_free(handle: *mut SudoersHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Check whether `user` may run `cmdline` on `host` as `runas_user` (and `runas_group`; if this
+/// is NULL the primary group of `runas_user` is used). Users and groups are looked up in the
+/// system user database. The words of `cmdline` (the command and its arguments) are separated by
+/// single spaces, so an argument cannot contain a space. Like sudo, this resolves the directory of
+/// the command through symbolic links, so rules for `/usr/bin/ls` also match `/bin/ls` when
+/// `/bin` links to `/usr/bin`.
+///
+/// Returns [SUDOERS_ALLOWED] or [SUDOERS_DENIED]; when allowed, the tags attached to the matching
+/// command are stored in `tags` (if it is not NULL). Returns [SUDOERS_ERROR] if an argument is
+/// invalid or a user or group cannot be found.
+///
+/// # Safety
+/// `handle` must be NULL or a live pointer returned by [sudoers_compile]; string arguments must be
+/// NULL or point to NUL-terminated strings; `tags` must be NULL or point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn sudoers_check(
+    handle: *const SudoersHandle,
+    user: *const c_char,
+    runas_user: *const c_char,
+    runas_group: *const c_char,
+    host: *const c_char,
+    cmdline: *const c_char,
+    tags: *mut c_uint,
+) -> c_int {
+    let (Some(SudoersHandle(sudoers)), Some(user), Some(runas_user), Some(host), Some(cmdline)) = (
+        handle.as_ref(),
+        to_str(user),
+        to_str(runas_user),
+        to_str(host),
+        to_str(cmdline),
+    ) else {
+        return SUDOERS_ERROR;
+    };
+    let runas_group = if runas_group.is_null() {
+        None
+    } else if let Some(name) = to_str(runas_group) {
+        Some(name)
+    } else {
+        return SUDOERS_ERROR;
+    };
+
+    let outcome = catch_unwind(AssertUnwindSafe(|| {
+        let current_user = User::from_name(user).ok()??.with_groups();
+        let target_user = User::from_name(runas_user).ok()??.with_groups();
+        let target_group = match runas_group {
+            Some(name) => Group::from_name(name).ok()??,
+            None => Group::from_gid(target_user.gid).ok()??,
+        };
+
+        let argv = cmdline.split(' ').map(str::to_string).collect::<Vec<_>>();
+        let resolved = resolve_directory(Path::new(&argv[0]), Path::new("/"))
+            .map(|path| path.display().to_string());
+        Some(sudoers::check_permission(
+            sudoers,
+            &current_user,
+            sudoers::Request {
+                user: &target_user,
+                group: &target_group,
+                clock: &Monotonic,
+                resolved_command: resolved.as_deref(),
+            },
+            host,
+            &argv,
+        ))
+    }));
+
+    match outcome {
+        Ok(Some(Some(found))) => {
+            if !tags.is_null() {
+                *tags = tag_bits(&found);
+            }
+            SUDOERS_ALLOWED
+        }
+        Ok(Some(None)) => SUDOERS_DENIED,
+        _ => SUDOERS_ERROR,
+    }
+}
+
+fn tag_bits(tags: &[Tag]) -> c_uint {
+    let in_effect =
+        |(i, tag): &(usize, &Tag)| !tags[i + 1..].iter().any(|later| tag.same_kind(later));
+    tags.iter()
+        .enumerate()
+        .filter(in_effect)
+        .fold(0, |bits, (_, tag)| {
+            bits | match tag {
+                Tag::NoPasswd => SUDOERS_TAG_NOPASSWD,
+                Tag::Passwd => SUDOERS_TAG_PASSWD,
+                Tag::SetEnv => SUDOERS_TAG_SETENV,
+                Tag::NoSetEnv => SUDOERS_TAG_NOSETENV,
+                Tag::NoExec => SUDOERS_TAG_NOEXEC,
+                Tag::Exec => SUDOERS_TAG_EXEC,
+                Tag::Intercept => SUDOERS_TAG_INTERCEPT,
+                Tag::NoIntercept => SUDOERS_TAG_NOINTERCEPT,
+                Tag::Timeout(_) => SUDOERS_TAG_TIMEOUT,
+                Tag::Cwd(_) => SUDOERS_TAG_CWD,
+                Tag::Chroot(_) => SUDOERS_TAG_CHROOT,
+                Tag::Umask(_) => SUDOERS_TAG_UMASK,
+                Tag::NotBefore(_) => SUDOERS_TAG_NOTBEFORE,
+                Tag::NotAfter(_) => SUDOERS_TAG_NOTAFTER,
+                Tag::LogInput => SUDOERS_TAG_LOG_INPUT,
+                Tag::NoLogInput => SUDOERS_TAG_NOLOG_INPUT,
+                Tag::LogOutput => SUDOERS_TAG_LOG_OUTPUT,
+                Tag::NoLogOutput => SUDOERS_TAG_NOLOG_OUTPUT,
+                Tag::Follow => SUDOERS_TAG_FOLLOW,
+                Tag::NoFollow => SUDOERS_TAG_NOFOLLOW,
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::ptr::null;
+
+    #[test]
+    fn null_arguments() {
+        unsafe {
+            assert!(sudoers_compile(null(), std::ptr::null_mut()).is_null());
+            let path = CString::new("/nonexistent/sudoers").unwrap();
+            assert!(sudoers_compile(path.as_ptr(), std::ptr::null_mut()).is_null());
+            let root = CString::new("root").unwrap();
+            assert_eq!(
+                sudoers_check(
+                    null(),
+                    root.as_ptr(),
+                    root.as_ptr(),
+                    null(),
+                    root.as_ptr(),
+                    root.as_ptr(),
+                    std::ptr::null_mut()
+                ),
+                SUDOERS_ERROR
+            );
+            sudoers_free(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn check_root() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let policy = "root ALL=(ALL:ALL) NOPASSWD: /bin/ls, PASSWD: TIMEOUT=10 CWD=* /bin/cat, NOPASSWD: /bin/echo\n";
+        std::io::Write::write_all(&mut file, policy.as_bytes()).unwrap();
+        let cstr = |s: &str| CString::new(s).unwrap();
+        let (path, root, host) = (
+            cstr(file.path().to_str().unwrap()),
+            cstr("root"),
+            cstr("host"),
+        );
+        unsafe {
+            let mut errors = 0;
+            let handle = sudoers_compile(path.as_ptr(), &mut errors);
+            assert!(!handle.is_null());
+            assert_eq!(errors, 0);
+
+            let check = |cmd: &str, tags: &mut c_uint| {
+                let cmd = cstr(cmd);
+                let (root, host) = (root.as_ptr(), host.as_ptr());
+                sudoers_check(handle, root, root, null(), host, cmd.as_ptr(), tags)
+            };
+            let mut tags = 0;
+            assert_eq!(check("/bin/ls", &mut tags), SUDOERS_ALLOWED);
+            assert_eq!(tags, SUDOERS_TAG_NOPASSWD);
+            assert_eq!(check("/bin/cat", &mut tags), SUDOERS_ALLOWED);
+            assert_eq!(
+                tags,
+                SUDOERS_TAG_PASSWD | SUDOERS_TAG_TIMEOUT | SUDOERS_TAG_CWD
+            );
+            assert_eq!(check("/bin/rm", &mut tags), SUDOERS_DENIED);
+
+            sudoers_free(handle);
+        }
+    }
+
+    #[test]
+    fn abi_version() {
+        assert_eq!(sudoers_abi_version(), SUDOERS_ABI_VERSION);
+    }
+}
@@ -0,0 +1,51 @@
+//! Compare checking a request against a policy of trivial rules (which takes the fast path) with
+//! checking it against the same policy plus one rule that forces the general algorithm.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use sudo_common::clock::Monotonic;
+use sudoers::{check_permission, Request, Sudoers};
+
+const POLICY: &str = "root ALL=(ALL:ALL) ALL\n%sudo ALL=(ALL:ALL) ALL\nuser ALL=(ALL) ALL\n";
+
+fn compile(text: &str) -> Sudoers {
+    let path = std::env::temp_dir().join(format!("sudoers-bench-{}", std::process::id()));
+    std::fs::write(&path, text).unwrap();
+    let (sudoers, diagnostics) = sudoers::compile(&path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert!(diagnostics.is_empty());
+    sudoers
+}
+
+fn bench(c: &mut Criterion) {
+    let policies = [
+        ("trivial", compile(POLICY)),
+        (
+            "general",
+            compile(&format!("{POLICY}nobody ALL=/usr/bin/false\n")),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("check_permission");
+    for (name, sudoers) in &policies {
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                check_permission(
+                    black_box(sudoers),
+                    &"user",
+                    Request {
+                        user: &"root",
+                        group: &(0, "root"),
+                        clock: &Monotonic,
+                        resolved_command: None,
+                    },
+                    "localhost",
+                    black_box(&["/usr/bin/id".to_string(), "-u".to_string()]),
+                )
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
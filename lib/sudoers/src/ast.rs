@@ -1,10 +1,12 @@
 use crate::basic_parser::*;
+use crate::defaults::{self, SettingKind};
 use crate::tokens::*;
 use std::iter::Peekable;
 
 /// The Sudoers file allows negating items with the exclamation mark.
 #[derive(Debug)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Qualified<T> {
     Allow(T),
     Forbid(T),
@@ -17,6 +19,7 @@ pub type SpecList<T> = Vec<Spec<T>>;
 /// An identifier is a name or a #number
 #[derive(Debug)]
 #[cfg_attr(test, derive(Clone, PartialEq, Eq))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Identifier {
     Name(String),
     ID(libc::gid_t),
@@ -25,54 +28,142 @@ pub enum Identifier {
 /// A userspecifier is either a username, or a (non-unix) group name, or netgroup
 #[derive(Debug)]
 #[cfg_attr(test, derive(Clone, PartialEq, Eq))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum UserSpecifier {
     User(Identifier),
     Group(Identifier),
+    /// A group that is not a Unix group, written `%:name`; see [UnixUser::in_nonunix_group]
+    ///
+    /// [UnixUser::in_nonunix_group]: sudo_common::sysuser::UnixUser::in_nonunix_group
     NonunixGroup(Identifier),
 }
 
 /// The RunAs specification consists of a (possibly empty) list of userspecifiers, followed by a (possibly empty) list of groups.
 #[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RunAs {
     pub users: SpecList<UserSpecifier>,
     pub groups: SpecList<Identifier>,
 }
 
 /// Commands in /etc/sudoers can have attributes attached to them, such as NOPASSWD, NOEXEC, ...
+/// Like in the original sudo, a command inherits the tags of the command before it in the same
+/// list, except for those that it overrides with a tag of the same kind (see [Tag::same_kind]).
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Tag {
     NoPasswd,
+    /// Undoes an inherited NOPASSWD
+    Passwd,
+    SetEnv,
+    NoSetEnv,
+    NoExec,
+    Exec,
+    /// Let the command only run the programs that sudoers allows, regardless of the `intercept`
+    /// setting
+    Intercept,
+    NoIntercept,
     Timeout(i32),
+    Cwd(ChDir),
+    Chroot(ChDir),
+    /// The umask for the command; this is an extension to the original sudo
+    Umask(u32),
+    /// The command is only allowed from this time on (in seconds since the epoch)
+    NotBefore(i64),
+    /// The command is only allowed until this time (in seconds since the epoch)
+    NotAfter(i64),
+    /// Record what is typed into the command, regardless of the `log_input` setting
+    LogInput,
+    NoLogInput,
+    /// Record what the command outputs, regardless of the `log_output` setting
+    LogOutput,
+    NoLogOutput,
+    /// Let sudoedit open files through symbolic links; sudo-rs has no sudoedit (nor the
+    /// `sudoedit_follow` setting), so this is only parsed and listed
+    Follow,
+    NoFollow,
+}
+
+impl Tag {
+    /// Whether two tags are about the same thing, like NOPASSWD and PASSWD, or two CWD options,
+    /// so that the one overrides the other
+    pub fn same_kind(&self, other: &Tag) -> bool {
+        use Tag::*;
+        let kind = |tag: &Tag| match tag {
+            NoPasswd | Passwd => 0,
+            SetEnv | NoSetEnv => 1,
+            Timeout(_) => 2,
+            Cwd(_) => 3,
+            Chroot(_) => 4,
+            NoExec | Exec => 5,
+            Intercept | NoIntercept => 6,
+            Umask(_) => 7,
+            NotBefore(_) => 8,
+            NotAfter(_) => 9,
+            LogInput | NoLogInput => 10,
+            LogOutput | NoLogOutput => 11,
+            Follow | NoFollow => 12,
+        };
+        kind(self) == kind(other)
+    }
+
+    /// Whether this tag restricts when a command may be run
+    pub fn is_time_window(&self) -> bool {
+        matches!(self, Tag::NotBefore(_) | Tag::NotAfter(_))
+    }
 }
 
 /// Commands with attached attributes.
 #[derive(Debug)]
-pub struct CommandSpec(pub Vec<Tag>, pub Spec<Command>);
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommandSpec(
+    pub Vec<Tag>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_command"))] pub Spec<Command>,
+);
 
 /// The main AST object for one sudoer-permission line
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PermissionSpec {
     pub users: SpecList<UserSpecifier>,
     pub permissions: Vec<(SpecList<Hostname>, Option<RunAs>, Vec<CommandSpec>)>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Def<T>(pub String, pub SpecList<T>);
 
 /// AST object for directive specifications (aliases, arguments, etc)
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Directive {
     UserAlias(Def<UserSpecifier>),
     HostAlias(Def<Hostname>),
-    CmndAlias(Def<Command>),
+    CmndAlias(#[cfg_attr(feature = "serde", serde(with = "serde_command::def"))] Def<Command>),
     RunasAlias(Def<UserSpecifier>),
-    Defaults(String, DefaultValue),
+    Defaults(String, DefaultValue, ConfigScope),
 }
 
+/// The situations in which a Defaults entry applies
 #[derive(Debug)]
-//TODO: integer values and "boolean context strings/lists/integers"
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConfigScope {
+    Generic,
+    /// `Defaults@host,...`: only when running on one of these hosts
+    Host(SpecList<Hostname>),
+    /// `Defaults:user,%group,...`: only when invoked by one of these users
+    User(SpecList<UserSpecifier>),
+    /// `Defaults>user,%group,...`: only when running a command as one of these users
+    Runas(SpecList<UserSpecifier>),
+    /// `Defaults!/bin/ls,...`: only when running one of these commands
+    Command(#[cfg_attr(feature = "serde", serde(with = "serde_command::list"))] SpecList<Command>),
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum DefaultValue {
     Flag(bool),
+    Integer(i64),
     Text(String),
 
     // encoding: -1 = subtract, 0 = set, +1 = add
@@ -80,6 +171,7 @@ pub enum DefaultValue {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Mode {
     Add,
     Set,
@@ -88,6 +180,7 @@ pub enum Mode {
 
 /// The Sudoers file can contain permissions and directives
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Sudo {
     Spec(PermissionSpec),
     Decl(Directive),
@@ -101,7 +194,6 @@ pub enum Sudo {
 /// identifier = name
 ///            | #<numerical id>
 /// ```
-
 impl Parse for Identifier {
     fn parse(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Self> {
         if accept_if(|c| c == '#', stream).is_ok() {
@@ -123,7 +215,6 @@ impl Many for Identifier {}
 ///
 /// This computes the correct negation with multiple exclamation marks in the parsing stage so we
 /// are not bothered by it later.
-
 impl<T: Parse> Parse for Qualified<T> {
     fn parse(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Self> {
         if is_syntax('!', stream)? {
@@ -150,7 +241,6 @@ impl<T: Many> Many for Qualified<T> {
 }
 
 /// Helper function for parsing Meta<T> things where T is not a token
-
 fn parse_meta<T: Parse>(
     stream: &mut Peekable<impl Iterator<Item = char>>,
     embed: impl FnOnce(String) -> T,
@@ -167,7 +257,6 @@ fn parse_meta<T: Parse>(
 }
 
 /// Since Identifier is not a token, add the parser for Meta<Identifier>
-
 impl Parse for Meta<Identifier> {
     fn parse(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Self> {
         parse_meta(stream, Identifier::Name)
@@ -219,7 +308,7 @@ impl Parse for Meta<UserSpecifier> {
 impl Parse for RunAs {
     fn parse(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Self> {
         try_syntax('(', stream)?;
-        let users = try_nonterminal(stream).unwrap_or_default();
+        let users = maybe(try_nonterminal(stream))?.unwrap_or_default();
         let groups = maybe(try_syntax(':', stream).and_then(|_| try_nonterminal(stream)))?
             .unwrap_or_default();
         expect_syntax(')', stream)?;
@@ -247,11 +336,49 @@ impl Parse for MetaOrTag {
         let Upper(keyword) = try_nonterminal(stream)?;
         let result = match keyword.as_str() {
             "NOPASSWD" => NoPasswd,
+            "PASSWD" => Passwd,
+            "SETENV" => SetEnv,
+            "NOSETENV" => NoSetEnv,
+            "NOEXEC" => NoExec,
+            "EXEC" => Exec,
+            "INTERCEPT" => Intercept,
+            "NOINTERCEPT" => NoIntercept,
+            "LOG_INPUT" => LogInput,
+            "NOLOG_INPUT" => NoLogInput,
+            "LOG_OUTPUT" => LogOutput,
+            "NOLOG_OUTPUT" => NoLogOutput,
+            "FOLLOW" => Follow,
+            "NOFOLLOW" => NoFollow,
             "TIMEOUT" => {
                 expect_syntax('=', stream)?;
                 let Decimal(t) = expect_nonterminal(stream)?;
                 return make(MetaOrTag(Only(Timeout(t))));
             }
+            "CWD" => {
+                expect_syntax('=', stream)?;
+                let path: ChDir = expect_nonterminal(stream)?;
+                return make(MetaOrTag(Only(Cwd(path))));
+            }
+            "CHROOT" => {
+                expect_syntax('=', stream)?;
+                let path: ChDir = expect_nonterminal(stream)?;
+                return make(MetaOrTag(Only(Chroot(path))));
+            }
+            "UMASK" => {
+                expect_syntax('=', stream)?;
+                let Octal(mask) = expect_nonterminal(stream)?;
+                return make(MetaOrTag(Only(Umask(mask))));
+            }
+            "NOTBEFORE" => {
+                expect_syntax('=', stream)?;
+                let Timestamp(time) = expect_nonterminal(stream)?;
+                return make(MetaOrTag(Only(NotBefore(time))));
+            }
+            "NOTAFTER" => {
+                expect_syntax('=', stream)?;
+                let Timestamp(time) = expect_nonterminal(stream)?;
+                return make(MetaOrTag(Only(NotAfter(time))));
+            }
             "ALL" => return make(MetaOrTag(All)),
             alias => return make(MetaOrTag(Alias(alias.to_string()))),
         };
@@ -265,7 +392,6 @@ impl Parse for MetaOrTag {
 /// ```text
 /// commandspec = [tags]*, command
 /// ```
-
 impl Parse for CommandSpec {
     fn parse(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Self> {
         let mut tags = Vec::new();
@@ -295,21 +421,39 @@ impl Many for CommandSpec {}
 /// ```text
 /// (host,runas,commandspec) = hostlist, "=", runas?, commandspec
 /// ```
-
 impl Parse for (SpecList<Hostname>, Option<RunAs>, Vec<CommandSpec>) {
     fn parse(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Self> {
         let hosts = try_nonterminal(stream)?;
         expect_syntax('=', stream)?;
         let runas = maybe(try_nonterminal(stream))?;
-        let cmds = expect_nonterminal(stream)?;
+        let mut cmds: Vec<CommandSpec> = expect_nonterminal(stream)?;
+        inherit_tags(&mut cmds);
 
         make((hosts, runas, cmds))
     }
 }
 
+/// Give every command in a list the tags of the command before it that it does not override; like
+/// in the original sudo, a command of ALL implies SETENV, but only for itself
+fn inherit_tags(cmds: &mut [CommandSpec]) {
+    let mut previous = Vec::new();
+    for CommandSpec(tags, spec) in cmds {
+        let inherited = previous
+            .iter()
+            .filter(|tag: &&Tag| !tags.iter().any(|own| own.same_kind(tag)))
+            .cloned()
+            .collect::<Vec<_>>();
+        tags.splice(0..0, inherited);
+        previous.clone_from(tags);
+
+        if matches!(spec, Qualified::Allow(Meta::All)) && !tags.contains(&Tag::NoSetEnv) {
+            tags.push(Tag::SetEnv);
+        }
+    }
+}
+
 /// A hostname, runas specifier, commandspec combination can occur multiple times in a single
 /// sudoer line (seperated by ":")
-
 impl Many for (SpecList<Hostname>, Option<RunAs>, Vec<CommandSpec>) {
     const SEP: char = ':';
 }
@@ -318,7 +462,6 @@ impl Many for (SpecList<Hostname>, Option<RunAs>, Vec<CommandSpec>) {
 /// ```text
 /// permissionspec = userlist, (host, runas, commandspec), [ ":", (host, runas, commandspec) ]*
 /// ```
-
 #[cfg(test)]
 impl Parse for PermissionSpec {
     fn parse(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Self> {
@@ -337,7 +480,6 @@ impl Parse for PermissionSpec {
 /// ```
 /// There is a syntactical ambiguity in the sudoer Directive and Permission specifications, so we
 /// have to parse them 'together' and do a delayed decision on which category we are in.
-
 impl Parse for Sudo {
     // note: original sudo would reject:
     //   "User_Alias, user machine = command"
@@ -395,7 +537,6 @@ impl Parse for Sudo {
 }
 
 /// Parse the include/include dir part that comes after the '#' or '@' prefix symbol
-
 fn parse_include(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Sudo> {
     let get_path = |stream: &mut _| {
         if accept_if(|c| c == '"', stream).is_ok() {
@@ -419,24 +560,6 @@ fn parse_include(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Su
     make(result)
 }
 
-// temporary stubs
-fn is_bool_param(_name: &str) -> bool {
-    true
-}
-// code currently doesn't recognize integer params,
-// so everything that isn't a list is a string
-#[allow(dead_code)]
-fn is_int_param(_name: &str) -> bool {
-    true
-}
-#[allow(dead_code)]
-fn is_string_param(_name: &str) -> bool {
-    true
-}
-fn is_list_param(_name: &str) -> bool {
-    _name != "secure_path" && _name != "lecture_file"
-}
-
 fn get_directive(
     perhaps_keyword: &Spec<UserSpecifier>,
     stream: &mut Peekable<impl Iterator<Item = char>>,
@@ -445,7 +568,9 @@ fn get_directive(
     use crate::ast::Meta::*;
     use crate::ast::Qualified::*;
     use crate::ast::UserSpecifier::*;
-    let Allow(Only(User(Identifier::Name(keyword)))) = perhaps_keyword else { return reject() };
+    let Allow(Only(User(Identifier::Name(keyword)))) = perhaps_keyword else {
+        return reject();
+    };
 
     /// Parse an alias definition
     fn parse_alias<T>(
@@ -461,17 +586,23 @@ fn get_directive(
         make(ctor(Def(name, expect_nonterminal(stream)?)))
     }
 
-    /// Parse multiple entries enclosed in quotes (for list-like Defaults-settings)
+    /// Parse multiple entries enclosed in quotes (for list-like Defaults-settings); entries are
+    /// separated by whitespace or commas, and can be of the form `NAME=value`
     fn parse_vars(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Vec<String>> {
         if accept_if(|c| c == '"', stream).is_ok() {
             let mut result = Vec::new();
-            while let Some(EnvVar(name)) = try_nonterminal(stream)? {
-                result.push(name);
-                if is_syntax('=', stream)? {
-                    let QuotedText(_) = expect_nonterminal(stream)?;
-                    expect_syntax('"', stream)?;
-                    unrecoverable!("values in environment variables not yet supported")
+            loop {
+                if is_syntax(',', stream)? {
+                    continue;
+                }
+                let Some(EnvVar(mut name)) = try_nonterminal(stream)? else {
+                    break;
+                };
+                if accept_if(|c| c == '=', stream).is_ok() {
+                    let EnvValue(value) = expect_nonterminal(stream)?;
+                    name = format!("{name}={value}");
                 }
+                result.push(name);
             }
             expect_syntax('"', stream)?;
             if result.is_empty() {
@@ -488,23 +619,70 @@ fn get_directive(
 
     /// Parse "Defaults" entries
     fn parse_default(stream: &mut Peekable<impl Iterator<Item = char>>) -> Parsed<Directive> {
+        let scope = if is_syntax('@', stream)? {
+            ConfigScope::Host(expect_nonterminal(stream)?)
+        } else if is_syntax(':', stream)? {
+            ConfigScope::User(expect_nonterminal(stream)?)
+        } else if is_syntax('>', stream)? {
+            ConfigScope::Runas(expect_nonterminal(stream)?)
+        } else if is_syntax('!', stream)? {
+            // `Defaults !flag` can only be told apart from `Defaults!/bin/ls flag` by what follows
+            // the `!`: a command is a path or an alias
+            if !stream
+                .peek()
+                .is_some_and(|c| *c == '/' || *c == '!' || c.is_uppercase())
+            {
+                let EnvVar(name) = expect_nonterminal(stream)?;
+                return make(Defaults(
+                    name,
+                    DefaultValue::Flag(false),
+                    ConfigScope::Generic,
+                ));
+            }
+            let commands: SpecList<CommandName> = expect_nonterminal(stream)?;
+            let unwrap = |meta| match meta {
+                Meta::All => Meta::All,
+                Meta::Alias(name) => Meta::Alias(name),
+                Meta::Only(CommandName(command)) => Meta::Only(command),
+            };
+            let commands = commands.into_iter().map(|spec| match spec {
+                Qualified::Allow(meta) => Qualified::Allow(unwrap(meta)),
+                Qualified::Forbid(meta) => Qualified::Forbid(unwrap(meta)),
+            });
+            ConfigScope::Command(commands.collect())
+        } else {
+            ConfigScope::Generic
+        };
+        let (name, value) = parse_setting(stream)?;
+
+        make(Defaults(name, value, scope))
+    }
+
+    /// Parse the setting in a "Defaults" entry
+    fn parse_setting(
+        stream: &mut Peekable<impl Iterator<Item = char>>,
+    ) -> Parsed<(String, DefaultValue)> {
+        let kind = |name: &String| match defaults::kind(name) {
+            Some(kind) => make(kind),
+            None => unrecoverable!("unknown setting: `{name}'"),
+        };
+
         let bool_setting = |name: String, value: bool| {
-            // TODO: other types in a boolean context
-            if is_bool_param(&name) {
-                make(Defaults(name, DefaultValue::Flag(value)))
-            } else {
+            // any setting can be negated (which unsets it), but only flags can be set this way
+            if value && !matches!(kind(&name)?, SettingKind::Flag { .. }) {
                 unrecoverable!("{name} is not a boolean setting");
             }
+            make((name, DefaultValue::Flag(value)))
         };
 
         let list_items = |mode: Mode, name: String, stream: &mut _| {
             expect_syntax('=', stream)?;
-            if !is_list_param(&name) {
+            if !matches!(kind(&name)?, SettingKind::List { .. }) {
                 unrecoverable!("{name} is not a list parameter");
             }
             let items = parse_vars(stream)?;
 
-            make(Defaults(name, DefaultValue::List(mode, items)))
+            make((name, DefaultValue::List(mode, items)))
         };
 
         if is_syntax('!', stream)? {
@@ -518,9 +696,10 @@ fn get_directive(
             } else if is_syntax('-', stream)? {
                 list_items(Mode::Del, name, stream)
             } else if is_syntax('=', stream)? {
-                if is_list_param(&name) {
+                let kind = kind(&name)?;
+                if let SettingKind::List { .. } = kind {
                     let items = parse_vars(stream)?;
-                    make(Defaults(name, DefaultValue::List(Mode::Set, items)))
+                    make((name, DefaultValue::List(Mode::Set, items)))
                 } else {
                     let text = if accept_if(|c| c == '"', stream).is_ok() {
                         let QuotedText(text) = expect_nonterminal(stream)?;
@@ -530,7 +709,8 @@ fn get_directive(
                         let StringParameter(name) = expect_nonterminal(stream)?;
                         name
                     };
-                    make(Defaults(name, DefaultValue::Text(text)))
+                    let value = defaults::parse_value(&name, kind, text)?;
+                    make((name, value))
                 }
             } else {
                 bool_setting(name, true)
@@ -551,7 +731,6 @@ fn get_directive(
 /// A bit of the hack to make semantic analysis easier: a CommandSpec has attributes, but most
 /// other elements that occur in a [crate::ast::Qualified] wrapper do not.
 /// The [Tagged] trait allows getting these tags (defaulting to `()`, i.e. no attributes)
-
 pub trait Tagged<U> {
     type Flags;
     fn into(&self) -> &Spec<U>;
@@ -561,7 +740,6 @@ pub trait Tagged<U> {
 pub const NO_TAG: &() = &();
 
 /// Default implementation
-
 impl<T> Tagged<T> for Spec<T> {
     type Flags = ();
     fn into(&self) -> &Spec<T> {
@@ -572,7 +750,6 @@ impl<T> Tagged<T> for Spec<T> {
     }
 }
 /// Special implementation for [CommandSpec]
-
 impl Tagged<Command> for CommandSpec {
     type Flags = Vec<Tag>;
     fn into(&self) -> &Spec<Command> {
@@ -582,3 +759,130 @@ impl Tagged<Command> for CommandSpec {
         &self.0
     }
 }
+
+/// Commands are glob patterns, which are (de)serialized as the text they were compiled from
+#[cfg(feature = "serde")]
+pub mod serde_command {
+    use super::*;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    type Text = (String, ArgsText);
+
+    #[derive(Serialize, Deserialize)]
+    pub(crate) enum ArgsText {
+        Exact(Vec<String>),
+        Pattern(String),
+    }
+
+    fn to_text(spec: &Spec<Command>) -> Spec<Text> {
+        let meta = |meta: &Meta<Command>| match meta {
+            Meta::All => Meta::All,
+            Meta::Only((cmd, args)) => Meta::Only((
+                cmd.to_string(),
+                match args {
+                    Args::Exact(words) => ArgsText::Exact(words.clone()),
+                    Args::Pattern(pattern) => ArgsText::Pattern(pattern.to_string()),
+                },
+            )),
+            Meta::Alias(name) => Meta::Alias(name.clone()),
+        };
+        match spec {
+            Qualified::Allow(item) => Qualified::Allow(meta(item)),
+            Qualified::Forbid(item) => Qualified::Forbid(meta(item)),
+        }
+    }
+
+    fn from_text<E: serde::de::Error>(spec: Spec<Text>) -> Result<Spec<Command>, E> {
+        let pattern = |text: &str| glob::Pattern::new(text).map_err(E::custom);
+        let meta = |meta: Meta<Text>| {
+            Ok(match meta {
+                Meta::All => Meta::All,
+                Meta::Only((cmd, args)) => Meta::Only((
+                    pattern(&cmd)?,
+                    match args {
+                        ArgsText::Exact(words) => Args::Exact(words),
+                        ArgsText::Pattern(args) => Args::Pattern(pattern(&args)?),
+                    },
+                )),
+                Meta::Alias(name) => Meta::Alias(name),
+            })
+        };
+        Ok(match spec {
+            Qualified::Allow(item) => Qualified::Allow(meta(item)?),
+            Qualified::Forbid(item) => Qualified::Forbid(meta(item)?),
+        })
+    }
+
+    pub fn serialize<S: Serializer>(spec: &Spec<Command>, s: S) -> Result<S::Ok, S::Error> {
+        to_text(spec).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Spec<Command>, D::Error> {
+        from_text(Spec::<Text>::deserialize(d)?)
+    }
+
+    /// For `Cmnd_Alias` definitions
+    pub mod def {
+        use super::*;
+
+        pub(crate) fn to_text(Def(name, specs): &Def<Command>) -> Def<Text> {
+            Def(name.clone(), specs.iter().map(super::to_text).collect())
+        }
+
+        pub(crate) fn from_text<E: serde::de::Error>(
+            Def(name, specs): Def<Text>,
+        ) -> Result<Def<Command>, E> {
+            let specs = specs.into_iter().map(super::from_text);
+            Ok(Def(name, specs.collect::<Result<_, _>>()?))
+        }
+
+        pub fn serialize<S: Serializer>(def: &Def<Command>, s: S) -> Result<S::Ok, S::Error> {
+            to_text(def).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Def<Command>, D::Error> {
+            from_text(Def::<Text>::deserialize(d)?)
+        }
+    }
+
+    /// For lists of commands, as in `Defaults!` entries
+    pub mod list {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            specs: &SpecList<Command>,
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            specs.iter().map(to_text).collect::<Vec<_>>().serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<SpecList<Command>, D::Error> {
+            let specs = Vec::<Spec<Text>>::deserialize(d)?.into_iter();
+            specs.map(from_text).collect()
+        }
+    }
+
+    /// For the table of `Cmnd_Alias` definitions, together with the order to process them in
+    pub mod table {
+        use super::*;
+
+        type Table<T> = (Vec<usize>, Vec<Def<T>>);
+
+        pub fn serialize<S: Serializer>(
+            (order, defs): &Table<Command>,
+            s: S,
+        ) -> Result<S::Ok, S::Error> {
+            (order, defs.iter().map(def::to_text).collect::<Vec<_>>()).serialize(s)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Table<Command>, D::Error> {
+            let (order, defs) = Table::<Text>::deserialize(d)?;
+            let defs = defs.into_iter().map(def::from_text);
+            let defs = defs.collect::<Result<Vec<_>, _>>()?;
+            if order.iter().any(|&i| i >= defs.len()) {
+                return Err(D::Error::custom("alias order out of range"));
+            }
+            Ok((order, defs))
+        }
+    }
+}
@@ -32,9 +32,11 @@ pub type Parsed<T> = Result<T, Status>;
 
 #[derive(Debug, Clone)]
 #[cfg_attr(test, derive(PartialEq))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Status {
-    Fatal(String), // not recoverable; stream in inconsistent state
-    Reject,        // parsing failed by no input consumed
+    Fatal(String),   // not recoverable; stream in inconsistent state
+    Reject,          // parsing failed by no input consumed
+    Warning(String), // diagnostic about a suspicious (but valid) input; never produced by parsers
 }
 
 pub fn make<T>(value: T) -> Parsed<T> {
@@ -0,0 +1,299 @@
+//! The settings that can be changed with `Defaults` entries in the sudoers file: what type of
+//! value each of them takes, which values are valid, and what it is set to if the sudoers file
+//! does not mention it.
+
+use crate::ast::DefaultValue;
+use crate::basic_parser::{make, unrecoverable, Parsed};
+use sudo_common::{env, prompt};
+
+/// The type of a setting, with its valid values and its default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingKind {
+    /// Set with `Defaults name`, cleared with `Defaults !name`
+    Flag { default: bool },
+    /// A whole number in `min..=max`, written in base `radix`
+    Integer {
+        radix: u32,
+        min: i64,
+        max: i64,
+        default: Option<i64>,
+    },
+    /// A number of minutes in `0..=max`, which may have a fraction; it is kept as it was written
+    Minutes {
+        max: u32,
+        default: Option<&'static str>,
+    },
+    /// One of a fixed set of words
+    Choice {
+        values: &'static [&'static str],
+        default: Option<&'static str>,
+    },
+    /// Free-form text, such as a path
+    Text { default: Option<&'static str> },
+    /// A set of words that can be replaced, added to (`+=`) or removed from (`-=`)
+    List { default: &'static [&'static str] },
+}
+
+use SettingKind::*;
+
+const fn flag(default: bool) -> SettingKind {
+    Flag { default }
+}
+
+const fn integer(min: i64, max: i64, default: i64) -> SettingKind {
+    Integer {
+        radix: 10,
+        min,
+        max,
+        default: Some(default),
+    }
+}
+
+const fn octal(max: i64, default: i64) -> SettingKind {
+    Integer {
+        radix: 8,
+        min: 0,
+        max,
+        default: Some(default),
+    }
+}
+
+const fn minutes(max: u32, default: &'static str) -> SettingKind {
+    Minutes {
+        max,
+        default: Some(default),
+    }
+}
+
+const fn choice(values: &'static [&'static str], default: Option<&'static str>) -> SettingKind {
+    Choice { values, default }
+}
+
+const fn text(default: Option<&'static str>) -> SettingKind {
+    Text { default }
+}
+
+const fn list(default: &'static [&'static str]) -> SettingKind {
+    List { default }
+}
+
+const PASSWORD_POLICIES: &[&str] = &["all", "always", "any", "never"];
+
+const FACILITIES: &[&str] = &[
+    "auth", "authpriv", "daemon", "user", "local0", "local1", "local2", "local3", "local4",
+    "local5", "local6", "local7",
+];
+
+const PRIORITIES: &[&str] = &[
+    "alert", "crit", "debug", "emerg", "err", "info", "notice", "warning",
+];
+
+/// All settings that sudo knows about, sorted by name
+pub const SETTINGS: &[(&str, SettingKind)] = &[
+    ("authenticate", flag(true)),
+    ("debug_flags", text(None)),
+    // an extension to the original sudo: a command that does not run as root can not gain any
+    // capabilities (on Linux), except those in retain_capabilities
+    ("drop_capabilities", flag(true)),
+    ("env_check", list(env::CHECK_ENV_TABLE)),
+    ("env_delete", list(env::DELETE_ENV_TABLE)),
+    ("env_keep", list(env::KEEP_ENV_TABLE)),
+    ("env_reset", flag(true)),
+    ("exempt_group", text(None)),
+    ("exit_with_parent", flag(false)),
+    // an extension to the original sudo, which loads a group_plugin library instead
+    ("group_provider", text(None)),
+    ("intercept", flag(false)),
+    ("iolog_dir", text(None)),
+    ("iolog_file", text(None)),
+    ("iolog_group", text(None)),
+    ("iolog_mode", octal(0o777, 0o600)),
+    ("iolog_user", text(Some("root"))),
+    // `!lecture` is the same as never
+    (
+        "lecture",
+        choice(&["always", "never", "once"], Some("once")),
+    ),
+    ("lecture_file", text(None)),
+    ("listpw", choice(PASSWORD_POLICIES, Some("any"))),
+    ("log_allowed", flag(true)),
+    ("log_denied", flag(true)),
+    ("log_host", flag(false)),
+    ("log_input", flag(false)),
+    ("log_output", flag(false)),
+    ("log_passwords", flag(false)),
+    ("log_year", flag(false)),
+    ("logfile", text(None)),
+    ("mail_badpass", flag(false)),
+    ("mailerflags", text(Some("-t"))),
+    ("mailerpath", text(Some("/usr/sbin/sendmail"))),
+    ("mailto", text(Some("root"))),
+    ("noexec", flag(false)),
+    ("noninteractive_auth", flag(false)),
+    ("passprompt", text(Some(prompt::DEFAULT_PROMPT))),
+    ("passprompt_override", flag(false)),
+    ("passwd_countdown", flag(false)),
+    // an extension to the original sudo: the delay in seconds after a failed authentication, which
+    // doubles with every further failure in a short time
+    ("passwd_delay", integer(0, 300, 2)),
+    // in minutes, and may be fractional
+    ("passwd_timeout", minutes(24 * 60, "5")),
+    ("passwd_tries", integer(1, 1000, 3)),
+    ("path_info", flag(true)),
+    ("requiretty", flag(false)),
+    // an extension to the original sudo: the only capabilities the command keeps (on Linux)
+    ("retain_capabilities", list(&[])),
+    ("rootpw", flag(false)),
+    ("runas_allow_unknown_id", flag(false)),
+    ("runas_check_shell", flag(false)),
+    ("runas_default", text(Some("root"))),
+    ("runaspw", flag(false)),
+    ("runchroot", text(None)),
+    ("runcwd", text(None)),
+    // an extension to the original sudo: run every command in a sandbox, as with `--sandbox`
+    ("sandbox", flag(false)),
+    // an extension to the original sudo: the paths that stay writable in a sandbox
+    ("sandbox_writable", list(&[])),
+    ("secure_path", text(None)),
+    // an extension to the original sudo: pass the home directory of the invoking user to the
+    // command as SUDO_HOME
+    ("set_sudo_home", flag(false)),
+    ("setenv", flag(false)),
+    ("syslog", choice(FACILITIES, Some("authpriv"))),
+    ("syslog_badpri", choice(PRIORITIES, Some("alert"))),
+    ("syslog_goodpri", choice(PRIORITIES, Some("notice"))),
+    ("targetpw", flag(false)),
+    // in minutes, and may be fractional
+    ("timestamp_timeout", minutes(24 * 60, "15")),
+    ("umask", octal(0o777, 0o022)),
+    ("umask_override", flag(false)),
+    ("verifypw", choice(PASSWORD_POLICIES, Some("all"))),
+];
+
+/// Look up the type of a setting; `None` if there is no setting with this name
+pub fn kind(name: &str) -> Option<SettingKind> {
+    SETTINGS
+        .binary_search_by(|(key, _)| (*key).cmp(name))
+        .ok()
+        .map(|index| SETTINGS[index].1)
+}
+
+/// Check `text` as the value for a (non-list) setting of the given kind
+pub fn parse_value(name: &str, kind: SettingKind, text: String) -> Parsed<DefaultValue> {
+    match kind {
+        Flag { .. } => unrecoverable!("{name} is a boolean setting and takes no value"),
+        List { .. } => make(DefaultValue::List(crate::ast::Mode::Set, vec![text])),
+        Text { .. } => make(DefaultValue::Text(text)),
+        Choice { values, .. } => {
+            if values.contains(&text.as_str()) {
+                make(DefaultValue::Text(text))
+            } else {
+                unrecoverable!(
+                    "invalid value for {name}: `{text}' (expected one of: {})",
+                    values.join(", ")
+                )
+            }
+        }
+        Minutes { max, .. } => match text.parse::<f64>() {
+            // this also rules out `inf` and `nan`
+            Ok(value) if (0.0..=f64::from(max)).contains(&value) => make(DefaultValue::Text(text)),
+            Ok(_) => unrecoverable!(
+                "value for {name} out of range: {text} (must be between 0 and {max} minutes)"
+            ),
+            Err(_) => unrecoverable!("invalid value for {name}: `{text}' is not a number"),
+        },
+        Integer {
+            radix, min, max, ..
+        } => {
+            let Ok(value) = i64::from_str_radix(&text, radix) else {
+                unrecoverable!("invalid value for {name}: `{text}' is not a number")
+            };
+            if !(min..=max).contains(&value) {
+                let (min, max) = if radix == 8 {
+                    (format!("{min:04o}"), format!("{max:04o}"))
+                } else {
+                    (min.to_string(), max.to_string())
+                };
+                unrecoverable!(
+                    "value for {name} out of range: {text} (must be between {min} and {max})"
+                )
+            }
+            make(DefaultValue::Integer(value))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(SETTINGS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        for (name, _) in SETTINGS {
+            assert!(kind(name).is_some(), "{name}");
+        }
+        assert!(kind("no_such_setting").is_none());
+    }
+
+    #[test]
+    fn value_validation() {
+        let check = |name, text: &str| parse_value(name, kind(name).unwrap(), text.to_string());
+        assert!(matches!(
+            check("umask", "0077"),
+            Ok(DefaultValue::Integer(0o77))
+        ));
+        assert!(matches!(
+            check("passwd_tries", "5"),
+            Ok(DefaultValue::Integer(5))
+        ));
+        assert!(matches!(
+            check("verifypw", "never"),
+            Ok(DefaultValue::Text(_))
+        ));
+        let message = |result| match result {
+            Err(crate::basic_parser::Status::Fatal(message)) => message,
+            _ => panic!("value was accepted"),
+        };
+        assert_eq!(
+            message(check("umask", "0999")),
+            "invalid value for umask: `0999' is not a number"
+        );
+        assert_eq!(
+            message(check("umask", "1000")),
+            "value for umask out of range: 1000 (must be between 0000 and 0777)"
+        );
+        assert_eq!(
+            message(check("passwd_tries", "0")),
+            "value for passwd_tries out of range: 0 (must be between 1 and 1000)"
+        );
+        assert_eq!(
+            message(check("listpw", "sometimes")),
+            "invalid value for listpw: `sometimes' (expected one of: all, always, any, never)"
+        );
+        assert!(check("env_reset", "yes").is_err());
+
+        assert!(check("passwd_timeout", "2.5").is_ok());
+        assert!(check("passwd_timeout", "0").is_ok());
+        for bad in ["inf", "-inf", "NaN", "1e300", "1441", "-1"] {
+            assert!(check("passwd_timeout", bad).is_err(), "{bad}");
+        }
+        assert_eq!(
+            message(check("passwd_timeout", "1e300")),
+            "value for passwd_timeout out of range: 1e300 (must be between 0 and 1440 minutes)"
+        );
+        assert_eq!(
+            message(check("passwd_timeout", "soon")),
+            "invalid value for passwd_timeout: `soon' is not a number"
+        );
+        assert!(check("timestamp_timeout", "7.5").is_ok());
+        assert!(check("timestamp_timeout", "later").is_err());
+    }
+
+    #[test]
+    fn settings_without_effect_are_unknown() {
+        for name in ["insults", "sudoedit_follow", "use_pty"] {
+            assert!(kind(name).is_none(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! A fast path for policies that only consist of rules like `%sudo ALL=(ALL:ALL) ALL`. Which of
+//! these rules applies only depends on the invoking user and the requested group, so they can be
+//! checked without resolving aliases, matching the command line, or allocating anything.
+
+use super::{find_item, in_group, match_user, Request, Tag};
+use crate::{ast::*, tokens::Meta};
+use std::collections::HashSet;
+use sudo_common::sysuser::{UnixGroup, UnixUser};
+
+/// Who a trivial rule allows to run commands as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+enum Target {
+    /// No runas specification: only root (with one of its own groups)
+    Root,
+    /// `(ALL)`: any user, with one of that user's groups
+    AnyUser,
+    /// `(ALL:ALL)`: any user and any group
+    Anything,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Rule {
+    /// Index of the rule in the sudoers file
+    rule: usize,
+    /// Index of the permission within the rule
+    permission: usize,
+    target: Target,
+}
+
+/// The trivial rules of a policy, in the order in which they appear
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct FastPath(Vec<Rule>);
+
+pub(crate) fn is_all<T>(list: &[Spec<T>]) -> bool {
+    matches!(list.last(), Some(Qualified::Allow(Meta::All)))
+}
+
+impl FastPath {
+    /// Check whether every rule of a policy is trivial: the users are not given with aliases, and
+    /// the hosts, runas users and commands all end in `ALL`; otherwise, there is no fast path.
+    pub(crate) fn compile(rules: &[PermissionSpec]) -> Option<FastPath> {
+        let mut fast = Vec::new();
+        for (index, spec) in rules.iter().enumerate() {
+            let aliased = |item: &Spec<UserSpecifier>| {
+                matches!(
+                    item,
+                    Qualified::Allow(Meta::Alias(_)) | Qualified::Forbid(Meta::Alias(_))
+                )
+            };
+            if spec.users.iter().any(aliased) {
+                return None;
+            }
+
+            for (permission, (hosts, runas, commands)) in spec.permissions.iter().enumerate() {
+                // a time window can make the rule stop matching, so it is not trivial
+                if !is_all(hosts)
+                    || !matches!(
+                        commands.last(),
+                        Some(CommandSpec(tags, Qualified::Allow(Meta::All)))
+                            if !tags.iter().any(Tag::is_time_window)
+                    )
+                {
+                    return None;
+                }
+                let target = match runas {
+                    None => Target::Root,
+                    Some(RunAs { users, groups }) if is_all(users) && groups.is_empty() => {
+                        Target::AnyUser
+                    }
+                    Some(RunAs { users, groups }) if is_all(users) && is_all(groups) => {
+                        Target::Anything
+                    }
+                    Some(_) => return None,
+                };
+                fast.push(Rule {
+                    rule: index,
+                    permission,
+                    target,
+                });
+            }
+        }
+
+        Some(FastPath(fast))
+    }
+
+    /// The equivalent of [check_permission](super::check_permission) for a policy with only
+    /// trivial rules: the last rule that matches decides, and its command always matches.
+    pub(crate) fn check<'a, User: UnixUser, Group: UnixGroup>(
+        &self,
+        rules: &'a [PermissionSpec],
+        am_user: &User,
+        request: &Request<User, Group>,
+    ) -> Option<&'a Vec<Tag>> {
+        let no_aliases = HashSet::new();
+        let own_group = in_group(request.user, request.group);
+        let matching = self.0.iter().rev().find(|fast| {
+            let target = match fast.target {
+                Target::Root => request.user.is_root() && own_group,
+                Target::AnyUser => own_group,
+                Target::Anything => true,
+            };
+            target
+                && find_item(&rules[fast.rule].users, &match_user(am_user), &no_aliases).is_some()
+        })?;
+
+        let (_, _, commands) = &rules[matching.rule].permissions[matching.permission];
+        commands.last().map(|CommandSpec(tags, _)| tags)
+    }
+}
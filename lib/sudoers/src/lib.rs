@@ -1,37 +1,66 @@
-//! Code that checks (and in the future: lists) permissions in the sudoers file
+//! Code that checks and lists permissions in the sudoers file
 
 mod ast;
 mod basic_parser;
+mod defaults;
+mod fast;
+mod lint;
+mod list;
 mod tokens;
 
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use ast::*;
-use sudo_common::sysuser::{UnixGroup, UnixUser};
+use sudo_common::{
+    clock::Clock,
+    debug_log,
+    sysuser::{UnixGroup, UnixUser},
+};
 use tokens::*;
 
 /// Export some necessary symbols from modules
 pub use ast::Tag;
+pub use defaults::SettingKind;
+pub use list::{ListedCommand, Privilege};
+pub use tokens::ChDir;
 pub type Error = basic_parser::Status;
 
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sudoers {
     rules: Vec<PermissionSpec>,
     aliases: AliasTable,
+    /// The Defaults entries that have not been applied to `settings` yet, in the order of the
+    /// sudoers file (with included files in the place of the include directive); see
+    /// [Sudoers::apply_scoped_defaults]
+    defaults: Vec<(ConfigScope, String, DefaultValue)>,
+    /// Present if all rules are trivial; see [fast::FastPath]
+    fast_path: Option<fast::FastPath>,
+    /// The files that the policy was read from, in the order in which they were read
+    files: Vec<PathBuf>,
+    /// For every rule, the index in `files` of the file it is in (if it was read from a file)
+    rule_files: Vec<Option<usize>>,
+    /// The items that generic `-=` entries could not remove, since the list did not have them
+    /// at that point, with the name of the list; see [Sudoers::lint]
+    absent_removals: Vec<(String, String)>,
     pub settings: Settings,
 }
 
 pub struct Request<'a, User: UnixUser, Group: UnixGroup> {
     pub user: &'a User,
     pub group: &'a Group,
+    /// The clock that NOTBEFORE and NOTAFTER are checked against
+    pub clock: &'a dyn Clock,
+    /// The command with its directory resolved through symbolic links, if that is another path;
+    /// see [Sudoers::apply_command_defaults]
+    pub resolved_command: Option<&'a str>,
 }
 
 /// This function takes a file argument for a sudoers file and processes it.
-
 pub fn compile(path: impl AsRef<Path>) -> Result<(Sudoers, Vec<Error>), std::io::Error> {
     let sudoers = read_sudoers(path.as_ref())?;
-    Ok(analyze(sudoers))
+    Ok(analyze_file(Some(path.as_ref()), sudoers))
 }
 
 fn read_sudoers(path: &Path) -> Result<Vec<basic_parser::Parsed<Sudo>>, std::io::Error> {
@@ -46,56 +75,140 @@ fn read_sudoers(path: &Path) -> Result<Vec<basic_parser::Parsed<Sudo>>, std::io:
     Ok(basic_parser::parse_lines(&mut buffer.chars().peekable()))
 }
 
+/// Parse and analyze sudoers text without following include directives, and evaluate a request
+/// against it; this is meant for testing the parser with arbitrary input (e.g. fuzzing), which
+/// must never cause a panic, only diagnostics.
+#[doc(hidden)]
+pub fn diagnose(text: &str) -> Vec<Error> {
+    let lines = basic_parser::parse_lines(&mut text.chars().peekable())
+        .into_iter()
+        .filter(|line| !matches!(line, Ok(Sudo::Include(_) | Sudo::IncludeDir(_))));
+    let (mut sudoers, diagnostics) = analyze(lines);
+    sudoers.apply_scoped_defaults(&"root", "localhost");
+    for action in [Action::Validate, Action::List] {
+        password_required(&sudoers, &"root", "localhost", action);
+    }
+    check_permission(
+        &sudoers,
+        &"root",
+        Request {
+            user: &"root",
+            group: &(0, "root"),
+            clock: &sudo_common::clock::Monotonic,
+            resolved_command: None,
+        },
+        "localhost",
+        &["/bin/true".to_string()],
+    );
+
+    diagnostics
+}
+
 #[derive(Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub(crate) struct AliasTable {
     user: VecOrd<Def<UserSpecifier>>,
     host: VecOrd<Def<Hostname>>,
+    #[cfg_attr(feature = "serde", serde(with = "ast::serde_command::table"))]
     cmnd: VecOrd<Def<Command>>,
     runas: VecOrd<Def<UserSpecifier>>,
 }
 
 /// A vector with a list defining the order in which it needs to be processed
-
 type VecOrd<T> = (Vec<usize>, Vec<T>);
 
 fn elems<T>(vec: &VecOrd<T>) -> impl Iterator<Item = &T> {
     vec.0.iter().map(|&i| &vec.1[i])
 }
 
+/// Why a request is not allowed by the policy; see [judge]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DenyReason {
+    /// No rule applies to the invoking user
+    NotInSudoers,
+    /// There are rules for the invoking user, but not on this host
+    HostMismatch,
+    /// The invoking user may run commands on this host, but not as the requested user or group
+    RunasNotAllowed,
+    /// The invoking user may run commands as the requested user and group, but not this one
+    CommandNotAllowed,
+    /// The command is allowed, but this tag of it rules out an option that was asked for (such as
+    /// `-D` without `CWD=*`); the policy does not see those options, so the caller reports this
+    TagForbids(Tag),
+}
+
 /// Check if the user `am_user` is allowed to run `cmdline` on machine `on_host` as the requested
 /// user/group. Not that in the sudoers file, later permissions override earlier restrictions.
-/// The `cmdline` argument should already be ready to essentially feed to an exec() call; or be
+/// The `cmdline` argument is the command and its arguments, as they would be passed to exec(); or
 /// a special command like 'sudoedit'.
+pub fn check_permission<User: UnixUser + PartialEq<User>, Group: UnixGroup>(
+    sudoers: &Sudoers,
+    am_user: &User,
+    request: Request<User, Group>,
+    on_host: &str,
+    cmdline: &[String],
+) -> Option<Vec<Tag>> {
+    judge(sudoers, am_user, request, on_host, cmdline).ok()
+}
 
+/// Like [check_permission], but if the request is not allowed, this tells why: the reason is the
+/// furthest that any rule got in matching the request.
+///
+/// The judgement only depends on the arguments: sudoers never consults the system itself (the
+/// host name is given, and the user and group types answer questions about group membership), and
+/// a [Sudoers] is `Send + Sync`, so requests can be judged concurrently on any thread.
 // This code is structure to allow easily reading the 'happy path'; i.e. as soon as something
 // doesn't match, we escape using the '?' mechanism.
-pub fn check_permission<User: UnixUser + PartialEq<User>, Group: UnixGroup>(
+pub fn judge<User: UnixUser + PartialEq<User>, Group: UnixGroup>(
     Sudoers {
         rules,
         aliases,
-        settings: _,
+        fast_path,
+        ..
     }: &Sudoers,
     am_user: &User,
     request: Request<User, Group>,
     on_host: &str,
-    cmdline: &str,
-) -> Option<Vec<Tag>> {
+    cmdline: &[String],
+) -> Result<Vec<Tag>, DenyReason> {
+    if let Some(fast_path) = fast_path {
+        if let Some(tags) = fast_path.check(rules, am_user, &request) {
+            debug_log!(
+                Match,
+                Info,
+                "{cmdline:?} on {on_host} (trivial rules): {tags:?}"
+            );
+            return Ok(tags.clone());
+        }
+        // denials are rare, and the reason for them is found by evaluating the rules in full
+    }
+
     let user_aliases = get_aliases(&aliases.user, &match_user(am_user));
-    let host_aliases = get_aliases(&aliases.host, &match_token(on_host));
-    let cmnd_aliases = get_aliases(&aliases.cmnd, &match_command(cmdline));
+    let host_aliases = get_aliases(&aliases.host, &match_host(on_host));
+    let matches_command = match_command(cmdline, request.resolved_command);
+    let cmnd_aliases = get_aliases(&aliases.cmnd, &matches_command);
     let runas_user_aliases = get_aliases(&aliases.runas, &match_user(request.user));
     let runas_group_aliases = get_aliases(&aliases.runas, &match_group_alias(request.group));
+    let now = request
+        .clock
+        .system_time()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64);
 
+    let (mut user_matched, mut host_matched, mut runas_matched) = (false, false, false);
     let allowed_commands = rules
         .iter()
         .filter_map(|sudo| {
             find_item(&sudo.users, &match_user(am_user), &user_aliases)?;
+            user_matched = true;
 
             let matching_rules = sudo
                 .permissions
                 .iter()
                 .filter_map(|(hosts, runas, cmds)| {
-                    find_item(hosts, &match_token(on_host), &host_aliases)?;
+                    find_item(hosts, &match_host(on_host), &host_aliases)?;
+                    host_matched = true;
 
                     if let Some(RunAs { users, groups }) = runas {
                         if !users.is_empty() || request.user != am_user {
@@ -107,8 +220,9 @@ pub fn check_permission<User: UnixUser + PartialEq<User>, Group: UnixGroup>(
                     } else if !(request.user.is_root() && in_group(request.user, request.group)) {
                         None?;
                     }
+                    runas_matched = true;
 
-                    Some(cmds)
+                    Some(cmds.iter().filter(move |spec| in_time_window(spec, now)))
                 })
                 .flatten();
 
@@ -116,13 +230,103 @@ pub fn check_permission<User: UnixUser + PartialEq<User>, Group: UnixGroup>(
         })
         .flatten();
 
-    find_item(allowed_commands, &match_command(cmdline), &cmnd_aliases).cloned()
+    let result = find_item(allowed_commands, &matches_command, &cmnd_aliases).cloned();
+    debug_log!(Match, Info, "{cmdline:?} on {on_host}: {result:?}");
+    result.ok_or(if runas_matched {
+        DenyReason::CommandNotAllowed
+    } else if host_matched {
+        DenyReason::RunasNotAllowed
+    } else if user_matched {
+        DenyReason::HostMismatch
+    } else {
+        DenyReason::NotInSudoers
+    })
+}
+
+/// Actions that do not run a command, but may still require the user to authenticate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Action {
+    /// `sudo -v`; governed by the `verifypw` setting
+    Validate,
+    /// `sudo -l`; governed by the `listpw` setting
+    List,
+}
+
+/// Check whether `am_user` needs to enter a password to perform `action` on `on_host`. Depending
+/// on the setting for the action, this is never, always, or if not `all` (or not `any`) of the
+/// commands the user may run on this host are tagged NOPASSWD.
+pub fn password_required<User: UnixUser>(
+    Sudoers {
+        rules,
+        aliases,
+        settings,
+        ..
+    }: &Sudoers,
+    am_user: &User,
+    on_host: &str,
+    action: Action,
+) -> bool {
+    let (name, default) = match action {
+        Action::Validate => ("verifypw", "all"),
+        Action::List => ("listpw", "any"),
+    };
+    let policy = settings.text(name).unwrap_or(default);
+
+    let user_aliases = get_aliases(&aliases.user, &match_user(am_user));
+    let host_aliases = get_aliases(&aliases.host, &match_host(on_host));
+
+    let mut nopasswd = rules
+        .iter()
+        .filter(|sudo| find_item(&sudo.users, &match_user(am_user), &user_aliases).is_some())
+        .flat_map(|sudo| &sudo.permissions)
+        .filter(|(hosts, _, _)| find_item(hosts, &match_host(on_host), &host_aliases).is_some())
+        .flat_map(|(_, _, cmds)| cmds)
+        .filter(|CommandSpec(_, spec)| matches!(spec, Qualified::Allow(_)))
+        .map(|CommandSpec(tags, _)| no_password(tags, settings.flag("authenticate")))
+        .peekable();
+
+    let required = match policy {
+        "never" => false,
+        "any" => !nopasswd.any(|tag| tag),
+        "all" => nopasswd.peek().is_none() || !nopasswd.all(|tag| tag),
+        _ => true,
+    };
+    debug_log!(
+        Match,
+        Info,
+        "{name}={policy} on {on_host}: password required: {required}"
+    );
+    required
+}
+
+/// Whether the NOTBEFORE and NOTAFTER tags of a command allow it to run at `now` (in seconds
+/// since the epoch); both ends of the window are inclusive
+fn in_time_window(CommandSpec(tags, _): &CommandSpec, now: i64) -> bool {
+    tags.iter().all(|tag| match tag {
+        Tag::NotBefore(time) => now >= *time,
+        Tag::NotAfter(time) => now <= *time,
+        _ => true,
+    })
+}
+
+/// Whether a command with these tags may be run without a password: of NOPASSWD and PASSWD, the
+/// last one wins (so `NOPASSWD: PASSWD: /bin/ls` needs a password); without either, a password
+/// is needed if the `authenticate` setting is on
+pub fn no_password(tags: &[Tag], authenticate: bool) -> bool {
+    tags.iter()
+        .rev()
+        .find_map(|tag| match tag {
+            Tag::NoPasswd => Some(true),
+            Tag::Passwd => Some(false),
+            _ => None,
+        })
+        .unwrap_or(!authenticate)
 }
 
 /// Find an item matching a certain predicate in an collection (optionally attributed) list of
 /// identifiers; identifiers can be directly identifying, wildcards, and can either be positive or
 /// negative (i.e. preceeded by an even number of exclamation marks in the sudoers file)
-
 fn find_item<'a, Predicate, T, Permit: Tagged<T> + 'a>(
     items: impl IntoIterator<Item = &'a Permit>,
     matches: &Predicate,
@@ -152,7 +356,10 @@ fn match_user(user: &impl UnixUser) -> impl Fn(&UserSpecifier) -> bool + '_ {
         UserSpecifier::User(id) => match_identifier(user, id),
         UserSpecifier::Group(Identifier::Name(name)) => user.in_group_by_name(name),
         UserSpecifier::Group(Identifier::ID(num)) => user.in_group_by_gid(*num),
-        _ => todo!(), // nonunix-groups, netgroups, etc.
+        UserSpecifier::NonunixGroup(Identifier::Name(name)) => user.in_nonunix_group(name),
+        UserSpecifier::NonunixGroup(Identifier::ID(num)) => {
+            user.in_nonunix_group(&format!("#{num}"))
+        }
     }
 }
 
@@ -173,7 +380,7 @@ fn in_group(user: &impl UnixUser, group: &impl UnixGroup) -> bool {
 fn match_group(group: &impl UnixGroup) -> impl Fn(&Identifier) -> bool + '_ {
     move |id| match id {
         Identifier::ID(num) => group.as_gid() == *num,
-        Identifier::Name(name) => group.try_as_name().map_or(false, |s| s == name),
+        Identifier::Name(name) => group.try_as_name() == Some(name),
     }
 }
 
@@ -183,27 +390,70 @@ fn match_group_alias(group: &impl UnixGroup) -> impl Fn(&UserSpecifier) -> bool
         /* the parser does not allow this, but can happen due to Runas_Alias,
          * see https://github.com/memorysafety/sudo-rs/issues/13 */
         _ => {
-            eprintln!("warning: ignoring %group syntax in runas_alias for checking sudo -g");
+            debug_log!(
+                Match,
+                Warn,
+                "ignoring %group syntax in runas_alias for checking sudo -g"
+            );
             false
         }
     }
 }
 
-fn match_token<T: basic_parser::Token + std::ops::Deref<Target = String>>(
-    text: &str,
-) -> (impl Fn(&T) -> bool + '_) {
-    move |token| token.as_str() == text
+/// Like DNS, host names are compared case-insensitively and without a trailing dot; a host in the
+/// sudoers file can also be a glob pattern, in which `*` also matches dots
+fn match_host(text: &str) -> impl Fn(&Hostname) -> bool {
+    fn canonical(name: &str) -> String {
+        name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+    }
+
+    let host = canonical(text);
+    move |pattern| {
+        let pattern = canonical(pattern);
+        if pattern.contains(['*', '?', '[']) {
+            glob::Pattern::new(&pattern).is_ok_and(|pattern| pattern.matches(&host))
+        } else {
+            pattern == host
+        }
+    }
+}
+
+/// A command line is the command and its arguments, as they are passed to exec; so an argument
+/// can contain spaces (or be empty), like the quoted arguments in the sudoers file. A command
+/// without wildcards also matches the `resolved` path of the command (see
+/// [Sudoers::apply_command_defaults]).
+fn match_command<'a>(
+    cmdline: &'a [String],
+    resolved: Option<&'a str>,
+) -> impl Fn(&Command) -> bool + 'a {
+    move |(cmdpat, argpat)| {
+        cmdline.split_first().is_some_and(|(cmd, args)| {
+            let literal = !cmdpat.as_str().contains(['*', '?', '[', '\\']);
+            !cmd.is_empty()
+                && (match_command_path(cmdpat, cmd)
+                    || resolved.is_some_and(|path| literal && cmdpat.matches(path)))
+                && argpat.matches(args)
+        })
+    }
 }
 
-fn match_command(text: &str) -> (impl Fn(&Command) -> bool + '_) {
-    let text = split_args(text);
-    let (cmd, args) = (text[0], text[1..].join(" "));
-    move |(cmdpat, argpat)| cmdpat.matches(cmd) && argpat.matches(&args)
+/// A command in the sudoers file that ends in `/` is a directory, which matches any file in it, but
+/// not in its subdirectories; so wildcards in it do not match a `/`
+fn match_command_path(pattern: &glob::Pattern, path: &str) -> bool {
+    if !pattern.as_str().ends_with('/') {
+        return pattern.matches(path);
+    }
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+    path.rsplit_once('/').is_some_and(|(dir, file)| {
+        !file.is_empty() && pattern.matches_with(&format!("{dir}/"), options)
+    })
 }
 
 /// Find all the aliases that a object is a member of; this requires [sanitize_alias_table] to have run first;
 /// I.e. this function should not be "pub".
-
 fn get_aliases<Predicate, T>(table: &VecOrd<Def<T>>, pred: &Predicate) -> HashSet<String>
 where
     Predicate: Fn(&T) -> bool,
@@ -219,7 +469,6 @@ where
 }
 
 /// Code to map an ast::Identifier to the UnixUser trait
-
 fn match_identifier(user: &impl UnixUser, ident: &ast::Identifier) -> bool {
     match ident {
         Identifier::Name(name) => user.has_name(name),
@@ -227,121 +476,496 @@ fn match_identifier(user: &impl UnixUser, ident: &ast::Identifier) -> bool {
     }
 }
 
-//TODO: don't derive Default, but implement it (based on what the actual defaults are)
-#[derive(Debug, Default)]
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Settings {
     pub flags: HashSet<String>,
+    pub int_value: HashMap<String, i64>,
     pub str_value: HashMap<String, String>,
-    pub list: HashMap<String, HashSet<String>>,
+    pub list: HashMap<String, ListItems>,
 }
 
-/// Process a sudoers-parsing file into a workable AST
-fn analyze(sudoers: impl IntoIterator<Item = basic_parser::Parsed<Sudo>>) -> (Sudoers, Vec<Error>) {
-    use DefaultValue::*;
-    use Directive::*;
+/// The items of a list setting, in the order in which they were added; an item is only in the
+/// list once
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListItems(Vec<String>);
 
-    let mut result: Sudoers = Default::default();
+impl ListItems {
+    pub fn contains(&self, item: &str) -> bool {
+        self.0.iter().any(|present| present == item)
+    }
 
-    impl Sudoers {
-        fn include(&mut self, path: &Path, diagnostics: &mut Vec<Error>) {
-            if let Ok(subsudoer) = read_sudoers(path) {
-                self.process(subsudoer, diagnostics)
-            } else {
-                diagnostics.push(Error::Fatal(format!(
-                    "cannot open sudoers file {}",
-                    path.display()
-                )))
+    pub fn iter(&self) -> std::slice::Iter<'_, String> {
+        self.0.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Append an item, unless it is already in the list
+    fn add(&mut self, item: String) {
+        if !self.contains(&item) {
+            self.0.push(item)
+        }
+    }
+
+    /// Remove an item; returns false if it was not in the list
+    fn remove(&mut self, item: &str) -> bool {
+        let len = self.0.len();
+        self.0.retain(|present| present != item);
+        self.0.len() != len
+    }
+}
+
+impl FromIterator<String> for ListItems {
+    fn from_iter<T: IntoIterator<Item = String>>(items: T) -> Self {
+        let mut list = ListItems::default();
+        items.into_iter().for_each(|item| list.add(item));
+        list
+    }
+}
+
+impl<'a> IntoIterator for &'a ListItems {
+    type Item = &'a String;
+    type IntoIter = std::slice::Iter<'a, String>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// The defaults for all settings, as given in the table of settings (see [SettingKind])
+impl Default for Settings {
+    fn default() -> Self {
+        let mut settings = Settings {
+            flags: HashSet::new(),
+            int_value: HashMap::new(),
+            str_value: HashMap::new(),
+            list: HashMap::new(),
+        };
+
+        for &(name, kind) in defaults::SETTINGS {
+            let name = name.to_string();
+            match kind {
+                SettingKind::Flag { default } => {
+                    if default {
+                        settings.flags.insert(name);
+                    }
+                }
+                SettingKind::Integer { default, .. } => {
+                    if let Some(value) = default {
+                        settings.int_value.insert(name, value);
+                    }
+                }
+                SettingKind::Choice { default, .. }
+                | SettingKind::Text { default }
+                | SettingKind::Minutes { default, .. } => {
+                    if let Some(value) = default {
+                        settings.str_value.insert(name, value.to_string());
+                    }
+                }
+                SettingKind::List { default } => {
+                    let items = default.iter().map(|item| item.to_string()).collect();
+                    settings.list.insert(name, items);
+                }
             }
         }
 
-        fn process(
-            &mut self,
-            sudoers: impl IntoIterator<Item = basic_parser::Parsed<Sudo>>,
-            diagnostics: &mut Vec<Error>,
-        ) {
-            for item in sudoers {
-                match item {
-                    Ok(line) => match line {
-                        Sudo::LineComment => {}
-
-                        Sudo::Spec(permission) => self.rules.push(permission),
-
-                        Sudo::Decl(UserAlias(def)) => self.aliases.user.1.push(def),
-                        Sudo::Decl(HostAlias(def)) => self.aliases.host.1.push(def),
-                        Sudo::Decl(CmndAlias(def)) => self.aliases.cmnd.1.push(def),
-                        Sudo::Decl(RunasAlias(def)) => self.aliases.runas.1.push(def),
-
-                        Sudo::Decl(Defaults(name, Flag(value))) => {
-                            if value {
-                                self.settings.flags.insert(name);
-                            } else {
-                                self.settings.flags.remove(&name);
+        settings
+    }
+}
+
+impl Settings {
+    /// Whether a flag is set
+    pub fn flag(&self, name: &str) -> bool {
+        debug_assert!(
+            matches!(defaults::kind(name), Some(SettingKind::Flag { .. })),
+            "{name} is not a flag"
+        );
+        self.flags.contains(name)
+    }
+
+    /// The value of an integer setting, unless it has been negated
+    pub fn integer(&self, name: &str) -> Option<i64> {
+        debug_assert!(
+            matches!(defaults::kind(name), Some(SettingKind::Integer { .. })),
+            "{name} is not an integer setting"
+        );
+        self.int_value.get(name).copied()
+    }
+
+    /// The value of a setting in minutes, unless it has been negated
+    pub fn minutes(&self, name: &str) -> Option<f64> {
+        debug_assert!(
+            matches!(defaults::kind(name), Some(SettingKind::Minutes { .. })),
+            "{name} is not a setting in minutes"
+        );
+        // the value was checked when the sudoers file was read
+        self.str_value
+            .get(name)
+            .and_then(|value| value.parse().ok())
+    }
+
+    /// The value of a text (or choice) setting, unless it is unset or has been negated
+    pub fn text(&self, name: &str) -> Option<&str> {
+        debug_assert!(
+            matches!(
+                defaults::kind(name),
+                Some(SettingKind::Text { .. } | SettingKind::Choice { .. })
+            ),
+            "{name} is not a text setting"
+        );
+        self.str_value.get(name).map(String::as_str)
+    }
+
+    /// Apply one Defaults entry, and return the items that a `-=` could not remove, since the
+    /// list did not have them (which is not an error, like in the original sudo)
+    fn apply(&mut self, name: String, value: DefaultValue) -> Vec<String> {
+        use DefaultValue::*;
+
+        let mut absent = Vec::new();
+        match value {
+            Flag(value) => {
+                debug_log!(Defaults, Debug, "{name} set to {value}");
+                if value {
+                    self.flags.insert(name);
+                } else {
+                    // negating a setting that has a value (such as `!syslog`) unsets it
+                    self.str_value.remove(&name);
+                    self.int_value.remove(&name);
+                    self.list.remove(&name);
+                    self.flags.remove(&name);
+                }
+            }
+            Integer(value) => {
+                debug_log!(Defaults, Debug, "{name} set to {value}");
+                self.int_value.insert(name, value);
+            }
+            Text(value) => {
+                debug_log!(Defaults, Debug, "{name} set to {value:?}");
+                self.str_value.insert(name, value);
+            }
+            List(mode, values) => {
+                debug_log!(Defaults, Debug, "{name} updated ({mode:?}) with {values:?}");
+                let slot: &mut _ = self.list.entry(name.clone()).or_default();
+                match mode {
+                    Mode::Set => *slot = values.into_iter().collect(),
+                    Mode::Add => values.into_iter().for_each(|value| slot.add(value)),
+                    Mode::Del => {
+                        for key in values {
+                            if !slot.remove(&key) {
+                                debug_log!(Defaults, Debug, "{key} was not in {name}");
+                                absent.push(key);
                             }
                         }
-                        Sudo::Decl(Defaults(name, Text(value))) => {
-                            self.settings.str_value.insert(name, value);
-                        }
+                    }
+                }
+            }
+        }
+
+        absent
+    }
+}
+
+/// The effective value of every setting, written as the `Defaults` entries that produce it (which
+/// is what `sudo -V` shows to root); settings that are unset are shown negated, and the items of
+/// a list are shown in the order in which they were added.
+impl std::fmt::Display for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let escape = |text: &str, special: &str| {
+            text.chars()
+                .flat_map(|c| special.contains(c).then_some('\\').into_iter().chain([c]))
+                .collect::<String>()
+        };
 
-                        Sudo::Decl(Defaults(name, List(mode, values))) => {
-                            let slot: &mut _ = self.settings.list.entry(name).or_default();
-                            match mode {
-                                Mode::Set => *slot = values.into_iter().collect(),
-                                Mode::Add => slot.extend(values),
-                                Mode::Del => {
-                                    for key in values {
-                                        slot.remove(&key);
-                                    }
+        for &(name, kind) in defaults::SETTINGS {
+            let value = match kind {
+                SettingKind::Flag { .. } => self.flags.contains(name).then(String::new),
+                SettingKind::Integer { radix: 8, .. } => self
+                    .int_value
+                    .get(name)
+                    .map(|value| format!("={value:04o}")),
+                SettingKind::Integer { .. } => {
+                    self.int_value.get(name).map(|value| format!("={value}"))
+                }
+                SettingKind::Minutes { .. } => {
+                    self.str_value.get(name).map(|value| format!("={value}"))
+                }
+                SettingKind::Choice { .. } | SettingKind::Text { .. } => self
+                    .str_value
+                    .get(name)
+                    .map(|value| format!("=\"{}\"", escape(value, "\\\""))),
+                SettingKind::List { .. } => self
+                    .list
+                    .get(name)
+                    .filter(|items| !items.is_empty())
+                    .map(|items| {
+                        let items = items
+                            .iter()
+                            .map(|item| match item.split_once('=') {
+                                Some((var, value)) => {
+                                    format!("{var}={}", escape(value, "\\\" \t,"))
                                 }
-                            }
-                        }
+                                None => item.clone(),
+                            })
+                            .collect::<Vec<_>>();
+                        format!("=\"{}\"", items.join(" "))
+                    }),
+            };
+            match value {
+                Some(value) => writeln!(f, "Defaults {name}{value}")?,
+                None => writeln!(f, "Defaults !{name}")?,
+            }
+        }
 
-                        Sudo::Include(path) => self.include(path.as_ref(), diagnostics),
-
-                        Sudo::IncludeDir(path) => {
-                            let Ok(files) = std::fs::read_dir(&path) else {
-                                diagnostics.push(Error::Fatal(format!("cannot open sudoers file {path}")));
-                                continue;
-                            };
-                            let mut safe_files = files
-                                .filter_map(|direntry| {
-                                    let path = direntry.ok()?.path();
-                                    let text = path.to_str()?;
-                                    if text.contains('~') || text.contains('.') {
-                                        None
-                                    } else {
-                                        Some(path)
-                                    }
-                                })
-                                .collect::<Vec<_>>();
-                            safe_files.sort();
-                            for file in safe_files {
-                                self.include(file.as_ref(), diagnostics)
-                            }
+        Ok(())
+    }
+}
+
+impl Sudoers {
+    /// Apply the `Defaults@host` entries for the host sudo is running on and the `Defaults:user`
+    /// entries for the invoking user (on top of the generic Defaults, which have been applied
+    /// while reading the sudoers file); hosts and users can be given using aliases. The
+    /// `Defaults>runas` and `Defaults!cmnd` entries are kept for [Sudoers::apply_runas_defaults]
+    /// and [Sudoers::apply_command_defaults].
+    ///
+    /// The settings are built up in layers, like in the original sudo: the generic entries, then
+    /// those for the host, the user, the runas user and the command. Within a layer the entries
+    /// take effect in the order of the sudoers file, so a list such as `env_keep` ends up with the
+    /// result of every `=`, `+=` and `-=` of that layer, across all included files.
+    pub fn apply_scoped_defaults<User: UnixUser>(&mut self, am_user: &User, on_host: &str) {
+        let host_aliases = get_aliases(&self.aliases.host, &match_host(on_host));
+        let user_aliases = get_aliases(&self.aliases.user, &match_user(am_user));
+
+        self.overlay(|scope| match scope {
+            ConfigScope::Host(hosts) => {
+                Some(find_item(hosts, &match_host(on_host), &host_aliases).is_some())
+            }
+            _ => None,
+        });
+        self.overlay(|scope| match scope {
+            ConfigScope::User(users) => {
+                Some(find_item(users, &match_user(am_user), &user_aliases).is_some())
+            }
+            _ => None,
+        });
+    }
+
+    /// Apply the `Defaults>runas` entries for the user that the command is going to run as; the
+    /// users can be given using a `Runas_Alias`. The `Defaults!cmnd` entries are kept.
+    pub fn apply_runas_defaults<User: UnixUser>(&mut self, runas_user: &User) {
+        let runas_aliases = get_aliases(&self.aliases.runas, &match_user(runas_user));
+
+        self.overlay(|scope| match scope {
+            ConfigScope::Runas(users) => {
+                Some(find_item(users, &match_user(runas_user), &runas_aliases).is_some())
+            }
+            _ => None,
+        });
+    }
+
+    /// Apply the `Defaults!cmnd` entries for the command line that is going to run; like in the
+    /// original sudo, these are applied last, once the policy has allowed the command.
+    ///
+    /// Like in the original sudo, a command without wildcards also matches the same name in a
+    /// directory that resolves to the same one, like `/bin/ls` and `/usr/bin/ls` if `/bin` links
+    /// to `/usr/bin`. Since sudoers does not look at the file system, the caller resolves the
+    /// directory of the command when it looks it up, and passes that path as `resolved_command`
+    /// (also in [Request]). The command itself is never resolved: a rule for `/usr/bin/vim`
+    /// matches `/usr/bin/vim` also if that links to `/usr/bin/vim.basic`, but not the latter.
+    pub fn apply_command_defaults(&mut self, cmdline: &[String], resolved_command: Option<&str>) {
+        let matches = match_command(cmdline, resolved_command);
+        let cmnd_aliases = get_aliases(&self.aliases.cmnd, &matches);
+
+        self.overlay(|scope| match scope {
+            ConfigScope::Command(commands) => {
+                Some(find_item(commands, &matches, &cmnd_aliases).is_some())
+            }
+            _ => None,
+        });
+    }
+
+    /// Apply one layer of the Defaults entries, in order: `layer` tells for every entry whether
+    /// it applies (`Some(true)`), does not (`Some(false)`) or belongs to another layer (`None`),
+    /// in which case it is kept for later. Returns the items that `-=` entries could not remove,
+    /// with the name of the list.
+    fn overlay(&mut self, layer: impl Fn(&ConfigScope) -> Option<bool>) -> Vec<(String, String)> {
+        let mut absent = Vec::new();
+        for (scope, name, value) in std::mem::take(&mut self.defaults) {
+            match layer(&scope) {
+                Some(true) => {
+                    let items = self.settings.apply(name.clone(), value);
+                    absent.extend(items.into_iter().map(|item| (name.clone(), item)));
+                }
+                Some(false) => {}
+                None => self.defaults.push((scope, name, value)),
+            }
+        }
+
+        absent
+    }
+
+    /// The non-Unix groups that the rules, aliases and remaining Defaults entries of the policy
+    /// refer to, as they are passed to [UnixUser::in_nonunix_group] (a `%:#number` as `#number`)
+    pub fn nonunix_groups(&self) -> Vec<String> {
+        let mut specs: Vec<&Spec<UserSpecifier>> = Vec::new();
+        for rule in &self.rules {
+            specs.extend(&rule.users);
+            for (_, runas, _) in &rule.permissions {
+                specs.extend(runas.iter().flat_map(|runas| &runas.users));
+            }
+        }
+        for Def(_, users) in self.aliases.user.1.iter().chain(&self.aliases.runas.1) {
+            specs.extend(users);
+        }
+        for (scope, _, _) in &self.defaults {
+            if let ConfigScope::User(users) | ConfigScope::Runas(users) = scope {
+                specs.extend(users);
+            }
+        }
+
+        let mut groups = Vec::new();
+        for spec in specs {
+            let (Qualified::Allow(Meta::Only(UserSpecifier::NonunixGroup(group)))
+            | Qualified::Forbid(Meta::Only(UserSpecifier::NonunixGroup(group)))) = spec
+            else {
+                continue;
+            };
+            let group = match group {
+                Identifier::Name(name) => name.clone(),
+                Identifier::ID(num) => format!("#{num}"),
+            };
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+        groups
+    }
+
+    /// The files that the policy was read from, in the order in which their contents take effect:
+    /// an included file comes in the place of the include directive, and the files in an included
+    /// directory in the byte order of their names
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    fn include(&mut self, path: &Path, diagnostics: &mut Vec<Error>) {
+        debug_log!(Parser, Diag, "including {}", path.display());
+        if let Ok(subsudoer) = read_sudoers(path) {
+            self.files.push(path.to_path_buf());
+            self.process(subsudoer, Some(self.files.len() - 1), diagnostics)
+        } else {
+            diagnostics.push(Error::Fatal(format!(
+                "cannot open sudoers file {}",
+                path.display()
+            )))
+        }
+    }
+
+    fn process(
+        &mut self,
+        sudoers: impl IntoIterator<Item = basic_parser::Parsed<Sudo>>,
+        file: Option<usize>,
+        diagnostics: &mut Vec<Error>,
+    ) {
+        use Directive::*;
+
+        for item in sudoers {
+            match item {
+                Ok(line) => match line {
+                    Sudo::LineComment => {}
+
+                    Sudo::Spec(permission) => {
+                        self.rules.push(permission);
+                        self.rule_files.push(file);
+                    }
+
+                    Sudo::Decl(UserAlias(def)) => self.aliases.user.1.push(def),
+                    Sudo::Decl(HostAlias(def)) => self.aliases.host.1.push(def),
+                    Sudo::Decl(CmndAlias(def)) => self.aliases.cmnd.1.push(def),
+                    Sudo::Decl(RunasAlias(def)) => self.aliases.runas.1.push(def),
+
+                    Sudo::Decl(Defaults(name, value, scope)) => {
+                        self.defaults.push((scope, name, value))
+                    }
+
+                    Sudo::Include(path) => self.include(path.as_ref(), diagnostics),
+
+                    // like the original sudo, files whose name ends in `~` or contains a `.` are
+                    // skipped (editor backups, and leftovers like `.dpkg-old` or `.rpmsave`), and
+                    // the others are included in the byte order of their names
+                    Sudo::IncludeDir(path) => {
+                        let Ok(files) = std::fs::read_dir(&path) else {
+                            diagnostics
+                                .push(Error::Fatal(format!("cannot open sudoers file {path}")));
+                            continue;
+                        };
+                        let mut safe_files = files
+                            .filter_map(|direntry| {
+                                let path = direntry.ok()?.path();
+                                let name = path.file_name()?.to_str()?;
+                                if name.ends_with('~') || name.contains('.') {
+                                    None
+                                } else {
+                                    Some(path)
+                                }
+                            })
+                            .collect::<Vec<_>>();
+                        safe_files.sort();
+                        for file in safe_files {
+                            self.include(file.as_ref(), diagnostics)
                         }
-                    },
+                    }
+                },
 
-                    Err(error) => diagnostics.push(error),
+                Err(error) => {
+                    debug_log!(Parser, Warn, "{error:?}");
+                    diagnostics.push(error)
                 }
             }
         }
     }
+}
 
+/// Process a sudoers-parsing file into a workable AST
+fn analyze(sudoers: impl IntoIterator<Item = basic_parser::Parsed<Sudo>>) -> (Sudoers, Vec<Error>) {
+    analyze_file(None, sudoers)
+}
+
+/// Like [analyze], for the contents of the sudoers file at `path`
+fn analyze_file(
+    path: Option<&Path>,
+    sudoers: impl IntoIterator<Item = basic_parser::Parsed<Sudo>>,
+) -> (Sudoers, Vec<Error>) {
+    let mut result: Sudoers = Default::default();
     let mut diagnostics = vec![];
-    result.process(sudoers, &mut diagnostics);
+    let file = path.map(|path| {
+        result.files.push(path.to_path_buf());
+        0
+    });
+    result.process(sudoers, file, &mut diagnostics);
+    // the generic Defaults are the first layer of the settings
+    result.absent_removals =
+        result.overlay(|scope| matches!(scope, ConfigScope::Generic).then_some(true));
 
     let alias = &mut result.aliases;
     alias.user.0 = sanitize_alias_table(&alias.user.1, &mut diagnostics);
     alias.host.0 = sanitize_alias_table(&alias.host.1, &mut diagnostics);
     alias.cmnd.0 = sanitize_alias_table(&alias.cmnd.1, &mut diagnostics);
     alias.runas.0 = sanitize_alias_table(&alias.runas.1, &mut diagnostics);
+    result.fast_path = fast::FastPath::compile(&result.rules);
 
     (result, diagnostics)
 }
 
+/// How deeply aliases may refer to other aliases
+const ALIAS_DEPTH_LIMIT: usize = 128;
+
 /// Alias definition inin a Sudoers file can come in any order; and aliases can refer to other aliases, etc.
 /// It is much easier if they are presented in a "definitional order" (i.e. aliases that use other aliases occur later)
 /// At the same time, this is a good place to detect problems in the aliases, such as unknown aliases and cycles.
-
 fn sanitize_alias_table<T>(table: &Vec<Def<T>>, diagnostics: &mut Vec<Error>) -> Vec<usize> {
     fn remqualify<U>(item: &Qualified<U>) -> &U {
         match item {
@@ -363,16 +987,20 @@ fn sanitize_alias_table<T>(table: &Vec<Def<T>>, diagnostics: &mut Vec<Error>) ->
             self.diagnostics.push(Error::Fatal(text))
         }
 
-        fn visit(&mut self, pos: usize) {
+        fn visit(&mut self, pos: usize, depth: usize) {
             if self.seen.insert(pos) {
-                let Def(_, members) = &self.table[pos];
+                let Def(id, members) = &self.table[pos];
+                if depth > ALIAS_DEPTH_LIMIT {
+                    self.complain(format!("alias nested too deeply: `{id}'"));
+                    return;
+                }
                 for elem in members {
-                    let Meta::Alias(name) = remqualify(elem) else { break };
+                    let Meta::Alias(name) = remqualify(elem) else { continue };
                     let Some(dependency) = self.table.iter().position(|Def(id,_)| id==name) else {
                         self.complain(format!("undefined alias: `{name}'"));
                         continue;
                     };
-                    self.visit(dependency);
+                    self.visit(dependency, depth + 1);
                 }
                 self.order.push(pos);
             } else if !self.order.contains(&pos) {
@@ -394,7 +1022,7 @@ fn sanitize_alias_table<T>(table: &Vec<Def<T>>, diagnostics: &mut Vec<Error>) ->
         if !dupe.insert(name) {
             visitor.complain(format!("multiple occurences of `{name}'"));
         } else {
-            visitor.visit(i);
+            visitor.visit(i, 0);
         }
     }
 
@@ -407,6 +1035,7 @@ mod test {
     use crate::ast;
     use basic_parser::{parse_eval, parse_lines, parse_string};
     use std::iter;
+    use sudo_common::clock::{FakeClock, Monotonic};
 
     macro_rules! sudoer {
         ($h:expr $(,$e:expr)*) => {
@@ -431,29 +1060,81 @@ mod test {
         sudoer![s].next().unwrap().unwrap()
     }
 
+    /// A command line as it is passed to the policy: written as a string, its words are separated
+    /// by single spaces; an argument that contains a space is written as an array of words
+    trait Argv {
+        fn argv(&self) -> Vec<String>;
+    }
+
+    impl Argv for str {
+        fn argv(&self) -> Vec<String> {
+            self.split(' ').map(str::to_string).collect()
+        }
+    }
+
+    impl<const N: usize> Argv for [&str; N] {
+        fn argv(&self) -> Vec<String> {
+            self.map(str::to_string).to_vec()
+        }
+    }
+
     #[test]
     fn ambiguous_spec() {
         let Sudo::Spec(_) = parse_eval::<ast::Sudo>("marc, User_Alias ALL = ALL") else { todo!() };
     }
 
+    #[test]
+    fn concurrent_judgements() {
+        fn shareable<T: Send + Sync>(value: T) -> T {
+            value
+        }
+        let (sudoers, _) = analyze(sudoer!["user ALL=(ALL:ALL) /bin/ls", "root ALL=ALL"]);
+        let sudoers = shareable(sudoers);
+        let request = || Request {
+            user: &"root",
+            group: &(0, "root"),
+            clock: &Monotonic,
+            resolved_command: None,
+        };
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        assert!(
+                            judge(&sudoers, &"user", request(), "server", &"/bin/ls".argv())
+                                .is_ok()
+                        );
+                        assert_eq!(
+                            judge(&sudoers, &"user", request(), "server", &"/bin/cat".argv()),
+                            Err(DenyReason::CommandNotAllowed)
+                        );
+                    }
+                });
+            }
+        });
+    }
+
     #[test]
     fn permission_test() {
         let root = || Request::<&str, _> {
             user: &"root",
             group: &(0, "root"),
+            clock: &Monotonic,
+            resolved_command: None,
         };
 
         macro_rules! FAIL {
             ([$($sudo:expr),*], $user:expr => $req:expr, $server:expr; $command:expr) => {
-                let (Sudoers { rules,aliases,settings }, _) = analyze(sudoer![$($sudo),*]);
-                assert_eq!(check_permission(&Sudoers { rules, aliases, settings }, &$user, $req, $server, $command), None);
+                let (sudoers, _) = analyze(sudoer![$($sudo),*]);
+                assert_eq!(check_permission(&sudoers, &$user, $req, $server, &$command.argv()), None);
             }
         }
 
         macro_rules! pass {
             ([$($sudo:expr),*], $user:expr => $req:expr, $server:expr; $command:expr $(=> [$($list:expr),*])?) => {
-                let (Sudoers { rules,aliases,settings }, _) = analyze(sudoer![$($sudo),*]);
-                let result = check_permission(&Sudoers { rules, aliases, settings }, &$user, $req, $server, $command);
+                let (sudoers, _) = analyze(sudoer![$($sudo),*]);
+                let result = check_permission(&sudoers, &$user, $req, $server, &$command.argv());
                 $(assert_eq!(result, Some(vec![$($list),*]));)?
                 assert!(!result.is_none());
             }
@@ -474,12 +1155,63 @@ mod test {
         pass!(["user ALL=(ALL:ALL) /bin/foo, NOPASSWD: /bin/bar"], "user" => root(), "server"; "/bin/foo");
         pass!(["user ALL=(ALL:ALL) /bin/foo, NOPASSWD: /bin/bar"], "user" => root(), "server"; "/bin/bar" => [NoPasswd]);
 
+        pass!(["user ALL=(ALL:ALL) CWD=/tmp /bin/ls"], "user" => root(), "server"; "/bin/ls" => [Cwd(ChDir::Path("/tmp".into()))]);
+        pass!(["user ALL=(ALL:ALL) CWD=* NOPASSWD: /bin/ls"], "user" => root(), "server"; "/bin/ls" => [Cwd(ChDir::Any), NoPasswd]);
+        SYNTAX!(["user ALL=(ALL:ALL) CWD=tmp /bin/ls"]);
+        pass!(["user ALL=(ALL:ALL) CHROOT=/srv/jail CWD=/tmp /bin/ls"], "user" => root(), "server"; "/bin/ls" => [Chroot(ChDir::Path("/srv/jail".into())), Cwd(ChDir::Path("/tmp".into()))]);
+        SYNTAX!(["user ALL=(ALL:ALL) CHROOT=jail /bin/ls"]);
+
+        pass!(["user ALL=(ALL:ALL) SETENV: /bin/ls"], "user" => root(), "server"; "/bin/ls" => [SetEnv]);
+        pass!(["user ALL=(ALL:ALL) ALL"], "user" => root(), "server"; "/bin/ls" => [SetEnv]);
+        pass!(["user ALL=(ALL:ALL) NOSETENV: ALL"], "user" => root(), "server"; "/bin/ls" => [NoSetEnv]);
+        pass!(["user ALL=(ALL:ALL) NOEXEC: /bin/less, EXEC: /bin/ls"], "user" => root(), "server"; "/bin/less" => [NoExec]);
+        pass!(["user ALL=(ALL:ALL) NOEXEC: /bin/less, EXEC: /bin/ls"], "user" => root(), "server"; "/bin/ls" => [Exec]);
+        pass!(["user ALL=(ALL:ALL) INTERCEPT: /bin/sh, NOINTERCEPT: /bin/ls"], "user" => root(), "server"; "/bin/sh" => [Intercept]);
+        pass!(["user ALL=(ALL:ALL) INTERCEPT: /bin/sh, NOINTERCEPT: /bin/ls"], "user" => root(), "server"; "/bin/ls" => [NoIntercept]);
+        pass!(["user ALL=(ALL:ALL) UMASK=0077 /bin/ls"], "user" => root(), "server"; "/bin/ls" => [Umask(0o077)]);
+        SYNTAX!(["user ALL=(ALL:ALL) UMASK=0778 /bin/ls"]);
+        SYNTAX!(["user ALL=(ALL:ALL) UMASK=1777 /bin/ls"]);
+        pass!(["user ALL=(ALL:ALL) NOTBEFORE=20200101000000Z /bin/ls"], "user" => root(), "server"; "/bin/ls" => [NotBefore(1577836800)]);
+        pass!(["user ALL=(ALL:ALL) NOTAFTER=2100010100Z /bin/ls"], "user" => root(), "server"; "/bin/ls" => [NotAfter(4102444800)]);
+        pass!(["user ALL=(ALL:ALL) NOTBEFORE=202001010100.5+0100 /bin/ls"], "user" => root(), "server"; "/bin/ls" => [NotBefore(1577836800)]);
+        SYNTAX!(["user ALL=(ALL:ALL) NOTBEFORE=2020 /bin/ls"]);
+        SYNTAX!(["user ALL=(ALL:ALL) NOTAFTER=20201301000000Z /bin/ls"]);
+        SYNTAX!(["user ALL=(ALL:ALL) NOTAFTER=20200101000000+01 /bin/ls"]);
+        pass!(["user ALL=(ALL:ALL) LOG_INPUT: NOLOG_OUTPUT: /bin/ls"], "user" => root(), "server"; "/bin/ls" => [LogInput, NoLogOutput]);
+        pass!(["user ALL=(ALL:ALL) LOG_OUTPUT: /bin/sh, NOLOG_OUTPUT: /bin/ls"], "user" => root(), "server"; "/bin/ls" => [NoLogOutput]);
+        pass!(["user ALL=(ALL:ALL) FOLLOW: /bin/sh, NOFOLLOW: /bin/ls"], "user" => root(), "server"; "/bin/ls" => [NoFollow]);
+
+        // tags carry over to the next command in the list, unless it overrides them
+        pass!(["user ALL=(ALL:ALL) NOPASSWD: /bin/ls, /bin/cat"], "user" => root(), "server"; "/bin/cat" => [NoPasswd]);
+        pass!(["user ALL=(ALL:ALL) NOPASSWD: CWD=/tmp /bin/ls, PASSWD: /bin/cat"], "user" => root(), "server"; "/bin/cat" => [Cwd(ChDir::Path("/tmp".into())), Passwd]);
+        pass!(["user ALL=(ALL:ALL) NOSETENV: /bin/ls, ALL"], "user" => root(), "server"; "/bin/cat" => [NoSetEnv]);
+        // but the SETENV that ALL implies does not
+        pass!(["user ALL=(ALL:ALL) ALL, /bin/ls"], "user" => root(), "server"; "/bin/ls" => []);
+
         pass!(["user ALL=/bin/e##o"], "user" => root(), "vm"; "/bin/e");
         SYNTAX!(["ALL ALL=(ALL) /bin/\n/echo"]);
 
         pass!(["user server=(ALL:ALL) ALL"], "user" => root(), "server"; "/bin/hello");
         FAIL!(["user laptop=(ALL:ALL) ALL"], "user" => root(), "server"; "/bin/hello");
 
+        // like in DNS, case and a trailing dot make no difference in host names
+        pass!(["user Server=(ALL:ALL) ALL"], "user" => root(), "SERVER"; "/bin/hello");
+        pass!(["user server.example.com.=ALL"], "user" => root(), "server.example.com"; "/bin/hello");
+        pass!(["user server.example.com=ALL"], "user" => root(), "Server.Example.COM."; "/bin/hello");
+        FAIL!(["user server.example.com=ALL"], "user" => root(), "server.example.com.."; "/bin/hello");
+        FAIL!(["user server.example.com=ALL"], "user" => root(), "server"; "/bin/hello");
+        // hosts can be glob patterns, in which a wildcard also matches dots
+        pass!(["user web*.example.com=ALL"], "user" => root(), "web01.example.com"; "/bin/hello");
+        pass!(["user web*.example.com=ALL"], "user" => root(), "WEB01.example.com."; "/bin/hello");
+        FAIL!(["user web*.example.com=ALL"], "user" => root(), "mail.example.com"; "/bin/hello");
+        FAIL!(["user web*.example.com=ALL"], "user" => root(), "web01.example.org"; "/bin/hello");
+        pass!(["user web*=ALL"], "user" => root(), "web01.example.com"; "/bin/hello");
+        pass!(["user db[0-9]=ALL"], "user" => root(), "db7"; "/bin/hello");
+        FAIL!(["user db[0-9]=ALL"], "user" => root(), "db10"; "/bin/hello");
+        pass!(["Host_Alias WEB=web?,*.web.example.com", "user WEB=ALL"], "user" => root(), "a.web.example.com"; "/bin/hello");
+        FAIL!(["Host_Alias WEB=web?,*.web.example.com", "user WEB=ALL"], "user" => root(), "web.example.com"; "/bin/hello");
+        SYNTAX!(["user db[0-9=ALL"]);
+
         pass!(["user ALL=!/bin/hello", "user ALL=/bin/hello"], "user" => root(), "server"; "/bin/hello");
         FAIL!(["user ALL=/bin/hello", "user ALL=!/bin/hello"], "user" => root(), "server"; "/bin/hello");
 
@@ -493,13 +1225,38 @@ mod test {
         }
         pass!(["user ALL=/bin/hello arg"], "user" => root(), "server"; "/bin/hello arg");
         pass!(["user ALL=/bin/hello  arg"], "user" => root(), "server"; "/bin/hello arg");
-        pass!(["user ALL=/bin/hello arg"], "user" => root(), "server"; "/bin/hello  arg");
+        // a string has its words separated by single spaces, so this has an empty argument
+        FAIL!(["user ALL=/bin/hello arg"], "user" => root(), "server"; "/bin/hello  arg");
         FAIL!(["user ALL=/bin/hello arg"], "user" => root(), "server"; "/bin/hello boo");
-        pass!(["user ALL=/bin/hello a*g"], "user" => root(), "server"; "/bin/hello  aaaarg");
+        pass!(["user ALL=/bin/hello a*g"], "user" => root(), "server"; "/bin/hello aaaarg");
         FAIL!(["user ALL=/bin/hello a*g"], "user" => root(), "server"; "/bin/hello boo");
         pass!(["user ALL=/bin/hello"], "user" => root(), "server"; "/bin/hello boo");
         FAIL!(["user ALL=/bin/hello \"\""], "user" => root(), "server"; "/bin/hello boo");
         pass!(["user ALL=/bin/hello \"\""], "user" => root(), "server"; "/bin/hello");
+        // arguments are compared one by one, so they keep their boundaries
+        pass!(["user ALL=/bin/hello \"a  b\""], "user" => root(), "server"; ["/bin/hello", "a  b"]);
+        FAIL!(["user ALL=/bin/hello \"a  b\""], "user" => root(), "server"; "/bin/hello a  b");
+        FAIL!(["user ALL=/bin/hello \"a  b\""], "user" => root(), "server"; "/bin/hello a b");
+        FAIL!(["user ALL=/bin/hello \"a  b\""], "user" => root(), "server"; ["/bin/hello", "\"a  b\""]);
+        pass!(["user ALL=/bin/hello \"a b\""], "user" => root(), "server"; ["/bin/hello", "a b"]);
+        FAIL!(["user ALL=/bin/hello \"a b\""], "user" => root(), "server"; ["/bin/hello", "a", "b"]);
+        FAIL!(["user ALL=/bin/hello a b"], "user" => root(), "server"; ["/bin/hello", "a b"]);
+        pass!(["user ALL=/bin/sh -c 'echo \"hi\"'"], "user" => root(), "server"; ["/bin/sh", "-c", "echo \"hi\""]);
+        FAIL!(["user ALL=/bin/sh -c 'echo \"hi\"'"], "user" => root(), "server"; "/bin/sh -c echo \"hi\"");
+        pass!(["user ALL=/bin/sh -c \"echo hi\""], "user" => root(), "server"; ["/bin/sh", "-c", "echo hi"]);
+        FAIL!(["user ALL=/bin/sh -c \"echo hi\""], "user" => root(), "server"; "/bin/sh -c echo hi");
+        // the command itself may contain a space
+        pass!(["user ALL=\"/opt/my app/run\" x"], "user" => root(), "server"; ["/opt/my app/run", "x"]);
+        FAIL!(["user ALL=\"/opt/my app/run\" x"], "user" => root(), "server"; "/opt/my app/run x");
+        // with wildcards, the arguments are matched as they are joined by single spaces
+        pass!(["user ALL=/bin/hello a*"], "user" => root(), "server"; ["/bin/hello", "ab c"]);
+        pass!(["user ALL=/bin/hello a*"], "user" => root(), "server"; ["/bin/hello", "ab", "c"]);
+        pass!(["user ALL=/bin/hello \"*\""], "user" => root(), "server"; "/bin/hello *");
+        FAIL!(["user ALL=/bin/hello \"*\""], "user" => root(), "server"; "/bin/hello boo");
+        pass!(["user ALL=/bin/hello a\"*\"b*"], "user" => root(), "server"; "/bin/hello a*bc");
+        pass!(["user ALL=/bin/hello \"\" x"], "user" => root(), "server"; "/bin/hello  x");
+        pass!(["Cmnd_Alias HELLO=/bin/hello \"a\\, b\"", "user ALL=HELLO"], "user" => root(), "server"; ["/bin/hello", "a, b"]);
+        SYNTAX!(["user ALL=/bin/hello \"a b"]);
         pass!(["user ALL=/bin/hel*"], "user" => root(), "server"; "/bin/hello");
         pass!(["user ALL=/bin/hel*"], "user" => root(), "server"; "/bin/help");
         pass!(["user ALL=/bin/hel*"], "user" => root(), "server"; "/bin/help me");
@@ -508,6 +1265,19 @@ mod test {
         pass!(["user ALL=/bin/hel* me"], "user" => root(), "server"; "/bin/help me");
         FAIL!(["user ALL=/bin/hel* me"], "user" => root(), "server"; "/bin/help me please");
 
+        // a directory matches the files in it, but not in its subdirectories
+        pass!(["user ALL=/usr/local/bin/"], "user" => root(), "server"; "/usr/local/bin/hello");
+        pass!(["user ALL=/usr/local/bin/"], "user" => root(), "server"; "/usr/local/bin/hello me");
+        FAIL!(["user ALL=/usr/local/bin/"], "user" => root(), "server"; "/usr/local/bin/sub/hello");
+        FAIL!(["user ALL=/usr/local/bin/"], "user" => root(), "server"; "/usr/local/bin");
+        FAIL!(["user ALL=/usr/local/bin/"], "user" => root(), "server"; "/usr/local/binary");
+        FAIL!(["user ALL=/usr/local/bin/ \"\""], "user" => root(), "server"; "/usr/local/bin/hello me");
+        pass!(["user ALL=/opt/*/bin/"], "user" => root(), "server"; "/opt/tool/bin/hello");
+        FAIL!(["user ALL=/opt/*/bin/"], "user" => root(), "server"; "/opt/tool/sub/bin/hello");
+        pass!(["Cmnd_Alias LOCAL=/usr/local/bin/,/usr/local/sbin/", "user ALL=LOCAL"], "user" => root(), "server"; "/usr/local/sbin/hello");
+        FAIL!(["Cmnd_Alias LOCAL=/usr/local/bin/", "user ALL=ALL,!LOCAL"], "user" => root(), "server"; "/usr/local/bin/hello");
+        pass!(["Cmnd_Alias LOCAL_2=/usr/local/bin/", "user ALL=LOCAL_2"], "user" => root(), "server"; "/usr/local/bin/hello");
+
         SYNTAX!(["User_Alias, marc ALL = ALL"]);
 
         pass!(["User_Alias FULLTIME=ALL,!marc","FULLTIME ALL=ALL"], "user" => root(), "server"; "/bin/bash");
@@ -522,16 +1292,97 @@ mod test {
         FAIL!(["Cmnd_Alias WHAT=/bin/dd,/bin/rm","user ALL=WHAT"], "user" => root(), "desktop"; "/bin/bash");
 
         pass!(["User_Alias A=B","User_Alias B=user","A ALL=ALL"], "user" => root(), "vm"; "/bin/ls");
+        pass!(["User_Alias A=marc,B","User_Alias B=user","A ALL=ALL"], "user" => root(), "vm"; "/bin/ls");
+        FAIL!(["User_Alias A=ALL,!B","User_Alias B=user,C","User_Alias C=marc","A ALL=ALL"], "marc" => root(), "vm"; "/bin/ls");
+        pass!(["User_Alias A=ALL,!B","User_Alias B=user,!C","User_Alias C=marc","A ALL=ALL"], "marc" => root(), "vm"; "/bin/ls");
+        FAIL!(["User_Alias A=ALL,!B","User_Alias B=user,!C","User_Alias C=marc","A ALL=ALL"], "user" => root(), "vm"; "/bin/ls");
         pass!(["Host_Alias A=B","Host_Alias B=vm","ALL A=ALL"], "user" => root(), "vm"; "/bin/ls");
         pass!(["Cmnd_Alias A=B","Cmnd_Alias B=/bin/ls","ALL ALL=A"], "user" => root(), "vm"; "/bin/ls");
 
-        FAIL!(["Runas_Alias TIME=%wheel,sudo","user ALL=() ALL"], "user" => Request{ user: &"sudo", group: &(42,"sudo") }, "vm"; "/bin/ls");
-        pass!(["Runas_Alias TIME=%wheel,sudo","user ALL=(TIME) ALL"], "user" => Request{ user: &"sudo", group: &(42,"sudo") }, "vm"; "/bin/ls");
-        FAIL!(["Runas_Alias TIME=%wheel,sudo","user ALL=(:TIME) ALL"], "user" => Request{ user: &"sudo", group: &(42,"sudo") }, "vm"; "/bin/ls");
-        pass!(["Runas_Alias TIME=%wheel,sudo","user ALL=(:TIME) ALL"], "user" => Request{ user: &"user", group: &(42,"sudo") }, "vm"; "/bin/ls");
-        pass!(["Runas_Alias TIME=%wheel,sudo","user ALL=(TIME) ALL"], "user" => Request{ user: &"wheel", group: &(37,"wheel") }, "vm"; "/bin/ls");
+        FAIL!(["Runas_Alias TIME=%wheel,sudo","user ALL=() ALL"], "user" => Request{ user: &"sudo", group: &(42,"sudo"), clock: &Monotonic, resolved_command: None }, "vm"; "/bin/ls");
+        pass!(["Runas_Alias TIME=%wheel,sudo","user ALL=(TIME) ALL"], "user" => Request{ user: &"sudo", group: &(42,"sudo"), clock: &Monotonic, resolved_command: None }, "vm"; "/bin/ls");
+        FAIL!(["Runas_Alias TIME=%wheel,sudo","user ALL=(:TIME) ALL"], "user" => Request{ user: &"sudo", group: &(42,"sudo"), clock: &Monotonic, resolved_command: None }, "vm"; "/bin/ls");
+        pass!(["Runas_Alias TIME=%wheel,sudo","user ALL=(:TIME) ALL"], "user" => Request{ user: &"user", group: &(42,"sudo"), clock: &Monotonic, resolved_command: None }, "vm"; "/bin/ls");
+        pass!(["Runas_Alias TIME=%wheel,sudo","user ALL=(TIME) ALL"], "user" => Request{ user: &"wheel", group: &(37,"wheel"), clock: &Monotonic, resolved_command: None }, "vm"; "/bin/ls");
 
-        pass!(["Runas_Alias \\"," TIME=%wheel\\",",sudo # hallo","user ALL\\","=(TIME) ALL"], "user" => Request{ user: &"wheel", group: &(37,"wheel") }, "vm"; "/bin/ls");
+        pass!(["Runas_Alias \\"," TIME=%wheel\\",",sudo # hallo","user ALL\\","=(TIME) ALL"], "user" => Request{ user: &"wheel", group: &(37,"wheel"), clock: &Monotonic, resolved_command: None }, "vm"; "/bin/ls");
+    }
+
+    #[test]
+    fn time_window_test() {
+        let (sudoers, _) = analyze(sudoer![
+            "user ALL=(ALL:ALL) NOTBEFORE=20200101000000Z NOTAFTER=20200102000000Z /bin/ls, /bin/cat",
+            "user ALL=(ALL:ALL) NOTBEFORE=20200101000000Z /bin/date"
+        ]);
+        assert!(sudoers.fast_path.is_none());
+
+        let clock = FakeClock::default();
+        let allowed = |sudoers: &Sudoers, command: &str| {
+            let request = Request {
+                user: &"root",
+                group: &(0, "root"),
+                clock: &clock,
+                resolved_command: None,
+            };
+            judge(sudoers, &"user", request, "server", &command.argv()).is_ok()
+        };
+        // the fake clock starts at the current time, which is after the window
+        assert!(!allowed(&sudoers, "/bin/ls"));
+        assert!(!allowed(&sudoers, "/bin/cat"));
+        assert!(allowed(&sudoers, "/bin/date"));
+
+        let (sudoers, _) = analyze(sudoer![
+            "user ALL=(ALL:ALL) NOTAFTER=99990101000000Z /bin/ls"
+        ]);
+        assert!(allowed(&sudoers, "/bin/ls"));
+        clock.advance(std::time::Duration::from_secs(3600));
+        assert!(allowed(&sudoers, "/bin/ls"));
+    }
+
+    #[test]
+    fn deny_reason_test() {
+        let judge = |lines: &[&str], user: &str, runas: &str, host: &str, command: &str| {
+            let (sudoers, _) = analyze(lines.iter().map(|line| Ok(parse_line(line))));
+            let request = Request {
+                user: &runas,
+                group: &(0, "root"),
+                clock: &Monotonic,
+                resolved_command: None,
+            };
+            judge(&sudoers, &user, request, host, &command.argv())
+        };
+        let policy = ["user server=(root) /bin/ls, !/bin/rm", "admin ALL=ALL"];
+        let deny = |user, runas, host, command| judge(&policy, user, runas, host, command).err();
+
+        use DenyReason::*;
+        for (request, reason) in [
+            (("user", "root", "server", "/bin/ls"), None),
+            (("nobody", "root", "server", "/bin/ls"), Some(NotInSudoers)),
+            (("user", "root", "laptop", "/bin/ls"), Some(HostMismatch)),
+            (
+                ("user", "daemon", "server", "/bin/ls"),
+                Some(RunasNotAllowed),
+            ),
+            (
+                ("user", "root", "server", "/bin/cat"),
+                Some(CommandNotAllowed),
+            ),
+            (
+                ("user", "root", "server", "/bin/rm"),
+                Some(CommandNotAllowed),
+            ),
+        ] {
+            let (user, runas, host, command) = request;
+            assert_eq!(deny(user, runas, host, command), reason, "{request:?}");
+        }
+
+        // the fast path only decides when a request is allowed
+        let trivial = ["admin ALL=(ALL:ALL) ALL"];
+        assert!(judge(&trivial, "admin", "daemon", "server", "/bin/ls").is_ok());
+        assert_eq!(
+            judge(&trivial, "user", "root", "server", "/bin/ls"),
+            Err(NotInSudoers)
+        );
     }
 
     #[test]
@@ -552,6 +1403,227 @@ mod test {
         }
     }
 
+    #[test]
+    fn lint_test() {
+        let lint = |lines: &[&str]| {
+            let (sudoers, _) = analyze(lines.iter().map(|line| Ok(parse_line(line))));
+            let warnings = sudoers.lint().into_iter().map(|warning| match warning {
+                Error::Warning(text) => text,
+                error => panic!("{error:?}"),
+            });
+            warnings.collect::<Vec<_>>()
+        };
+
+        assert!(lint(&["Cmnd_Alias LS=/bin/ls", "User_Alias U=user", "U ALL=LS"]).is_empty());
+        assert!(lint(&["Host_Alias H=server", "Defaults@H !lecture", "root ALL=ALL"]).is_empty());
+        assert_eq!(
+            lint(&[
+                "Cmnd_Alias A=/bin/ls",
+                "Cmnd_Alias B=A",
+                "Runas_Alias R=root",
+                "user ALL=B"
+            ]),
+            ["unused Runas_Alias `R'"]
+        );
+        assert_eq!(
+            lint(&["user ALL=(OPERATOR) LS"]),
+            [
+                "undefined Runas_Alias `OPERATOR' is used, and never matches",
+                "undefined Cmnd_Alias `LS' is used, and never matches"
+            ]
+        );
+
+        assert_eq!(
+            lint(&["!user ALL=ALL", "user !server=ALL", "user ALL=(!root) ALL"]),
+            [
+                "rule 1 never applies: all of its users are negated",
+                "rule 2 never applies: all of its hosts are negated",
+                "rule 3 never applies: all of its runas users are negated"
+            ]
+        );
+        assert!(lint(&["ALL,!user ALL=ALL", "user ALL,!server=(ALL,!root) ALL"]).is_empty());
+
+        assert_eq!(
+            lint(&["user ALL=/bin/ls \"\", !ALL", "user ALL=/bin/cat, /bin/rm"]),
+            ["rule 1: /bin/ls \"\" is never allowed, because a later !ALL overrides it"]
+        );
+        assert_eq!(
+            lint(&["user ALL=(ALL) /bin/ls *.txt", "ALL ALL=(ALL:ALL) !ALL"]),
+            ["rule 1: /bin/ls *.txt is never allowed, because a later !ALL overrides it"]
+        );
+        // the later negation does not apply to all hosts or all runas users
+        assert!(lint(&["user ALL=(ALL) /bin/ls", "ALL server=(ALL) !ALL"]).is_empty());
+        assert!(lint(&["user ALL=(ALL) /bin/ls", "ALL ALL=!ALL"]).is_empty());
+        assert!(lint(&["user ALL=!ALL", "user ALL=/bin/ls"]).is_empty());
+
+        assert_eq!(
+            lint(&[
+                "user ALL=NOPASSWD: /bin/ls, /bin/cat",
+                "user ALL=NOPASSWD: /bin/cat"
+            ]),
+            ["rule 2 repeats rule 1 for /bin/cat"]
+        );
+        assert_eq!(
+            lint(&[
+                "user ALL=/bin/ls",
+                "user ALL=!/bin/ls",
+                "user ALL=NOEXEC: /bin/ls"
+            ]),
+            [
+                "rule 2 conflicts with rule 1 for /bin/ls; the later rule wins",
+                "rule 3 conflicts with rule 2 for /bin/ls; the later rule wins"
+            ]
+        );
+        // different users, hosts or runas specifications
+        assert!(lint(&["user ALL=/bin/ls", "other ALL=/bin/ls"]).is_empty());
+        assert!(lint(&["user ALL=/bin/ls", "user server=/bin/ls"]).is_empty());
+        assert!(lint(&["user ALL=/bin/ls", "user ALL=(ALL) /bin/ls"]).is_empty());
+    }
+
+    #[test]
+    fn includedir_test() {
+        let dir = std::env::temp_dir().join(format!("sudoers-includedir-{}", std::process::id()));
+        let dropins = dir.join("sudoers.d");
+        std::fs::create_dir_all(&dropins).unwrap();
+        let write = |name: &str, text: &str| std::fs::write(dropins.join(name), text).unwrap();
+        write("20-ops", "ops ALL=/bin/ls\n");
+        write("10-Ops", "ops ALL=!/bin/ls\n");
+        write("10-ops", "ops ALL=/bin/ls\n");
+        write("30-old.dpkg-old", "ops ALL=ALL\n");
+        write("30-backup~", "ops ALL=ALL\n");
+        let main = dir.join("sudoers");
+        let text = format!("root ALL=ALL\n@includedir {}\n", dropins.display());
+        std::fs::write(&main, text).unwrap();
+
+        let (sudoers, errors) = compile(&main).unwrap();
+        assert!(errors.is_empty());
+        // the files are included in the byte order of their names; some are skipped
+        let names = ["sudoers", "10-Ops", "10-ops", "20-ops"];
+        let files = sudoers.files().iter().map(|file| file.file_name().unwrap());
+        assert!(files.eq(names));
+        let warnings = sudoers.lint();
+        let warnings = warnings.iter().map(|warning| format!("{warning:?}\n"));
+        let warnings = warnings.collect::<String>();
+        let file = |name: &str| dropins.join(name).display().to_string();
+        assert!(warnings.contains(&format!("rule 3 ({}) conflicts with", file("10-ops"))));
+        assert!(warnings.contains(&format!("rule 4 ({}) repeats rule 3", file("20-ops"))));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn symlinked_directory_test() {
+        // as if /bin links to /usr/bin, and /usr/bin/vim to vim.basic in it
+        let (mut sudoers, _) = analyze(sudoer![
+            "user ALL=/usr/bin/vim, /sbin/v*",
+            "Defaults!/usr/bin/vim env_keep = EDITOR"
+        ]);
+        let allowed = |command: &str, resolved_command: Option<&str>| {
+            let request = Request {
+                user: &"root",
+                group: &(0, "root"),
+                clock: &sudo_common::clock::Monotonic,
+                resolved_command,
+            };
+            check_permission(&sudoers, &"user", request, "server", &command.argv()).is_some()
+        };
+        assert!(allowed("/usr/bin/vim", None));
+        // the same file through a linked directory
+        assert!(allowed("/bin/vim", Some("/usr/bin/vim")));
+        assert!(!allowed("/bin/vim", None));
+        // but the command itself is not resolved, and wildcards are only matched by name
+        assert!(!allowed("/usr/bin/vim.basic", None));
+        assert!(!allowed("/usr/bin/vi", None));
+        assert!(allowed("/sbin/vi", Some("/usr/sbin/vi")));
+        assert!(!allowed("/bin/vi", Some("/sbin/vi")));
+
+        sudoers.apply_command_defaults(&"/bin/vim".argv(), Some("/usr/bin/vim"));
+        assert!(sudoers.settings.list["env_keep"].contains("EDITOR"));
+    }
+
+    #[test]
+    fn defaults_merge_test() {
+        let dir = std::env::temp_dir().join(format!("sudoers-merge-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let part = dir.join("part");
+        let text = "Defaults env_keep += \"C A\"\n\
+                    Defaults:alice env_keep -= C\n\
+                    Defaults@server env_keep += E\n";
+        std::fs::write(&part, text).unwrap();
+        let main = dir.join("sudoers");
+        let text = format!(
+            "Defaults env_keep = \"B A\"\n@include {}\nDefaults env_keep -= A\n\
+             Defaults:alice env_keep += \"D B\"\n",
+            part.display()
+        );
+        std::fs::write(&main, text).unwrap();
+
+        let env_keep = |user: &str, host: &str| {
+            let (mut sudoers, errors) = compile(&main).unwrap();
+            assert!(errors.is_empty());
+            sudoers.apply_scoped_defaults(&user, host);
+            let dump = sudoers.settings.to_string();
+            let line = dump.lines().find(|line| line.contains("env_keep"));
+            line.unwrap().to_string()
+        };
+        // every layer is applied in the order of the files, the generic one first, and then
+        // those of the host and the user
+        assert_eq!(env_keep("bob", "laptop"), "Defaults env_keep=\"B C\"");
+        assert_eq!(env_keep("bob", "server"), "Defaults env_keep=\"B C E\"");
+        assert_eq!(env_keep("alice", "server"), "Defaults env_keep=\"B E D\"");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn list_test() {
+        let (sudoers, _) = analyze(sudoer![
+            "Cmnd_Alias PKG = /usr/bin/apt, /usr/bin/dpkg",
+            "user ALL=(root, %#10 : wheel) NOPASSWD: PKG, CWD=/srv !/bin/sh",
+            "user laptop=ALL",
+            "%users server=NOTAFTER=20300101120000Z UMASK=027 /bin/ls -l",
+            "marc ALL=ALL",
+            "anna ALL=/bin/sh -c 'echo \"hi\"', /bin/true \"\", /bin/echo a'*'*"
+        ]);
+        let listed = |tags: &[&str], command: &str| ListedCommand {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            command: command.to_string(),
+        };
+        let strings = |items: &[&str]| items.iter().map(ToString::to_string).collect::<Vec<_>>();
+
+        let privileges = sudoers.list(&"user", "server");
+        assert_eq!(privileges.len(), 1);
+        assert_eq!(privileges[0].file, None);
+        assert_eq!(privileges[0].runas_users, strings(&["root", "%#10"]));
+        assert_eq!(privileges[0].runas_groups, strings(&["wheel"]));
+        assert_eq!(
+            privileges[0].commands,
+            [
+                listed(&["NOPASSWD"], "PKG"),
+                listed(&["NOPASSWD", "CWD=/srv"], "!/bin/sh")
+            ]
+        );
+
+        let privileges = sudoers.list(&"users", "server");
+        assert_eq!(privileges[0].runas_users, strings(&["root"]));
+        let tags = ["NOTAFTER=20300101120000Z", "UMASK=0027"];
+        assert_eq!(privileges[0].commands, [listed(&tags, "/bin/ls -l")]);
+        assert_eq!(sudoers.list(&"user", "laptop").len(), 2);
+        assert!(sudoers.list(&"nobody", "server").is_empty());
+
+        // arguments are listed the way they can be written in the sudoers file
+        let commands = [
+            "/bin/sh -c \"echo \"'\"'\"hi\"'\"'\"\"",
+            "/bin/true \"\"",
+            "/bin/echo a[*]*",
+        ];
+        let privileges = sudoers.list(&"anna", "server");
+        assert_eq!(
+            privileges[0].commands,
+            commands.map(|command| listed(&[], command))
+        );
+    }
+
     #[test]
     fn directive_test() {
         let _everybody = parse_eval::<Spec<UserSpecifier>>("ALL");
@@ -567,6 +1639,467 @@ mod test {
         }
     }
 
+    #[test]
+    fn password_required_test() {
+        let check = |lines: &[&str], user: &str, action| {
+            let text = lines.join("\n") + "\n";
+            let (sudoers, _) = analyze(parse_lines(&mut text.chars().peekable()));
+            password_required(&sudoers, &user, "server", action)
+        };
+        let mixed = [
+            "user ALL=(ALL) NOPASSWD: /bin/ls",
+            "user ALL=(ALL) /bin/rm",
+            "user laptop=(ALL) NOPASSWD: /bin/sh",
+        ];
+        assert!(check(&mixed, "user", Action::Validate));
+        assert!(!check(&mixed, "user", Action::List));
+        assert!(check(&mixed, "marc", Action::List));
+
+        let relaxed = [
+            "Defaults verifypw=any",
+            "user ALL=(ALL) NOPASSWD: /bin/ls, /bin/rm",
+        ];
+        assert!(!check(&relaxed, "user", Action::Validate));
+        // tags carry over to the next command
+        assert!(!check(&relaxed[1..], "user", Action::Validate));
+        let overridden = ["user ALL=(ALL) NOPASSWD: /bin/ls, PASSWD: /bin/rm"];
+        assert!(check(&overridden, "user", Action::Validate));
+        let undone = ["user ALL=(ALL) NOPASSWD: PASSWD: /bin/ls"];
+        assert!(check(&undone, "user", Action::List));
+        let redone = ["user ALL=(ALL) PASSWD: NOPASSWD: /bin/ls"];
+        assert!(!check(&redone, "user", Action::List));
+
+        let strict = ["Defaults listpw=always", relaxed[1]];
+        assert!(check(&strict, "user", Action::List));
+        let lax = ["Defaults verifypw=never", mixed[1]];
+        assert!(!check(&lax, "user", Action::Validate));
+
+        // the tags override the authenticate setting
+        let trusting = ["Defaults !authenticate", mixed[1]];
+        assert!(!check(&trusting, "user", Action::Validate));
+        let tagged = ["Defaults !authenticate", overridden[0]];
+        assert!(check(&tagged, "user", Action::Validate));
+        assert!(!check(&tagged, "user", Action::List));
+    }
+
+    #[test]
+    fn env_list_test() {
+        let vars = |line: &str| match parse_line(line) {
+            Sudo::Decl(Directive::Defaults(_, DefaultValue::List(_, items), _)) => items,
+            _ => panic!("incorrectly parsed"),
+        };
+        assert_eq!(vars("Defaults env_keep += \"FOO BAR\""), ["FOO", "BAR"]);
+        assert_eq!(
+            vars("Defaults env_keep += \"FOO,BAR, BAZ\""),
+            ["FOO", "BAR", "BAZ"]
+        );
+        assert_eq!(
+            vars("Defaults env_keep = \"LANG=C LC_*\""),
+            ["LANG=C", "LC_*"]
+        );
+        assert_eq!(
+            vars("Defaults env_keep = \"EDITOR=vi\\ -x\""),
+            ["EDITOR=vi -x"]
+        );
+        assert!(parse_string::<Sudo>("Defaults env_keep = \"FOO=\"").is_err());
+
+        let (sudoers, diagnostics) = analyze(sudoer![
+            "Defaults env_keep += \"FOO FOO\"",
+            "Defaults env_keep += FOO",
+            "Defaults env_keep -= \"PATH NONEXISTENT\""
+        ]);
+        let env_keep = &sudoers.settings.list["env_keep"];
+        assert!(env_keep.contains("FOO") && env_keep.contains("DISPLAY"));
+        assert!(!env_keep.contains("PATH"));
+        // like the original sudo, this is only reported when the policy is checked
+        assert!(diagnostics.is_empty());
+        let warnings = sudoers.lint();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(&warnings[0], Error::Warning(msg) if msg.contains("NONEXISTENT")));
+
+        let (sudoers, diagnostics) = analyze(sudoer![
+            "Defaults:bob env_keep -= \"FOO BAR\"",
+            "Defaults:alice env_keep += FOO"
+        ]);
+        assert!(diagnostics.is_empty());
+        let warnings = sudoers.lint();
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(&warnings[0], Error::Warning(msg) if msg.contains("BAR")));
+    }
+
+    #[test]
+    fn host_defaults_test() {
+        let settings = |host: &str| {
+            let (mut sudoers, _) = analyze(sudoer![
+                "Host_Alias SERVERS = web, db",
+                "Defaults@SERVERS env_keep += FOO",
+                "Defaults@laptop,web secure_path=/bin",
+                "Defaults@!db exit_with_parent",
+                "Defaults listpw=never"
+            ]);
+            sudoers.apply_scoped_defaults(&"user", host);
+            sudoers.settings
+        };
+
+        let web = settings("web");
+        assert!(web.list["env_keep"].contains("FOO"));
+        assert_eq!(web.str_value["secure_path"], "/bin");
+        assert_eq!(web.str_value["listpw"], "never");
+
+        let db = settings("db");
+        assert!(db.list["env_keep"].contains("FOO"));
+        assert!(!db.str_value.contains_key("secure_path"));
+        assert!(!db.flags.contains("exit_with_parent"));
+
+        let laptop = settings("laptop");
+        assert!(!laptop.list["env_keep"].contains("FOO"));
+        assert_eq!(laptop.str_value["secure_path"], "/bin");
+        assert_eq!(laptop.str_value["listpw"], "never");
+    }
+
+    #[test]
+    fn user_defaults_test() {
+        let settings = |user: &str| {
+            let (mut sudoers, _) = analyze(sudoer![
+                "User_Alias ADMINS = alice, %wheel",
+                "Defaults:ADMINS env_keep += FOO",
+                "Defaults:bob secure_path=/bin",
+                "Defaults@web secure_path=/usr/bin"
+            ]);
+            sudoers.apply_scoped_defaults(&user, "web");
+            sudoers.settings
+        };
+
+        assert!(settings("alice").list["env_keep"].contains("FOO"));
+        assert!(settings("wheel").list["env_keep"].contains("FOO"));
+        assert!(!settings("bob").list["env_keep"].contains("FOO"));
+        // user-specific Defaults take precedence over host-specific ones
+        assert_eq!(settings("bob").str_value["secure_path"], "/bin");
+        assert_eq!(settings("alice").str_value["secure_path"], "/usr/bin");
+    }
+
+    #[test]
+    fn runas_defaults_test() {
+        let settings = |runas: &str| {
+            let (mut sudoers, diagnostics) = analyze(sudoer![
+                "Runas_Alias OPERATORS = operator, %backup",
+                "Defaults>backupuser umask=0027",
+                "Defaults>OPERATORS env_keep += FOO",
+                "Defaults>root,!backupuser secure_path=/sbin"
+            ]);
+            assert!(diagnostics.is_empty());
+            sudoers.apply_scoped_defaults(&"user", "server");
+            assert_eq!(sudoers.settings.integer("umask"), Some(0o022));
+            sudoers.apply_runas_defaults(&runas);
+            sudoers.settings
+        };
+
+        assert_eq!(settings("backupuser").integer("umask"), Some(0o027));
+        assert_eq!(settings("backupuser").text("secure_path"), None);
+        assert!(settings("operator").list["env_keep"].contains("FOO"));
+        assert!(settings("backup").list["env_keep"].contains("FOO"));
+        assert!(!settings("root").list["env_keep"].contains("FOO"));
+        assert_eq!(settings("root").text("secure_path"), Some("/sbin"));
+        assert_eq!(settings("root").integer("umask"), Some(0o022));
+    }
+
+    #[test]
+    fn requiretty_test() {
+        let requiretty = |user: &str, cmdline: &str| {
+            let (mut sudoers, diagnostics) = analyze(sudoer![
+                "User_Alias AUTOMATION = ci, backup",
+                "Cmnd_Alias BACKUP = /usr/local/bin/backup",
+                "Defaults requiretty",
+                "Defaults:deploy !requiretty",
+                "Defaults:AUTOMATION requiretty",
+                "Defaults!BACKUP !requiretty"
+            ]);
+            assert!(diagnostics.is_empty());
+            sudoers.apply_scoped_defaults(&user, "server");
+            sudoers.apply_command_defaults(&cmdline.argv(), None);
+            sudoers.settings.flag("requiretty")
+        };
+
+        assert!(requiretty("alice", "/usr/bin/apt upgrade"));
+        assert!(requiretty("backup", "/usr/bin/rsync"));
+        assert!(!requiretty("deploy", "/usr/bin/systemctl restart web"));
+        // the command-specific entries are applied last, for every user
+        assert!(!requiretty("backup", "/usr/local/bin/backup --full"));
+        assert!(!requiretty("alice", "/usr/local/bin/backup"));
+    }
+
+    #[test]
+    fn command_defaults_test() {
+        let settings = |cmdline: &str| {
+            let (mut sudoers, diagnostics) = analyze(sudoer![
+                "Cmnd_Alias CAPTURE = /usr/bin/tcpdump, /usr/bin/wireshark",
+                "Defaults!CAPTURE retain_capabilities = cap_net_raw",
+                "Defaults!/usr/bin/less,/usr/bin/more noexec",
+                "Defaults !env_reset",
+                "Defaults:user secure_path=/bin"
+            ]);
+            assert!(diagnostics.is_empty());
+            sudoers.apply_scoped_defaults(&"user", "server");
+            // command-specific Defaults are only applied once the command is known
+            assert!(!sudoers.settings.flags.contains("noexec"));
+            sudoers.apply_command_defaults(&cmdline.argv(), None);
+            sudoers.settings
+        };
+
+        let tcpdump = settings("/usr/bin/tcpdump -i eth0");
+        assert!(tcpdump.list["retain_capabilities"].contains("cap_net_raw"));
+        assert!(!tcpdump.flags.contains("noexec"));
+        assert!(!tcpdump.flags.contains("env_reset"));
+        assert_eq!(tcpdump.str_value["secure_path"], "/bin");
+
+        let less = settings("/usr/bin/less /etc/fstab");
+        assert!(less.flags.contains("noexec"));
+        assert!(less.list["retain_capabilities"].is_empty());
+    }
+
+    #[test]
+    fn fast_path_test() {
+        let trivial: &[&[&str]] = &[
+            &["user ALL=(ALL:ALL) ALL"],
+            &["root ALL=(ALL:ALL) ALL", "%user ALL=(ALL) NOPASSWD: ALL"],
+            &["ALL,!bob ALL=(ALL) ALL", "bob ALL=ALL"],
+            &[
+                "user ALL=(ALL) /bin/ls, ALL",
+                "user ALL=(ALL:ALL) NOSETENV: ALL",
+            ],
+        ];
+        let requests = [
+            (&"root", (0, "root")),
+            (&"user", (0, "root")),
+            (&"user", (1000, "user")),
+        ];
+
+        for policy in trivial {
+            let (sudoers, _) = analyze(policy.iter().map(|line| Ok(parse_line(line))));
+            assert!(sudoers.fast_path.is_some(), "{policy:?}");
+            let general = Sudoers {
+                fast_path: None,
+                ..analyze(policy.iter().map(|line| Ok(parse_line(line)))).0
+            };
+            for am_user in ["root", "user", "bob"] {
+                for (user, group) in &requests {
+                    let check = |sudoers| {
+                        let request = Request {
+                            user: *user,
+                            group,
+                            clock: &Monotonic,
+                            resolved_command: None,
+                        };
+                        check_permission(sudoers, &am_user, request, "server", &"/bin/ls".argv())
+                    };
+                    assert_eq!(
+                        check(&sudoers),
+                        check(&general),
+                        "{policy:?} {am_user} {user}:{group:?}"
+                    );
+                }
+            }
+        }
+
+        for policy in [
+            &["User_Alias ADMINS = user", "ADMINS ALL=(ALL) ALL"][..],
+            &["user server=(ALL) ALL"],
+            &["user ALL=(ALL) ALL, /bin/ls"],
+            &["user ALL=(root) ALL"],
+        ] {
+            let (sudoers, _) = analyze(policy.iter().map(|line| Ok(parse_line(line))));
+            assert!(sudoers.fast_path.is_none(), "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn path_info_test() {
+        let (sudoers, _) = analyze(sudoer!["Defaults env_reset"]);
+        assert!(sudoers.settings.flags.contains("path_info"));
+        let (sudoers, _) = analyze(sudoer!["Defaults !path_info"]);
+        assert!(!sudoers.settings.flags.contains("path_info"));
+    }
+
+    #[test]
+    fn logging_defaults_test() {
+        let (sudoers, _) = analyze(sudoer!["Defaults log_year"]);
+        assert_eq!(sudoers.settings.str_value["syslog"], "authpriv");
+        assert!(sudoers.settings.flags.contains("log_year"));
+        let (sudoers, _) = analyze(sudoer![
+            "Defaults !syslog",
+            "Defaults logfile=/var/log/sudo.log"
+        ]);
+        assert!(!sudoers.settings.str_value.contains_key("syslog"));
+        assert_eq!(sudoers.settings.str_value["logfile"], "/var/log/sudo.log");
+    }
+
+    #[test]
+    fn umask_defaults_test() {
+        let (sudoers, _) = analyze(sudoer!["Defaults umask=0077", "Defaults umask_override"]);
+        assert_eq!(sudoers.settings.integer("umask"), Some(0o77));
+        assert!(sudoers.settings.flag("umask_override"));
+        let (sudoers, _) = analyze(sudoer!["Defaults !umask"]);
+        assert_eq!(sudoers.settings.integer("umask"), None);
+    }
+
+    #[test]
+    fn settings_display_test() {
+        let (mut sudoers, _) = analyze(sudoer![
+            "Defaults:bob umask=0077",
+            "Defaults secure_path=\"/usr/bin:/my \\\"bin\\\"\"",
+            "Defaults env_keep += \"EDITOR=vi\\ -x\"",
+            "Defaults !env_check",
+            "Defaults !syslog"
+        ]);
+        sudoers.apply_scoped_defaults(&"bob", "server");
+        let dump = sudoers.settings.to_string();
+        for line in [
+            "Defaults env_reset",
+            "Defaults !log_year",
+            "Defaults !env_check",
+            "Defaults !syslog",
+            "Defaults passwd_tries=3",
+            "Defaults umask=0077",
+            "Defaults verifypw=\"all\"",
+            "Defaults secure_path=\"/usr/bin:/my \\\"bin\\\"\"",
+        ] {
+            assert!(dump.lines().any(|entry| entry == line), "{line}");
+        }
+        assert!(dump.contains("EDITOR=vi\\ -x"));
+
+        // the output can be read back as a sudoers file
+        let (reread, diagnostics) = analyze(dump.lines().map(|line| Ok(parse_line(line))));
+        assert!(diagnostics.is_empty());
+        assert_eq!(reread.settings.to_string(), dump);
+    }
+
+    #[test]
+    fn invalid_defaults_test() {
+        let fatal = |line: &str| match &diagnose(&format!("{line}\n"))[..] {
+            [Error::Fatal(message), ..] => message.clone(),
+            diagnostics => panic!("{line}: {diagnostics:?}"),
+        };
+        assert_eq!(
+            fatal("Defaults frobnicate"),
+            "unknown setting: `frobnicate'"
+        );
+        assert_eq!(
+            fatal("Defaults secure_path"),
+            "secure_path is not a boolean setting"
+        );
+        assert_eq!(
+            fatal("Defaults umask += 077"),
+            "umask is not a list parameter"
+        );
+        assert!(fatal("Defaults umask=01000").contains("out of range"));
+        assert!(fatal("Defaults verifypw=sometimes").contains("expected one of"));
+        assert!(diagnose("Defaults !secure_path\nDefaults passwd_tries=5\n").is_empty());
+
+        let (sudoers, _) = analyze(sudoer!["Defaults passwd_tries=5"]);
+        assert_eq!(sudoers.settings.integer("passwd_tries"), Some(5));
+        assert_eq!(Settings::default().integer("passwd_tries"), Some(3));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+        let (sudoers, _) = analyze(sudoer![
+            "Cmnd_Alias SHELLS = /bin/sh, /bin/b*sh",
+            "Defaults env_keep += FOO",
+            "Defaults@web secure_path=/usr/bin",
+            "user ALL=(root) NOPASSWD: /bin/ls -l, !SHELLS"
+        ]);
+        let json = serde_json::to_string(&sudoers).unwrap();
+        // patterns are stored as the text they were written as
+        assert!(json.contains(r#"{"Only":["/bin/b*sh","*"]}"#));
+        let copy: Sudoers = serde_json::from_str(&json).unwrap();
+
+        let root = || Request::<&str, _> {
+            user: &"root",
+            group: &(0, "root"),
+            clock: &Monotonic,
+            resolved_command: None,
+        };
+        assert_eq!(
+            check_permission(&copy, &"user", root(), "server", &"/bin/ls -l".argv()),
+            Some(vec![Tag::NoPasswd])
+        );
+        assert!(check_permission(&copy, &"user", root(), "server", &"/bin/bash".argv()).is_none());
+        assert!(copy.settings.list["env_keep"].contains("FOO"));
+    }
+
+    #[test]
+    fn group_id_test() {
+        /// a user that is only known by its group memberships
+        struct Member(Vec<libc::gid_t>);
+        impl UnixUser for Member {
+            fn in_group_by_gid(&self, gid: libc::gid_t) -> bool {
+                self.0.contains(&gid)
+            }
+        }
+        let member = Member(vec![1000, 42]);
+        let other = Member(vec![1000]);
+
+        let sudoers = || {
+            analyze(sudoer![
+                "Defaults:%#42 secure_path=/bin",
+                "%#42 ALL=(ALL) NOPASSWD: ALL"
+            ])
+            .0
+        };
+
+        let mut policy = sudoers();
+        policy.apply_scoped_defaults(&member, "host");
+        assert_eq!(policy.settings.str_value["secure_path"], "/bin");
+        assert!(!password_required(
+            &policy,
+            &member,
+            "host",
+            Action::Validate
+        ));
+
+        let mut policy = sudoers();
+        policy.apply_scoped_defaults(&other, "host");
+        assert!(!policy.settings.str_value.contains_key("secure_path"));
+        assert!(password_required(&policy, &other, "host", Action::Validate));
+    }
+
+    #[test]
+    fn nonunix_group_test() {
+        /// a user that is only known by the non-Unix groups it is a member of
+        struct Member(&'static [&'static str]);
+        impl UnixUser for Member {
+            fn in_nonunix_group(&self, name: &str) -> bool {
+                self.0.contains(&name)
+            }
+        }
+
+        let (policy, _) = analyze(sudoer![
+            "%:domain-admins ALL=(ALL) NOPASSWD: ALL",
+            "%:S-1-5-32-544 ALL=(ALL) NOPASSWD: ALL",
+            "%:#513 ALL=(ALL) NOPASSWD: /bin/ls",
+            "%admins ALL=(ALL) NOPASSWD: /bin/cat"
+        ]);
+        let may_list = |groups| !password_required(&policy, &Member(groups), "host", Action::List);
+        assert!(may_list(&["domain-admins"]));
+        assert!(may_list(&["S-1-5-32-544"]));
+        assert!(may_list(&["#513"]));
+        assert!(!may_list(&["admins"]));
+        assert!(!may_list(&[]));
+
+        let (policy, _) = analyze(sudoer![
+            "User_Alias ADMINS = %:domain-admins, !%:#513",
+            "Runas_Alias DBA = %:dba",
+            "Defaults:%:auditors log_input",
+            "ADMINS ALL=(DBA, %:dba) ALL",
+            "%:S-1-5-32-544 ALL=(ALL) ALL"
+        ]);
+        assert_eq!(
+            policy.nonunix_groups(),
+            ["dba", "S-1-5-32-544", "domain-admins", "#513", "auditors"]
+        );
+    }
+
     #[test]
     // the overloading of '#' causes a lot of issues
     fn hashsign_test() {
@@ -636,6 +2169,28 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_alias_depth() {
+        // alias names can only contain capitals
+        let letter = |n: usize| (b'A' + n as u8) as char;
+        let name = |i: usize| format!("A{}{}", letter(i / 26), letter(i % 26));
+        let chain = |n: usize| {
+            (0..n)
+                .map(|i| format!("User_Alias {} = user, {}", name(i), name(i + 1)))
+                .chain([format!("User_Alias {} = marc", name(n))])
+                .collect::<Vec<_>>()
+        };
+        let check = |lines: Vec<String>| {
+            let text = lines.join("\n") + "\n";
+            analyze(parse_lines(&mut text.chars().peekable())).1
+        };
+        assert!(check(chain(ALIAS_DEPTH_LIMIT)).is_empty());
+        assert!(!check(chain(ALIAS_DEPTH_LIMIT + 1)).is_empty());
+
+        let (_, diagnostics) = analyze(sudoer!["User_Alias A = user, B", "User_Alias B = !A"]);
+        assert!(matches!(&diagnostics[..], [Error::Fatal(msg)] if msg.contains("recursive")));
+    }
+
     #[test]
     fn test_topo_positive() {
         test_topo_sort(3);
@@ -676,7 +2231,7 @@ mod test {
 
             for i in (1..=siz).rev() {
                 let pos = n % i;
-                n = n / i;
+                n /= i;
                 data.swap(i - 1, pos);
             }
 
@@ -0,0 +1,281 @@
+//! Warnings about a policy that is valid, but is probably not what its author meant: aliases that
+//! are never used or never defined, and rules that can never apply, or that are undone by a later
+//! `!ALL`, or that repeat or contradict an earlier rule (which happens easily when several packages
+//! drop files into an included directory), and `-=` Defaults that remove what is not there. Like
+//! the checks of `visudo -c`, these are reported as [Error::Warning]s.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::*;
+use crate::fast::is_all;
+use crate::tokens::{Command, Meta};
+use crate::{elems, Error, Sudoers, VecOrd};
+
+impl Sudoers {
+    /// Look for likely mistakes in the policy; this must be done before the scoped Defaults are
+    /// applied, since those are only kept until then. Rules are numbered from 1, in the order in
+    /// which they appear in the sudoers file (and the files that it includes).
+    pub fn lint(&self) -> Vec<Error> {
+        let mut diagnostics = Vec::new();
+        self.lint_aliases(&mut diagnostics);
+        self.lint_rules(&mut diagnostics);
+        self.lint_duplicates(&mut diagnostics);
+        self.lint_removals(&mut diagnostics);
+        diagnostics
+    }
+
+    /// How a rule is referred to in warnings: its number, and the file it is in
+    fn origin(&self, index: usize) -> String {
+        let number = index + 1;
+        match self.rule_files.get(index).copied().flatten() {
+            Some(file) => format!("rule {number} ({})", self.files[file].display()),
+            None => format!("rule {number}"),
+        }
+    }
+
+    fn lint_aliases(&self, diagnostics: &mut Vec<Error>) {
+        let mut user = Usage::default();
+        let mut host = Usage::default();
+        let mut runas = Usage::default();
+        let mut cmnd = Usage::default();
+
+        for rule in &self.rules {
+            user.by_policy(&rule.users);
+            for (hosts, runas_spec, commands) in &rule.permissions {
+                host.by_policy(hosts);
+                if let Some(RunAs { users, groups }) = runas_spec {
+                    runas.by_policy(users);
+                    runas.by_policy(groups);
+                }
+                for CommandSpec(_, command) in commands {
+                    cmnd.by_policy(std::slice::from_ref(command));
+                }
+            }
+        }
+        for (scope, _, _) in &self.defaults {
+            match scope {
+                ConfigScope::Generic => {}
+                ConfigScope::Host(hosts) => host.by_policy(hosts),
+                ConfigScope::User(users) => user.by_policy(users),
+                ConfigScope::Runas(users) => runas.by_policy(users),
+                ConfigScope::Command(commands) => cmnd.by_policy(commands),
+            }
+        }
+
+        let aliases = &self.aliases;
+        user.report("User_Alias", &aliases.user, diagnostics);
+        host.report("Host_Alias", &aliases.host, diagnostics);
+        runas.report("Runas_Alias", &aliases.runas, diagnostics);
+        cmnd.report("Cmnd_Alias", &aliases.cmnd, diagnostics);
+    }
+
+    fn lint_rules(&self, diagnostics: &mut Vec<Error>) {
+        let mut warn = |text: String| diagnostics.push(Error::Warning(text));
+
+        for (index, rule) in self.rules.iter().enumerate() {
+            let number = index + 1;
+            if only_negations(&rule.users) {
+                warn(format!(
+                    "rule {number} never applies: all of its users are negated"
+                ));
+                continue;
+            }
+            for (hosts, runas, commands) in &rule.permissions {
+                if only_negations(hosts) {
+                    warn(format!(
+                        "rule {number} never applies: all of its hosts are negated"
+                    ));
+                    continue;
+                }
+                if let Some(RunAs { users, .. }) = runas {
+                    if only_negations(users) {
+                        warn(format!(
+                            "rule {number} never applies: all of its runas users are negated"
+                        ));
+                        continue;
+                    }
+                }
+
+                for (position, CommandSpec(_, command)) in commands.iter().enumerate() {
+                    let Qualified::Allow(allowed) = command else {
+                        continue;
+                    };
+                    let later = &commands[position + 1..];
+                    let negated_here = later.iter().any(forbids_all);
+                    let negated_later = self.rules[index + 1..]
+                        .iter()
+                        .filter(|later| is_all(&later.users))
+                        .flat_map(|later| &later.permissions)
+                        .any(|(later_hosts, later_runas, later_commands)| {
+                            is_all(later_hosts)
+                                && covers(later_runas, runas)
+                                && later_commands.iter().any(forbids_all)
+                        });
+                    if negated_here || negated_later {
+                        warn(format!(
+                            "rule {number}: {} is never allowed, because a later !ALL overrides it",
+                            describe(allowed)
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Commands that an earlier rule already mentions for the same users, hosts and runas
+    /// specification: either with the same outcome, which is harmless but redundant, or with a
+    /// different one, in which case the later rule wins.
+    /// A `-=` that removes an item from a list that does not have it; the original sudo ignores
+    /// these, so they are only reported here. For scoped entries, which are applied later, an
+    /// item is only reported if no entry at all puts it in the list.
+    fn lint_removals(&self, diagnostics: &mut Vec<Error>) {
+        let mut warn = |key: &str, name: &str| {
+            diagnostics.push(Error::Warning(format!(
+                "cannot remove {key} from {name}: not present"
+            )))
+        };
+
+        for (name, key) in &self.absent_removals {
+            warn(key, name);
+        }
+
+        let added = |name: &str, key: &String| {
+            self.settings
+                .list
+                .get(name)
+                .is_some_and(|list| list.contains(key))
+                || self.defaults.iter().any(|(_, other, value)| {
+                    other == name
+                        && matches!(value, DefaultValue::List(Mode::Set | Mode::Add, values)
+                            if values.contains(key))
+                })
+        };
+        for (_, name, value) in &self.defaults {
+            if let DefaultValue::List(Mode::Del, values) = value {
+                for key in values.iter().filter(|key| !added(name, key)) {
+                    warn(key, name);
+                }
+            }
+        }
+    }
+
+    fn lint_duplicates(&self, diagnostics: &mut Vec<Error>) {
+        let mut seen = HashMap::<String, (usize, &CommandSpec)>::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            for (hosts, runas, commands) in &rule.permissions {
+                for spec in commands {
+                    let CommandSpec(tags, Qualified::Allow(command) | Qualified::Forbid(command)) =
+                        spec;
+                    // the parsed form is the same for entries that are written the same way
+                    let key = format!("{:?} {hosts:?} {runas:?} {command:?}", rule.users);
+                    if let Some((earlier, CommandSpec(earlier_tags, earlier_command))) =
+                        seen.insert(key, (index, spec))
+                    {
+                        if earlier == index {
+                            continue;
+                        }
+                        let same_outcome = matches!(
+                            (earlier_command, &spec.1),
+                            (Qualified::Allow(_), Qualified::Allow(_))
+                                | (Qualified::Forbid(_), Qualified::Forbid(_))
+                        ) && tags.iter().all(|tag| earlier_tags.contains(tag))
+                            && earlier_tags.iter().all(|tag| tags.contains(tag));
+                        let (later, earlier) = (self.origin(index), self.origin(earlier));
+                        let command = describe(command);
+                        diagnostics.push(Error::Warning(if same_outcome {
+                            format!("{later} repeats {earlier} for {command}")
+                        } else {
+                            format!(
+                                "{later} conflicts with {earlier} for {command}; the later rule wins"
+                            )
+                        }));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The aliases of one kind that are referred to by rules and Defaults entries
+#[derive(Default)]
+struct Usage {
+    by_policy: HashSet<String>,
+}
+
+impl Usage {
+    fn by_policy<T>(&mut self, list: &[Spec<T>]) {
+        self.by_policy.extend(alias_names(list));
+    }
+
+    fn report<T>(&self, kind: &str, table: &VecOrd<Def<T>>, diagnostics: &mut Vec<Error>) {
+        // aliases that refer to undefined aliases have already been reported by the analysis
+        let by_aliases = elems(table)
+            .flat_map(|Def(_, members)| alias_names(members))
+            .collect::<HashSet<_>>();
+
+        let defined = table
+            .1
+            .iter()
+            .map(|Def(name, _)| name)
+            .collect::<HashSet<_>>();
+        let mut warn = |text: String| diagnostics.push(Error::Warning(text));
+        for Def(name, _) in &table.1 {
+            if !self.by_policy.contains(name) && !by_aliases.contains(name) {
+                warn(format!("unused {kind} `{name}'"));
+            }
+        }
+        let mut undefined = self
+            .by_policy
+            .iter()
+            .filter(|name| !defined.contains(name))
+            .collect::<Vec<_>>();
+        undefined.sort();
+        for name in undefined {
+            warn(format!(
+                "undefined {kind} `{name}' is used, and never matches"
+            ));
+        }
+    }
+}
+
+fn alias_names<T>(list: &[Spec<T>]) -> impl Iterator<Item = String> + '_ {
+    list.iter().filter_map(|item| match item {
+        Qualified::Allow(Meta::Alias(name)) | Qualified::Forbid(Meta::Alias(name)) => {
+            Some(name.clone())
+        }
+        _ => None,
+    })
+}
+
+/// A list that only has negated items never matches anything
+fn only_negations<T>(list: &[Spec<T>]) -> bool {
+    !list.is_empty() && list.iter().all(|item| matches!(item, Qualified::Forbid(_)))
+}
+
+fn forbids_all(CommandSpec(_, command): &CommandSpec) -> bool {
+    matches!(command, Qualified::Forbid(Meta::All))
+}
+
+/// Whether a rule with runas specification `later` applies to every target user and group that
+/// one with `earlier` applies to
+fn covers(later: &Option<RunAs>, earlier: &Option<RunAs>) -> bool {
+    match later {
+        None => earlier.is_none(),
+        Some(RunAs { users, groups }) => {
+            let own_group_only = earlier
+                .as_ref()
+                .is_none_or(|earlier| earlier.groups.is_empty());
+            is_all(users) && (is_all(groups) || own_group_only)
+        }
+    }
+}
+
+/// A command as it is written in the sudoers file
+pub(crate) fn describe(command: &Meta<Command>) -> String {
+    match command {
+        Meta::All => "ALL".to_string(),
+        Meta::Alias(name) => name.clone(),
+        Meta::Only((path, args)) if args.any() => path.as_str().to_string(),
+        Meta::Only((path, args)) => format!("{} {args}", path.as_str()),
+    }
+}
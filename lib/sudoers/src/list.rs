@@ -0,0 +1,150 @@
+//! What `sudo -l` shows: the parts of the rules that apply to a user on a host, written the way
+//! they are in the sudoers file (so aliases are shown by name, not expanded). The layout of the
+//! listing is up to the caller.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use sudo_common::sysuser::UnixUser;
+
+use crate::ast::*;
+use crate::lint::describe;
+use crate::tokens::{format_timestamp, ChDir, Meta};
+use crate::{find_item, get_aliases, match_host, match_user, Sudoers};
+
+/// The commands of one rule that the user may run on the host, with the same runas specification
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Privilege {
+    /// The file that the rule is in, if it was read from a file
+    pub file: Option<PathBuf>,
+    /// The users that the commands may be run as; a rule without a runas specification only
+    /// allows root
+    pub runas_users: Vec<String>,
+    pub runas_groups: Vec<String>,
+    pub commands: Vec<ListedCommand>,
+}
+
+/// A command of a rule, with all of its tags (including those it inherits); a command that is
+/// not allowed starts with `!`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ListedCommand {
+    pub tags: Vec<String>,
+    pub command: String,
+}
+
+impl Sudoers {
+    /// The privileges of `user` on `host`, in the order of the sudoers file; like the original
+    /// sudo, this does not take time windows (which are shown as tags) into account, and neither
+    /// which target users exist
+    pub fn list<User: UnixUser>(&self, user: &User, host: &str) -> Vec<Privilege> {
+        let user_aliases = get_aliases(&self.aliases.user, &match_user(user));
+        let host_aliases = get_aliases(&self.aliases.host, &match_host(host));
+
+        let mut privileges = Vec::new();
+        for (index, rule) in self.rules.iter().enumerate() {
+            if find_item(&rule.users, &match_user(user), &user_aliases).is_none() {
+                continue;
+            }
+            let file = self.rule_files.get(index).copied().flatten();
+            for (hosts, runas, commands) in &rule.permissions {
+                if find_item(hosts, &match_host(host), &host_aliases).is_none() {
+                    continue;
+                }
+                let (runas_users, runas_groups) = match runas {
+                    Some(RunAs { users, groups }) => (
+                        users
+                            .iter()
+                            .map(|user| qualified(user, user_name))
+                            .collect(),
+                        groups
+                            .iter()
+                            .map(|group| qualified(group, group_name))
+                            .collect(),
+                    ),
+                    None => (vec!["root".to_string()], Vec::new()),
+                };
+                let commands = commands
+                    .iter()
+                    .map(|CommandSpec(tags, command)| ListedCommand {
+                        tags: tags.iter().map(Tag::to_string).collect(),
+                        command: qualified(command, describe),
+                    })
+                    .collect();
+                privileges.push(Privilege {
+                    file: file.map(|file| self.files[file].clone()),
+                    runas_users,
+                    runas_groups,
+                    commands,
+                });
+            }
+        }
+        privileges
+    }
+}
+
+/// An item of a list, with a `!` if it is negated
+fn qualified<T>(item: &Spec<T>, name: impl Fn(&Meta<T>) -> String) -> String {
+    match item {
+        Qualified::Allow(item) => name(item),
+        Qualified::Forbid(item) => format!("!{}", name(item)),
+    }
+}
+
+fn user_name(user: &Meta<UserSpecifier>) -> String {
+    match user {
+        Meta::All => "ALL".to_string(),
+        Meta::Alias(name) => name.clone(),
+        Meta::Only(UserSpecifier::User(id)) => identifier(id),
+        Meta::Only(UserSpecifier::Group(id)) => format!("%{}", identifier(id)),
+        Meta::Only(UserSpecifier::NonunixGroup(id)) => format!("%:{}", identifier(id)),
+    }
+}
+
+fn group_name(group: &Meta<Identifier>) -> String {
+    match group {
+        Meta::All => "ALL".to_string(),
+        Meta::Alias(name) => name.clone(),
+        Meta::Only(id) => identifier(id),
+    }
+}
+
+fn identifier(id: &Identifier) -> String {
+    match id {
+        Identifier::Name(name) => name.clone(),
+        Identifier::ID(id) => format!("#{id}"),
+    }
+}
+
+/// A tag as it is written in the sudoers file, without the colon that follows some tags
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dir = |dir: &ChDir| match dir {
+            ChDir::Any => "*".to_string(),
+            ChDir::Path(path) => path.display().to_string(),
+        };
+        match self {
+            Tag::NoPasswd => write!(f, "NOPASSWD"),
+            Tag::Passwd => write!(f, "PASSWD"),
+            Tag::SetEnv => write!(f, "SETENV"),
+            Tag::NoSetEnv => write!(f, "NOSETENV"),
+            Tag::NoExec => write!(f, "NOEXEC"),
+            Tag::Exec => write!(f, "EXEC"),
+            Tag::Intercept => write!(f, "INTERCEPT"),
+            Tag::NoIntercept => write!(f, "NOINTERCEPT"),
+            Tag::Timeout(seconds) => write!(f, "TIMEOUT={seconds}"),
+            Tag::Cwd(path) => write!(f, "CWD={}", dir(path)),
+            Tag::Chroot(path) => write!(f, "CHROOT={}", dir(path)),
+            Tag::Umask(mask) => write!(f, "UMASK={mask:04o}"),
+            Tag::NotBefore(time) => write!(f, "NOTBEFORE={}", format_timestamp(*time)),
+            Tag::NotAfter(time) => write!(f, "NOTAFTER={}", format_timestamp(*time)),
+            Tag::LogInput => write!(f, "LOG_INPUT"),
+            Tag::NoLogInput => write!(f, "NOLOG_INPUT"),
+            Tag::LogOutput => write!(f, "LOG_OUTPUT"),
+            Tag::NoLogOutput => write!(f, "NOLOG_OUTPUT"),
+            Tag::Follow => write!(f, "FOLLOW"),
+            Tag::NoFollow => write!(f, "NOFOLLOW"),
+        }
+    }
+}
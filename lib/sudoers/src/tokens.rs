@@ -31,7 +31,29 @@ impl Token for Digits {
     const MAX_LEN: usize = 10;
 
     fn construct(s: String) -> Parsed<Self> {
-        Ok(Digits(s.parse().unwrap()))
+        match s.parse() {
+            Ok(value) => Ok(Digits(value)),
+            Err(_) => Err(Status::Fatal(format!("number out of range: {s}"))),
+        }
+    }
+
+    fn accept(c: char) -> bool {
+        c.is_ascii_digit()
+    }
+}
+
+/// A file mode creation mask, written in octal
+#[derive(Debug)]
+pub struct Octal(pub u32);
+
+impl Token for Octal {
+    const MAX_LEN: usize = 4;
+
+    fn construct(s: String) -> Parsed<Self> {
+        match u32::from_str_radix(&s, 8) {
+            Ok(value) if value <= 0o777 => Ok(Octal(value)),
+            _ => Err(Status::Fatal(format!("invalid umask: {s}"))),
+        }
     }
 
     fn accept(c: char) -> bool {
@@ -39,12 +61,128 @@ impl Token for Digits {
     }
 }
 
+/// A point in time for NOTBEFORE and NOTAFTER, like `20250301120000Z`: the year, month, day and
+/// hour, optionally followed by minutes, seconds and a fraction of a second (which is ignored),
+/// and then `Z` for UTC, an offset such as `+0100`, or nothing for local time. It is kept as the
+/// number of seconds since the epoch.
+#[derive(Debug)]
+pub struct Timestamp(pub i64);
+
+impl Token for Timestamp {
+    const MAX_LEN: usize = 32;
+
+    fn construct(s: String) -> Parsed<Self> {
+        let invalid = || Status::Fatal(format!("invalid timestamp: {s}"));
+        let digits = |text: &str| !text.is_empty() && text.bytes().all(|c| c.is_ascii_digit());
+
+        let (time, zone) = s.split_at(s.find(['Z', '+', '-']).unwrap_or(s.len()));
+        let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+        if !digits(time) || ![10, 12, 14].contains(&time.len()) || !digits(fraction) {
+            return Err(invalid());
+        }
+        let field = |start: usize, len: usize| {
+            time.get(start..start + len)
+                .map_or(0, |digits| digits.parse::<i64>().unwrap_or(0))
+        };
+        let (year, month, day) = (field(0, 4), field(4, 2), field(6, 2));
+        let (hour, minute, second) = (field(8, 2), field(10, 2), field(12, 2));
+        if !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(invalid());
+        }
+
+        let offset = match zone.split_at(zone.len().min(1)) {
+            ("", _) => None,
+            ("Z", "") => Some(0),
+            (sign @ ("+" | "-"), offset) if offset.len() == 4 && digits(offset) => {
+                let offset = offset[..2].parse::<i64>().unwrap_or(0) * 3600
+                    + offset[2..].parse::<i64>().unwrap_or(0) * 60;
+                Some(if sign == "+" { offset } else { -offset })
+            }
+            _ => return Err(invalid()),
+        };
+        let seconds = match offset {
+            Some(offset) => {
+                days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+                    - offset
+            }
+            None => local_time(year, month, day, hour, minute, second).ok_or_else(invalid)?,
+        };
+        Ok(Timestamp(seconds))
+    }
+
+    fn accept(c: char) -> bool {
+        c.is_ascii_digit() || "Z.+-".contains(c)
+    }
+}
+
+/// The number of days between 1970-01-01 and a date in the (proleptic) Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// A point in time as [Timestamp] reads it, in UTC (such as `20250301120000Z`)
+pub fn format_timestamp(seconds: i64) -> String {
+    let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+    // the inverse of days_from_civil
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}{:02}{:02}{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// The seconds since the epoch of a date and time in the local time zone
+fn local_time(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> Option<i64> {
+    // SAFETY: all fields of a tm are plain integers (and a pointer to the zone name)
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = i32::try_from(year - 1900).ok()?;
+    tm.tm_mon = (month - 1) as i32;
+    tm.tm_mday = day as i32;
+    tm.tm_hour = hour as i32;
+    tm.tm_min = minute as i32;
+    tm.tm_sec = second as i32;
+    // let mktime find out whether daylight saving time is in effect
+    tm.tm_isdst = -1;
+    match unsafe { libc::mktime(&mut tm) } {
+        -1 => None,
+        seconds => Some(seconds),
+    }
+}
+
 #[derive(Debug)]
 pub struct Decimal(pub i32);
 
 impl Token for Decimal {
     fn construct(s: String) -> Parsed<Self> {
-        Ok(Decimal(s.parse().unwrap()))
+        match s.parse() {
+            Ok(value) => Ok(Decimal(value)),
+            Err(_) => Err(Status::Fatal(format!("invalid number: {s}"))),
+        }
     }
 
     fn accept(c: char) -> bool {
@@ -56,17 +194,25 @@ impl Token for Decimal {
     }
 }
 
-/// A hostname consists of alphanumeric characters and ".", "-",  "_"
+/// A hostname consists of alphanumeric characters and ".", "-",  "_"; it can also be a glob
+/// pattern such as `web*.example.com`
 #[derive(Debug, Deref)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Hostname(pub String);
 
 impl Token for Hostname {
     fn construct(text: String) -> Parsed<Self> {
+        if let Err(err) = glob::Pattern::new(&text) {
+            return Err(Status::Fatal(format!(
+                "invalid host pattern `{text}': {}",
+                err.msg
+            )));
+        }
         Ok(Hostname(text))
     }
 
     fn accept(c: char) -> bool {
-        c.is_ascii_alphanumeric() || ".-_".contains(c)
+        c.is_ascii_alphanumeric() || ".-_*?[]".contains(c)
     }
 }
 
@@ -76,6 +222,7 @@ impl Many for Hostname {}
 /// (Maybe this is better defined not as a Token but simply directly as an implementation of [crate::basic_parser::Parse])
 #[derive(Debug)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Meta<T> {
     All,
     Only(T),
@@ -116,7 +263,8 @@ impl<T: Many> Many for Meta<T> {
     const LIMIT: usize = T::LIMIT;
 }
 
-/// An identifier that consits of only uppercase characters.
+/// An identifier that starts with an uppercase character, followed by uppercase characters,
+/// digits and underscores (like `LOG_INPUT`).
 #[derive(Debug, Deref)]
 pub struct Upper(pub String);
 
@@ -126,16 +274,117 @@ impl Token for Upper {
     }
 
     fn accept(c: char) -> bool {
+        c.is_uppercase() || c.is_ascii_digit() || c == '_'
+    }
+
+    fn accept_1st(c: char) -> bool {
         c.is_uppercase()
     }
 }
 
 /// A struct that represents valid command strings; this can contain escape sequences and are
 /// limited to 1024 characters.
-pub type Command = (glob::Pattern, glob::Pattern);
+pub type Command = (glob::Pattern, Args);
+
+/// The arguments of a command in the sudoers file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Args {
+    /// Without wildcards (outside quotes), the arguments of the command line must be exactly
+    /// these; an empty list (written as `""`) allows none
+    Exact(Vec<String>),
+    /// With wildcards, the arguments of the command line are matched as they are joined by single
+    /// spaces, like in the original sudo; so a wildcard can match several arguments
+    Pattern(glob::Pattern),
+}
 
-pub fn split_args(text: &str) -> Vec<&str> {
-    text.split_whitespace().collect::<Vec<_>>()
+impl Args {
+    /// Whether this allows the arguments of a command line
+    pub fn matches(&self, args: &[String]) -> bool {
+        match self {
+            Args::Exact(words) => words == args,
+            Args::Pattern(pattern) => pattern.matches(&args.join(" ")),
+        }
+    }
+
+    /// Whether any arguments are allowed, because the sudoers file does not mention them
+    pub fn any(&self) -> bool {
+        matches!(self, Args::Pattern(pattern) if pattern.as_str() == "*")
+    }
+}
+
+/// The arguments as they could be written in the sudoers file (a pattern as it was compiled)
+impl std::fmt::Display for Args {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let words = match self {
+            Args::Exact(words) if words.is_empty() => return f.write_str("\"\""),
+            Args::Exact(words) => words,
+            Args::Pattern(pattern) => return f.write_str(pattern.as_str()),
+        };
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            if !word.is_empty()
+                && !word.contains(|c: char| c.is_whitespace() || "\"'*?[".contains(c))
+            {
+                f.write_str(word)?;
+            } else {
+                // a double quote in the word is written in single quotes
+                write!(f, "\"{}\"", word.replace('"', "\"'\"'\""))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A word of a command in the sudoers file
+#[derive(Default)]
+struct Word {
+    /// The word without its quotes
+    text: String,
+    /// The word as a wildcard pattern, in which the quoted part only matches itself
+    pattern: String,
+    /// Whether the word has a wildcard outside quotes
+    wildcard: bool,
+}
+
+/// Split a command in the sudoers file into words, like a shell would: a part of a word in double
+/// or single quotes can contain whitespace, and a wildcard in it only matches itself. An empty word
+/// (written as `""`) is kept.
+fn split_words(text: &str) -> Result<Vec<Word>, String> {
+    let mut words = Vec::new();
+    let mut word: Option<Word> = None;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                let word = word.get_or_insert_with(Word::default);
+                let mut closed = false;
+                for quoted in chars.by_ref() {
+                    if quoted == c {
+                        closed = true;
+                        break;
+                    }
+                    word.text.push(quoted);
+                    word.pattern
+                        .push_str(&glob::Pattern::escape(&quoted.to_string()));
+                }
+                if !closed {
+                    return Err(format!("unterminated quote in command: {text}"));
+                }
+            }
+            c if c.is_whitespace() => words.extend(word.take()),
+            c => {
+                let word = word.get_or_insert_with(Word::default);
+                word.text.push(c);
+                word.pattern.push(c);
+                word.wildcard |= "*?[".contains(c);
+            }
+        }
+    }
+    words.extend(word);
+
+    Ok(words)
 }
 
 impl Token for Command {
@@ -145,16 +394,28 @@ impl Token for Command {
         let cvt_err = |pat: Result<_, glob::PatternError>| {
             pat.map_err(|err| Status::Fatal(format!("wildcard pattern error {}", err.msg)))
         };
-        let mut cmdvec = split_args(&s);
-        if cmdvec.len() == 1 {
-            // if no arguments are mentioned, anything is allowed
-            cmdvec.push("*");
-        } else if cmdvec.len() >= 2 && cmdvec.last() == Some(&"\"\"") {
-            // if the magic "" appears, no (further) arguments are allowed
-            cmdvec.pop();
+        let mut words = split_words(&s).map_err(Status::Fatal)?;
+        if words.is_empty() {
+            return Err(Status::Fatal("empty command".to_string()));
         }
-        let cmd = cvt_err(glob::Pattern::new(cmdvec[0]))?;
-        let args = cvt_err(glob::Pattern::new(&cmdvec[1..].join(" ")))?;
+        let cmd = cvt_err(glob::Pattern::new(&words.remove(0).pattern))?;
+        let args = if words.is_empty() {
+            // if no arguments are mentioned, anything is allowed
+            Args::Pattern(cvt_err(glob::Pattern::new("*"))?)
+        } else {
+            if words.last().is_some_and(|word| word.text.is_empty()) {
+                // if the magic "" appears, no (further) arguments are allowed
+                words.pop();
+            }
+            if words.iter().any(|word| word.wildcard) {
+                let patterns = words.into_iter().map(|word| word.pattern);
+                Args::Pattern(cvt_err(glob::Pattern::new(
+                    &patterns.collect::<Vec<_>>().join(" "),
+                ))?)
+            } else {
+                Args::Exact(words.into_iter().map(|word| word.text).collect())
+            }
+        };
 
         Ok((cmd, args))
     }
@@ -171,6 +432,64 @@ impl Token for Command {
 
 impl Many for Command {}
 
+/// A command in a `Defaults!` entry: unlike in a rule, it ends at whitespace, so it can not have
+/// arguments (and matches any)
+#[derive(Debug)]
+pub struct CommandName(pub Command);
+
+impl Token for CommandName {
+    const MAX_LEN: usize = <Command as Token>::MAX_LEN;
+
+    fn construct(s: String) -> Parsed<Self> {
+        Ok(CommandName(<Command as Token>::construct(s)?))
+    }
+
+    fn accept(c: char) -> bool {
+        <Command as Token>::accept(c) && !c.is_whitespace()
+    }
+
+    const ESCAPE: char = <Command as Token>::ESCAPE;
+    fn escaped(c: char) -> bool {
+        <Command as Token>::escaped(c)
+    }
+}
+
+impl Many for CommandName {}
+
+/// A working directory specification (as used in the CWD tag and the runcwd setting): either an
+/// absolute path, a path starting with `~`, or `*` which allows the user to choose using `-D`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChDir {
+    Any,
+    Path(std::path::PathBuf),
+}
+
+impl Token for ChDir {
+    const MAX_LEN: usize = 1024;
+
+    fn construct(s: String) -> Parsed<Self> {
+        if s == "*" {
+            Ok(ChDir::Any)
+        } else if s.starts_with('/') || s.starts_with('~') {
+            Ok(ChDir::Path(s.into()))
+        } else {
+            Err(Status::Fatal(format!(
+                "working directory `{s}' must be a fully qualified path"
+            )))
+        }
+    }
+
+    fn accept(c: char) -> bool {
+        !Self::escaped(c) && !c.is_control() && !c.is_whitespace()
+    }
+
+    const ESCAPE: char = '\\';
+    fn escaped(c: char) -> bool {
+        "\\,:=#".contains(c)
+    }
+}
+
 /// An environment variable name pattern consists of alphanumeric characters as well as "_", "%" and wildcard "*"
 /// (Value patterns are not supported yet)
 pub struct EnvVar(pub String);
@@ -185,6 +504,27 @@ impl Token for EnvVar {
     }
 }
 
+/// The value in a `NAME=value` entry of an environment variable list; this ends at whitespace, a
+/// comma or a double quote, unless those are escaped
+pub struct EnvValue(pub String);
+
+impl Token for EnvValue {
+    const MAX_LEN: usize = 1024;
+
+    fn construct(s: String) -> Parsed<Self> {
+        Ok(EnvValue(s))
+    }
+
+    fn accept(c: char) -> bool {
+        !c.is_control() && !Self::escaped(c)
+    }
+
+    const ESCAPE: char = '\\';
+    fn escaped(c: char) -> bool {
+        "\\\" \t,".contains(c)
+    }
+}
+
 pub struct QuotedText(pub String);
 
 impl Token for QuotedText {
@@ -0,0 +1,100 @@
+//! The parser must agree with the original sudo on the sudoers files in the corpus
+//! (test-framework/sudoers-corpus): `visudo -c` accepts the files in `accepted` and rejects those
+//! in `rejected`. Files are added with test-framework/import-sudoers.sh, which sorts them by what
+//! visudo says; a file that this test then fails on points at a gap in the grammar. Until it is
+//! closed, the file is listed in `known-gaps`.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use sudoers::Error;
+
+fn corpus_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../../test-framework/sudoers-corpus")
+}
+
+fn corpus(verdict: &str) -> Vec<PathBuf> {
+    let dir = corpus_dir().join(verdict);
+    let mut files = fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("{}: {err}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sudoers"))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// The files (as `<verdict>/<name>`) that sudo-rs is known to disagree on
+fn known_gaps() -> HashSet<String> {
+    fs::read_to_string(corpus_dir().join("known-gaps"))
+        .unwrap()
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// The file as it is listed in `known-gaps`
+fn name(path: &Path) -> String {
+    let verdict = path.parent().and_then(|dir| dir.file_name()).unwrap();
+    let file = path.file_name().unwrap();
+    format!("{}/{}", verdict.to_string_lossy(), file.to_string_lossy())
+}
+
+/// Whether the file is accepted; like `visudo -c`, warnings do not reject it
+fn accepted(path: &Path) -> Result<(), Vec<Error>> {
+    let text = fs::read_to_string(path).unwrap();
+    let fatal = sudoers::diagnose(&text)
+        .into_iter()
+        .filter(|diagnostic| !matches!(diagnostic, Error::Warning(_)))
+        .collect::<Vec<_>>();
+    if fatal.is_empty() {
+        Ok(())
+    } else {
+        Err(fatal)
+    }
+}
+
+#[test]
+fn agrees_with_visudo() {
+    let accepted_files = corpus("accepted");
+    let rejected_files = corpus("rejected");
+    assert!(!accepted_files.is_empty() && !rejected_files.is_empty());
+
+    // what sudo-rs disagrees with visudo about, if anything
+    let verdicts = accepted_files
+        .iter()
+        .map(|path| {
+            (
+                path,
+                accepted(path).err().map(|err| format!("rejected: {err:?}")),
+            )
+        })
+        .chain(
+            rejected_files
+                .iter()
+                .map(|path| (path, accepted(path).ok().map(|_| "accepted".to_string()))),
+        );
+
+    let mut known_gaps = known_gaps();
+    // all disagreements at once, so that one run shows every gap
+    let mut disagreements = Vec::new();
+    for (path, disagreement) in verdicts {
+        let name = name(path);
+        match (known_gaps.remove(&name), disagreement) {
+            (false, Some(disagreement)) => disagreements.push(format!("{name}: {disagreement}")),
+            (true, None) => {
+                disagreements.push(format!("{name}: agrees now, remove it from known-gaps"))
+            }
+            _ => {}
+        }
+    }
+    for name in known_gaps {
+        disagreements.push(format!("{name}: in known-gaps, but not in the corpus"));
+    }
+    assert!(disagreements.is_empty(), "{}", disagreements.join("\n"));
+}
@@ -0,0 +1,94 @@
+//! Property-based tests for the sudoers parser: no input may cause a panic, and well-formed input
+//! must be accepted without diagnostics.
+
+use proptest::prelude::*;
+
+/// Fragments of sudoers syntax, so that generated input gets further into the grammar than
+/// purely random text does
+fn fragment() -> impl Strategy<Value = String> {
+    prop_oneof![
+        Just("ALL"),
+        Just("Defaults"),
+        Just("User_Alias"),
+        Just("Host_Alias"),
+        Just("Cmnd_Alias"),
+        Just("Runas_Alias"),
+        Just("NOPASSWD:"),
+        Just("CWD="),
+        Just("#include"),
+        Just("@"),
+        Just("="),
+        Just("+="),
+        Just("-="),
+        Just("!"),
+        Just("%"),
+        Just("%:"),
+        Just("#"),
+        Just(":"),
+        Just(","),
+        Just("("),
+        Just(")"),
+        Just("\""),
+        Just("\\"),
+        Just(" "),
+        Just("\n"),
+        Just("*"),
+        Just("\"\""),
+        Just("/bin/ls"),
+    ]
+    .prop_map(str::to_string)
+    .boxed()
+    .prop_union("[a-z]{1,8}".boxed())
+    .or("[A-Z]{1,8}".boxed())
+    .or("[0-9]{1,12}".boxed())
+    .or("[+-]{1,2}".boxed())
+}
+
+/// A well-formed user specification line, built from its parts
+fn rule() -> impl Strategy<Value = String> {
+    let user = prop_oneof![
+        "[a-z][a-z0-9]{0,7}",
+        "%[a-z]{1,8}",
+        "#[0-9]{1,5}",
+        "%#[0-9]{1,5}"
+    ];
+    let host = prop_oneof![Just("ALL".to_string()), "[a-z]{1,8}"];
+    let runas = proptest::option::of("[a-z]{1,8}");
+    let tag = prop_oneof![Just(""), Just("NOPASSWD: "), Just("CWD=/tmp ")];
+    let command = prop_oneof![
+        Just("ALL".to_string()),
+        "/[a-z]{1,8}(/[a-z]{1,8}){0,2}",
+        "/bin/[a-z]{1,8} [a-z\\-]{1,8}",
+    ];
+
+    (user, host, runas, tag, prop::collection::vec(command, 1..4)).prop_map(
+        |(user, host, runas, tag, commands)| {
+            let runas = runas.map(|user| format!("({user}) ")).unwrap_or_default();
+            format!("{user} {host} = {runas}{tag}{}\n", commands.join(", "))
+        },
+    )
+}
+
+proptest! {
+    #[test]
+    fn arbitrary_text_does_not_panic(text in ".*") {
+        sudoers::diagnose(&text);
+    }
+
+    #[test]
+    fn arbitrary_fragments_do_not_panic(fragments in prop::collection::vec(fragment(), 0..40)) {
+        sudoers::diagnose(&fragments.concat());
+    }
+
+    #[test]
+    fn well_formed_rules_are_accepted(rules in prop::collection::vec(rule(), 1..5)) {
+        let diagnostics = sudoers::diagnose(&rules.concat());
+        prop_assert!(diagnostics.is_empty(), "{rules:?}: {diagnostics:?}");
+    }
+}
+
+#[test]
+fn large_numbers_are_errors() {
+    assert!(!sudoers::diagnose("User_Alias FOO = #99999999999\n").is_empty());
+    assert!(!sudoers::diagnose("ALL ALL=(#4294967296) ALL\n").is_empty());
+}
@@ -0,0 +1,56 @@
+//! The sudo binary can also be installed under other names (as a symlink to it); the name it is
+//! invoked by selects what it does.
+
+use std::{ffi::OsStr, path::Path};
+
+use sudo_cli::SudoOptions;
+
+/// What the binary does, according to the name it was invoked by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Personality {
+    Sudo,
+    /// `sudoedit`, which is `sudo -e`
+    Sudoedit,
+}
+
+impl Personality {
+    /// Only the file name of `argv[0]` counts; an unknown name (or none) means sudo
+    pub fn from_argv0(argv0: Option<&OsStr>) -> Self {
+        match argv0.map(Path::new).and_then(Path::file_name) {
+            Some(name) if name == "sudoedit" => Personality::Sudoedit,
+            _ => Personality::Sudo,
+        }
+    }
+
+    /// The name that messages are prefixed with
+    pub fn name(self) -> &'static str {
+        match self {
+            Personality::Sudo => "sudo",
+            Personality::Sudoedit => "sudoedit",
+        }
+    }
+
+    /// Parse the command line (exiting on errors, and after showing the help)
+    pub fn parse_options(self) -> SudoOptions {
+        match self {
+            Personality::Sudo => SudoOptions::parse(),
+            Personality::Sudoedit => SudoOptions::parse_sudoedit(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn personality_from_argv0() {
+        let personality = |argv0: &str| Personality::from_argv0(Some(OsStr::new(argv0)));
+        assert_eq!(personality("sudo"), Personality::Sudo);
+        assert_eq!(personality("/usr/bin/sudo"), Personality::Sudo);
+        assert_eq!(personality("sudoedit"), Personality::Sudoedit);
+        assert_eq!(personality("/usr/bin/sudoedit"), Personality::Sudoedit);
+        assert_eq!(personality("sudoedit-wrapper"), Personality::Sudo);
+        assert_eq!(Personality::from_argv0(None), Personality::Sudo);
+    }
+}
@@ -0,0 +1,47 @@
+//! What scripts can find out about this sudo without parsing messages: `sudo -V --quiet` prints
+//! just the version number, and `sudo --has-feature <name>` exits with status 0 if the feature is
+//! supported and 1 if it is not (or if the name is unknown, as it would be to an older version).
+//! The original sudo rejects both options, so they also tell sudo-rs apart from it.
+
+/// The features that can be asked about, and whether this build supports them; names are never
+/// reused for something else, and a name is only removed if nothing can support it anymore
+const FEATURES: &[(&str, bool)] = &[
+    // sudo -l --json, and --list-version
+    ("list-json", true),
+    // the I/O logs of log_input and log_output, which sudoreplay plays back
+    ("iolog", true),
+    ("intercept", true),
+    ("noexec", true),
+    ("sandbox", cfg!(target_os = "linux")),
+    ("pam", cfg!(feature = "pam")),
+    // passwords are checked against /etc/shadow instead of with PAM
+    ("shadow", cfg!(feature = "shadow")),
+    ("privsep", cfg!(feature = "privsep")),
+    ("sudoedit", false),
+];
+
+/// Whether this build supports the feature called `name`
+pub fn has_feature(name: &str) -> bool {
+    FEATURES
+        .iter()
+        .any(|&(feature, supported)| feature == name && supported)
+}
+
+/// The text of `sudo -V --quiet`
+pub fn version() -> &'static str {
+    env!("CARGO_PKG_VERSION")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probes() {
+        assert!(has_feature("iolog"));
+        assert_eq!(has_feature("pam"), cfg!(feature = "pam"));
+        assert!(!has_feature("sudoedit"));
+        assert!(!has_feature("time-travel"));
+        assert!(version().split('.').all(|part| part.parse::<u32>().is_ok()));
+    }
+}
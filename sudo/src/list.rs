@@ -0,0 +1,336 @@
+//! The output of `sudo -l` without a command: the short format, the long format of `-ll`, and
+//! JSON with `--json`. Scripts can depend on these: a change to any of them (other than a new
+//! translation) comes with a new version, and the earlier versions stay available through
+//! `--list-version`. The examples in `test-framework/golden` are the reference for every version.
+//!
+//! The fixed texts of the short and long formats are translated according to the locale of the
+//! invoking user; JSON is never translated. Scripts that read the short or long format should run
+//! sudo with `LC_ALL=C`.
+
+use std::ops::RangeInclusive;
+
+use sudo_common::error::Error;
+use sudoers::Privilege;
+
+/// The versions of the list formats that this sudo can produce
+pub const VERSIONS: RangeInclusive<u32> = 1..=1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Short,
+    Long,
+    Json,
+}
+
+/// The fixed texts of the short and long formats, in which `{user}` and `{host}` are replaced
+pub struct Messages {
+    pub allowed: &'static str,
+    pub not_allowed: &'static str,
+    pub entry: &'static str,
+    pub runas_users: &'static str,
+    pub runas_groups: &'static str,
+    pub commands: &'static str,
+}
+
+const ENGLISH: Messages = Messages {
+    allowed: "User {user} may run the following commands on {host}:",
+    not_allowed: "Sorry, user {user} may not run sudo on {host}.",
+    entry: "Sudoers entry:",
+    runas_users: "RunAsUsers:",
+    runas_groups: "RunAsGroups:",
+    commands: "Commands:",
+};
+
+/// The translations, by language code
+const TRANSLATIONS: &[(&str, Messages)] = &[(
+    "de",
+    Messages {
+        allowed: "Benutzer {user} darf die folgenden Befehle auf {host} ausführen:",
+        not_allowed: "Benutzer {user} darf sudo auf {host} leider nicht ausführen.",
+        entry: "Sudoers-Eintrag:",
+        runas_users: "Als Benutzer:",
+        runas_groups: "Als Gruppen:",
+        commands: "Befehle:",
+    },
+)];
+
+/// The texts for the locale in the environment of the invoking user (looked up with `var`): as
+/// with gettext, the first of `LC_ALL`, `LC_MESSAGES` and `LANG` that is set selects the language,
+/// and English is used for `C`, `POSIX` and languages without a translation
+pub fn messages(var: impl Fn(&str) -> Option<String>) -> &'static Messages {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(var)
+        .find(|value| !value.is_empty())
+        .unwrap_or_default();
+    // like de_AT.UTF-8@euro
+    let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+    TRANSLATIONS
+        .iter()
+        .find(|(code, _)| *code == language)
+        .map_or(&ENGLISH, |(_, messages)| messages)
+}
+
+/// The version that was asked for with `--list-version`, or the latest one
+pub fn version(requested: Option<u32>) -> Result<u32, Error> {
+    match requested {
+        None => Ok(*VERSIONS.end()),
+        Some(version) if VERSIONS.contains(&version) => Ok(version),
+        Some(version) => Err(Error::Configuration(format!(
+            "list format version {version} is not supported (supported: {} to {})",
+            VERSIONS.start(),
+            VERSIONS.end()
+        ))),
+    }
+}
+
+/// The listing of the privileges of `user` on `host`, in a version that [version] accepted (all
+/// versions are the same so far)
+pub fn render(
+    privileges: &[Privilege],
+    user: &str,
+    host: &str,
+    format: Format,
+    version: u32,
+    messages: &Messages,
+) -> String {
+    debug_assert!(VERSIONS.contains(&version));
+    let fill = |text: &str| text.replace("{user}", user).replace("{host}", host);
+    if format == Format::Json {
+        return json(privileges, user, host, version);
+    }
+    if privileges.is_empty() {
+        return fill(messages.not_allowed) + "\n";
+    }
+
+    let mut output = fill(messages.allowed) + "\n";
+    for privilege in privileges {
+        if format == Format::Short {
+            output += &format!("    {}\n", short(privilege));
+            continue;
+        }
+        output += &format!("\n{}", messages.entry);
+        if let Some(file) = &privilege.file {
+            output += &format!(" {}", file.display());
+        }
+        let users = privilege.runas_users.join(", ");
+        output += &format!("\n    {} {users}\n", messages.runas_users);
+        if !privilege.runas_groups.is_empty() {
+            let groups = privilege.runas_groups.join(", ");
+            output += &format!("    {} {groups}\n", messages.runas_groups);
+        }
+        output += &format!("    {}\n", messages.commands);
+        for command in &privilege.commands {
+            output += &format!("\t{}{}\n", tags(&command.tags), command.command);
+        }
+    }
+    output
+}
+
+/// A privilege on one line, like in the sudoers file: a tag is only written where it changes
+fn short(privilege: &Privilege) -> String {
+    let mut runas = privilege.runas_users.join(", ");
+    if !privilege.runas_groups.is_empty() {
+        runas = format!("{runas} : {}", privilege.runas_groups.join(", "));
+    }
+    let mut previous: &[String] = &[];
+    let commands = privilege.commands.iter().map(|command| {
+        let changed = command
+            .tags
+            .iter()
+            .filter(|tag| !previous.contains(tag))
+            .cloned()
+            .collect::<Vec<_>>();
+        previous = &command.tags;
+        format!("{}{}", tags(&changed), command.command)
+    });
+    format!("({runas}) {}", commands.collect::<Vec<_>>().join(", "))
+}
+
+/// Tags as they go in front of a command: `NOPASSWD: ` but `CWD=/tmp `
+fn tags(tags: &[String]) -> String {
+    tags.iter()
+        .map(|tag| match tag.contains('=') {
+            true => format!("{tag} "),
+            false => format!("{tag}: "),
+        })
+        .collect()
+}
+
+/// The JSON format, laid out with one command per line
+fn json(privileges: &[Privilege], user: &str, host: &str, version: u32) -> String {
+    let list = |items: &[String]| {
+        let items = items.iter().map(|item| string(item)).collect::<Vec<_>>();
+        format!("[{}]", items.join(", "))
+    };
+    let mut entries = Vec::new();
+    for privilege in privileges {
+        let file = match &privilege.file {
+            Some(file) => string(&file.to_string_lossy()),
+            None => "null".to_string(),
+        };
+        let commands = privilege.commands.iter().map(|command| {
+            let text = string(&command.command);
+            format!(
+                "        {{\"command\": {text}, \"tags\": {}}}",
+                list(&command.tags)
+            )
+        });
+        let lines = [
+            "    {".to_string(),
+            format!("      \"file\": {file},"),
+            format!("      \"runas_users\": {},", list(&privilege.runas_users)),
+            format!("      \"runas_groups\": {},", list(&privilege.runas_groups)),
+            "      \"commands\": [".to_string(),
+            commands.collect::<Vec<_>>().join(",\n"),
+            "      ]".to_string(),
+            "    }".to_string(),
+        ];
+        entries.push(lines.join("\n"));
+    }
+    let privileges = match entries.is_empty() {
+        true => "[]".to_string(),
+        false => format!("[\n{}\n  ]", entries.join(",\n")),
+    };
+    let lines = [
+        "{".to_string(),
+        format!("  \"version\": {version},"),
+        format!("  \"user\": {},", string(user)),
+        format!("  \"host\": {},", string(host)),
+        format!("  \"privileges\": {privileges}"),
+        "}\n".to_string(),
+    ];
+    lines.join("\n")
+}
+
+/// A JSON string
+fn string(text: &str) -> String {
+    let mut result = String::from('"');
+    for c in text.chars() {
+        match c {
+            '"' => result += "\\\"",
+            '\\' => result += "\\\\",
+            '\n' => result += "\\n",
+            '\t' => result += "\\t",
+            c if c.is_control() => result += &format!("\\u{:04x}", c as u32),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sudoers::ListedCommand;
+
+    /// The privileges that the examples in test-framework/golden list, as those of
+    ///
+    /// ```text
+    /// Cmnd_Alias PKG = /usr/bin/apt, /usr/bin/dpkg
+    /// alice ALL=(root, %#10 : wheel) NOPASSWD: PKG, CWD=/srv !/bin/sh
+    /// alice ALL=TIMEOUT=60 /usr/bin/systemctl restart "nginx"
+    /// ```
+    fn privileges() -> Vec<Privilege> {
+        let command = |tags: &[&str], command: &str| ListedCommand {
+            tags: tags.iter().map(ToString::to_string).collect(),
+            command: command.to_string(),
+        };
+        let strings = |items: &[&str]| items.iter().map(ToString::to_string).collect();
+        vec![
+            Privilege {
+                file: Some("/etc/sudoers".into()),
+                runas_users: strings(&["root", "%#10"]),
+                runas_groups: strings(&["wheel"]),
+                commands: vec![
+                    command(&["NOPASSWD"], "PKG"),
+                    command(&["NOPASSWD", "CWD=/srv"], "!/bin/sh"),
+                ],
+            },
+            Privilege {
+                file: Some("/etc/sudoers.d/web".into()),
+                runas_users: strings(&["root"]),
+                runas_groups: Vec::new(),
+                commands: vec![command(
+                    &["TIMEOUT=60"],
+                    "/usr/bin/systemctl restart \"nginx\"",
+                )],
+            },
+        ]
+    }
+
+    #[test]
+    fn golden_files() {
+        let privileges = privileges();
+        for (format, golden) in [
+            (
+                Format::Short,
+                include_str!("../../test-framework/golden/list-v1.txt"),
+            ),
+            (
+                Format::Long,
+                include_str!("../../test-framework/golden/list-v1-long.txt"),
+            ),
+            (
+                Format::Json,
+                include_str!("../../test-framework/golden/list-v1.json"),
+            ),
+        ] {
+            let output = render(&privileges, "alice", "web1", format, 1, &ENGLISH);
+            assert_eq!(output, golden, "{format:?}");
+        }
+
+        for format in [Format::Short, Format::Long] {
+            let output = render(&[], "bob", "web1", format, 1, &ENGLISH);
+            assert_eq!(output, "Sorry, user bob may not run sudo on web1.\n");
+        }
+        let output = render(&[], "bob", "web1", Format::Json, 1, &ENGLISH);
+        assert_eq!(
+            output,
+            include_str!("../../test-framework/golden/list-v1-empty.json")
+        );
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(version(None).unwrap(), 1);
+        assert_eq!(version(Some(1)).unwrap(), 1);
+        assert_eq!(
+            version(Some(2)).unwrap_err().to_string(),
+            "list format version 2 is not supported (supported: 1 to 1)"
+        );
+    }
+
+    #[test]
+    fn translations() {
+        let environment = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                let value = vars.iter().find(|(var, _)| *var == name);
+                value.map(|(_, value)| value.to_string())
+            }
+        };
+        let german = messages(environment(&[("LANG", "de_DE.UTF-8")]));
+        let output = render(&privileges(), "alice", "web1", Format::Short, 1, german);
+        assert!(output.starts_with("Benutzer alice darf die folgenden Befehle auf web1"));
+        // LC_ALL goes first, and an empty variable does not count
+        let english = environment(&[("LC_ALL", "C"), ("LANG", "de_DE.UTF-8")]);
+        assert_eq!(messages(english).entry, "Sudoers entry:");
+        let empty = environment(&[("LC_ALL", ""), ("LC_MESSAGES", "de_AT@euro")]);
+        assert_eq!(messages(empty).entry, "Sudoers-Eintrag:");
+        assert_eq!(messages(environment(&[])).entry, "Sudoers entry:");
+
+        // JSON is the same in every language
+        let json = render(&privileges(), "alice", "web1", Format::Json, 1, german);
+        assert_eq!(
+            json,
+            include_str!("../../test-framework/golden/list-v1.json")
+        );
+    }
+
+    #[test]
+    fn json_strings() {
+        assert_eq!(string("a\"b\\c\n\x07"), r#""a\"b\\c\n\u0007""#);
+        assert_eq!(string("größe"), "\"größe\"");
+    }
+}
@@ -1,41 +1,175 @@
-use std::env;
-use sudo_cli::SudoOptions;
+mod dispatch;
+mod features;
+mod list;
+
+use dispatch::Personality;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use sudo_cli::{names::NamePolicy, SudoOptions, TargetUser};
 use sudo_common::{
-    context::{CommandAndArguments, Context},
-    env::Environment,
+    clock::Monotonic,
+    config::{self, CONFIG_FILE},
+    context::{command_umask, resolve_directory, CommandAndArguments, Context},
+    debug::{self, DEBUG_ENV},
+    debug_log,
+    env::{format_environment, Environment},
     error::Error,
-    pam::authenticate,
+    exec::unprivileged,
+    group_provider, intercept, iolog,
+    logging::{self, Logger},
+    metrics::{self, metrics},
+    pam::{
+        authenticate, is_lectured, set_lectured, with_session, Askpass, CliConverser, Conversation,
+        Frontend, NonInteractive, Throttle, Tty, FAILURES_DIR, LECTURED_DIR,
+    },
+    prompt::{expand_prompt, PromptValues, DEFAULT_LECTURE, DEFAULT_PROMPT},
+    sysuser::UnixUser,
+};
+use sudo_system::{
+    audit,
+    capabilities::CapabilitySet,
+    database::database,
+    hostname, shells,
+    syslog::{Facility, Priority},
+    term, Process, User,
 };
-use sudo_system::{hostname, Group, User};
-use sudoers::Tag;
+use sudoers::{Action, ChDir, DenyReason, Settings, Sudoers, Tag};
 
-/// retrieve user information and build context object
-fn build_context(sudo_options: &SudoOptions) -> Result<Context, Error> {
+/// look up the user invoking sudo
+fn current_user() -> Result<User, Error> {
+    let db = database();
+    let user = db
+        .user_by_uid(User::real_uid())
+        .map_err(|_| Error::UserNotFound)?
+        .ok_or(Error::UserNotFound)?;
+    Ok(db.with_groups(user))
+}
+
+/// look up a user given by name (with `-U`)
+fn user_with_groups(name: &str) -> Result<User, Error> {
+    let db = database();
+    let user = db
+        .user_by_name(name)
+        .map_err(|_| Error::UserNotFound)?
+        .ok_or(Error::UserNotFound)?;
+    Ok(db.with_groups(user))
+}
+
+/// Check the user names given with `-u` and `-U` according to `user_names` in sudo-rs.conf
+fn check_user_names(sudo_options: &SudoOptions) -> Result<(), Error> {
+    let policy = match config::setting("user_names") {
+        Some(value) => value
+            .parse::<NamePolicy>()
+            .map_err(|err| Error::Configuration(format!("{err} in {CONFIG_FILE}")))?,
+        None => NamePolicy::default(),
+    };
+    let target = match &sudo_options.user {
+        Some(TargetUser::Name(name)) => Some(name),
+        _ => None,
+    };
+    for name in target.into_iter().chain(&sudo_options.other_user) {
+        policy
+            .check(name)
+            .map_err(|reason| Error::InvalidUserName {
+                name: name.clone(),
+                reason,
+            })?;
+    }
+    Ok(())
+}
+
+/// look up the user given with -u (by default: runas_default, which is root unless set); like in the original sudo, a `#uid` that is
+/// not in the password database is only accepted with runas_allow_unknown_id, as a user named
+/// `#uid` that has the primary group of the invoking user, and no home directory or shell
+fn target_user(
+    sudo_options: &SudoOptions,
+    current_user: &User,
+    settings: &Settings,
+) -> Result<User, Error> {
+    let (user, name) = match &sudo_options.user {
+        None => {
+            let name = settings.text("runas_default").unwrap_or("root");
+            (database().user_by_name(name), name.to_string())
+        }
+        Some(TargetUser::Name(name)) => (database().user_by_name(name), name.clone()),
+        Some(TargetUser::Id(uid)) => (database().user_by_uid(*uid), format!("#{uid}")),
+    };
+    match (user.map_err(|_| Error::UserNotFound)?, &sudo_options.user) {
+        (Some(user), _) => Ok(user),
+        (None, Some(TargetUser::Id(uid))) if settings.flag("runas_allow_unknown_id") => Ok(User {
+            uid: *uid,
+            gid: current_user.gid,
+            name,
+            gecos: String::new(),
+            home: String::new(),
+            shell: String::new(),
+            passwd: String::new(),
+            groups: None,
+        }),
+        (None, _) => Err(Error::UnknownUser(name)),
+    }
+}
+
+/// determine the command to run (and the shell for -s and -i); if the command will run in
+/// another root directory, it is looked up there
+fn resolve_command(
+    sudo_options: &SudoOptions,
+    current_user: &User,
+    target_user: &User,
+    root: Option<&Path>,
+) -> Result<CommandAndArguments, Error> {
     let command_args = sudo_options
         .external_args
         .iter()
         .map(|v| v.as_str())
         .collect::<Vec<&str>>();
 
-    let command = CommandAndArguments::try_from(command_args)?;
+    if sudo_options.login {
+        Ok(CommandAndArguments::shell(
+            target_user.shell.clone().into(),
+            command_args,
+        ))
+    } else if sudo_options.shell {
+        let shell = env::var("SHELL").unwrap_or_else(|_| current_user.shell.clone());
+        Ok(CommandAndArguments::shell(shell.into(), command_args))
+    } else if let Some(root) = root {
+        CommandAndArguments::try_in_root(command_args, root)
+    } else {
+        CommandAndArguments::try_from(command_args)
+    }
+}
 
+/// retrieve user information and build context object
+fn build_context(
+    sudo_options: &SudoOptions,
+    current_user: User,
+    root: Option<&Path>,
+    settings: &Settings,
+) -> Result<Context, Error> {
     let hostname = hostname();
 
-    let current_user = User::real()
-        .map_err(|_| Error::UserNotFound)?
-        .ok_or(Error::UserNotFound)?;
+    let db = database();
 
-    let target_user = User::from_name(sudo_options.user.as_deref().unwrap_or("root"))
-        .map_err(|_| Error::UserNotFound)?
-        .ok_or(Error::UserNotFound)?
-        .with_groups();
+    let target_user = db.with_groups(target_user(sudo_options, &current_user, settings)?);
 
-    let target_group = Group::from_gid(target_user.gid)
+    // the command is looked up in the same root directory that resolve_chroot settles on
+    let root = root
+        .map(|root| expand_tilde(root, &target_user))
+        .transpose()?;
+    let command = resolve_command(sudo_options, &current_user, &target_user, root.as_deref())?;
+
+    let target_group = db
+        .group_by_gid(target_user.gid)
         .map_err(|_| Error::UserNotFound)?
         .ok_or(Error::UserNotFound)?;
 
-    let mut context = Context {
+    let context = Context {
         hostname,
+        chdir: None,
+        chroot: root,
         command,
         current_user,
         target_user,
@@ -43,71 +177,1611 @@ fn build_context(sudo_options: &SudoOptions) -> Result<Context, Error> {
         target_environment: Default::default(),
         preserve_env: sudo_options.preserve_env,
         set_home: sudo_options.set_home,
+        login: sudo_options.login,
+        background: sudo_options.background,
+        exit_with_parent: false,
+        noexec: false,
+        intercept: None,
+        umask: None,
+        capabilities: None,
+        sandbox: None,
+        timeout: None,
+        log_input: false,
+        log_output: false,
+        log_passwords: false,
+        iolog: None,
         preserve_env_list: sudo_options.preserve_env_list.clone(),
+        env_keep: Vec::new(),
+        env_check: Vec::new(),
+        env_delete: Vec::new(),
+        env_reset: true,
     };
 
-    let current = env::vars().collect::<Environment>();
-    context.target_environment = sudo_common::env::get_target_environment(current, &context);
-
     Ok(context)
 }
 
-/// parse suoers file and check permission to run the provided command given the context
-fn check_sudoers(context: &Context, sudo_options: &SudoOptions) -> Result<Option<Vec<Tag>>, Error> {
+/// parse the sudoers file, without applying the Defaults that only apply to some users or hosts;
+/// like every evaluation of the policy, the parser runs in an unprivileged helper process (see
+/// [unprivileged])
+fn read_sudoers() -> Result<Sudoers, Error> {
     // TODO: move to global configuration
     let sudoers_path = "/etc/sudoers.test";
 
-    let (sudoers, syntax_errors) = sudoers::compile(sudoers_path)
-        .map_err(|e| Error::Configuration(format!("no sudoers file {e}")))?;
+    let (sudoers, syntax_errors) =
+        unprivileged(|| sudoers::compile(sudoers_path).map_err(|e| e.to_string()))?
+            .map_err(|e| Error::Configuration(format!("no sudoers file {e}")))?;
+    report(syntax_errors);
+
+    // the helpers that evaluate the policy inherit the provider, with the answers that it has given
+    // so far (see [ask_group_provider])
+    if let Some(program) = sudoers.settings.str_value.get("group_provider") {
+        group_provider::set_group_provider(group_provider::External::new(program));
+    }
+
+    Ok(sudoers)
+}
 
+/// Have the group provider, which runs as root, answer whether these users are members of the
+/// non-Unix groups that the policy refers to; this must happen before the helpers that evaluate
+/// the policy for them are forked, since they cannot ask it themselves
+fn ask_group_provider(sudoers: &Sudoers, users: &[&User]) {
+    let groups = sudoers.nonunix_groups();
+    for user in users {
+        group_provider::ask_in_advance(&user.name, &groups);
+    }
+}
+
+/// Apply Defaults to the policy in an unprivileged helper, which sends back the updated policy
+fn apply_defaults(sudoers: &mut Sudoers, apply: impl FnOnce(&mut Sudoers)) -> Result<(), Error> {
+    *sudoers = unprivileged(|| {
+        let mut sudoers = std::mem::take(sudoers);
+        apply(&mut sudoers);
+        sudoers
+    })?;
+    Ok(())
+}
+
+fn report(syntax_errors: Vec<sudoers::Error>) {
     for error in syntax_errors {
-        eprintln!("Parse error: {error:?}");
+        match error {
+            sudoers::Error::Warning(message) => eprintln!("sudo: warning: {message}"),
+            error => eprintln!("Parse error: {error:?}"),
+        }
+    }
+}
+
+/// parse the sudoers file (applying the Defaults for this user and host) and set up debug logging
+/// as configured in it
+fn load_sudoers(current_user: &User) -> Result<Sudoers, Error> {
+    let mut sudoers = read_sudoers()?;
+    ask_group_provider(&sudoers, &[current_user]);
+    let hostname = hostname();
+    apply_defaults(&mut sudoers, |sudoers| {
+        sudoers.apply_scoped_defaults(current_user, &hostname)
+    })?;
+
+    if let Some(spec) = sudoers.settings.str_value.get("debug_flags") {
+        if let Err(message) = debug::configure(spec) {
+            eprintln!("sudo: invalid debug_flags: {message}");
+        }
+    }
+
+    Ok(sudoers)
+}
+
+/// The command and its arguments that will be run, as they are matched against sudoers
+fn command_argv(context: &Context) -> Vec<String> {
+    let command = &context.command;
+    std::iter::once(command.command.to_string_lossy().into_owned())
+        .chain(command.arguments.iter().cloned())
+        .collect()
+}
+
+/// The command with its directory resolved through symbolic links, as it is also matched against
+/// sudoers (see [CommandAndArguments::resolved])
+fn resolved_command(context: &Context) -> Option<String> {
+    let resolved = context.command.resolved.as_ref()?;
+    Some(resolved.to_string_lossy().into_owned())
+}
+
+/// The command line that will be run, as it is logged (the arguments joined by spaces)
+fn command_line(context: &Context) -> String {
+    command_argv(context).join(" ")
+}
+
+/// check permission to run the provided command given the context, in an unprivileged helper; if
+/// that fails, the command is not allowed
+fn check_sudoers(sudoers: &Sudoers, context: &Context) -> Result<Vec<Tag>, DenyReason> {
+    unprivileged(|| {
+        sudoers::judge(
+            sudoers,
+            &context.current_user,
+            sudoers::Request {
+                user: &context.target_user,
+                group: &context.target_group,
+                clock: &Monotonic,
+                resolved_command: resolved_command(context).as_deref(),
+            },
+            &context.hostname,
+            &command_argv(context),
+        )
+    })
+    .unwrap_or_else(|err| {
+        eprintln!("sudo: {err}");
+        Err(DenyReason::CommandNotAllowed)
+    })
+}
+
+/// Log a request that the policy does not allow, and give the error for it, with the same texts
+/// as the original sudo
+fn deny(reason: DenyReason, context: &Context, logger: &mut Logger) -> Error {
+    use DenyReason::*;
+
+    let user = &context.current_user.name;
+    let (problem, message) = match reason {
+        NotInSudoers => (
+            "user NOT in sudoers",
+            format!("{user} is not in the sudoers file"),
+        ),
+        HostMismatch => (
+            "user NOT authorized on host",
+            format!("{user} is not allowed to run sudo on {}", context.hostname),
+        ),
+        RunasNotAllowed | CommandNotAllowed | TagForbids(_) => (
+            "command not allowed",
+            format!(
+                "sorry, user {user} is not allowed to execute '{}' as {} on {}",
+                command_line(context),
+                context.target_user.name,
+                context.hostname
+            ),
+        ),
+    };
+    log_command(context, logger, Some(problem));
+    metrics().denied();
+
+    Error::Authentication(message)
+}
+
+/// The policy for the programs that an intercepted command runs: like the command itself, each of
+/// them has to be allowed by sudoers, for the same users; no password is asked for them. Requests
+/// are answered while sudo supervises the command, which has to stay responsive, so the policy is
+/// evaluated in sudo itself rather than in a helper process (see [unprivileged]).
+fn intercept_policy(sudoers: Sudoers, context: &Context) -> intercept::Policy {
+    let current_user = context.current_user.clone();
+    let target_user = context.target_user.clone();
+    let target_group = context.target_group.clone();
+    let hostname = context.hostname.clone();
+    let root = context.chroot.clone().unwrap_or_else(|| PathBuf::from("/"));
+    Box::new(move |path, arguments| {
+        // the first argument is the name that the program is run as
+        let argv = std::iter::once(path.to_string_lossy().into_owned())
+            .chain(arguments.iter().skip(1).cloned())
+            .collect::<Vec<_>>();
+        let resolved = resolve_directory(path, &root).map(|path| path.display().to_string());
+        sudoers::check_permission(
+            &sudoers,
+            &current_user,
+            sudoers::Request {
+                user: &target_user,
+                group: &target_group,
+                clock: &Monotonic,
+                resolved_command: resolved.as_deref(),
+            },
+            &hostname,
+            &argv,
+        )
+        .is_some()
+    })
+}
+
+/// Report running (or refusing to run) the command to the Linux audit subsystem
+fn audit_command(context: &Context, success: bool) {
+    let cwd = env::current_dir().unwrap_or_default();
+    let record = audit::format_record(
+        &[
+            ("cwd", audit::encode_value(&cwd.to_string_lossy())),
+            ("cmd", audit::encode_value(&command_line(context))),
+            (
+                "terminal",
+                term::tty_name().unwrap_or_else(|| "?".to_string()),
+            ),
+        ],
+        success,
+    );
+    if let Err(err) = audit::log(audit::Event::Command, &record) {
+        debug_log!(Main, Warn, "cannot write audit record: {err}");
     }
+}
+
+/// The event log, as configured by the `syslog`, `logfile`, `log_year`, `log_host`,
+/// `log_allowed` and `log_denied` settings
+fn event_logger(settings: &Settings, hostname: &str) -> Logger {
+    let priority = |name: &str, default: Priority| {
+        settings
+            .str_value
+            .get(name)
+            .and_then(|value| Priority::from_name(value))
+            .unwrap_or(default)
+    };
+    let syslog = settings.str_value.get("syslog").map(|name| {
+        Facility::from_name(name).unwrap_or_else(|| {
+            debug_log!(Main, Warn, "unknown syslog facility {name}");
+            Facility::AUTHPRIV
+        })
+    });
 
-    Ok(sudoers::check_permission(
-        &sudoers,
-        &context.current_user,
-        sudoers::Request {
-            user: &context.target_user,
-            group: &context.target_group,
+    Logger::new(
+        logging::Options {
+            syslog,
+            syslog_goodpri: priority("syslog_goodpri", Priority::NOTICE),
+            syslog_badpri: priority("syslog_badpri", Priority::ALERT),
+            logfile: settings.str_value.get("logfile").map(PathBuf::from),
+            log_year: settings.flags.contains("log_year"),
+            log_host: settings.flags.contains("log_host"),
+            log_allowed: settings.flags.contains("log_allowed"),
+            log_denied: settings.flags.contains("log_denied"),
+            mail_badpass: settings.flag("mail_badpass").then(|| logging::Mailer {
+                path: PathBuf::from(settings.text("mailerpath").unwrap_or("/usr/sbin/sendmail")),
+                flags: settings.text("mailerflags").unwrap_or_default().to_string(),
+                to: settings.text("mailto").unwrap_or("root").to_string(),
+            }),
         },
-        &context.hostname,
-        &sudo_options.external_args.join(" "),
-    ))
+        hostname,
+    )
+}
+
+/// Report running the command, or refusing to run it because of `problem`, to the audit
+/// subsystem and the event log (unless `log_allowed` or `log_denied` turns that off)
+fn log_command(context: &Context, logger: &mut Logger, problem: Option<&str>) {
+    if !logger.logs(problem.is_none()) {
+        return;
+    }
+    audit_command(context, problem.is_none());
+
+    let message = command_event(context, problem);
+    if let Err(err) = logger.log(&context.current_user.name, &message, problem.is_none()) {
+        debug_log!(Main, Warn, "cannot write to the log file: {err}");
+    }
+}
+
+/// The message about running the command (or refusing to run it because of `problem`), as it is
+/// logged
+fn command_event(context: &Context, problem: Option<&str>) -> String {
+    let mut message = problem
+        .map(|problem| format!("{problem} ; "))
+        .unwrap_or_default();
+    message.push_str(&format!(
+        "TTY={} ; PWD={} ; USER={} ; COMMAND={}",
+        term::tty_name().unwrap_or_else(|| "unknown".to_string()),
+        env::current_dir().unwrap_or_default().display(),
+        context.target_user.name,
+        command_line(context)
+    ));
+    message
 }
 
-fn main() -> Result<(), Error> {
+/// Expand a leading `~` (the home directory of the target user) or `~user` in a path
+fn expand_tilde(path: &Path, target_user: &User) -> Result<PathBuf, Error> {
+    let Some(text) = path.to_str().and_then(|text| text.strip_prefix('~')) else {
+        return Ok(path.to_path_buf());
+    };
+    let (name, rest) = text.split_once('/').unwrap_or((text, ""));
+
+    let home = if name.is_empty() {
+        target_user.home.clone()
+    } else {
+        database()
+            .user_by_name(name)
+            .map_err(|_| Error::UserNotFound)?
+            .ok_or(Error::UserNotFound)?
+            .home
+    };
+
+    if rest.is_empty() {
+        Ok(PathBuf::from(home))
+    } else {
+        Ok(Path::new(&home).join(rest))
+    }
+}
+
+/// Determine the umask for the command from the UMASK tag of the matching command, or else the
+/// umask setting
+fn resolve_umask(tags: &[Tag], settings: &Settings) -> Option<libc::mode_t> {
+    let configured = match tags.iter().rev().find_map(|tag| match tag {
+        Tag::Umask(mask) => Some(*mask),
+        _ => None,
+    }) {
+        Some(mask) => Some(mask as libc::mode_t),
+        // the sudoers parser has checked that this is a valid mode
+        None => settings.integer("umask").map(|mask| mask as libc::mode_t),
+    };
+
+    command_umask(
+        Process::umask(),
+        configured,
+        settings.flag("umask_override"),
+    )
+}
+
+/// Determine the capabilities that the command can have: unless the drop_capabilities setting is
+/// turned off, a command that does not run as root can only have those in retain_capabilities,
+/// and so can a command that runs as root if any are listed there
+fn resolve_capabilities(
+    target_user: &User,
+    settings: &Settings,
+) -> Result<Option<CapabilitySet>, Error> {
+    let names = settings
+        .list
+        .get("retain_capabilities")
+        .into_iter()
+        .flatten();
+    let retain = CapabilitySet::from_names(names.map(String::as_str)).map_err(|name| {
+        Error::Configuration(format!("unknown capability in retain_capabilities: {name}"))
+    })?;
+
+    if !settings.flag("drop_capabilities") || (target_user.uid == 0 && retain.is_empty()) {
+        Ok(None)
+    } else {
+        Ok(Some(retain))
+    }
+}
+
+/// Determine whether what is typed into the command, and what it outputs, is recorded: the
+/// LOG_INPUT and LOG_OUTPUT tags (and their NOLOG_ counterparts) of the matching command decide,
+/// or else the `log_input` and `log_output` settings, unless `log_allowed` is turned off
+fn resolve_io_logging(tags: &[Tag], settings: &Settings) -> (bool, bool) {
+    let tagged = |on: Tag, off: Tag| {
+        tags.iter().rev().find_map(|tag| match tag {
+            tag if *tag == on => Some(true),
+            tag if *tag == off => Some(false),
+            _ => None,
+        })
+    };
+    let log_allowed = settings.flag("log_allowed");
+    let input = tagged(Tag::LogInput, Tag::NoLogInput)
+        .unwrap_or_else(|| log_allowed && settings.flag("log_input"));
+    let output = tagged(Tag::LogOutput, Tag::NoLogOutput)
+        .unwrap_or_else(|| log_allowed && settings.flag("log_output"));
+    (input, output)
+}
+
+/// Who owns the files and directories of I/O logs, and with which permissions: the `iolog_user`
+/// and `iolog_group` settings (by default, the primary group of iolog_user), and `iolog_mode`
+fn iolog_ownership(settings: &Settings) -> Result<iolog::Ownership, Error> {
+    let db = database();
+    let unknown =
+        |setting: &str, name: &str| Error::Configuration(format!("unknown {setting} {name}"));
+    let name = settings.text("iolog_user").unwrap_or("root");
+    let user = db
+        .user_by_name(name)
+        .ok()
+        .flatten()
+        .ok_or_else(|| unknown("iolog_user", name))?;
+    let gid = match settings.text("iolog_group") {
+        Some(name) => {
+            db.group_by_name(name)
+                .ok()
+                .flatten()
+                .ok_or_else(|| unknown("iolog_group", name))?
+                .gid
+        }
+        None => user.gid,
+    };
+    let mode = settings
+        .integer("iolog_mode")
+        .map_or(0o600, |mode| mode as u32);
+
+    Ok(iolog::Ownership {
+        uid: user.uid,
+        gid,
+        mode,
+    })
+}
+
+/// Start the I/O log of the command, in the session that the `iolog_dir` and `iolog_file` settings
+/// name (by default, the next sequence number in /var/log/sudo-io)
+fn open_iolog(context: &Context, settings: &Settings) -> Result<iolog::Session, Error> {
+    let user = &context.current_user;
+    let group = database()
+        .group_by_gid(user.gid)
+        .ok()
+        .flatten()
+        .map_or_else(|| format!("#{}", user.gid), |group| group.name);
+    let command = &context.command.command;
+    let name = command.file_name().unwrap_or_default().to_string_lossy();
+    let escapes = iolog::Escapes {
+        user: &user.name,
+        group: &group,
+        runas_user: &context.target_user.name,
+        runas_group: &context.target_group.name,
+        hostname: &context.hostname,
+        command: &name,
+    };
+    let ownership = iolog_ownership(settings)?;
+    let dir = settings
+        .text("iolog_dir")
+        .unwrap_or(iolog::DEFAULT_IOLOG_DIR);
+    let file = settings
+        .text("iolog_file")
+        .unwrap_or(iolog::DEFAULT_IOLOG_FILE);
+    let failed = |err: io::Error| Error::Configuration(format!("unable to create I/O log: {err}"));
+    let path = iolog::session_path(dir, file, &escapes, &ownership).map_err(failed)?;
+
+    let info = iolog::SessionInfo {
+        time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as libc::time_t),
+        user: user.name.clone(),
+        runas_user: context.target_user.name.clone(),
+        runas_group: context.target_group.name.clone(),
+        tty: term::tty_name().map_or_else(|| "unknown".to_string(), |tty| format!("/dev/{tty}")),
+        cwd: env::current_dir().unwrap_or_default().display().to_string(),
+        command: command_line(context),
+    };
+    debug_log!(Main, Info, "recording the session in {}", path.display());
+    iolog::Session::create(&path, &info, &ownership).map_err(failed)
+}
+
+/// Check that the modes the command runs in can be combined, now that the policy has determined
+/// them: a command in the background (`-b`) can not depend on sudo, which does not wait for it
+fn check_modes(context: &Context, intercept: bool) -> Result<(), Error> {
+    if !context.background {
+        return Ok(());
+    }
+    if context.log_input || context.log_output {
+        return Err(Error::BackgroundNotAllowed(
+            "its input or output is recorded in an I/O log",
+        ));
+    }
+    if intercept {
+        return Err(Error::BackgroundNotAllowed(
+            "the programs that it runs are checked by sudo (INTERCEPT)",
+        ));
+    }
+    Ok(())
+}
+
+/// Determine whether the command runs in a sandbox (with `--sandbox`, or the sandbox setting), and
+/// which paths stay writable there: those in sandbox_writable, which must be absolute
+fn resolve_sandbox(
+    sudo_options: &SudoOptions,
+    settings: &Settings,
+) -> Result<Option<Vec<PathBuf>>, Error> {
+    if !sudo_options.sandbox && !settings.flag("sandbox") {
+        return Ok(None);
+    }
+    let paths = settings.list.get("sandbox_writable").into_iter().flatten();
+    let mut writable = Vec::new();
+    for path in paths.map(PathBuf::from) {
+        if !path.is_absolute() {
+            return Err(Error::Configuration(format!(
+                "sandbox_writable paths must be absolute: {}",
+                path.display()
+            )));
+        }
+        writable.push(path);
+    }
+    Ok(Some(writable))
+}
+
+/// Determine the working directory for the command; the user can only choose one with `-D` if
+/// the policy (the CWD tag of the matching command, or else the runcwd setting) is `*`. Like the
+/// policy, `-D` can start with `~` or `~user`. Without either, a login shell (`-i`) starts in the
+/// home directory of the target user, if there is one.
+fn resolve_chdir(
+    context: &Context,
+    sudo_options: &SudoOptions,
+    tags: &[Tag],
+    settings: &Settings,
+) -> Result<Option<PathBuf>, Error> {
+    let policy = tags
+        .iter()
+        .rev()
+        .find_map(|tag| match tag {
+            Tag::Cwd(chdir) => Some(chdir.clone()),
+            _ => None,
+        })
+        .or_else(|| {
+            settings
+                .str_value
+                .get("runcwd")
+                .map(|value| match value.as_str() {
+                    "*" => ChDir::Any,
+                    path => ChDir::Path(path.into()),
+                })
+        });
+
+    match (&sudo_options.directory, policy) {
+        (Some(dir), Some(ChDir::Any)) => {
+            // relative paths are relative to the working directory of the invoking user
+            let cwd = env::current_dir()
+                .map_err(|e| Error::Configuration(format!("cannot get working directory: {e}")))?;
+            Ok(Some(cwd.join(expand_tilde(dir, &context.target_user)?)))
+        }
+        (Some(dir), _) => Err(Error::ChDirNotAllowed {
+            chdir: dir.clone(),
+            command: context.command.command.clone(),
+        }),
+        (None, Some(ChDir::Path(path))) => Ok(Some(expand_tilde(&path, &context.target_user)?)),
+        (None, _) if context.login => {
+            // like the original sudo, a login shell also starts if the home directory is missing
+            let home = PathBuf::from(&context.target_user.home);
+            let in_root = match &context.chroot {
+                Some(root) => root.join(home.strip_prefix("/").unwrap_or(&home)),
+                None => home.clone(),
+            };
+            Ok(in_root.is_dir().then_some(home))
+        }
+        (None, _) => Ok(None),
+    }
+}
+
+/// Check that the policy allows preserving the environment the user asked for: `-E`, and naming
+/// variables with `--preserve-env` that would not be kept anyway, require the SETENV tag (which a
+/// command of `ALL` implies) or else the setenv flag.
+fn check_env_preservation(
+    context: &Context,
+    current_env: &Environment,
+    tags: &[Tag],
+    settings: &Settings,
+) -> Result<(), Error> {
+    let allowed = tags
+        .iter()
+        .rev()
+        .find_map(|tag| match tag {
+            Tag::SetEnv => Some(true),
+            Tag::NoSetEnv => Some(false),
+            _ => None,
+        })
+        .unwrap_or_else(|| settings.flags.contains("setenv"));
+    if allowed {
+        return Ok(());
+    }
+
+    if context.preserve_env {
+        return Err(Error::PreserveEnvNotAllowed);
+    }
+    let disallowed = sudo_common::env::disallowed_variables(current_env, context);
+    if !disallowed.is_empty() {
+        return Err(Error::SetEnvNotAllowed(disallowed));
+    }
+
+    Ok(())
+}
+
+/// The root directory the user asked for with `-R`, or else the one given by the runchroot
+/// setting; the command is looked up in it before the policy is checked.
+fn requested_chroot(sudo_options: &SudoOptions, settings: &Settings) -> Option<PathBuf> {
+    sudo_options.chroot.clone().or_else(|| {
+        settings
+            .str_value
+            .get("runchroot")
+            .filter(|path| *path != "*")
+            .map(PathBuf::from)
+    })
+}
+
+/// Determine the root directory for the command; like for `-D`, the user can only choose one with
+/// `-R` if the policy (the CHROOT tag of the matching command, or else runchroot) is `*`.
+fn resolve_chroot(
+    context: &Context,
+    sudo_options: &SudoOptions,
+    tags: &[Tag],
+    settings: &Settings,
+) -> Result<Option<PathBuf>, Error> {
+    let policy = tags
+        .iter()
+        .rev()
+        .find_map(|tag| match tag {
+            Tag::Chroot(chroot) => Some(chroot.clone()),
+            _ => None,
+        })
+        .or_else(|| {
+            settings
+                .str_value
+                .get("runchroot")
+                .map(|value| match value.as_str() {
+                    "*" => ChDir::Any,
+                    path => ChDir::Path(path.into()),
+                })
+        });
+
+    match (&sudo_options.chroot, policy) {
+        (Some(dir), Some(ChDir::Any)) => {
+            let cwd = env::current_dir()
+                .map_err(|e| Error::Configuration(format!("cannot get working directory: {e}")))?;
+            Ok(Some(cwd.join(expand_tilde(dir, &context.target_user)?)))
+        }
+        (Some(dir), _) => Err(Error::ChRootNotAllowed {
+            chroot: dir.clone(),
+            command: context.command.command.clone(),
+        }),
+        (None, Some(ChDir::Path(path))) => Ok(Some(expand_tilde(&path, &context.target_user)?)),
+        (None, _) => Ok(None),
+    }
+}
+
+/// Without path_info, a command that cannot be found is reported in the same way as a command
+/// that is not allowed, so users cannot find out which programs exist outside their reach
+fn hide_path_info(err: Error, settings: &Settings) -> Error {
+    match err {
+        Error::CommandNotFound(_) if !settings.flags.contains("path_info") => {
+            Error::auth("no permission")
+        }
+        err => err,
+    }
+}
+
+/// Whether the invoking user is a member of the `exempt_group` (given by name or as `#gid`)
+fn is_exempt(user: &User, settings: &Settings) -> bool {
+    let Some(group) = settings.str_value.get("exempt_group") else {
+        return false;
+    };
+    let group = group.strip_prefix('%').unwrap_or(group);
+    match group.strip_prefix('#') {
+        Some(gid) => gid.parse().is_ok_and(|gid| user.in_group_by_gid(gid)),
+        None => user.in_group_by_name(group),
+    }
+}
+
+/// With the requiretty flag, sudo only runs from a terminal. Since `Defaults!cmnd` entries can
+/// turn the flag off, this is checked once those are applied (and before authentication), so that
+/// `Defaults!BACKUP !requiretty` lets cron run the backup commands.
+fn check_tty(settings: &Settings, has_tty: bool) -> Result<(), Error> {
+    if settings.flag("requiretty") && !has_tty {
+        return Err(Error::TtyRequired);
+    }
+    Ok(())
+}
+
+/// Whether sudo has a controlling terminal
+fn has_tty() -> bool {
+    term::Terminal::open().is_ok()
+}
+
+/// With the runas_check_shell flag, commands can only be run as a user whose shell is listed in
+/// /etc/shells
+fn check_shell(target_user: &User, settings: &Settings) -> Result<(), Error> {
+    if !settings.flag("runas_check_shell") {
+        return Ok(());
+    }
+    let valid = shells::is_valid_shell(Path::new(&target_user.shell))
+        .map_err(|err| Error::Configuration(format!("cannot read /etc/shells: {err}")))?;
+    if !valid {
+        return Err(Error::Authentication(format!(
+            "invalid shell for user {}: {}",
+            target_user.name, target_user.shell
+        )));
+    }
+
+    Ok(())
+}
+
+/// The user whose password has to be entered: normally the invoking user, but the rootpw,
+/// runaspw and targetpw flags (in that order of precedence) select another one.
+fn auth_user(current_user: &User, target_user: &User, settings: &Settings) -> Result<User, Error> {
+    let by_name = |name: &str| {
+        database()
+            .user_by_name(name)
+            .map_err(|_| Error::UserNotFound)?
+            .ok_or(Error::UserNotFound)
+    };
+
+    if settings.flags.contains("rootpw") {
+        database()
+            .user_by_uid(0)
+            .map_err(|_| Error::UserNotFound)?
+            .ok_or(Error::UserNotFound)
+    } else if settings.flags.contains("runaspw") {
+        by_name(
+            settings
+                .str_value
+                .get("runas_default")
+                .map_or("root", |name| name.as_str()),
+        )
+    } else if settings.flags.contains("targetpw") {
+        Ok(target_user.clone())
+    } else {
+        Ok(current_user.clone())
+    }
+}
+
+/// Set up the password prompt; `passwd_timeout` is given in minutes (0, or `!passwd_timeout`,
+/// means no timeout), at most a day, which the parser of the sudoers file checks. The
+/// prompt of `-p` always replaces that of PAM, like `passprompt` does with `passprompt_override`.
+fn converser(
+    sudo_options: &SudoOptions,
+    settings: &Settings,
+    values: &PromptValues,
+) -> Result<CliConverser, Error> {
+    let minutes = settings.minutes("passwd_timeout").unwrap_or(0.0);
+
+    let template = sudo_options
+        .prompt
+        .as_deref()
+        .or(settings.text("passprompt"))
+        .unwrap_or(DEFAULT_PROMPT);
+
+    Ok(CliConverser {
+        timeout: (minutes > 0.0).then(|| Duration::from_secs_f64(minutes * 60.0)),
+        countdown: settings.flags.contains("passwd_countdown"),
+        prompt: Some(expand_prompt(template, values)),
+        override_prompt: sudo_options.prompt.is_some()
+            || settings.flags.contains("passprompt_override"),
+        auth_user: values.auth_user.to_string(),
+        conversation: frontend(sudo_options)?,
+    })
+}
+
+/// Where the authentication conversation takes place: on the terminal, through the askpass
+/// program (`-A`, from SUDO_ASKPASS or else `askpass` in sudo-rs.conf), or nowhere (`-n`)
+fn frontend(sudo_options: &SudoOptions) -> Result<Frontend, Error> {
+    if sudo_options.non_interactive {
+        return Ok(Frontend::NonInteractive(NonInteractive));
+    }
+    if !sudo_options.askpass {
+        return Ok(Frontend::Tty(Tty::default()));
+    }
+    let program = env::var_os("SUDO_ASKPASS")
+        .map(PathBuf::from)
+        .or_else(|| config::setting("askpass").map(PathBuf::from))
+        .ok_or_else(|| {
+            Error::Configuration(
+                "no askpass program specified, try setting SUDO_ASKPASS".to_string(),
+            )
+        })?;
+    Ok(Frontend::Askpass(Askpass::new(program)))
+}
+
+/// Show the lecture before the user authenticates, if the lecture setting asks for it (`always`,
+/// or `once` for a user who has not had it yet); this tells whether the user got to see it
+fn lecture(conversation: &mut impl Conversation, user: &str, settings: &Settings) -> bool {
+    let due = match settings.text("lecture") {
+        Some("always") => true,
+        Some("once") => !is_lectured(Path::new(LECTURED_DIR), user),
+        _ => false,
+    };
+    if !due {
+        return false;
+    }
+    let text = settings
+        .text("lecture_file")
+        .and_then(|path| fs::read_to_string(path).ok())
+        .unwrap_or_else(|| DEFAULT_LECTURE.to_string());
+    conversation.lecture(text.trim_end_matches('\n'))
+}
+
+/// Authenticate the user that [auth_user] selects, which takes longer after failures (see
+/// [Throttle]); a wrong password can be entered again, up to `passwd_tries` times in all
+fn authenticate_user(
+    current_user: &User,
+    target_user: &User,
+    sudo_options: &SudoOptions,
+    settings: &Settings,
+) -> Result<(), Error> {
+    let user = auth_user(current_user, target_user, settings)?;
+    let host = hostname();
+    let values = PromptValues {
+        host: &host,
+        auth_user: &user.name,
+        target_user: &target_user.name,
+        user: &current_user.name,
+    };
+    let seconds = settings.integer("passwd_delay").unwrap_or(2);
+    let throttle = Throttle {
+        delay: Duration::from_secs(seconds as u64),
+        dir: FAILURES_DIR.into(),
+        clock: Monotonic,
+    };
+    // without noninteractive_auth, -n does not even try whether PAM can do without a password
+    if sudo_options.non_interactive && !settings.flag("noninteractive_auth") {
+        return Err(Error::auth("a password is required"));
+    }
+
+    // nobody can enter the password again with -n
+    let tries = if sudo_options.non_interactive {
+        1
+    } else {
+        settings.integer("passwd_tries").unwrap_or(3) as u32
+    };
+    let tty = term::tty_name();
+    let mut lectured = false;
+    let mut result = Ok(());
+    for attempt in 1..=tries {
+        result = throttle.attempt(&user.name, tty.as_deref(), || {
+            let mut converser = converser(sudo_options, settings, &values)?;
+            if attempt == 1 {
+                lectured = lecture(&mut converser.conversation, &current_user.name, settings);
+            } else {
+                converser.conversation.show("Sorry, try again.");
+            }
+            authenticate(&user.name, converser)
+        });
+        if !matches!(result, Err(Error::IncorrectPassword(_))) {
+            break;
+        }
+    }
+    if lectured && result.is_ok() {
+        if let Err(err) = set_lectured(Path::new(LECTURED_DIR), &current_user.name) {
+            debug_log!(Auth, Warn, "cannot record the lecture: {err}");
+        }
+    }
+    metrics().authenticated(result.is_ok());
+    result.map_err(|err| match err {
+        Error::IncorrectPassword(_) => Error::IncorrectPassword(tries),
+        err => err,
+    })
+}
+
+/// Handle `sudo -v`, which only needs to authenticate the user (if the verifypw setting requires
+/// it)
+fn authenticate_only(sudo_options: &SudoOptions) -> Result<(), Error> {
+    let current_user = current_user()?;
+    let sudoers = load_sudoers(&current_user)?;
+    let target_user = target_user(sudo_options, &current_user, &sudoers.settings)?;
+    check_tty(&sudoers.settings, has_tty())?;
+
+    let password_required = unprivileged(|| {
+        sudoers::password_required(&sudoers, &current_user, &hostname(), Action::Validate)
+    })?;
+    if password_required && !is_exempt(&current_user, &sudoers.settings) {
+        authenticate_user(&current_user, &target_user, sudo_options, &sudoers.settings)?;
+    }
+    Ok(())
+}
+
+/// What `sudo -l` needs: the user whose privileges are listed (the one given with `-U`, which only
+/// root may do, or else the invoking user), and the policy for that user; the invoking user has
+/// authenticated first if the listpw setting requires it
+fn prepare_listing(sudo_options: &SudoOptions) -> Result<(User, Sudoers), Error> {
+    let current_user = current_user()?;
+    let list_user = match &sudo_options.other_user {
+        Some(name) if *name != current_user.name => {
+            if !current_user.is_root() {
+                return Err(Error::Authentication(format!(
+                    "you are not allowed to list the privileges of {name}"
+                )));
+            }
+            user_with_groups(name)?
+        }
+        _ => current_user.clone(),
+    };
+    let sudoers = load_sudoers(&list_user)?;
+    ask_group_provider(&sudoers, &[&current_user]);
+    let settings = &sudoers.settings;
+    check_tty(settings, has_tty())?;
+
+    let password_required = unprivileged(|| {
+        sudoers::password_required(&sudoers, &current_user, &hostname(), Action::List)
+    })?;
+    if password_required && !is_exempt(&current_user, settings) {
+        let target_user = target_user(sudo_options, &current_user, settings)?;
+        authenticate_user(&current_user, &target_user, sudo_options, settings)?;
+    }
+    Ok((list_user, sudoers))
+}
+
+/// Handle `sudo -l` without a command: print the privileges of the invoking user (or the user
+/// given with `-U`) on this host, in the format and version that were asked for (see [list]), and
+/// return whether there are any
+fn list_privileges(sudo_options: &SudoOptions) -> Result<bool, Error> {
+    let version = list::version(sudo_options.list_version)?;
+    let format = match (sudo_options.json, sudo_options.long_list) {
+        (true, _) => list::Format::Json,
+        (false, true) => list::Format::Long,
+        (false, false) => list::Format::Short,
+    };
+    let (list_user, sudoers) = prepare_listing(sudo_options)?;
+
+    let host = hostname();
+    let privileges = unprivileged(|| sudoers.list(&list_user, &host))?;
+    let messages = list::messages(|name| env::var(name).ok());
+    let name = &list_user.name;
+    let output = list::render(&privileges, name, &host, format, version, messages);
+    print!("{output}");
+    Ok(!privileges.is_empty())
+}
+
+/// Handle `sudo -l command`: print the fully qualified command line if the invoking user (or the
+/// user given with `-U`) may run it, and return whether that is the case. The long format of `-ll`
+/// makes no difference here.
+fn check_command(sudo_options: &SudoOptions) -> Result<bool, Error> {
+    let (list_user, sudoers) = prepare_listing(sudo_options)?;
+    let settings = &sudoers.settings;
+
+    let root = requested_chroot(sudo_options, settings);
+    let context = build_context(sudo_options, list_user, root.as_deref(), settings)
+        .map_err(|err| hide_path_info(err, settings))?;
+    ask_group_provider(&sudoers, &[&context.target_user]);
+    if check_sudoers(&sudoers, &context).is_err() {
+        return Ok(false);
+    }
+
+    println!("{}", command_line(&context));
+    Ok(true)
+}
+
+/// Handle `sudo -V`: print the version and, if invoked by root, every effective Defaults setting
+/// for the invoking user (or the user given with `-U`) on this host (or the host given with `-h`),
+/// so that the outcome of host- and user-specific Defaults can be checked. With `--quiet`, only the
+/// version number is printed.
+fn show_version(sudo_options: &SudoOptions) -> Result<(), Error> {
+    if sudo_options.quiet {
+        println!("{}", features::version());
+        return Ok(());
+    }
+    println!("sudo-rs {}", features::version());
+
+    let current_user = current_user()?;
+    if !current_user.is_root() {
+        return Ok(());
+    }
+    let user = match &sudo_options.other_user {
+        Some(name) => user_with_groups(name)?,
+        None => current_user,
+    };
+    let host = sudo_options.host.clone().unwrap_or_else(hostname);
+
+    let mut sudoers = read_sudoers()?;
+    ask_group_provider(&sudoers, &[&user]);
+    apply_defaults(&mut sudoers, |sudoers| {
+        sudoers.apply_scoped_defaults(&user, &host)
+    })?;
+    print!(
+        "\nDefaults for {} on {host}:\n{}",
+        user.name, sudoers.settings
+    );
+
+    Ok(())
+}
+
+fn main() {
+    let personality = Personality::from_argv0(env::args_os().next().as_deref());
+    if let Err(error) = sudo_process(personality) {
+        eprintln!("{}: {error}", personality.name());
+        std::process::exit(1);
+    }
+}
+
+fn sudo_process(personality: Personality) -> Result<(), Error> {
+    // the user database is only consulted by unprivileged helpers
+    #[cfg(feature = "privsep")]
+    sudo_system::database::set_database(sudo_system::database::Cached::new(
+        sudo_common::exec::UnprivilegedDatabase,
+    ));
+
+    if let Err(message) = debug::configure_from_env() {
+        eprintln!("sudo: invalid {DEBUG_ENV}: {message}");
+    }
+
     // parse cli options
-    let sudo_options = SudoOptions::parse();
+    let sudo_options = personality.parse_options();
+    if let Some(feature) = &sudo_options.has_feature {
+        std::process::exit(if features::has_feature(feature) { 0 } else { 1 });
+    }
+    check_user_names(&sudo_options)?;
 
-    // build context and environment
-    let context = build_context(&sudo_options)?;
+    if sudo_options.version {
+        return show_version(&sudo_options);
+    }
+
+    // everything else needs the privileges of root, and fails in obscure ways without them
+    let executable = env::current_exe().unwrap_or_else(|_| PathBuf::from("sudo"));
+    if let Some(problem) = sudo_system::setuid::check(&executable) {
+        return Err(Error::NotPrivileged(problem));
+    }
+    metrics::set_metrics(metrics::configured().map_err(Error::Configuration)?);
+
+    // credentials are never cached, so there is nothing to discard (or ignore, with a command),
+    // and nothing that `-N` could keep from being updated
+    if sudo_options.only_resets_timestamp() {
+        return Ok(());
+    }
+
+    if sudo_options.edit {
+        return Err(Error::conf("editing files is not supported yet"));
+    }
+
+    if sudo_options.list && !sudo_options.external_args.is_empty() {
+        if !check_command(&sudo_options)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if sudo_options.list {
+        if !list_privileges(&sudo_options)? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if sudo_options.validate {
+        return authenticate_only(&sudo_options);
+    }
 
     // check sudoers file for permission
-    match check_sudoers(&context, &sudo_options)? {
-        Some(tags) => {
-            if !tags.contains(&Tag::NoPasswd) {
-                // authenticate user using pam
-                authenticate(&context.current_user.name)?;
+    let current_user = current_user()?;
+    let mut sudoers = load_sudoers(&current_user)?;
+    let settings = &sudoers.settings;
+
+    // build context and environment
+    let root = requested_chroot(&sudo_options, settings);
+    let mut context = build_context(&sudo_options, current_user, root.as_deref(), settings)
+        .map_err(|err| hide_path_info(err, settings))?;
+    ask_group_provider(&sudoers, &[&context.target_user]);
+    apply_defaults(&mut sudoers, |sudoers| {
+        sudoers.apply_runas_defaults(&context.target_user)
+    })?;
+    let settings = &sudoers.settings;
+    let mut logger = event_logger(settings, &context.hostname);
+    let judgement = check_sudoers(&sudoers, &context);
+    debug_log!(
+        Main,
+        Info,
+        "{} requests {} as {}:{}: {judgement:?}",
+        context.current_user.name,
+        context.command.command.display(),
+        context.target_user.name,
+        context.target_group.name
+    );
+    let tags = match judgement {
+        Ok(tags) => tags,
+        Err(reason) => return Err(deny(reason, &context, &mut logger)),
+    };
+    let (argv, resolved) = (command_argv(&context), resolved_command(&context));
+    apply_defaults(&mut sudoers, |sudoers| {
+        sudoers.apply_command_defaults(&argv, resolved.as_deref())
+    })?;
+    let settings = &sudoers.settings;
+    if let Err(err) = check_tty(settings, has_tty()) {
+        log_command(&context, &mut logger, Some(&err.to_string()));
+        return Err(err);
+    }
+    if let Err(err) = check_shell(&context.target_user, settings) {
+        log_command(&context, &mut logger, Some(&err.to_string()));
+        return Err(err);
+    }
+
+    context.chroot = resolve_chroot(&context, &sudo_options, &tags, settings)?;
+    if context.chroot != root {
+        // the CHROOT tag selected another root directory, in which the command has to be found
+        context.command = resolve_command(
+            &sudo_options,
+            &context.current_user,
+            &context.target_user,
+            context.chroot.as_deref(),
+        )
+        .map_err(|err| hide_path_info(err, settings))?;
+        if let Err(reason) = check_sudoers(&sudoers, &context) {
+            return Err(deny(reason, &context, &mut logger));
+        }
+    }
+    context.chdir = resolve_chdir(&context, &sudo_options, &tags, settings)?;
+    context.exit_with_parent = settings.flags.contains("exit_with_parent");
+    context.noexec = tags
+        .iter()
+        .rev()
+        .find_map(|tag| match tag {
+            Tag::NoExec => Some(true),
+            Tag::Exec => Some(false),
+            _ => None,
+        })
+        .unwrap_or_else(|| settings.flags.contains("noexec"));
+    // NOEXEC is the stricter of the two
+    let intercept = !context.noexec
+        && tags
+            .iter()
+            .rev()
+            .find_map(|tag| match tag {
+                Tag::Intercept => Some(true),
+                Tag::NoIntercept => Some(false),
+                _ => None,
+            })
+            .unwrap_or_else(|| settings.flags.contains("intercept"));
+    context.umask = resolve_umask(&tags, settings);
+    // a timeout of 0 (or less) means that there is none
+    context.timeout = tags
+        .iter()
+        .rev()
+        .find_map(|tag| match tag {
+            Tag::Timeout(seconds) => Some(*seconds),
+            _ => None,
+        })
+        .filter(|&seconds| seconds > 0)
+        .map(|seconds| Duration::from_secs(seconds as u64));
+    context.capabilities = resolve_capabilities(&context.target_user, settings)?;
+    context.sandbox = resolve_sandbox(&sudo_options, settings)?;
+    (context.log_input, context.log_output) = resolve_io_logging(&tags, settings);
+    context.log_passwords = settings.flag("log_passwords");
+    if let Err(err) = check_modes(&context, intercept) {
+        log_command(&context, &mut logger, Some(&err.to_string()));
+        return Err(err);
+    }
+
+    // determine the environment of the command
+    let list = |name: &str| {
+        settings
+            .list
+            .get(name)
+            .map(|items| items.iter().cloned().collect())
+            .unwrap_or_default()
+    };
+    context.env_keep = list("env_keep");
+    context.env_check = list("env_check");
+    context.env_delete = list("env_delete");
+    context.env_reset = settings.flag("env_reset");
+    let current = env::vars().collect::<Environment>();
+    if let Err(err) = check_env_preservation(&context, &current, &tags, settings) {
+        log_command(&context, &mut logger, Some(&err.to_string()));
+        return Err(err);
+    }
+    context.target_environment = sudo_common::env::get_target_environment(current, &context);
+
+    if settings.flag("set_sudo_home") {
+        context
+            .target_environment
+            .insert("SUDO_HOME".to_string(), context.current_user.home.clone());
+    }
+
+    let exempt = is_exempt(&context.current_user, settings);
+    if let Some(path) = settings.str_value.get("secure_path") {
+        if !exempt {
+            context
+                .target_environment
+                .insert("PATH".to_string(), path.clone());
+        }
+    }
+
+    if !sudoers::no_password(&tags, settings.flag("authenticate")) && !exempt {
+        // authenticate user using pam
+        let (current_user, target_user) = (&context.current_user, &context.target_user);
+        if let Err(err) = authenticate_user(current_user, target_user, &sudo_options, settings) {
+            log_command(&context, &mut logger, Some(&err.to_string()));
+            if let Error::IncorrectPassword(_) = err {
+                let message = command_event(&context, Some(&err.to_string()));
+                if let Err(err) = logger.mail_badpass(&context.current_user.name, &message) {
+                    debug_log!(Main, Warn, "cannot mail about the wrong password: {err}");
+                }
             }
+            return Err(err);
         }
-        None => {
-            return Err(Error::auth("no permission"));
+    }
+    if sudo_options.preview_env {
+        // a dry run, for checking what the policy does to the environment
+        print!("{}", format_environment(&context.target_environment));
+        return Ok(());
+    }
+    if context.log_input || context.log_output {
+        match open_iolog(&context, settings) {
+            Ok(session) => context.iolog = Some(session),
+            Err(err) => {
+                log_command(&context, &mut logger, Some(&err.to_string()));
+                return Err(err);
+            }
         }
-    };
+    }
+    log_command(&context, &mut logger, None);
+    if intercept {
+        context.intercept = Some(intercept_policy(sudoers, &context));
+    }
 
-    // run command and return corresponding exit code
-    match sudo_common::exec::exec(context) {
-        Ok(status) => {
-            if let Some(code) = status.code() {
-                std::process::exit(code);
-            } else {
-                std::process::exit(1);
+    // run command and return corresponding exit code; the session has to be closed before exiting
+    let target_user = context.target_user.name.clone();
+    let (background, start) = (context.background, Instant::now());
+    match with_session(&target_user, || sudo_common::exec::exec(context))? {
+        // terminate the same way as the command did
+        Ok(reason) => {
+            if !background {
+                metrics().command_finished(start.elapsed());
             }
+            reason.exit()
         }
         Err(e) => {
-            eprintln!("{e:?}");
-            std::process::exit(1);
+            eprintln!("sudo: {e}");
+            std::process::exit(e.exit_code());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> SudoOptions {
+        SudoOptions::try_parse_from(args.iter().copied()).unwrap()
+    }
+
+    fn root() -> User {
+        database().user_by_uid(0).unwrap().unwrap()
+    }
+
+    #[test]
+    fn tilde_expansion() {
+        let root = root();
+        let home = Path::new(&root.home);
+        for (path, expanded) in [
+            ("/tmp", PathBuf::from("/tmp")),
+            ("tmp/~", PathBuf::from("tmp/~")),
+            ("~", home.to_path_buf()),
+            ("~/bin", home.join("bin")),
+            ("~root", home.to_path_buf()),
+            ("~root/bin", home.join("bin")),
+        ] {
+            assert_eq!(expand_tilde(Path::new(path), &root).unwrap(), expanded);
         }
+        let err = expand_tilde(Path::new("~no-such-user/bin"), &root).unwrap_err();
+        assert!(matches!(err, Error::UserNotFound));
+    }
+
+    #[test]
+    fn working_directories() {
+        let root = root();
+        let home = PathBuf::from(&root.home);
+        let cwd = env::current_dir().unwrap();
+        let path = |text: &str| ChDir::Path(text.into());
+        // the working directory of the command (if any), or None if -D is refused
+        for (args, tag, runcwd, expected) in [
+            // without -D, the policy applies
+            (&["sudo", "true"][..], None, None, Some(None)),
+            (
+                &["sudo", "true"],
+                None,
+                Some("/tmp"),
+                Some(Some("/tmp".into())),
+            ),
+            (
+                &["sudo", "true"],
+                Some(path("~/bin")),
+                None,
+                Some(Some(home.join("bin"))),
+            ),
+            // -D needs the policy to allow any directory
+            (&["sudo", "-D", "/tmp", "true"], None, None, None),
+            (&["sudo", "-D", "/tmp", "true"], None, Some("/var"), None),
+            (
+                &["sudo", "-D", "/tmp", "true"],
+                None,
+                Some("*"),
+                Some(Some("/tmp".into())),
+            ),
+            (
+                &["sudo", "-D", "/tmp", "true"],
+                Some(path("/var")),
+                Some("*"),
+                None,
+            ),
+            (
+                &["sudo", "-D", "/tmp", "true"],
+                Some(ChDir::Any),
+                Some("/var"),
+                Some(Some("/tmp".into())),
+            ),
+            (
+                &["sudo", "-D", "tmp", "true"],
+                None,
+                Some("*"),
+                Some(Some(cwd.join("tmp"))),
+            ),
+            (
+                &["sudo", "-D", "~", "true"],
+                None,
+                Some("*"),
+                Some(Some(home.clone())),
+            ),
+            (
+                &["sudo", "-D", "~root/bin", "true"],
+                None,
+                Some("*"),
+                Some(Some(home.join("bin"))),
+            ),
+            (&["sudo", "-D", "~", "true"], None, None, None),
+            // a login shell starts in the home directory, unless the policy or -D says otherwise
+            (&["sudo", "-i"], None, None, Some(Some(home.clone()))),
+            (
+                &["sudo", "-i"],
+                None,
+                Some("/tmp"),
+                Some(Some("/tmp".into())),
+            ),
+            (&["sudo", "-i"], None, Some("*"), Some(Some(home.clone()))),
+            (
+                &["sudo", "-i", "-D", "/tmp"],
+                None,
+                Some("*"),
+                Some(Some("/tmp".into())),
+            ),
+            (&["sudo", "-i", "-D", "/tmp"], None, None, None),
+        ] {
+            let mut settings = Settings::default();
+            if let Some(runcwd) = runcwd {
+                settings
+                    .str_value
+                    .insert("runcwd".to_string(), runcwd.to_string());
+            }
+            let sudo_options = options(args);
+            let context = build_context(&sudo_options, root.clone(), None, &settings).unwrap();
+            let tags = Vec::from_iter(tag.clone().map(Tag::Cwd));
+            let chdir = resolve_chdir(&context, &sudo_options, &tags, &settings);
+            match expected {
+                Some(expected) => {
+                    assert_eq!(chdir.unwrap(), expected, "{args:?} {tag:?} {runcwd:?}")
+                }
+                None => assert!(
+                    matches!(chdir, Err(Error::ChDirNotAllowed { .. })),
+                    "{args:?} {tag:?} {runcwd:?}"
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn root_directories() {
+        let root = root();
+        let home = PathBuf::from(&root.home);
+        let cwd = env::current_dir().unwrap();
+        // the root directory of the command (if any), or None if -R is refused
+        for (args, runchroot, expected) in [
+            (&["sudo", "true"][..], None, Some(None)),
+            (&["sudo", "true"], Some("/srv"), Some(Some("/srv".into()))),
+            (
+                &["sudo", "true"],
+                Some("~/jail"),
+                Some(Some(home.join("jail"))),
+            ),
+            (&["sudo", "-R", "/srv", "true"], None, None),
+            (&["sudo", "-R", "/srv", "true"], Some("/var"), None),
+            (
+                &["sudo", "-R", "/srv", "true"],
+                Some("*"),
+                Some(Some("/srv".into())),
+            ),
+            (
+                &["sudo", "-R", "jail", "true"],
+                Some("*"),
+                Some(Some(cwd.join("jail"))),
+            ),
+            (
+                &["sudo", "-R", "~/root", "true"],
+                Some("*"),
+                Some(Some(home.join("root"))),
+            ),
+            (
+                &["sudo", "-R", "~root", "true"],
+                Some("*"),
+                Some(Some(home.clone())),
+            ),
+            (&["sudo", "-R", "~/root", "true"], None, None),
+        ] {
+            let mut settings = Settings::default();
+            if let Some(runchroot) = runchroot {
+                settings
+                    .str_value
+                    .insert("runchroot".to_string(), runchroot.to_string());
+            }
+            let sudo_options = options(args);
+            let context = build_context(&sudo_options, root.clone(), None, &settings).unwrap();
+            let chroot = resolve_chroot(&context, &sudo_options, &[], &settings);
+            match expected {
+                Some(expected) => assert_eq!(chroot.unwrap(), expected, "{args:?} {runchroot:?}"),
+                None => assert!(
+                    matches!(chroot, Err(Error::ChRootNotAllowed { .. })),
+                    "{args:?} {runchroot:?}"
+                ),
+            }
+        }
+    }
+
+    /// A user that is not in the user database
+    fn someone(name: &str) -> User {
+        User {
+            uid: 123456,
+            gid: 123456,
+            name: name.to_string(),
+            gecos: String::new(),
+            home: "/".to_string(),
+            shell: "/bin/sh".to_string(),
+            passwd: String::new(),
+            groups: Some(vec![123456, 0]),
+        }
+    }
+
+    #[test]
+    fn exempt_group() {
+        let user = someone("someone");
+        let exempt = |group: Option<&str>| {
+            let mut settings = Settings::default();
+            if let Some(group) = group {
+                settings
+                    .str_value
+                    .insert("exempt_group".to_string(), group.to_string());
+            }
+            is_exempt(&user, &settings)
+        };
+        assert!(!exempt(None));
+        for group in ["root", "%root", "#0", "%#0", "#123456"] {
+            assert!(exempt(Some(group)), "{group}");
+        }
+        for group in ["#1", "%#1", "no-such-group", "%no-such-group", "#root", "#"] {
+            assert!(!exempt(Some(group)), "{group}");
+        }
+    }
+
+    #[test]
+    fn runas_check_shell() {
+        let mut user = someone("someone");
+        user.shell = "/no/such/shell".to_string();
+        let mut settings = Settings::default();
+        assert!(check_shell(&user, &settings).is_ok());
+        settings.flags.insert("runas_check_shell".to_string());
+        assert!(matches!(
+            check_shell(&user, &settings),
+            Err(Error::Authentication(message)) if message.contains("/no/such/shell")
+        ));
+    }
+
+    #[test]
+    fn io_logging() {
+        let resolve = |tags: &[Tag], flags: &[&str]| {
+            let mut settings = Settings::default();
+            for flag in flags {
+                match flag.strip_prefix('!') {
+                    Some(flag) => settings.flags.remove(flag),
+                    None => settings.flags.insert(flag.to_string()),
+                };
+            }
+            resolve_io_logging(tags, &settings)
+        };
+        let both = ["log_input", "log_output"];
+        assert_eq!(resolve(&[], &[]), (false, false));
+        assert_eq!(resolve(&[], &both), (true, true));
+        assert_eq!(
+            resolve(&[], &["log_input", "log_output", "!log_allowed"]),
+            (false, false)
+        );
+        assert_eq!(resolve(&[Tag::NoLogOutput], &both), (true, false));
+        assert_eq!(resolve(&[Tag::LogInput], &["!log_allowed"]), (true, false));
+        assert_eq!(
+            resolve(&[Tag::LogOutput, Tag::NoLogOutput], &[]),
+            (false, false)
+        );
+    }
+
+    #[test]
+    fn io_log_ownership() {
+        let ownership = |values: &[(&str, &str)], mode: Option<i64>| {
+            let mut settings = Settings::default();
+            for (name, value) in values {
+                settings
+                    .str_value
+                    .insert(name.to_string(), value.to_string());
+            }
+            if let Some(mode) = mode {
+                settings.int_value.insert("iolog_mode".to_string(), mode);
+            }
+            iolog_ownership(&settings).map_err(|err| err.to_string())
+        };
+        let root = iolog::Ownership {
+            uid: 0,
+            gid: 0,
+            mode: 0o600,
+        };
+        assert_eq!(ownership(&[], None), Ok(root));
+        assert_eq!(
+            ownership(
+                &[("iolog_user", "root"), ("iolog_group", "root")],
+                Some(0o640)
+            ),
+            Ok(iolog::Ownership {
+                mode: 0o640,
+                ..root
+            })
+        );
+        assert_eq!(
+            ownership(&[("iolog_user", "no-such-user")], None),
+            Err("unknown iolog_user no-such-user".to_string())
+        );
+        assert_eq!(
+            ownership(&[("iolog_group", "no-such-group")], None),
+            Err("unknown iolog_group no-such-group".to_string())
+        );
+    }
+
+    #[test]
+    fn requiretty() {
+        let mut settings = Settings::default();
+        assert!(check_tty(&settings, false).is_ok());
+        settings.flags.insert("requiretty".to_string());
+        assert!(check_tty(&settings, true).is_ok());
+        let err = check_tty(&settings, false).unwrap_err();
+        assert_eq!(err.to_string(), "sorry, you must have a tty to run sudo");
+    }
+
+    #[test]
+    fn unknown_target_uid() {
+        let unknown = options(&["sudo", "-u", "#4000000123", "true"]);
+        let nobody = options(&["sudo", "-u", "no-such-user", "true"]);
+        let mut settings = Settings::default();
+        let err = target_user(&unknown, &root(), &settings).unwrap_err();
+        assert_eq!(err.to_string(), "unknown user #4000000123");
+        let err = target_user(&nobody, &root(), &settings).unwrap_err();
+        assert_eq!(err.to_string(), "unknown user no-such-user");
+
+        settings.flags.insert("runas_allow_unknown_id".to_string());
+        let user = target_user(&unknown, &root(), &settings).unwrap();
+        assert_eq!((user.uid, user.gid), (4000000123, 0));
+        assert_eq!(
+            (user.name.as_str(), user.home.as_str(), user.shell.as_str()),
+            ("#4000000123", "", "")
+        );
+        // names still have to exist
+        let err = target_user(&nobody, &root(), &settings);
+        assert!(matches!(err, Err(Error::UnknownUser(_))));
+    }
+
+    /// without -u, the command runs as runas_default, whose password runaspw asks for
+    #[test]
+    fn default_target_user() {
+        let none = options(&["sudo", "true"]);
+        let mut settings = Settings::default();
+        assert_eq!(target_user(&none, &root(), &settings).unwrap().name, "root");
+
+        settings
+            .str_value
+            .insert("runas_default".to_string(), "daemon".to_string());
+        settings.flags.insert("runaspw".to_string());
+        let target = target_user(&none, &root(), &settings).unwrap();
+        assert_eq!(target.name, "daemon");
+        let auth = auth_user(&someone("current"), &target, &settings).unwrap();
+        assert_eq!(auth.name, target.name);
+
+        let explicit = options(&["sudo", "-u", "root", "true"]);
+        assert_eq!(
+            target_user(&explicit, &root(), &settings).unwrap().name,
+            "root"
+        );
+    }
+
+    #[test]
+    fn background_modes() {
+        let settings = Settings::default();
+        let mut context =
+            build_context(&options(&["sudo", "-b", "true"]), root(), None, &settings).unwrap();
+        assert!(check_modes(&context, false).is_ok());
+        assert!(matches!(
+            check_modes(&context, true),
+            Err(Error::BackgroundNotAllowed(_))
+        ));
+        context.log_output = true;
+        assert!(matches!(
+            check_modes(&context, false),
+            Err(Error::BackgroundNotAllowed(_))
+        ));
+        context.background = false;
+        assert!(check_modes(&context, true).is_ok());
+    }
+
+    /// rootpw comes before runaspw, which comes before targetpw
+    #[test]
+    fn password_of_whom() {
+        let (current, target) = (someone("current"), someone("target"));
+        let auth_user = |flags: &[&str]| {
+            let mut settings = Settings::default();
+            settings
+                .flags
+                .extend(flags.iter().map(|flag| flag.to_string()));
+            settings
+                .str_value
+                .insert("runas_default".to_string(), "daemon".to_string());
+            auth_user(&current, &target, &settings).unwrap().name
+        };
+        assert_eq!(auth_user(&[]), "current");
+        assert_eq!(auth_user(&["targetpw"]), "target");
+        assert_eq!(auth_user(&["runaspw"]), "daemon");
+        assert_eq!(auth_user(&["runaspw", "targetpw"]), "daemon");
+        assert_eq!(auth_user(&["rootpw"]), "root");
+        assert_eq!(auth_user(&["rootpw", "runaspw", "targetpw"]), "root");
+        assert_eq!(auth_user(&["rootpw", "targetpw"]), "root");
     }
 }
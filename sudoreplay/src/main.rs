@@ -0,0 +1,313 @@
+//! sudoreplay-rs: list the sessions recorded in the I/O log directory, and play back the terminal
+//! output of a session.
+
+use clap::Parser;
+use std::{
+    collections::hash_map::{Entry, HashMap},
+    ffi::CString,
+    fs::File,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use sudo_common::iolog::{self, SessionInfo, DEFAULT_IOLOG_DIR};
+
+#[cfg(target_os = "linux")]
+use libc::strptime;
+
+// the libc crate only declares strptime for some platforms
+#[cfg(not(target_os = "linux"))]
+extern "C" {
+    fn strptime(
+        text: *const libc::c_char,
+        format: *const libc::c_char,
+        tm: *mut libc::tm,
+    ) -> *mut libc::c_char;
+}
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "sudoreplay-rs",
+    about = "sudoreplay - replay sudo session logs",
+    override_usage = "usage: sudoreplay [-d dir] [-m num] [-s num] ID
+    usage: sudoreplay [-d dir] -l [search expression]"
+)]
+struct Cli {
+    #[arg(
+        short = 'd',
+        long = "directory",
+        help = "specify directory for session logs",
+        value_name = "dir",
+        default_value = DEFAULT_IOLOG_DIR
+    )]
+    directory: PathBuf,
+    #[arg(
+        short = 'l',
+        long,
+        help = "list available session IDs, with optional expression"
+    )]
+    list: bool,
+    #[arg(
+        short = 'm',
+        long = "max-wait",
+        help = "max number of seconds to wait between events",
+        value_name = "num"
+    )]
+    max_wait: Option<f64>,
+    #[arg(
+        short = 's',
+        long,
+        help = "speed up or slow down output",
+        value_name = "num",
+        default_value_t = 1.0
+    )]
+    speed: f64,
+    #[arg(
+        help = "session ID, or search expression when listing",
+        trailing_var_arg = true
+    )]
+    args: Vec<String>,
+}
+
+/// A condition in a search expression; all conditions have to hold for a session to be listed
+#[derive(Debug, PartialEq)]
+enum Condition {
+    User(String),
+    Runas(String),
+    Group(String),
+    Tty(String),
+    Cwd(String),
+    Command(String),
+    FromDate(libc::time_t),
+    ToDate(libc::time_t),
+}
+
+impl Condition {
+    fn matches(&self, info: &SessionInfo) -> bool {
+        match self {
+            Condition::User(user) => &info.user == user,
+            Condition::Runas(user) => &info.runas_user == user,
+            Condition::Group(group) => &info.runas_group == group,
+            Condition::Tty(tty) => {
+                info.tty == *tty || info.tty.strip_prefix("/dev/") == Some(tty.as_str())
+            }
+            Condition::Cwd(cwd) => &info.cwd == cwd,
+            Condition::Command(command) => info.command.contains(command.as_str()),
+            Condition::FromDate(time) => info.time >= *time,
+            Condition::ToDate(time) => info.time < *time,
+        }
+    }
+}
+
+/// Parse a date as `YYYY-MM-DD`, optionally followed by a time, in local time; or as a number of
+/// seconds since the epoch
+fn parse_date(text: &str) -> Option<libc::time_t> {
+    if let Ok(seconds) = text.parse() {
+        return Some(seconds);
+    }
+
+    let text = CString::new(text).ok()?;
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%d"]
+        .into_iter()
+        .find_map(|format| {
+            let format = CString::new(format).ok()?;
+            unsafe {
+                let mut tm: libc::tm = std::mem::zeroed();
+                let end = strptime(text.as_ptr(), format.as_ptr(), &mut tm);
+                if end.is_null() || *end != 0 {
+                    return None;
+                }
+                tm.tm_isdst = -1;
+                Some(libc::mktime(&mut tm)).filter(|&time| time != -1)
+            }
+        })
+}
+
+/// Parse a search expression, which consists of `keyword value` pairs
+fn parse_expression(args: &[String]) -> Result<Vec<Condition>, String> {
+    let mut conditions = Vec::new();
+    let mut args = args.iter();
+    while let Some(keyword) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{keyword} requires an argument"))?
+            .clone();
+        let date = || parse_date(&value).ok_or_else(|| format!("could not parse date \"{value}\""));
+        conditions.push(match keyword.as_str() {
+            "user" => Condition::User(value),
+            "runas" => Condition::Runas(value),
+            "group" => Condition::Group(value),
+            "tty" => Condition::Tty(value),
+            "cwd" => Condition::Cwd(value),
+            "command" => Condition::Command(value),
+            "fromdate" => Condition::FromDate(date()?),
+            "todate" => Condition::ToDate(date()?),
+            _ => return Err(format!("unknown search term \"{keyword}\"")),
+        });
+    }
+
+    Ok(conditions)
+}
+
+/// A line describing a session, in the format of sudo's log messages
+fn describe(id: &str, info: &SessionInfo) -> String {
+    let format = CString::new("%b %e %H:%M:%S %Y").unwrap_or_default();
+    let mut line = format!(
+        "{} : {} : TTY={} ; CWD={} ; USER={} ; ",
+        iolog::format_time(info.time, &format),
+        info.user,
+        info.tty,
+        info.cwd,
+        info.runas_user
+    );
+    if !info.runas_group.is_empty() {
+        line.push_str(&format!("GROUP={} ; ", info.runas_group));
+    }
+    line.push_str(&format!("TSID={id} ; COMMAND={}", info.command));
+    line
+}
+
+fn list(dir: &Path, expression: &[String]) -> io::Result<()> {
+    let conditions = parse_expression(expression)
+        .map_err(|message| io::Error::new(io::ErrorKind::InvalidInput, message))?;
+
+    let mut stdout = io::stdout().lock();
+    for (id, info) in iolog::list_sessions(dir) {
+        if conditions.iter().all(|condition| condition.matches(&info)) {
+            writeln!(stdout, "{}", describe(&id, &info))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn replay(dir: &Path, speed: f64, max_wait: Option<f64>) -> io::Result<()> {
+    let info = SessionInfo::parse(&std::fs::read_to_string(dir.join("log"))?)?;
+    let timing = iolog::parse_timing(&std::fs::read_to_string(dir.join("timing"))?)?;
+
+    println!("Replaying sudo session: {}", info.command);
+
+    let mut stdout = io::stdout().lock();
+    let mut files = HashMap::new();
+    let mut buf = Vec::new();
+    for entry in timing {
+        std::thread::sleep(delay(entry.delay, speed, max_wait));
+
+        // input was echoed by the terminal, so it is already part of the output
+        let Some(name) = entry.stream.file_name() else {
+            continue;
+        };
+        if !entry.stream.is_output() {
+            continue;
+        }
+        let file = match files.entry(name) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(open_stream(&dir.join(name))?),
+        };
+        buf.resize(entry.size, 0);
+        file.read_exact(&mut buf)?;
+        stdout.write_all(&buf)?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+/// How long to wait before an event that came `delay` seconds after the previous one; a tiny speed
+/// factor makes that infinite, which is capped at `max_wait` or else at the longest wait there is
+fn delay(delay: f64, speed: f64, max_wait: Option<f64>) -> Duration {
+    let mut delay = delay / speed;
+    if let Some(max_wait) = max_wait {
+        delay = delay.min(max_wait);
+    }
+    Duration::try_from_secs_f64(delay).unwrap_or(Duration::MAX)
+}
+
+/// Open the data file of a stream; compressed logs are not supported
+fn open_stream(path: &Path) -> io::Result<File> {
+    let mut file = File::open(path)?;
+    let mut magic = [0; 2];
+    let compressed = file.read(&mut magic)? == 2 && magic == [0x1f, 0x8b];
+    if compressed {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{}: compressed I/O logs are not supported", path.display()),
+        ));
+    }
+    File::open(path)
+}
+
+fn run(cli: Cli) -> io::Result<()> {
+    if cli.list {
+        return list(&cli.directory, &cli.args);
+    }
+
+    let [id] = cli.args.as_slice() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a single session ID is required",
+        ));
+    };
+    if !(cli.speed.is_finite() && cli.speed > 0.0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid speed factor: {}", cli.speed),
+        ));
+    }
+    let max_wait = cli.max_wait.filter(|wait| wait.is_finite() && *wait >= 0.0);
+
+    replay(&iolog::session_dir(&cli.directory, id), cli.speed, max_wait)
+        .map_err(|err| io::Error::new(err.kind(), format!("{id}: {err}")))
+}
+
+fn main() {
+    if let Err(error) = run(Cli::parse()) {
+        eprintln!("sudoreplay: {error}");
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_expressions() {
+        let args = |text: &str| text.split(' ').map(str::to_string).collect::<Vec<_>>();
+        let info = SessionInfo {
+            time: parse_date("2023-11-14 12:00").unwrap(),
+            user: "alice".to_string(),
+            runas_user: "root".to_string(),
+            runas_group: String::new(),
+            tty: "/dev/pts/1".to_string(),
+            cwd: "/home/alice".to_string(),
+            command: "/usr/bin/id -u".to_string(),
+        };
+        let matches = |text: &str| {
+            parse_expression(&args(text))
+                .unwrap()
+                .iter()
+                .all(|condition| condition.matches(&info))
+        };
+
+        assert!(matches("user alice tty pts/1"));
+        assert!(matches("runas root command /usr/bin/id"));
+        assert!(matches("fromdate 2023-11-14 todate 2023-11-15"));
+        assert!(!matches("user bob"));
+        assert!(!matches("user alice fromdate 2023-11-15"));
+
+        assert!(parse_expression(&args("user")).is_err());
+        assert!(parse_expression(&args("host web")).is_err());
+        assert!(parse_expression(&args("todate tomorrow")).is_err());
+        assert_eq!(parse_date("1700000000"), Some(1700000000));
+    }
+
+    #[test]
+    fn delays() {
+        assert_eq!(delay(1.5, 2.0, None), Duration::from_millis(750));
+        assert_eq!(delay(1.5, 0.5, Some(2.0)), Duration::from_secs(2));
+        assert_eq!(delay(1.5, 1e-320, Some(2.0)), Duration::from_secs(2));
+        assert_eq!(delay(1.5, 1e-320, None), Duration::MAX);
+        assert_eq!(delay(0.0, 1e-320, None), Duration::ZERO);
+    }
+}
@@ -1,5 +1,8 @@
 use std::env;
-use sudo_common::sysuser::{UnixGroup, UnixUser};
+use sudo_common::{
+    clock::Monotonic,
+    sysuser::{UnixGroup, UnixUser},
+};
 
 fn chatty_check_permission(
     sudoers: sudoers::Sudoers,
@@ -12,7 +15,12 @@ fn chatty_check_permission(
         "Is '{}' allowed on '{}' to run: '{}' (as {}:{:?})?",
         am_user, on_host, chosen_poison, request.user, request.group
     );
-    let result = sudoers::check_permission(&sudoers, &am_user, request, on_host, chosen_poison);
+    // the words of the command line are separated by whitespace
+    let argv = chosen_poison
+        .split_whitespace()
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    let result = sudoers::check_permission(&sudoers, &am_user, request, on_host, &argv);
     println!("OUTCOME: {result:?}");
 }
 
@@ -35,7 +43,7 @@ impl UnixUser for GroupID {
         self.0 == uid
     }
     fn has_name(&self, name: &str) -> bool {
-        self.1.as_ref().map_or(false, |s| s == name)
+        self.1.as_deref() == Some(name)
     }
 }
 
@@ -64,9 +72,26 @@ impl UnixUser for UserRecord {
     }
 }
 
+/// With `--files`, print the order in which the files of the policy take effect, and the rules
+/// that repeat or contradict earlier ones (possibly in another file)
+fn list_files(cfg: &sudoers::Sudoers, warn: Vec<sudoers::Error>) {
+    for (number, file) in cfg.files().iter().enumerate() {
+        println!("{}: {}", number + 1, file.display());
+    }
+    for foobar in warn {
+        println!("ERROR: {foobar:?}")
+    }
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if let Ok((cfg, warn)) = sudoers::compile("./sudoers") {
+    if let Ok((mut cfg, mut warn)) = sudoers::compile("./sudoers") {
+        if args.get(1).map(String::as_str) == Some("--files") {
+            warn.extend(cfg.lint());
+            return list_files(&cfg, warn);
+        }
+        warn.extend(cfg.lint());
+        cfg.apply_scoped_defaults(&args[1].as_str(), &args[2]);
         for foobar in warn {
             println!("ERROR: {foobar:?}")
         }
@@ -81,7 +106,9 @@ fn main() {
                     group: &args
                         .get(5)
                         .map(|x| GroupID(2347, Some(x.clone())))
-                        .unwrap_or_else(|| (GroupID(0, Some("root".to_owned()))))
+                        .unwrap_or_else(|| GroupID(0, Some("root".to_owned()))),
+                    clock: &Monotonic,
+                    resolved_command: None,
                 },
                 &args[2],
                 &args[3],
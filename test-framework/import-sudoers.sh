@@ -0,0 +1,36 @@
+#!/bin/sh
+# Add sudoers files to the corpus that the sudoers parser is tested against (see
+# lib/sudoers/tests/corpus.rs), sorted by whether the visudo of the original sudo accepts them.
+# Run it where that visudo is installed, such as the container of the Dockerfile, with the files
+# and the corpus mounted:
+#
+#   docker build -t sudo-rs .
+#   docker run --rm -v /path/to/files:/import \
+#       -v "$PWD/test-framework/sudoers-corpus:/usr/src/sudo-rs/test-framework/sudoers-corpus" \
+#       sudo-rs test-framework/import-sudoers.sh /import/*
+#
+# Anonymize the files before they are added: replace user, group and host names (user1, %group1,
+# host1), and use the documentation networks (192.0.2.0/24, 2001:db8::/32) for addresses. An
+# #include of a file that does not exist in the container makes visudo reject the file.
+# Afterwards, `cargo test -p sudoers --test corpus` lists the files that sudo-rs disagrees on.
+
+set -eu
+
+corpus=$(dirname "$0")/sudoers-corpus
+check=$(mktemp)
+trap 'rm -f "$check"' EXIT
+
+for file in "$@"; do
+    name=$(basename "$file" .sudoers).sudoers
+    # visudo also checks the owner and the mode of the file, which are not what is imported
+    cp "$file" "$check"
+    chmod 0440 "$check"
+    if visudo -c -q -f "$check" >/dev/null 2>&1; then
+        verdict=accepted
+    else
+        verdict=rejected
+    fi
+    rm -f "$corpus/accepted/$name" "$corpus/rejected/$name"
+    cp "$file" "$corpus/$verdict/$name"
+    echo "$verdict/$name"
+done
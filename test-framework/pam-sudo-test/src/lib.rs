@@ -0,0 +1,305 @@
+//! A PAM module for tests, which plays a script that is given as its arguments in the pam.d file,
+//! so that tests can take sudo through any conversation, and to any outcome, without a real
+//! authentication stack. The arguments are handled in order:
+//!
+//! - `echo_off=TEXT` and `echo_on=TEXT` ask for an answer, without and with echo
+//! - `info=TEXT` and `error=TEXT` show a message
+//! - `expect=TEXT` fails with `PAM_AUTH_ERR` unless the last answer was TEXT
+//! - `result=NAME` ends the script with the result of that name (see [RESULTS]); a script that
+//!   runs to its end succeeds
+//!
+//! Linux-PAM splits the arguments at spaces, except inside square brackets, as in
+//! `[echo_off=Password: ]`. Every function of the module plays the script of its own line, so an
+//! `account` line can make `pam_acct_mgmt` fail, for instance; only `pam_setcred` always succeeds,
+//! since it shares the `auth` lines with `pam_authenticate`, and a `password` line is played once
+//! for `pam_chauthtok`, not also for its preliminary check.
+//!
+//! Example service files are in `test-framework/pam.d`.
+
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    ptr,
+};
+
+const PAM_SUCCESS: c_int = 0;
+const PAM_SERVICE_ERR: c_int = 3;
+const PAM_AUTH_ERR: c_int = 7;
+const PAM_CONV_ERR: c_int = 19;
+
+const PAM_CONV: c_int = 5;
+
+/// pam_chauthtok calls the module twice; the first time only to see whether it is ready
+const PAM_PRELIM_CHECK: c_int = 0x4000;
+
+const PAM_PROMPT_ECHO_OFF: c_int = 1;
+const PAM_PROMPT_ECHO_ON: c_int = 2;
+const PAM_ERROR_MSG: c_int = 3;
+const PAM_TEXT_INFO: c_int = 4;
+
+/// The results that a script can end with, by the names of `result=`
+pub const RESULTS: &[(&str, c_int)] = &[
+    ("success", PAM_SUCCESS),
+    ("perm_denied", 6),
+    ("auth_err", PAM_AUTH_ERR),
+    ("cred_insufficient", 8),
+    ("authinfo_unavail", 9),
+    ("user_unknown", 10),
+    ("maxtries", 11),
+    ("new_authtok_reqd", 12),
+    ("acct_expired", 13),
+    ("session_err", 14),
+    ("conv_err", PAM_CONV_ERR),
+    ("authtok_err", 20),
+    ("abort", 26),
+    ("authtok_expired", 27),
+];
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+type Converse = unsafe extern "C" fn(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int;
+
+#[repr(C)]
+struct PamConv {
+    conv: Option<Converse>,
+    appdata_ptr: *mut c_void,
+}
+
+// the module is not linked against libpam, which the application has already loaded
+extern "C" {
+    fn pam_get_item(pamh: *const c_void, item_type: c_int, item: *mut *const c_void) -> c_int;
+}
+
+/// One step of a script
+#[derive(Debug, PartialEq, Eq)]
+pub enum Step {
+    Ask { echo: bool, prompt: CString },
+    Show { error: bool, text: CString },
+    Expect(String),
+    Result(c_int),
+}
+
+/// Read a script from the arguments of the module
+pub fn parse<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<Vec<Step>, String> {
+    let text = |value: &str| CString::new(value).map_err(|_| format!("NUL in {value:?}"));
+    args.into_iter()
+        .map(|arg| {
+            let (key, value) = arg
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, not {arg:?}"))?;
+            Ok(match key {
+                "echo_off" | "echo_on" => Step::Ask {
+                    echo: key == "echo_on",
+                    prompt: text(value)?,
+                },
+                "info" | "error" => Step::Show {
+                    error: key == "error",
+                    text: text(value)?,
+                },
+                "expect" => Step::Expect(value.to_string()),
+                "result" => RESULTS
+                    .iter()
+                    .find(|(name, _)| *name == value)
+                    .map(|(_, code)| Step::Result(*code))
+                    .ok_or_else(|| format!("unknown result {value:?}"))?,
+                _ => return Err(format!("unknown step {key:?}")),
+            })
+        })
+        .collect()
+}
+
+/// Send one message through the conversation function of the application; the answer is empty
+/// for messages that only show something
+unsafe fn converse(conv: &PamConv, style: c_int, text: &CStr) -> Option<String> {
+    let message = PamMessage {
+        msg_style: style,
+        msg: text.as_ptr(),
+    };
+    let mut messages = [&message as *const PamMessage];
+    let mut response: *mut PamResponse = ptr::null_mut();
+    let status = (conv.conv?)(1, messages.as_mut_ptr(), &mut response, conv.appdata_ptr);
+    if status != PAM_SUCCESS {
+        return None;
+    }
+
+    let mut answer = String::new();
+    // the application allocates the response with malloc, and the module frees it
+    if !response.is_null() {
+        let resp = (*response).resp;
+        if !resp.is_null() {
+            answer = CStr::from_ptr(resp).to_string_lossy().into_owned();
+            libc::free(resp.cast());
+        }
+        libc::free(response.cast());
+    }
+    Some(answer)
+}
+
+unsafe fn play(pamh: *const c_void, steps: &[Step]) -> c_int {
+    let mut item = ptr::null();
+    if pam_get_item(pamh, PAM_CONV, &mut item) != PAM_SUCCESS || item.is_null() {
+        return PAM_CONV_ERR;
+    }
+    let conv = &*item.cast::<PamConv>();
+
+    let mut last_answer = None;
+    for step in steps {
+        match step {
+            Step::Ask { echo, prompt } => {
+                let style = if *echo {
+                    PAM_PROMPT_ECHO_ON
+                } else {
+                    PAM_PROMPT_ECHO_OFF
+                };
+                match converse(conv, style, prompt) {
+                    Some(answer) => last_answer = Some(answer),
+                    None => return PAM_CONV_ERR,
+                }
+            }
+            Step::Show { error, text } => {
+                let style = if *error { PAM_ERROR_MSG } else { PAM_TEXT_INFO };
+                if converse(conv, style, text).is_none() {
+                    return PAM_CONV_ERR;
+                }
+            }
+            Step::Expect(expected) => {
+                if last_answer.as_ref() != Some(expected) {
+                    return PAM_AUTH_ERR;
+                }
+            }
+            Step::Result(code) => return *code,
+        }
+    }
+    PAM_SUCCESS
+}
+
+/// Play the script given by the arguments of a module function
+unsafe fn run(pamh: *const c_void, argc: c_int, argv: *const *const c_char) -> c_int {
+    let args = (0..argc.max(0) as usize)
+        .map(|i| CStr::from_ptr(*argv.add(i)).to_str())
+        .collect::<Result<Vec<_>, _>>();
+    match args.map_err(|err| err.to_string()).and_then(parse) {
+        Ok(steps) => play(pamh, &steps),
+        Err(_) => PAM_SERVICE_ERR,
+    }
+}
+
+/// # Safety
+/// The arguments have to be those that PAM gives to pam_sm_authenticate(3)
+#[no_mangle]
+pub unsafe extern "C" fn pam_sm_authenticate(
+    pamh: *const c_void,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    run(pamh, argc, argv)
+}
+
+#[no_mangle]
+pub extern "C" fn pam_sm_setcred(
+    _pamh: *const c_void,
+    _flags: c_int,
+    _argc: c_int,
+    _argv: *const *const c_char,
+) -> c_int {
+    PAM_SUCCESS
+}
+
+/// # Safety
+/// The arguments have to be those that PAM gives to pam_sm_acct_mgmt(3)
+#[no_mangle]
+pub unsafe extern "C" fn pam_sm_acct_mgmt(
+    pamh: *const c_void,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    run(pamh, argc, argv)
+}
+
+/// # Safety
+/// The arguments have to be those that PAM gives to pam_sm_chauthtok(3)
+#[no_mangle]
+pub unsafe extern "C" fn pam_sm_chauthtok(
+    pamh: *const c_void,
+    flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    if flags & PAM_PRELIM_CHECK != 0 {
+        return PAM_SUCCESS;
+    }
+    run(pamh, argc, argv)
+}
+
+/// # Safety
+/// The arguments have to be those that PAM gives to pam_sm_open_session(3)
+#[no_mangle]
+pub unsafe extern "C" fn pam_sm_open_session(
+    pamh: *const c_void,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    run(pamh, argc, argv)
+}
+
+/// # Safety
+/// The arguments have to be those that PAM gives to pam_sm_close_session(3)
+#[no_mangle]
+pub unsafe extern "C" fn pam_sm_close_session(
+    pamh: *const c_void,
+    _flags: c_int,
+    argc: c_int,
+    argv: *const *const c_char,
+) -> c_int {
+    run(pamh, argc, argv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts() {
+        let steps = parse([
+            "echo_off=Password: ",
+            "info=a=b",
+            "expect=",
+            "result=maxtries",
+        ]);
+        assert_eq!(
+            steps.unwrap(),
+            [
+                Step::Ask {
+                    echo: false,
+                    prompt: c"Password: ".into()
+                },
+                Step::Show {
+                    error: false,
+                    text: c"a=b".into()
+                },
+                Step::Expect(String::new()),
+                Step::Result(11),
+            ]
+        );
+        assert!(parse(["echo_off"]).is_err());
+        assert!(parse(["result=fine"]).is_err());
+        assert!(parse(["prompt=PIN: "]).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+//! Play scripts through libpam itself, with a service file in a temporary directory; libpam is
+//! loaded at run time, so that building the tests does not need its development files.
+
+use std::{
+    collections::VecDeque,
+    env,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    fs,
+    path::PathBuf,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+#[repr(C)]
+struct PamMessage {
+    msg_style: c_int,
+    msg: *const c_char,
+}
+
+#[repr(C)]
+struct PamResponse {
+    resp: *mut c_char,
+    resp_retcode: c_int,
+}
+
+#[repr(C)]
+struct PamConv {
+    conv: unsafe extern "C" fn(
+        c_int,
+        *mut *const PamMessage,
+        *mut *mut PamResponse,
+        *mut c_void,
+    ) -> c_int,
+    appdata_ptr: *mut c_void,
+}
+
+type PamStartConfdir = unsafe extern "C" fn(
+    *const c_char,
+    *const c_char,
+    *const PamConv,
+    *const c_char,
+    *mut *mut c_void,
+) -> c_int;
+type PamFunction = unsafe extern "C" fn(*mut c_void, c_int) -> c_int;
+
+/// The module as cargo has built it for these tests, next to the test binary
+fn library() -> PathBuf {
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let library = deps.join("libpam_sudo_test.so");
+    assert!(library.exists(), "{} is missing", library.display());
+    library
+}
+
+/// The side of the application: answers prompts in order, and records every message
+#[derive(Default)]
+struct Transcript {
+    answers: VecDeque<&'static str>,
+    messages: Vec<String>,
+}
+
+unsafe extern "C" fn converse(
+    num_msg: c_int,
+    msg: *mut *const PamMessage,
+    resp: *mut *mut PamResponse,
+    appdata_ptr: *mut c_void,
+) -> c_int {
+    let transcript = &mut *appdata_ptr.cast::<Transcript>();
+    let responses = libc::calloc(num_msg as usize, size_of::<PamResponse>()).cast::<PamResponse>();
+    for i in 0..num_msg as usize {
+        let message = &**msg.add(i);
+        let text = CStr::from_ptr(message.msg).to_string_lossy();
+        transcript
+            .messages
+            .push(format!("{} {text}", message.msg_style));
+        if message.msg_style <= 2 {
+            let answer = CString::new(transcript.answers.pop_front().unwrap_or_default()).unwrap();
+            (*responses.add(i)).resp = libc::strdup(answer.as_ptr());
+        }
+    }
+    *resp = responses;
+    0
+}
+
+/// Authenticate with the given lines as the pam.d file, and then check the account like sudo does
+/// (if authentication succeeded); returns the results, and the messages of the conversation
+fn run(lines: &str, answers: &[&'static str]) -> (c_int, Option<c_int>, Vec<String>) {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let dir = env::temp_dir().join(format!("pam-sudo-test-{}-{run}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let module = library();
+    let config = lines.replace("pam_sudo_test.so", module.to_str().unwrap());
+    fs::write(dir.join("sudo-test"), config).unwrap();
+
+    let mut transcript = Transcript {
+        answers: answers.iter().copied().collect(),
+        ..Default::default()
+    };
+    let conv = PamConv {
+        conv: converse,
+        appdata_ptr: (&mut transcript as *mut Transcript).cast(),
+    };
+    let results = unsafe {
+        // the module is not linked against libpam, so its symbols have to be global
+        let libpam = libc::dlopen(c"libpam.so.0".as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL);
+        assert!(!libpam.is_null(), "libpam is missing");
+        let function = |name: &CStr| {
+            let function = libc::dlsym(libpam, name.as_ptr());
+            assert!(!function.is_null(), "{name:?} is missing");
+            function
+        };
+        let start: PamStartConfdir = std::mem::transmute(function(c"pam_start_confdir"));
+        let authenticate: PamFunction = std::mem::transmute(function(c"pam_authenticate"));
+        let acct_mgmt: PamFunction = std::mem::transmute(function(c"pam_acct_mgmt"));
+        let end: PamFunction = std::mem::transmute(function(c"pam_end"));
+
+        let confdir = CString::new(dir.to_str().unwrap()).unwrap();
+        let mut pamh = ptr::null_mut();
+        let status = start(
+            c"sudo-test".as_ptr(),
+            c"root".as_ptr(),
+            &conv,
+            confdir.as_ptr(),
+            &mut pamh,
+        );
+        assert_eq!(status, 0);
+        let auth = authenticate(pamh, 0);
+        let results = (auth, (auth == 0).then(|| acct_mgmt(pamh, 0)));
+        end(pamh, 0);
+        results
+    };
+    fs::remove_dir_all(dir).unwrap();
+    (results.0, results.1, transcript.messages)
+}
+
+#[test]
+fn multiple_prompts() {
+    let (auth, account, messages) = run(
+        "auth required pam_sudo_test.so [echo_off=Password: ] expect=hunter2 \
+         [info=Check your phone] [echo_on=Verification code: ] expect=123456\n\
+         account required pam_sudo_test.so\n",
+        &["hunter2", "123456"],
+    );
+    assert_eq!((auth, account), (0, Some(0)));
+    assert_eq!(
+        messages,
+        [
+            "1 Password: ",
+            "4 Check your phone",
+            "2 Verification code: "
+        ]
+    );
+}
+
+#[test]
+fn failures() {
+    let lines = "auth required pam_sudo_test.so [echo_off=PIN: ] expect=1234\n\
+                 account required pam_sudo_test.so [error=Your password has expired] \
+                 result=new_authtok_reqd\n";
+    // PAM_AUTH_ERR
+    let (auth, account, messages) = run(lines, &["0000"]);
+    assert_eq!((auth, account), (7, None));
+    assert_eq!(messages, ["1 PIN: "]);
+
+    // PAM_NEW_AUTHTOK_REQD
+    let (auth, account, messages) = run(lines, &["1234"]);
+    assert_eq!((auth, account), (0, Some(12)));
+    assert_eq!(messages, ["1 PIN: ", "3 Your password has expired"]);
+}
+
+/// The example service files work as they say
+#[test]
+fn fixtures() {
+    let fixture = |name: &str| {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../pam.d");
+        fs::read_to_string(dir.join(name)).unwrap()
+    };
+    let (auth, account, _) = run(&fixture("multi-prompt"), &["hunter2", "123456"]);
+    assert_eq!((auth, account), (0, Some(0)));
+    let (auth, account, _) = run(&fixture("custom-prompt"), &["1234"]);
+    assert_eq!((auth, account), (0, Some(0)));
+    let (auth, account, _) = run(&fixture("expired-password"), &["hunter2"]);
+    assert_eq!((auth, account), (0, Some(12)));
+    let (auth, account, _) = run(&fixture("account-expired"), &["hunter2"]);
+    assert_eq!((auth, account), (0, Some(13)));
+}